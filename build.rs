@@ -0,0 +1,86 @@
+// Compiles the GLSL under shaders/ to the SPIR-V the renderer embeds with
+// include_bytes!, so editing a shader is enough to get it rebuilt. The
+// SPIR-V goes in OUT_DIR, out of the source tree.
+//
+// glslc does the compiling, from the Vulkan SDK or GLSLC if it's elsewhere.
+// Its errors name the shader and line, and fail the build.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Source, defines and output of every shader permutation.
+const SHADERS: &[(&str, &[&str], &str)] = &[
+	("shader.vert", &[], "vert.spv"),
+	("shader.vert", &["OBJECT_BUFFER"], "object_vert.spv"),
+	("shader.vert", &["OBJECT_BUFFER", "DEPTH_ONLY"], "depth_vert.spv"),
+	("shader.frag", &[], "frag.spv"),
+	("shader.frag", &["PARALLAX"], "parallax_frag.spv"),
+	("shader.frag", &["PARALLAX", "PARALLAX_SHADOWS"], "parallax_shadowed_frag.spv"),
+	("vertex_color.frag", &[], "vertex_color_frag.spv"),
+	("depth_alpha.frag", &[], "depth_alpha_frag.spv"),
+	("shadow.vert", &[], "shadow_vert.spv"),
+	("debug_line.vert", &[], "debug_line_vert.spv"),
+	("debug_line.frag", &[], "debug_line_frag.spv"),
+	("composite.vert", &[], "composite_vert.spv"),
+	("composite.frag", &[], "composite_frag.spv"),
+	("overdraw.frag", &[], "overdraw_frag.spv"),
+	("outline.frag", &[], "outline_frag.spv"),
+	("sdf.frag", &[], "sdf_frag.spv"),
+	("fluid.comp", &[], "fluid_comp.spv"),
+	("particles.comp", &[], "particles_comp.spv"),
+	("particle.vert", &[], "particle_vert.spv"),
+	("particle.frag", &[], "particle_frag.spv"),
+	("noise.comp", &[], "noise_comp.spv"),
+	("noise.comp", &["NOISE_3D"], "noise_3d_comp.spv"),
+	("sky.frag", &[], "sky_frag.spv"),
+	("portal.vert", &[], "portal_vert.spv"),
+	("portal.frag", &[], "portal_frag.spv"),
+	("egui.vert", &[], "egui_vert.spv"),
+	("egui.frag", &[], "egui_frag.spv"),
+	("bloom.comp", &[], "bloom_comp.spv"),
+	("mipmap.comp", &["FORMAT=rgba8"], "mipmap_rgba8_comp.spv"),
+	("mipmap.comp", &["FORMAT=rgba16f"], "mipmap_rgba16f_comp.spv"),
+	("mipmap.comp", &["FORMAT=rgba32f"], "mipmap_rgba32f_comp.spv"),
+	("reduce.comp", &[], "reduce_comp.spv"),
+	("reduce.comp", &["SUBGROUPS"], "reduce_subgroups_comp.spv"),
+	("prefix_sum.comp", &[], "prefix_sum_comp.spv"),
+	("prefix_sum.comp", &["SUBGROUPS"], "prefix_sum_subgroups_comp.spv"),
+	("dispatch_args.comp", &[], "dispatch_args_comp.spv"),
+];
+
+fn main()
+{
+	let glslc = env::var("GLSLC").unwrap_or_else(|_| "glslc".into());
+	let shaders = Path::new("shaders");
+	let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("cargo sets OUT_DIR for build scripts"));
+
+	println!("cargo:rerun-if-env-changed=GLSLC");
+
+	let mut failed = false;
+	for (source, defines, output) in SHADERS
+	{
+		let source = shaders.join(source);
+		println!("cargo:rerun-if-changed={}", source.display());
+
+		// subgroup ops are Vulkan 1.1 SPIR-V, everything else stays loadable by 1.0
+		let target_env = if defines.contains(&"SUBGROUPS") { "vulkan1.1" } else { "vulkan1.0" };
+
+		let status = Command::new(&glslc)
+			.args(defines.iter().map(|d| format!("-D{}", d)))
+			.arg(format!("--target-env={}", target_env))
+			.arg(&source)
+			.arg("-o")
+			.arg(out_dir.join(output))
+			.status()
+			.unwrap_or_else(|e| panic!("Failed to run {} ({}), install the Vulkan SDK or set GLSLC", glslc, e));
+
+		// glslc has already printed the errors, keep going to show them all
+		failed |= !status.success();
+	}
+
+	if failed
+	{
+		panic!("Failed to compile shaders");
+	}
+}
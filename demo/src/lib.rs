@@ -0,0 +1,54 @@
+// Demo logic
+//
+// The per-frame behavior of the demo, kept out of the renderer so it can be
+// rebuilt and reloaded while the app runs (see `hot_reload.rs` in the
+// renderer). Everything the logic may read or change is in `DemoState`,
+// which the renderer owns and hands over each frame, so nothing here has to
+// survive a reload.
+//
+// Both sides are built from this crate, so the state's layout only has to
+// stay put within one build. `ABI_VERSION` is bumped whenever it changes so
+// a library built against an old layout is refused rather than misread.
+
+use nalgebra_glm as glm;
+
+pub const ABI_VERSION: u32 = 1;
+/// Must match `MAX_MODELS` in the renderer.
+pub const MAX_MODELS: usize = 4;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct DemoState
+{
+	/// Seconds since the app started.
+	pub time: f32,
+	/// Seconds since the last frame.
+	pub dt: f32,
+	/// How many models are drawn, up to `MAX_MODELS`.
+	pub models: u32,
+	/// Hours since midnight, and in-game hours per real second.
+	pub sun_hours: f32,
+	pub sun_speed: f32,
+	/// Where each model starts out, set by the renderer.
+	pub model_positions: [[f32; 3]; MAX_MODELS],
+	/// Each model's transform, column-major, set by the logic.
+	pub model_transforms: [[f32; 16]; MAX_MODELS],
+}
+
+#[no_mangle]
+pub extern "C" fn demo_abi_version() -> u32
+{
+	ABI_VERSION
+}
+
+/// Runs once a frame, before the renderer reads the state back.
+#[no_mangle]
+pub extern "C" fn demo_update(state: &mut DemoState)
+{
+	for i in 0..(state.models as usize).min(MAX_MODELS)
+	{
+		let model = glm::translate(&glm::identity(), &glm::Vec3::from(state.model_positions[i]));
+		let model = glm::rotate(&model, state.time * glm::radians(&glm::vec1(90.0))[0], &glm::vec3(0.0, 0.0, 1.0));
+		state.model_transforms[i].copy_from_slice(model.as_slice());
+	}
+}
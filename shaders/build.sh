@@ -1,3 +0,0 @@
-#!/bin/bash
-glslc shader.vert -o vert.spv
-glslc shader.frag -o frag.spv
@@ -0,0 +1,208 @@
+#version 450
+
+layout(location = 0) in vec2 fragUV;
+
+// intermediate render targets
+layout(binding = 0) uniform sampler2D sceneColor;
+layout(binding = 1) uniform sampler2DMS sceneDepth;
+// fluid simulation state, (velocity.xy, density, pressure)
+layout(binding = 2) uniform sampler2D fluid;
+// first mip of the bloom chain, already holding every smaller mip
+layout(binding = 3) uniform sampler2D bloom;
+// the left eye's scene color when rendering in stereo, the scene color otherwise
+layout(binding = 4) uniform sampler2D leftEye;
+
+// matches MAX_EFFECTS in post_process.rs
+const int MAX_EFFECTS = 8;
+
+const int EFFECT_BLOOM = 0;
+const int EFFECT_EXPOSURE = 1;
+const int EFFECT_TONEMAP = 2;
+const int EFFECT_SATURATION = 3;
+const int EFFECT_VIGNETTE = 4;
+
+// matches StereoMode in stereo.rs
+const int STEREO_SIDE_BY_SIDE = 1;
+const int STEREO_ANAGLYPH = 2;
+
+layout(push_constant) uniform PushConstants
+{
+	// which target to show, negative shows all of them in a grid
+	int target;
+	float near;
+	float far;
+	// contrast adaptive sharpening applied to the upsampled scene, 0 disables it
+	float sharpness;
+	// orthographic depth is already linear
+	int orthographic;
+	// stops added to the final image before post-processing, e.g. at night
+	float exposure;
+	// post-processing applied to the final image in order, see post_process.rs
+	int effectCount;
+	int effects[MAX_EFFECTS];
+	float effectParameters[MAX_EFFECTS];
+	// how the two eyes are combined, 0 when there's only one
+	int stereo;
+} pcs;
+
+layout(location = 0) out vec4 outColor;
+
+// only these are shown in the grid, overdraw replaces the scene color when selected
+const int TARGET_COUNT = 2;
+
+// matches OVERDRAW_STEP in overdraw.frag
+const float OVERDRAW_LAYERS = 16.0;
+// layer count that maps to the hottest color
+const float OVERDRAW_MAX = 8.0;
+
+vec3 heatmap(float t)
+{
+	t = clamp(t, 0.0, 1.0);
+	vec3 cold = mix(vec3(0.0, 0.0, 1.0), vec3(0.0, 1.0, 0.0), clamp(t * 2.0, 0.0, 1.0));
+	return mix(cold, vec3(1.0, 0.0, 0.0), clamp(t * 2.0 - 1.0, 0.0, 1.0));
+}
+
+// FSR1 RCAS-style sharpening, neighbours are a texel apart in the scene target
+// so this still works when it's smaller than the window
+vec3 sharpen(vec2 uv)
+{
+	vec2 texel = 1.0 / vec2(textureSize(sceneColor, 0));
+
+	vec3 c = texture(sceneColor, uv).rgb;
+	vec3 n = texture(sceneColor, uv + vec2(0.0, -texel.y)).rgb;
+	vec3 s = texture(sceneColor, uv + vec2(0.0, texel.y)).rgb;
+	vec3 e = texture(sceneColor, uv + vec2(texel.x, 0.0)).rgb;
+	vec3 w = texture(sceneColor, uv + vec2(-texel.x, 0.0)).rgb;
+
+	vec3 minColor = min(c, min(min(n, s), min(e, w)));
+	vec3 maxColor = max(c, max(max(n, s), max(e, w)));
+
+	// back off where there's already a lot of local contrast to avoid ringing
+	vec3 amount = sqrt(clamp(min(minColor, 1.0 - maxColor) / max(maxColor, 1e-5), 0.0, 1.0));
+	vec3 weight = -amount * mix(0.125, 0.2, pcs.sharpness);
+
+	return clamp((c + (n + s + e + w) * weight) / (1.0 + 4.0 * weight), 0.0, 1.0);
+}
+
+// when the scene is larger than the window a single bilinear tap would skip
+// texels, so average four taps spread over the output pixel's footprint
+vec3 downsample(vec2 uv)
+{
+	vec2 pixel = fwidth(fragUV);
+
+	return 0.25 * (
+		texture(sceneColor, uv + pixel * vec2(-0.25, -0.25)).rgb +
+		texture(sceneColor, uv + pixel * vec2(0.25, -0.25)).rgb +
+		texture(sceneColor, uv + pixel * vec2(-0.25, 0.25)).rgb +
+		texture(sceneColor, uv + pixel * vec2(0.25, 0.25)).rgb);
+}
+
+vec3 postProcess(vec3 color, vec2 uv)
+{
+	for (int i = 0; i < pcs.effectCount; i++)
+	{
+		float parameter = pcs.effectParameters[i];
+		switch (pcs.effects[i])
+		{
+		case EFFECT_BLOOM:
+			color += texture(bloom, uv).rgb * parameter;
+			break;
+		case EFFECT_EXPOSURE:
+			color *= exp2(parameter);
+			break;
+		case EFFECT_TONEMAP:
+			color = color * (1.0 + color / (parameter * parameter)) / (1.0 + color);
+			break;
+		case EFFECT_SATURATION:
+			color = max(mix(vec3(dot(color, vec3(0.2126, 0.7152, 0.0722))), color, parameter), 0.0);
+			break;
+		case EFFECT_VIGNETTE:
+		{
+			vec2 offset = uv - 0.5;
+			color *= clamp(1.0 - 2.0 * parameter * dot(offset, offset), 0.0, 1.0);
+			break;
+		}
+		}
+	}
+
+	return color;
+}
+
+vec3 showTarget(int target, vec2 uv)
+{
+	if (target == 3)
+	{
+		float density = clamp(texture(fluid, uv).z, 0.0, 1.0);
+		return mix(texture(sceneColor, uv).rgb, vec3(0.85, 0.88, 0.92), density);
+	}
+
+	if (target == 2)
+	{
+		float layers = texture(sceneColor, uv).r * OVERDRAW_LAYERS;
+		return layers < 0.5 ? vec3(0.0) : heatmap((layers - 1.0) / (OVERDRAW_MAX - 1.0));
+	}
+
+	if (target == 1)
+	{
+		ivec2 size = textureSize(sceneDepth);
+		float depth = texelFetch(sceneDepth, ivec2(uv * vec2(size)), 0).r;
+		// undo the perspective divide so depth is readable
+		float distance = pcs.orthographic != 0
+			? pcs.near + depth * (pcs.far - pcs.near)
+			: pcs.near * pcs.far / (pcs.far - depth * (pcs.far - pcs.near));
+		return vec3(distance / pcs.far);
+	}
+
+	vec3 color;
+	if (pcs.sharpness > 0.0)
+	{
+		color = sharpen(uv);
+	}
+	else
+	{
+		bool supersampled = any(greaterThan(fwidth(fragUV) * vec2(textureSize(sceneColor, 0)), vec2(1.0)));
+		color = supersampled ? downsample(uv) : texture(sceneColor, uv).rgb;
+	}
+
+	return postProcess(color * exp2(pcs.exposure), uv);
+}
+
+// the scene color is the right eye, bloom comes from it for both
+vec3 showStereo(vec2 uv)
+{
+	if (pcs.stereo == STEREO_SIDE_BY_SIDE)
+	{
+		// each eye squeezed into half the width, as 3D displays expect
+		return uv.x < 0.5
+			? postProcess(texture(leftEye, vec2(uv.x * 2.0, uv.y)).rgb * exp2(pcs.exposure), vec2(uv.x * 2.0, uv.y))
+			: showTarget(0, vec2(uv.x * 2.0 - 1.0, uv.y));
+	}
+
+	// red/cyan, the left eye's red is its luminance so saturated reds and
+	// cyans don't show up in only one eye
+	vec3 left = postProcess(texture(leftEye, uv).rgb * exp2(pcs.exposure), uv);
+	vec3 right = showTarget(0, uv);
+	return vec3(dot(left, vec3(0.299, 0.587, 0.114)), right.gb);
+}
+
+void main()
+{
+	if (pcs.target == 0 && pcs.stereo != 0)
+	{
+		outColor = vec4(showStereo(fragUV), 1.0);
+	}
+	else if (pcs.target < 0)
+	{
+		int columns = int(ceil(sqrt(float(TARGET_COUNT))));
+		vec2 cell = fragUV * float(columns);
+		int index = int(cell.y) * columns + int(cell.x);
+
+		outColor = index < TARGET_COUNT
+			? vec4(showTarget(index, fract(cell)), 1.0)
+			: vec4(0.0, 0.0, 0.0, 1.0);
+	}
+	else
+	{
+		outColor = vec4(showTarget(pcs.target, fragUV), 1.0);
+	}
+}
@@ -0,0 +1,19 @@
+#version 450
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inColor;
+
+layout(location = 0) out vec3 fragColor;
+
+// same uniform buffer as the main pipeline, debug lines are already in world space
+layout(binding = 0) uniform UniformBufferObject
+{
+	mat4 view;
+	mat4 proj;
+} ubo;
+
+void main()
+{
+	gl_Position = ubo.proj * ubo.view * vec4(inPosition, 1.0);
+	fragColor = inColor;
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location=1) in vec2 fragTexCoord;
+layout(location=7) flat in float fragAlphaCutoff;
+
+layout(binding=1) uniform sampler2D texSampler;
+
+// depth-only, so there's no color output, just the same cut out as shader.frag
+void main()
+{
+	if (texture(texSampler, fragTexCoord).a < fragAlphaCutoff)
+	{
+		discard;
+	}
+}
@@ -0,0 +1,15 @@
+#version 450
+
+// an sRGB texture, so it's sampled linear
+layout(set = 0, binding = 0) uniform sampler2D texSampler;
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+// both are premultiplied, blended with ONE, ONE_MINUS_SRC_ALPHA
+void main()
+{
+	outColor = fragColor * texture(texSampler, fragTexCoord);
+}
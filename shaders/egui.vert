@@ -0,0 +1,30 @@
+#version 450
+
+layout(push_constant) uniform PushConstants
+{
+	// in egui's points, which its vertices are in
+	vec2 screenSize;
+} pcs;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec2 inTexCoord;
+layout(location = 2) in vec4 inColor;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragColor;
+
+// egui's colors are sRGB, the swapchain encodes linear color
+vec3 toLinear(vec3 srgb)
+{
+	bvec3 cutoff = lessThan(srgb, vec3(0.04045));
+	vec3 lower = srgb / 12.92;
+	vec3 higher = pow((srgb + 0.055) / 1.055, vec3(2.4));
+	return mix(higher, lower, cutoff);
+}
+
+void main()
+{
+	gl_Position = vec4(2.0 * inPosition / pcs.screenSize - 1.0, 0.0, 1.0);
+	fragTexCoord = inTexCoord;
+	fragColor = vec4(toLinear(inColor.rgb), inColor.a);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(location=0) out vec4 outColor;
+
+// every layer adds one step with additive blending,
+// the composite pass turns the total into a heatmap
+const float OVERDRAW_STEP = 1.0 / 16.0;
+
+void main()
+{
+	outColor = vec4(OVERDRAW_STEP, OVERDRAW_STEP, OVERDRAW_STEP, 1.0);
+}
@@ -0,0 +1,25 @@
+#version 450
+
+// the particle buffer the compute shader wrote, bound as a vertex buffer
+layout(location = 0) in vec4 inPosition;
+layout(location = 1) in vec4 inVelocity;
+
+layout(location = 0) out vec3 fragColor;
+
+// same uniform buffer as the main pipeline, particles are already in world space
+layout(binding = 0) uniform UniformBufferObject
+{
+	mat4 view;
+	mat4 proj;
+} ubo;
+
+void main()
+{
+	gl_Position = ubo.proj * ubo.view * vec4(inPosition.xyz, 1.0);
+	gl_PointSize = 1.0;
+
+	// hotter the faster they move, fading out over their last second
+	float heat = clamp(length(inVelocity.xyz) / 4.0, 0.0, 1.0);
+	float fade = clamp(inPosition.w, 0.0, 1.0);
+	fragColor = mix(vec3(1.0, 0.3, 0.05), vec3(1.0, 0.9, 0.5), heat) * fade;
+}
@@ -0,0 +1,13 @@
+#version 450
+
+// the view through the portal, rendered at the scene's resolution
+layout(set = 1, binding = 0) uniform sampler2D portalColor;
+
+layout(location = 0) out vec4 outColor;
+
+// drawn fullscreen but only where the portal marked the stencil, and the
+// view lines up with the scene pixel for pixel
+void main()
+{
+	outColor = vec4(texelFetch(portalColor, ivec2(gl_FragCoord.xy), 0).rgb, 1.0);
+}
@@ -0,0 +1,25 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform UniformBufferObject
+{
+	mat4 view;
+	mat4 proj;
+} ubo;
+
+layout(push_constant) uniform PushConstants
+{
+	// places the unit quad, scaled to the portal's size
+	mat4 model;
+} pcs;
+
+// two triangles from -0.5 to 0.5, counter-clockwise seen from the front
+const vec2 CORNERS[6] = vec2[](
+	vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+	vec2(-0.5, -0.5), vec2(0.5, 0.5), vec2(-0.5, 0.5)
+);
+
+// only marks the stencil, there's no fragment shader
+void main()
+{
+	gl_Position = ubo.proj * ubo.view * pcs.model * vec4(CORNERS[gl_VertexIndex], 0.0, 1.0);
+}
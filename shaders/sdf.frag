@@ -0,0 +1,96 @@
+#version 450
+
+layout(location = 0) in vec2 fragUV;
+
+layout(binding = 0) uniform UniformBufferObject
+{
+	mat4 view;
+	mat4 proj;
+	int debugView;
+	float time;
+} ubo;
+
+layout(location = 0) out vec4 outColor;
+
+const int MAX_STEPS = 128;
+const float MAX_DISTANCE = 20.0;
+const float HIT_DISTANCE = 0.001;
+
+// the demo scene sits behind the models
+const vec3 SCENE_ORIGIN = vec3(-3.0, 0.0, 0.0);
+const vec3 LIGHT_DIRECTION = normalize(vec3(1.0, 0.5, 1.0));
+
+float sphere(vec3 p, float radius)
+{
+	return length(p) - radius;
+}
+
+float torus(vec3 p, float radius, float thickness)
+{
+	vec2 q = vec2(length(p.xy) - radius, p.z);
+	return length(q) - thickness;
+}
+
+float smoothUnion(float a, float b, float k)
+{
+	float h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+	return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+float scene(vec3 p)
+{
+	p -= SCENE_ORIGIN;
+	float t = ubo.time;
+
+	float ring = torus(p, 1.0, 0.25);
+	float bob = sphere(p - vec3(0.0, 0.0, sin(t) * 1.2), 0.5);
+	float orbit = sphere(p - vec3(cos(t * 0.7), sin(t * 0.7), 0.0), 0.4);
+
+	return smoothUnion(ring, smoothUnion(bob, orbit, 0.4), 0.4);
+}
+
+vec3 normalAt(vec3 p)
+{
+	vec2 e = vec2(0.001, 0.0);
+	return normalize(vec3(
+		scene(p + e.xyy) - scene(p - e.xyy),
+		scene(p + e.yxy) - scene(p - e.yxy),
+		scene(p + e.yyx) - scene(p - e.yyx)));
+}
+
+void main()
+{
+	// unproject the pixel onto the near and far planes to get its ray,
+	// this works for both perspective and orthographic cameras
+	mat4 viewProj = ubo.proj * ubo.view;
+	mat4 inverseViewProj = inverse(viewProj);
+	vec2 ndc = fragUV * 2.0 - 1.0;
+	vec4 near = inverseViewProj * vec4(ndc, 0.0, 1.0);
+	vec4 far = inverseViewProj * vec4(ndc, 1.0, 1.0);
+
+	vec3 origin = near.xyz / near.w;
+	vec3 direction = normalize(far.xyz / far.w - origin);
+
+	float distance = 0.0;
+	for (int i = 0; i < MAX_STEPS && distance < MAX_DISTANCE; i++)
+	{
+		vec3 p = origin + direction * distance;
+		float d = scene(p);
+
+		if (d < HIT_DISTANCE)
+		{
+			vec3 normal = normalAt(p);
+			float diffuse = max(dot(normal, LIGHT_DIRECTION), 0.0);
+			outColor = vec4(vec3(0.9, 0.5, 0.3) * (0.2 + 0.8 * diffuse), 1.0);
+
+			// depth lets the rasterized scene and the SDFs occlude each other
+			vec4 clip = viewProj * vec4(p, 1.0);
+			gl_FragDepth = clip.z / clip.w;
+			return;
+		}
+
+		distance += d;
+	}
+
+	discard;
+}
@@ -3,21 +3,583 @@
 // input color and texture coord from vertex shader
 layout(location=0) in vec3 fragColor;
 layout(location=1) in vec2 fragTexCoord;
+layout(location=2) in vec3 fragWorldPos;
+layout(location=3) in vec2 fragLightmapCoord;
+layout(location=4) flat in float fragOpacity;
+// which of ubo.irradiance lights this model, -1 leaves it unlit
+layout(location=5) flat in int fragProbeIndex;
+// static geometry with a baked lightmap ignores the probes
+layout(location=6) flat in int fragLightmapped;
+// texels with less alpha are cut out, 0 keeps them all
+layout(location=7) flat in float fragAlphaCutoff;
+// the object's own color, multiplied into the albedo
+layout(location=8) flat in vec3 fragTint;
+
+// must match LightUniform in lights.rs
+struct Light
+{
+	// w is the range
+	vec4 position;
+	// w is 1 for point lights and 0 for spot lights
+	vec4 color;
+	// w is the cosine of the cone's angle
+	vec4 direction;
+	// x is the first of its shadow views, -1 if it casts none
+	vec4 shadow;
+};
+
+// must match ShadowViewUniform in lights.rs
+struct ShadowView
+{
+	mat4 viewProj;
+	// offset and scale of its tile in the atlas, zero sized without one
+	vec4 uvTransform;
+	// x is the width of a texel a unit from the light, y the normal offset
+	// in texels and z the PCF kernel width
+	vec4 filtering;
+};
+
+// debug view is switched through the uniform buffer so no pipeline rebuild is needed
+layout(binding=0) uniform UniformBufferObject
+{
+	mat4 view;
+	mat4 proj;
+	int debugView;
+	float time;
+	// irradiance from the light probes around each model, as SH coefficients
+	vec4 irradiance[4][9];
+	// position, box min and box max of each reflection probe
+	vec4 reflectionProbes[4][3];
+	vec4 cameraPosition;
+	int reflectionProbeCount;
+	float reflectivity;
+	// HDR emissive color, w is set when emissiveTexture should be sampled
+	vec4 emissive;
+	// towards the sun, for parallax self-shadowing, w is how much daylight there is
+	vec4 sunDirection;
+	// depth of the height map in UV units, 0 disables parallax
+	float heightScale;
+	// strength of the mirror smooth coat reflecting the probes
+	float clearcoat;
+	// how much of the opaque scene shows through, bent by the index of refraction
+	float transmission;
+	float ior;
+	// the sun's specular highlight, off when specular is 0
+	float roughness;
+	float specular;
+	// KHR_materials_anisotropy style, stretches the highlight along a direction
+	// rotated from the texture's u axis
+	float anisotropyStrength;
+	float anisotropyRotation;
+	// back faces are lit like front faces instead of from behind
+	bool doubleSided;
+	// how much of the mirror plane's reflection replaces the surface
+	float mirror;
+	// the scene's spot and point lights, and the faces of their shadows
+	int lightCount;
+	Light lights[8];
+	ShadowView shadowViews[16];
+} ubo;
 
 // uniform binding for sampler
 layout(binding=1) uniform sampler2D texSampler;
 
-// push constant
-layout(push_constant) uniform PushConstants
-{
-	layout(offset = 64) float opacity;
-} pcs;
+// captured surroundings of each reflection probe
+layout(binding=2) uniform samplerCube reflectionCubemaps[4];
+
+// baked lighting for static geometry
+layout(binding=3) uniform sampler2D lightmap;
+
+// multiplied with ubo.emissive
+layout(binding=4) uniform sampler2D emissiveTexture;
+
+// the opaque scene, only sampled when the material is transmissive
+layout(binding=6) uniform sampler2D refractionColor;
+
+// the scene seen in the mirror plane, with x flipped, only sampled by mirrors
+layout(binding=8) uniform sampler2D mirrorColor;
+
+// every light's shadow map, in tiles, compared against with hardware PCF
+layout(binding=9) uniform sampler2DShadow shadowAtlas;
+
+#ifdef PARALLAX
+// white is highest, only read by the parallax permutations
+layout(binding=5) uniform sampler2D heightMap;
+#endif
 
 // create variable for framebuffer (we have one so index 0)
 layout(location=0) out vec4 outColor;
 
+// must match DebugView in main.rs
+const int DEBUG_UVS = 1;
+const int DEBUG_NORMALS = 2;
+const int DEBUG_MIP_LEVEL = 3;
+const int DEBUG_TANGENTS = 4;
+const int DEBUG_ROUGHNESS = 5;
+
+const float PI = 3.14159265;
+
+// how far behind the surface refracted rays pick up the scene, in world units
+const float REFRACTION_THICKNESS = 0.1;
+// reflectance at normal incidence of a coat with the glTF default IOR of 1.5
+const float CLEARCOAT_F0 = 0.04;
+// matches SUN_COLOR in sky.frag
+const vec3 SUN_COLOR = vec3(1.0, 0.95, 0.85) * 3.0;
+// reflectance at normal incidence of common dielectrics
+const float SPECULAR_F0 = 0.04;
+
+const vec3 MIP_COLORS[6] = vec3[](
+	vec3(0.0, 0.0, 1.0),
+	vec3(0.0, 1.0, 1.0),
+	vec3(0.0, 1.0, 0.0),
+	vec3(1.0, 1.0, 0.0),
+	vec3(1.0, 0.5, 0.0),
+	vec3(1.0, 0.0, 0.0)
+);
+
+// we don't have vertex tangents, so build the frame from screen space derivatives
+mat3 cotangentFrame(vec3 normal, vec3 position, vec2 uv)
+{
+	vec3 dp1 = dFdx(position);
+	vec3 dp2 = dFdy(position);
+	vec2 duv1 = dFdx(uv);
+	vec2 duv2 = dFdy(uv);
+
+	// wound the same way as the derivatives so the frame isn't mirrored
+	vec3 faceNormal = normalize(cross(dp1, dp2));
+	vec3 dp2perp = cross(dp2, faceNormal);
+	vec3 dp1perp = cross(faceNormal, dp1);
+	vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+	vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+	float scale = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+	return mat3(tangent * scale, bitangent * scale, normal);
+}
+
+#ifdef PARALLAX
+// more layers at grazing angles, where the offset is largest
+const float MIN_LAYERS = 8.0;
+const float MAX_LAYERS = 32.0;
+// how quickly self-shadowing darkens with occluder depth
+const float SHADOW_SHARPNESS = 8.0;
+
+// depth below the surface, 0 at the top of the height map
+float depthAt(vec2 uv, vec2 dx, vec2 dy)
+{
+	return 1.0 - textureGrad(heightMap, uv, dx, dy).r;
+}
+
+// steps along the view ray through the height field in layers until it
+// dips below the surface, then interpolates between the last two layers
+vec2 parallaxOcclusion(vec2 uv, vec3 view, out float depth)
+{
+	// the gradients of the unoffset UVs keep mip selection stable inside the loop
+	vec2 dx = dFdx(uv);
+	vec2 dy = dFdy(uv);
+
+	float layers = mix(MAX_LAYERS, MIN_LAYERS, abs(view.z));
+	float layerDepth = 1.0 / layers;
+	vec2 step = view.xy / max(view.z, 0.05) * ubo.heightScale / layers;
+
+	vec2 current = uv;
+	float currentDepth = 0.0;
+	float surfaceDepth = depthAt(current, dx, dy);
+
+	for (int i = 0; i < int(MAX_LAYERS) && currentDepth < surfaceDepth; i++)
+	{
+		current -= step;
+		currentDepth += layerDepth;
+		surfaceDepth = depthAt(current, dx, dy);
+	}
+
+	vec2 previous = current + step;
+	float after = surfaceDepth - currentDepth;
+	float before = depthAt(previous, dx, dy) - currentDepth + layerDepth;
+	float weight = after / (after - before);
+
+	depth = currentDepth - layerDepth * weight;
+	return mix(current, previous, weight);
+}
+
+#ifdef PARALLAX_SHADOWS
+// marches from the parallax hit towards the light, darkening by how far
+// below the height field the ray passes, 1 is fully lit
+float parallaxShadow(vec2 uv, float depth, vec3 light)
+{
+	if (light.z <= 0.0 || depth <= 0.0)
+	{
+		return 1.0;
+	}
+
+	vec2 dx = dFdx(uv);
+	vec2 dy = dFdy(uv);
+
+	float layers = mix(MAX_LAYERS, MIN_LAYERS, light.z);
+	float layerDepth = depth / layers;
+	vec2 step = light.xy / light.z * ubo.heightScale * depth / layers;
+
+	vec2 current = uv + step;
+	float currentDepth = depth - layerDepth;
+	float occlusion = 0.0;
+
+	for (int i = 1; i < int(MAX_LAYERS) && currentDepth > 0.0; i++)
+	{
+		// occluders closer to the hit cast darker shadows
+		float below = currentDepth - depthAt(current, dx, dy);
+		occlusion = max(occlusion, below * (1.0 - float(i) / layers));
+
+		current += step;
+		currentDepth -= layerDepth;
+	}
+
+	return 1.0 - clamp(occlusion * SHADOW_SHARPNESS, 0.0, 1.0);
+}
+#endif
+#endif
+
+// must match sh_basis in light_probes.rs
+vec3 irradiance(vec3 n)
+{
+	vec4 sh[9] = ubo.irradiance[fragProbeIndex];
+	vec3 result = sh[0].rgb * 0.282095
+		+ sh[1].rgb * 0.488603 * n.y
+		+ sh[2].rgb * 0.488603 * n.z
+		+ sh[3].rgb * 0.488603 * n.x
+		+ sh[4].rgb * 1.092548 * n.x * n.y
+		+ sh[5].rgb * 1.092548 * n.y * n.z
+		+ sh[6].rgb * 0.315392 * (3.0 * n.z * n.z - 1.0)
+		+ sh[7].rgb * 1.092548 * n.x * n.z
+		+ sh[8].rgb * 0.546274 * (n.x * n.x - n.y * n.y);
+	return max(result, vec3(0.0));
+}
+
+// intersects the reflected ray with the probe's box and returns the
+// direction from the probe to the hit, so nearby walls line up
+vec3 boxProject(vec3 position, vec3 direction, vec3 probePosition, vec3 boxMin, vec3 boxMax)
+{
+	vec3 first = (boxMax - position) / direction;
+	vec3 second = (boxMin - position) / direction;
+	vec3 furthest = max(first, second);
+	float distance = min(min(furthest.x, furthest.y), furthest.z);
+	return position + direction * distance - probePosition;
+}
+
+// color reflected by the first probe whose box contains the fragment
+vec3 reflection(vec3 normal)
+{
+	vec3 direction = reflect(normalize(fragWorldPos - ubo.cameraPosition.xyz), normal);
+
+	// looping keeps the sampler index dynamically uniform
+	for (int i = 0; i < 4; i++)
+	{
+		if (i >= ubo.reflectionProbeCount)
+		{
+			break;
+		}
+
+		vec3 boxMin = ubo.reflectionProbes[i][1].xyz;
+		vec3 boxMax = ubo.reflectionProbes[i][2].xyz;
+		if (all(greaterThanEqual(fragWorldPos, boxMin)) && all(lessThanEqual(fragWorldPos, boxMax)))
+		{
+			vec3 lookup = boxProject(fragWorldPos, direction, ubo.reflectionProbes[i][0].xyz, boxMin, boxMax);
+			return texture(reflectionCubemaps[i], lookup).rgb;
+		}
+	}
+
+	return vec3(0.0);
+}
+
+// Schlick's approximation of the Fresnel reflectance
+float fresnel(float cosTheta, float f0)
+{
+	return f0 + (1.0 - f0) * pow(1.0 - cosTheta, 5.0);
+}
+
+// anisotropic GGX, alphas along the tangent and bitangent
+float distributionAnisotropic(float alphaT, float alphaB, float th, float bh, float nh)
+{
+	float a2 = alphaT * alphaB;
+	vec3 d = vec3(alphaB * th, alphaT * bh, a2 * nh);
+	float w2 = a2 / dot(d, d);
+	return a2 * w2 * w2 / PI;
+}
+
+// height correlated Smith visibility for the anisotropic distribution
+float visibilityAnisotropic(float alphaT, float alphaB, vec3 v, vec3 l, float nv, float nl)
+{
+	float lambdaV = nl * length(vec3(alphaT * v.x, alphaB * v.y, nv));
+	float lambdaL = nv * length(vec3(alphaT * l.x, alphaB * l.y, nl));
+	return 0.5 / (lambdaV + lambdaL);
+}
+
+// sunlight reflected off the surface, with the anisotropy direction given
+// in tangent space so brushed metal follows its texture
+vec3 sunSpecular(mat3 tangentToWorld, vec3 toCamera)
+{
+	vec2 direction = vec2(cos(ubo.anisotropyRotation), sin(ubo.anisotropyRotation));
+	vec3 normal = tangentToWorld[2];
+	vec3 tangent = normalize(tangentToWorld * vec3(direction, 0.0));
+	tangent = normalize(tangent - normal * dot(normal, tangent));
+	vec3 bitangent = cross(normal, tangent);
+	mat3 worldToFrame = transpose(mat3(tangent, bitangent, normal));
+
+	vec3 v = worldToFrame * toCamera;
+	vec3 l = worldToFrame * ubo.sunDirection.xyz;
+	float nl = l.z;
+	if (nl <= 0.0 || v.z <= 0.0)
+	{
+		return vec3(0.0);
+	}
+
+	vec3 h = normalize(v + l);
+
+	// as in the glTF extension, the strength stretches the tangent alpha towards 1
+	float alpha = max(ubo.roughness * ubo.roughness, 0.002);
+	float alphaT = mix(alpha, 1.0, ubo.anisotropyStrength * ubo.anisotropyStrength);
+	float alphaB = alpha;
+
+	float d = distributionAnisotropic(alphaT, alphaB, h.x, h.y, h.z);
+	float vis = visibilityAnisotropic(alphaT, alphaB, v, l, v.z, nl);
+	float f = fresnel(max(dot(v, h), 0.0), SPECULAR_F0);
+
+	return SUN_COLOR * d * vis * f * nl * ubo.specular;
+}
+
+// the cube face looking along the direction's major axis, in the order of
+// FACES in cubemap.rs
+int cubeFace(vec3 direction)
+{
+	vec3 a = abs(direction);
+	if (a.x >= a.y && a.x >= a.z)
+	{
+		return direction.x > 0.0 ? 0 : 1;
+	}
+	if (a.y >= a.z)
+	{
+		return direction.y > 0.0 ? 2 : 3;
+	}
+	return direction.z > 0.0 ? 4 : 5;
+}
+
+// 1 where the light reaches the fragment, 0 where its shadow map has
+// something closer to the light, filtered over the light's PCF kernel
+float punctualShadow(Light light, vec3 toLight, float distance, vec3 normal)
+{
+	int first = int(light.shadow.x);
+	if (first < 0)
+	{
+		return 1.0;
+	}
+
+	int view = first + (light.color.w > 0.0 ? cubeFace(-toLight) : 0);
+	vec4 uvTransform = ubo.shadowViews[view].uvTransform;
+	if (uvTransform.z <= 0.0)
+	{
+		return 1.0;
+	}
+
+	// pushed out along the normal by some of the shadow map's texels, as
+	// wide as they are at the fragment's distance
+	vec4 filtering = ubo.shadowViews[view].filtering;
+	vec3 position = fragWorldPos + normal * filtering.x * filtering.y * distance;
+
+	vec4 clip = ubo.shadowViews[view].viewProj * vec4(position, 1.0);
+	vec3 ndc = clip.xyz / clip.w;
+	vec2 uv = uvTransform.xy + (ndc.xy * 0.5 + 0.5) * uvTransform.zw;
+
+	// the taps stay inside the tile so they don't pick up another light's
+	vec2 texel = 1.0 / vec2(textureSize(shadowAtlas, 0));
+	vec2 tileMin = uvTransform.xy + texel * 0.5;
+	vec2 tileMax = uvTransform.xy + uvTransform.zw - texel * 0.5;
+
+	int radius = int(filtering.z) / 2;
+	float lit = 0.0;
+	for (int y = -radius; y <= radius; y++)
+	{
+		for (int x = -radius; x <= radius; x++)
+		{
+			vec2 tap = clamp(uv + vec2(x, y) * texel, tileMin, tileMax);
+			lit += texture(shadowAtlas, vec3(tap, ndc.z));
+		}
+	}
+
+	float taps = float(radius * 2 + 1);
+	return lit / (taps * taps);
+}
+
+// diffuse light from the scene's spot and point lights, fading out
+// smoothly at their range
+vec3 punctualLights(vec3 normal)
+{
+	vec3 result = vec3(0.0);
+	for (int i = 0; i < ubo.lightCount; i++)
+	{
+		Light light = ubo.lights[i];
+		vec3 toLight = light.position.xyz - fragWorldPos;
+		float distance = length(toLight);
+		toLight /= distance;
+
+		float window = clamp(1.0 - pow(distance / light.position.w, 4.0), 0.0, 1.0);
+		float attenuation = window * window / (distance * distance + 1.0);
+		if (light.color.w == 0.0)
+		{
+			float cosAngle = dot(-toLight, light.direction.xyz);
+			attenuation *= smoothstep(light.direction.w, mix(light.direction.w, 1.0, 0.1), cosAngle);
+		}
+
+		float nl = max(dot(normal, toLight), 0.0);
+		if (nl * attenuation > 0.0)
+		{
+			result += light.color.rgb * nl * attenuation * punctualShadow(light, toLight, distance, normal);
+		}
+	}
+	return result;
+}
+
+// the opaque scene seen through a thin slab of the material
+vec3 transmitted(vec3 normal, vec3 toCamera)
+{
+	vec3 refracted = refract(-toCamera, normal, 1.0 / ubo.ior);
+	vec4 clip = ubo.proj * ubo.view * vec4(fragWorldPos + refracted * REFRACTION_THICKNESS, 1.0);
+	return texture(refractionColor, clip.xy / clip.w * 0.5 + 0.5).rgb;
+}
+
+// the reflection of whatever is in front of the mirror plane, at the
+// fragment's spot on screen
+vec3 mirrored()
+{
+	vec4 clip = ubo.proj * ubo.view * vec4(fragWorldPos, 1.0);
+	vec2 screen = clip.xy / clip.w * 0.5 + 0.5;
+	return texture(mirrorColor, vec2(1.0 - screen.x, screen.y)).rgb;
+}
+
 // called for every fragment (which was output from the vertex shader)
 void main()
 {
-	outColor = vec4(texture(texSampler, fragTexCoord).rgb, pcs.opacity);
+	switch (ubo.debugView)
+	{
+		case DEBUG_UVS:
+			outColor = vec4(fract(fragTexCoord), 0.0, 1.0);
+			return;
+		case DEBUG_NORMALS:
+		{
+			// we don't have vertex normals, so use the face normal from screen space derivatives
+			vec3 normal = normalize(cross(dFdy(fragWorldPos), dFdx(fragWorldPos)));
+			outColor = vec4(normal * 0.5 + 0.5, 1.0);
+			return;
+		}
+		case DEBUG_MIP_LEVEL:
+		{
+			float lod = textureQueryLod(texSampler, fragTexCoord).y;
+			outColor = vec4(MIP_COLORS[clamp(int(lod), 0, 5)], 1.0);
+			return;
+		}
+		case DEBUG_TANGENTS:
+		{
+			// the derivative frame's tangent, which follows the texture's u axis
+			vec3 normal = normalize(cross(dFdy(fragWorldPos), dFdx(fragWorldPos)));
+			vec3 tangent = normalize(cotangentFrame(normal, fragWorldPos, fragTexCoord)[0]);
+			outColor = vec4(tangent * 0.5 + 0.5, 1.0);
+			return;
+		}
+		case DEBUG_ROUGHNESS:
+			// the material's, there's no roughness map
+			outColor = vec4(vec3(ubo.roughness), 1.0);
+			return;
+	}
+
+	// the derivative normal always points one way on screen, so turn it to
+	// the camera and back again on the back of single sided surfaces
+	vec3 toCamera = normalize(ubo.cameraPosition.xyz - fragWorldPos);
+	vec3 normal = normalize(cross(dFdy(fragWorldPos), dFdx(fragWorldPos)));
+	normal = faceforward(normal, -toCamera, normal);
+	if (!ubo.doubleSided && !gl_FrontFacing)
+	{
+		normal = -normal;
+	}
+
+	vec2 uv = fragTexCoord;
+	float shadow = 1.0;
+
+#ifdef PARALLAX
+	if (ubo.heightScale > 0.0)
+	{
+		mat3 tangentToWorld = cotangentFrame(normal, fragWorldPos, fragTexCoord);
+		mat3 worldToTangent = transpose(tangentToWorld);
+		vec3 view = normalize(worldToTangent * toCamera);
+
+		float depth;
+		uv = parallaxOcclusion(fragTexCoord, view, depth);
+
+#ifdef PARALLAX_SHADOWS
+		shadow = parallaxShadow(uv, depth, normalize(worldToTangent * ubo.sunDirection.xyz));
+#endif
+	}
+#endif
+
+	vec4 texel = texture(texSampler, uv);
+	if (texel.a < fragAlphaCutoff)
+	{
+		discard;
+	}
+
+	// the vertex colors carry glTF primitives' base color, white otherwise
+	vec3 albedo = texel.rgb * fragColor * fragTint * shadow;
+	// transmitted light is tinted by the surface but not lit by it
+	vec3 tint = albedo;
+
+	if (fragLightmapped != 0)
+	{
+		albedo *= texture(lightmap, fragLightmapCoord).rgb;
+	}
+	else if (fragProbeIndex >= 0)
+	{
+		albedo *= irradiance(normal) / PI;
+	}
+
+	albedo += tint * punctualLights(normal) / PI;
+
+	vec3 color = albedo;
+	if (ubo.reflectivity > 0.0)
+	{
+		color = mix(albedo, reflection(normal), ubo.reflectivity);
+	}
+
+	float cosTheta = max(dot(normal, toCamera), 0.0);
+
+	if (ubo.specular > 0.0)
+	{
+		mat3 tangentToWorld = cotangentFrame(normal, fragWorldPos, uv);
+		color += sunSpecular(tangentToWorld, toCamera) * shadow * ubo.sunDirection.w;
+	}
+
+	if (ubo.transmission > 0.0)
+	{
+		// what passes through is tinted by the surface, what's reflected isn't
+		float ratio = (ubo.ior - 1.0) / (ubo.ior + 1.0);
+		float reflected = fresnel(cosTheta, ratio * ratio);
+		vec3 through = transmitted(normal, toCamera) * tint;
+		color = mix(color, mix(through, reflection(normal), reflected), ubo.transmission);
+	}
+
+	if (ubo.mirror > 0.0)
+	{
+		color = mix(color, mirrored(), ubo.mirror);
+	}
+
+	if (ubo.clearcoat > 0.0)
+	{
+		color = mix(color, reflection(normal), ubo.clearcoat * fresnel(cosTheta, CLEARCOAT_F0));
+	}
+
+	// emitted light isn't affected by lighting and can go well above 1 to bloom
+	vec3 emissive = ubo.emissive.rgb;
+	if (ubo.emissive.w > 0.0)
+	{
+		emissive *= texture(emissiveTexture, uv).rgb;
+	}
+	color += emissive;
+
+	// transmission replaces blending, the scene behind is already in the color
+	outColor = vec4(color, mix(fragOpacity, 1.0, ubo.transmission));
 }
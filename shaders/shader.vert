@@ -2,12 +2,30 @@
 
 // input vertex attricutes
 layout(location = 0) in vec3 inPosition;
+#ifndef DEPTH_ONLY
 layout(location = 1) in vec3 inColor;
 layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in vec2 inLightmapCoord;
 
 // output color and texture coord
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec2 fragTexCoord;
+// world space position, used by the debug views
+layout(location = 2) out vec3 fragWorldPos;
+layout(location = 3) out vec2 fragLightmapCoord;
+// the object's constants, the same for every fragment
+layout(location = 4) flat out float fragOpacity;
+// which of ubo.irradiance lights the object, -1 leaves it unlit
+layout(location = 5) flat out int fragProbeIndex;
+// static geometry with a baked lightmap ignores the probes
+layout(location = 6) flat out int fragLightmapped;
+// texels with less alpha are discarded
+layout(location = 7) flat out float fragAlphaCutoff;
+layout(location = 8) flat out vec3 fragTint;
+#endif
+
+// the depth pre-pass and the main pass must agree on depth exactly
+invariant gl_Position;
 
 // Uniform Buffer - Model View Projection Matrix
 layout(binding = 0) uniform UniformBufferObject
@@ -16,16 +34,52 @@ layout(binding = 0) uniform UniformBufferObject
 	mat4 proj;
 } ubo;
 
+// must match ObjectData in main.rs
+struct Object
+{
+	mat4 model;
+	float opacity;
+	int probeIndex;
+	int lightmapped;
+	float alphaCutoff;
+	// multiplied into the albedo, w is padding
+	vec4 tint;
+};
+
+#ifdef OBJECT_BUFFER
+// every object drawn this frame, so many can share one instanced draw
+layout(std430, binding = 7) readonly buffer Objects
+{
+	Object objects[];
+};
+#else
 // Push Constant
 layout(push_constant) uniform PushConstants
 {
-	mat4 model;
+	Object object;
 } pcs;
+#endif
 
 // gets invoked for each vertex
 void main()
 {
-	gl_Position = ubo.proj * ubo.view * pcs.model * vec4(inPosition, 1.0);
+#ifdef OBJECT_BUFFER
+	Object object = objects[gl_InstanceIndex];
+#else
+	Object object = pcs.object;
+#endif
+
+	vec4 worldPos = object.model * vec4(inPosition, 1.0);
+	gl_Position = ubo.proj * ubo.view * worldPos;
+#ifndef DEPTH_ONLY
 	fragColor = inColor;
 	fragTexCoord = inTexCoord;
+	fragWorldPos = worldPos.xyz;
+	fragLightmapCoord = inLightmapCoord;
+	fragOpacity = object.opacity;
+	fragProbeIndex = object.probeIndex;
+	fragLightmapped = object.lightmapped;
+	fragAlphaCutoff = object.alphaCutoff;
+	fragTint = object.tint.rgb;
+#endif
 }
@@ -0,0 +1,31 @@
+#version 450
+
+// a light's view of the frame's objects, for its tile of the shadow atlas
+layout(location = 0) in vec3 inPosition;
+
+// must match ObjectData in main.rs
+struct Object
+{
+	mat4 model;
+	float opacity;
+	int probeIndex;
+	int lightmapped;
+	float alphaCutoff;
+	vec4 tint;
+};
+
+layout(std430, binding = 7) readonly buffer Objects
+{
+	Object objects[];
+};
+
+// the light's view-projection, in place of the object the scene pushes
+layout(push_constant) uniform PushConstants
+{
+	mat4 viewProj;
+} pcs;
+
+void main()
+{
+	gl_Position = pcs.viewProj * objects[gl_InstanceIndex].model * vec4(inPosition, 1.0);
+}
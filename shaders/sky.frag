@@ -0,0 +1,165 @@
+#version 450
+
+layout(location = 0) in vec2 fragUV;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject
+{
+	mat4 view;
+	mat4 proj;
+	int debugView;
+	float time;
+} ubo;
+
+// perlin-worley base shape in r, worley detail octaves in gba
+layout(set = 1, binding = 0) uniform sampler3D cloudNoise;
+
+layout(push_constant) uniform PushConstants
+{
+	// towards the sun
+	vec3 sunDirection;
+	float coverage;
+	// already scaled by the sun's brightness, black once it has set
+	vec3 sunColor;
+	// scales the sky gradient and ambient light, dim but not black at night
+	float skyBrightness;
+} pcs;
+
+layout(location = 0) out vec4 outColor;
+
+const float CLOUD_BOTTOM = 5.0;
+const float CLOUD_TOP = 8.0;
+const float MAX_CLOUD_DISTANCE = 80.0;
+const int CLOUD_STEPS = 48;
+const int LIGHT_STEPS = 6;
+// world units per repeat of the noise texture
+const float NOISE_SCALE = 1.0 / 24.0;
+const vec3 WIND = vec3(0.6, 0.2, 0.0);
+const float EXTINCTION = 1.5;
+
+float remap(float value, float low, float high, float newLow, float newHigh)
+{
+	return newLow + (value - low) / (high - low) * (newHigh - newLow);
+}
+
+float henyeyGreenstein(float cosTheta, float g)
+{
+	float g2 = g * g;
+	return (1.0 - g2) / (4.0 * 3.14159265 * pow(1.0 + g2 - 2.0 * g * cosTheta, 1.5));
+}
+
+float cloudDensity(vec3 p)
+{
+	float height = (p.z - CLOUD_BOTTOM) / (CLOUD_TOP - CLOUD_BOTTOM);
+	// round off the bottom and top of the layer
+	float heightShape = clamp(height * 4.0, 0.0, 1.0) * clamp((1.0 - height) * 2.0, 0.0, 1.0);
+
+	vec4 noise = texture(cloudNoise, (p + WIND * ubo.time) * NOISE_SCALE);
+	float base = remap(noise.r * heightShape, 1.0 - pcs.coverage, 1.0, 0.0, 1.0);
+	if (base <= 0.0)
+	{
+		return 0.0;
+	}
+
+	// erode the edges with the higher octaves
+	float detail = dot(noise.gba, vec3(0.625, 0.25, 0.125));
+	return max(remap(base, detail * 0.4, 1.0, 0.0, 1.0), 0.0);
+}
+
+// optical depth towards the sun
+float lightOpticalDepth(vec3 p)
+{
+	float stepSize = (CLOUD_TOP - CLOUD_BOTTOM) / float(LIGHT_STEPS) / max(pcs.sunDirection.z, 0.1);
+	float depth = 0.0;
+
+	for (int i = 1; i <= LIGHT_STEPS; i++)
+	{
+		depth += cloudDensity(p + pcs.sunDirection * stepSize * float(i)) * stepSize;
+	}
+
+	return depth * EXTINCTION;
+}
+
+vec3 skyColor(vec3 direction)
+{
+	float up = clamp(direction.z, 0.0, 1.0);
+	vec3 sky = mix(vec3(0.75, 0.85, 0.95), vec3(0.25, 0.45, 0.8), pow(up, 0.5)) * pcs.skyBrightness;
+	float sun = pow(max(dot(direction, pcs.sunDirection), 0.0), 800.0);
+	return sky + pcs.sunColor * sun;
+}
+
+void main()
+{
+	mat4 inverseViewProj = inverse(ubo.proj * ubo.view);
+	vec2 ndc = fragUV * 2.0 - 1.0;
+	vec4 near = inverseViewProj * vec4(ndc, 0.0, 1.0);
+	vec4 far = inverseViewProj * vec4(ndc, 1.0, 1.0);
+
+	vec3 origin = near.xyz / near.w;
+	vec3 direction = normalize(far.xyz / far.w - origin);
+
+	vec3 background = skyColor(direction);
+
+	// clouds are a horizontal slab, only visible looking up at it from below
+	if (direction.z <= 0.01 || origin.z >= CLOUD_BOTTOM)
+	{
+		outColor = vec4(background, 1.0);
+		return;
+	}
+
+	float start = (CLOUD_BOTTOM - origin.z) / direction.z;
+	float end = min((CLOUD_TOP - origin.z) / direction.z, MAX_CLOUD_DISTANCE);
+	if (start >= end)
+	{
+		outColor = vec4(background, 1.0);
+		return;
+	}
+
+	float stepSize = (end - start) / float(CLOUD_STEPS);
+	float cosTheta = dot(direction, pcs.sunDirection);
+	vec3 ambient = vec3(0.6, 0.7, 0.85) * 0.5 * pcs.skyBrightness;
+
+	float transmittance = 1.0;
+	vec3 scattered = vec3(0.0);
+
+	for (int i = 0; i < CLOUD_STEPS && transmittance > 0.01; i++)
+	{
+		vec3 p = origin + direction * (start + stepSize * (float(i) + 0.5));
+		float density = cloudDensity(p);
+		if (density <= 0.0)
+		{
+			continue;
+		}
+
+		float sunDepth = lightOpticalDepth(p);
+
+		// approximate multiple scattering by summing octaves with weaker
+		// extinction and a more isotropic phase function each time
+		vec3 light = vec3(0.0);
+		float a = 1.0;
+		float b = 1.0;
+		float c = 1.0;
+		for (int octave = 0; octave < 3; octave++)
+		{
+			float phase = mix(henyeyGreenstein(cosTheta, -0.2 * c), henyeyGreenstein(cosTheta, 0.8 * c), 0.5);
+			light += a * pcs.sunColor * exp(-sunDepth * b) * phase;
+			a *= 0.5;
+			b *= 0.5;
+			c *= 0.5;
+		}
+		light += ambient;
+
+		float extinction = density * EXTINCTION;
+		float stepTransmittance = exp(-extinction * stepSize);
+
+		// energy conserving integration over the step
+		scattered += transmittance * light * (1.0 - stepTransmittance);
+		transmittance *= stepTransmittance;
+	}
+
+	// fade out towards the horizon where the step count can't keep up
+	float fade = smoothstep(0.01, 0.15, direction.z);
+	transmittance = mix(1.0, transmittance, fade);
+	scattered *= fade;
+
+	outColor = vec4(background * transmittance + scattered, 1.0);
+}
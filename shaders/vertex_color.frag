@@ -0,0 +1,15 @@
+#version 450
+
+// input color and texture coord from vertex shader
+layout(location=0) in vec3 fragColor;
+layout(location=1) in vec2 fragTexCoord;
+layout(location=4) flat in float fragOpacity;
+layout(location=8) flat in vec3 fragTint;
+
+layout(location=0) out vec4 outColor;
+
+// untextured variant, only uses the interpolated vertex color
+void main()
+{
+	outColor = vec4(fragColor * fragTint, fragOpacity);
+}
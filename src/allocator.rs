@@ -0,0 +1,251 @@
+// GPU memory allocator
+//
+// Device local buffers and images are suballocated from large blocks, a
+// list of blocks per memory type, instead of getting a `vkAllocateMemory`
+// each: drivers only allow a few thousand allocations and every one is
+// slow. Buffers and images never share a block, so linear and optimal
+// resources can't end up next to each other and `bufferImageGranularity`
+// never comes into it. A block hands out ranges first fit, merges freed
+// ranges back with their neighbours, and is released once it's empty.
+//
+// Host visible memory, and anything bigger than half a block, gets a
+// dedicated allocation instead so it can still be mapped from offset 0.
+//
+// Everything handed out is recorded in the memory report, which is how
+// leaks are found when the allocator is destroyed.
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::sync::Mutex;
+
+use crate::memory_report;
+use crate::{get_memory_type_index, AppData};
+
+const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Memory bound to one buffer or image.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Allocation
+{
+	pub memory: vk::DeviceMemory,
+	/// Where the resource starts in `memory`.
+	pub offset: vk::DeviceSize,
+	pub size: vk::DeviceSize,
+	pub memory_type: u32,
+	// false for dedicated allocations
+	suballocated: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind
+{
+	Buffer,
+	Image,
+}
+
+#[derive(Debug)]
+struct Block
+{
+	memory: vk::DeviceMemory,
+	memory_type: u32,
+	kind: Kind,
+	/// Unused ranges as offset and size, in offset order.
+	free: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+impl Block
+{
+	fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize>
+	{
+		let (i, offset) = self.free.iter().enumerate().find_map(|(i, &(start, length))|
+			{
+				let offset = start.div_ceil(alignment) * alignment;
+				(offset + size <= start + length).then_some((i, offset))
+			})?;
+
+		// whatever's left either side of the allocation stays free
+		let (start, length) = self.free.remove(i);
+		let end = offset + size;
+		if end < start + length
+		{
+			self.free.insert(i, (end, start + length - end));
+		}
+		if offset > start
+		{
+			self.free.insert(i, (start, offset - start));
+		}
+
+		Some(offset)
+	}
+
+	fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize)
+	{
+		let i = self.free.partition_point(|&(start, _)| start < offset);
+		self.free.insert(i, (offset, size));
+
+		// merge with the following range, then the preceding one
+		if i + 1 < self.free.len() && offset + size == self.free[i + 1].0
+		{
+			self.free[i].1 += self.free.remove(i + 1).1;
+		}
+		if i > 0 && self.free[i - 1].0 + self.free[i - 1].1 == offset
+		{
+			self.free[i - 1].1 += self.free.remove(i).1;
+		}
+	}
+
+	fn is_empty(&self) -> bool
+	{
+		self.free == [(0, BLOCK_SIZE)]
+	}
+
+	fn unused(&self) -> vk::DeviceSize
+	{
+		self.free.iter().map(|(_, size)| size).sum()
+	}
+}
+
+static BLOCKS: Mutex<Vec<Block>> = Mutex::new(Vec::new());
+
+/// Allocates memory for a buffer and binds it.
+pub unsafe fn allocate_buffer(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	buffer: vk::Buffer,
+	properties: vk::MemoryPropertyFlags,
+	label: impl Into<String>,
+	) -> Result<Allocation>
+{
+	let requirements = device.get_buffer_memory_requirements(buffer);
+	let allocation = allocate(instance, device, data, requirements, properties, Kind::Buffer, label.into())?;
+	device.bind_buffer_memory(buffer, allocation.memory, allocation.offset)?;
+	Ok(allocation)
+}
+
+/// Allocates memory for an image and binds it.
+pub unsafe fn allocate_image(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	image: vk::Image,
+	properties: vk::MemoryPropertyFlags,
+	label: impl Into<String>,
+	) -> Result<Allocation>
+{
+	let requirements = device.get_image_memory_requirements(image);
+	let allocation = allocate(instance, device, data, requirements, properties, Kind::Image, label.into())?;
+	device.bind_image_memory(image, allocation.memory, allocation.offset)?;
+	Ok(allocation)
+}
+
+unsafe fn allocate(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	requirements: vk::MemoryRequirements,
+	properties: vk::MemoryPropertyFlags,
+	kind: Kind,
+	label: String,
+	) -> Result<Allocation>
+{
+	let memory_type = get_memory_type_index(instance, data, properties, requirements)?;
+	let flags = instance
+		.get_physical_device_memory_properties(data.physical_device)
+		.memory_types[memory_type as usize]
+		.property_flags;
+
+	let allocation = if flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) || requirements.size > BLOCK_SIZE / 2
+	{
+		let info = vk::MemoryAllocateInfo::builder()
+			.allocation_size(requirements.size)
+			.memory_type_index(memory_type);
+		let memory = device.allocate_memory(&info, None)?;
+		Allocation { memory, offset: 0, size: requirements.size, memory_type, suballocated: false }
+	}
+	else
+	{
+		suballocate(device, requirements, memory_type, kind)?
+	};
+
+	memory_report::record(&allocation, label);
+	Ok(allocation)
+}
+
+unsafe fn suballocate(device: &Device, requirements: vk::MemoryRequirements, memory_type: u32, kind: Kind) -> Result<Allocation>
+{
+	let mut blocks = BLOCKS.lock().unwrap();
+	let (size, alignment) = (requirements.size, requirements.alignment.max(1));
+
+	let found = blocks
+		.iter_mut()
+		.filter(|b| b.memory_type == memory_type && b.kind == kind)
+		.find_map(|b| b.allocate(size, alignment).map(|offset| (b.memory, offset)));
+
+	let (memory, offset) = match found
+	{
+		Some(found) => found,
+		None =>
+		{
+			let info = vk::MemoryAllocateInfo::builder()
+				.allocation_size(BLOCK_SIZE)
+				.memory_type_index(memory_type);
+			let memory = device.allocate_memory(&info, None)?;
+			debug!("New {:?} block for memory type {} ({} blocks)", kind, memory_type, blocks.len() + 1);
+
+			let mut block = Block { memory, memory_type, kind, free: vec![(0, BLOCK_SIZE)] };
+			let offset = block.allocate(size, alignment).expect("allocation fits an empty block");
+			blocks.push(block);
+			(memory, offset)
+		},
+	};
+
+	Ok(Allocation { memory, offset, size, memory_type, suballocated: true })
+}
+
+/// Frees memory from `allocate_buffer` or `allocate_image`, after whatever
+/// was bound to it has been destroyed. The default allocation is ignored.
+pub unsafe fn free(device: &Device, allocation: Allocation)
+{
+	if allocation.memory.is_null()
+	{
+		return;
+	}
+
+	memory_report::forget(&allocation);
+
+	if !allocation.suballocated
+	{
+		device.free_memory(allocation.memory, None);
+		return;
+	}
+
+	let mut blocks = BLOCKS.lock().unwrap();
+	if let Some(i) = blocks.iter().position(|b| b.memory == allocation.memory)
+	{
+		blocks[i].free(allocation.offset, allocation.size);
+		if blocks[i].is_empty()
+		{
+			device.free_memory(blocks.remove(i).memory, None);
+		}
+	}
+}
+
+/// Size and unused bytes of every block, by memory type.
+pub fn blocks() -> Vec<(u32, vk::DeviceSize, vk::DeviceSize)>
+{
+	BLOCKS.lock().unwrap().iter().map(|b| (b.memory_type, BLOCK_SIZE, b.unused())).collect()
+}
+
+/// Releases every block before the device is destroyed, reporting whatever
+/// was never freed as leaked.
+pub unsafe fn destroy(device: &Device)
+{
+	memory_report::log_leaks();
+	for block in BLOCKS.lock().unwrap().drain(..)
+	{
+		device.free_memory(block.memory, None);
+	}
+}
@@ -0,0 +1,175 @@
+// Reference-counted GPU assets
+//
+// Loaded textures are owned by a cache keyed on their path. Users hold
+// `AssetHandle`s; the cache keeps one reference itself, so an asset is
+// unused once that's the only one left and can be freed by `unload_unused`.
+// When the resident size goes over budget unused assets are evicted
+// automatically, least recently used first.
+
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::allocator::{self, Allocation};
+
+/// Fraction of the largest device-local heap that cached textures may use
+/// before unused ones get evicted.
+const BUDGET_FRACTION: vk::DeviceSize = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssetHandle(Rc<str>);
+
+impl AssetHandle
+{
+	pub fn path(&self) -> &str
+	{
+		&self.0
+	}
+
+	fn is_unused(&self) -> bool
+	{
+		Rc::strong_count(&self.0) == 1
+	}
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Texture
+{
+	pub image: vk::Image,
+	pub memory: Allocation,
+	pub view: vk::ImageView,
+	pub format: vk::Format,
+	pub mip_levels: u32,
+	pub size: vk::DeviceSize,
+}
+
+#[derive(Clone, Debug)]
+struct Entry
+{
+	handle: AssetHandle,
+	texture: Texture,
+	last_used: Instant,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TextureCache
+{
+	entries: HashMap<String, Entry>,
+	budget: Option<vk::DeviceSize>,
+}
+
+impl TextureCache
+{
+	/// Sets the eviction budget based on the device's memory heaps.
+	pub unsafe fn set_budget_from_device(
+		&mut self,
+		instance: &Instance,
+		physical_device: vk::PhysicalDevice,
+		)
+	{
+		let memory = instance.get_physical_device_memory_properties(physical_device);
+
+		let largest_heap = memory.memory_heaps[..memory.memory_heap_count as usize]
+			.iter()
+			.filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+			.map(|heap| heap.size)
+			.max();
+
+		self.budget = largest_heap.map(|size| size / BUDGET_FRACTION);
+	}
+
+	/// Total size of all resident textures in bytes.
+	pub fn resident_size(&self) -> vk::DeviceSize
+	{
+		self.entries.values().map(|e| e.texture.size).sum()
+	}
+
+	pub fn get(&mut self, path: &str) -> Option<AssetHandle>
+	{
+		self.entries.get_mut(path).map(|entry|
+			{
+				entry.last_used = Instant::now();
+				entry.handle.clone()
+			})
+	}
+
+	pub fn texture(&self, handle: &AssetHandle) -> &Texture
+	{
+		&self.entries[handle.path()].texture
+	}
+
+	/// Takes ownership of a loaded texture, evicting unused textures
+	/// if that pushes us over budget.
+	pub unsafe fn insert(&mut self, device: &Device, path: &str, texture: Texture) -> AssetHandle
+	{
+		let handle = AssetHandle(Rc::from(path));
+
+		self.entries.insert(path.to_string(), Entry {
+			handle: handle.clone(),
+			texture,
+			last_used: Instant::now(),
+		});
+
+		if let Some(budget) = self.budget
+		{
+			self.evict_to(device, budget);
+		}
+
+		handle
+	}
+
+	/// Frees every texture that's no longer referenced outside the cache.
+	/// Returns the number of textures freed.
+	pub unsafe fn unload_unused(&mut self, device: &Device) -> usize
+	{
+		self.evict_to(device, 0)
+	}
+
+	unsafe fn evict_to(&mut self, device: &Device, budget: vk::DeviceSize) -> usize
+	{
+		let mut unused = self.entries
+			.iter()
+			.filter(|(_, e)| e.handle.is_unused())
+			.map(|(path, e)| (e.last_used, path.clone()))
+			.collect::<Vec<_>>();
+
+		unused.sort();
+
+		let mut resident = self.resident_size();
+		let mut freed = 0;
+
+		for (_, path) in unused
+		{
+			if resident <= budget
+			{
+				break;
+			}
+
+			let entry = self.entries.remove(&path).unwrap();
+			info!("Unloading texture {} ({} bytes)", path, entry.texture.size);
+			destroy_texture(device, &entry.texture);
+			resident -= entry.texture.size;
+			freed += 1;
+		}
+
+		freed
+	}
+
+	/// Frees everything regardless of outstanding handles.
+	pub unsafe fn destroy(&mut self, device: &Device)
+	{
+		self.entries
+			.drain()
+			.for_each(|(_, e)| destroy_texture(device, &e.texture));
+	}
+}
+
+unsafe fn destroy_texture(device: &Device, texture: &Texture)
+{
+	device.destroy_image_view(texture.view, None);
+	device.destroy_image(texture.image, None);
+	allocator::free(device, texture.memory);
+}
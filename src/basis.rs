@@ -0,0 +1,378 @@
+// Basis Universal (.basis / UASTC) texture support
+//
+// Basis files are a GPU-agnostic intermediate format, so at load time we
+// transcode them to the best block-compressed format the device can sample
+// from. Desktop GPUs usually get BC7, mobile GPUs ASTC 4x4, and anything
+// else falls back to plain RGBA8.
+//
+// UASTC textures can also come in a KTX2 container, told apart from .basis
+// files by its identifier. Its levels are UASTC blocks, supercompressed
+// with zstd or not, and go through the low level UASTC transcoder one at a
+// time. ETC1S KTX2 files need the BasisLZ global codebooks, which the
+// transcoder doesn't take from a container, so they're refused.
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use basis_universal::{
+	DecodeFlags,
+	LowLevelUastcTranscoder,
+	SliceParametersUastc,
+	TranscodeParameters,
+	Transcoder,
+	TranscoderBlockFormat,
+	TranscoderTextureFormat,
+};
+
+use std::fs;
+
+use crate::{
+	begin_single_time_commands,
+	create_image,
+	create_staging_buffer,
+	destroy_staging_buffer,
+	end_single_time_commands,
+	transition_image_layout,
+	AppData,
+};
+
+/// The first 12 bytes of every KTX2 file.
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+/// The data format descriptor's color model for UASTC.
+const KHR_DF_MODEL_UASTC: u8 = 166;
+/// UASTC channel ids that carry alpha, RGBA and RRRG.
+const KHR_DF_CHANNEL_UASTC_ALPHA: [u8; 2] = [3, 5];
+const KTX2_SUPERCOMPRESSION_NONE: u32 = 0;
+const KTX2_SUPERCOMPRESSION_ZSTD: u32 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TranscodeTarget
+{
+	Bc7,
+	Astc4x4,
+	Rgba8,
+}
+
+impl TranscodeTarget
+{
+	pub fn vk_format(self) -> vk::Format
+	{
+		match self
+		{
+			TranscodeTarget::Bc7 => vk::Format::BC7_SRGB_BLOCK,
+			TranscodeTarget::Astc4x4 => vk::Format::ASTC_4X4_SRGB_BLOCK,
+			TranscodeTarget::Rgba8 => vk::Format::R8G8B8A8_SRGB,
+		}
+	}
+
+	fn transcoder_format(self) -> TranscoderTextureFormat
+	{
+		match self
+		{
+			TranscodeTarget::Bc7 => TranscoderTextureFormat::BC7_RGBA,
+			TranscodeTarget::Astc4x4 => TranscoderTextureFormat::ASTC_4x4_RGBA,
+			TranscodeTarget::Rgba8 => TranscoderTextureFormat::RGBA32,
+		}
+	}
+
+	fn block_format(self) -> TranscoderBlockFormat
+	{
+		match self
+		{
+			TranscodeTarget::Bc7 => TranscoderBlockFormat::BC7,
+			TranscodeTarget::Astc4x4 => TranscoderBlockFormat::ASTC_4x4,
+			TranscodeTarget::Rgba8 => TranscoderBlockFormat::RGBA32,
+		}
+	}
+}
+
+/// Picks the best format we can transcode to on the selected physical device.
+pub unsafe fn get_transcode_target(
+	instance: &Instance,
+	data: &AppData,
+	) -> TranscodeTarget
+{
+	let features = instance.get_physical_device_features(data.physical_device);
+
+	let sampleable = |format: vk::Format|
+	{
+		instance
+			.get_physical_device_format_properties(data.physical_device, format)
+			.optimal_tiling_features
+			.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+	};
+
+	if features.texture_compression_bc == vk::TRUE
+		&& sampleable(TranscodeTarget::Bc7.vk_format())
+	{
+		TranscodeTarget::Bc7
+	}
+	else if features.texture_compression_astc_ldr == vk::TRUE
+		&& sampleable(TranscodeTarget::Astc4x4.vk_format())
+	{
+		TranscodeTarget::Astc4x4
+	}
+	else
+	{
+		TranscodeTarget::Rgba8
+	}
+}
+
+/// A transcoded texture, one byte buffer per mip level.
+struct TranscodedImage
+{
+	width: u32,
+	height: u32,
+	levels: Vec<Vec<u8>>,
+}
+
+fn transcode(bytes: &[u8], target: TranscodeTarget) -> Result<TranscodedImage>
+{
+	basis_universal::transcoder_init();
+
+	let mut transcoder = Transcoder::new();
+
+	if !transcoder.validate_header(bytes)
+	{
+		return Err(anyhow!("Invalid basis file header"));
+	}
+
+	transcoder
+		.prepare_transcoding(bytes)
+		.map_err(|_| anyhow!("Failed to prepare basis file for transcoding"))?;
+
+	let level_count = transcoder.image_level_count(bytes, 0);
+
+	let description = transcoder
+		.image_level_description(bytes, 0, 0)
+		.ok_or_else(|| anyhow!("Basis file contains no images"))?;
+
+	let levels = (0..level_count)
+		.map(|level_index|
+			{
+				transcoder
+					.transcode_image_level(
+						bytes,
+						target.transcoder_format(),
+						TranscodeParameters {
+							image_index: 0,
+							level_index,
+							decode_flags: None,
+							output_row_pitch_in_blocks_or_pixels: None,
+							output_rows_in_pixels: None,
+						},
+					)
+					.map_err(|e| anyhow!("Failed to transcode basis level {}: {:?}", level_index, e))
+			})
+		.collect::<Result<Vec<_>>>()?;
+
+	transcoder.end_transcoding();
+
+	Ok(TranscodedImage {
+		width: description.original_width,
+		height: description.original_height,
+		levels,
+	})
+}
+
+/// Transcodes a KTX2 file holding a 2D UASTC texture.
+fn transcode_ktx2(bytes: &[u8], target: TranscodeTarget) -> Result<TranscodedImage>
+{
+	let truncated = || anyhow!("KTX2 file is truncated");
+	let u32_at = |offset: usize| bytes
+		.get(offset..offset + 4)
+		.map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+		.ok_or_else(truncated);
+	let u64_at = |offset: usize| -> Result<usize> { Ok((u32_at(offset)? as u64 | (u32_at(offset + 4)? as u64) << 32) as usize) };
+
+	let width = u32_at(20)?;
+	let height = u32_at(24)?;
+	if u32_at(28)? > 1 || u32_at(32)? > 1 || u32_at(36)? != 1
+	{
+		return Err(anyhow!("Only 2D KTX2 textures are supported, not arrays, cubemaps or volumes"));
+	}
+
+	let level_count = u32_at(40)?.max(1);
+	let supercompression = u32_at(44)?;
+	if supercompression != KTX2_SUPERCOMPRESSION_NONE && supercompression != KTX2_SUPERCOMPRESSION_ZSTD
+	{
+		return Err(anyhow!("KTX2 supercompression scheme {} isn't supported, only UASTC with zstd or none", supercompression));
+	}
+
+	// the descriptor's total size, then its basic block: the color model is
+	// in the block's third word and the first sample's channel id in the
+	// low bits of its seventh word's last byte
+	let dfd = u32_at(48)? as usize;
+	let color_model = *bytes.get(dfd + 12).ok_or_else(truncated)?;
+	if color_model != KHR_DF_MODEL_UASTC
+	{
+		return Err(anyhow!("Only UASTC KTX2 textures are supported, this one has color model {}", color_model));
+	}
+	let channel = *bytes.get(dfd + 31).ok_or_else(truncated)? & 0xF;
+	let has_alpha = KHR_DF_CHANNEL_UASTC_ALPHA.contains(&channel);
+
+	basis_universal::transcoder_init();
+	let transcoder = LowLevelUastcTranscoder::new();
+
+	// the level index follows the 80 byte header, 24 bytes a level
+	let levels = (0..level_count as usize)
+		.map(|level|
+			{
+				let entry = 80 + level * 24;
+				let offset = u64_at(entry)?;
+				let length = u64_at(entry + 8)?;
+				let stored = bytes.get(offset..offset + length).ok_or_else(truncated)?;
+
+				let blocks = match supercompression
+				{
+					KTX2_SUPERCOMPRESSION_ZSTD => zstd::bulk::decompress(stored, u64_at(entry + 16)?)?,
+					_ => stored.to_vec(),
+				};
+
+				let level_width = (width >> level).max(1);
+				let level_height = (height >> level).max(1);
+				let parameters = SliceParametersUastc {
+					num_blocks_x: (level_width + 3) / 4,
+					num_blocks_y: (level_height + 3) / 4,
+					has_alpha,
+					original_width: level_width,
+					original_height: level_height,
+				};
+
+				transcoder
+					.transcode_slice(&blocks, parameters, DecodeFlags::empty(), target.block_format())
+					.map_err(|e| anyhow!("Failed to transcode KTX2 level {}: {:?}", level, e))
+			})
+		.collect::<Result<Vec<_>>>()?;
+
+	Ok(TranscodedImage { width, height, levels })
+}
+
+/// Loads a `.basis` or UASTC `.ktx2` file into `data.texture_image`, using the mip levels
+/// stored in the file rather than generating them with blits (which isn't
+/// possible for block-compressed formats).
+pub unsafe fn create_basis_texture_image(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	path: &str,
+	) -> Result<()>
+{
+	let target = get_transcode_target(instance, data);
+	info!("Transcoding {} to {:?}", path, target);
+
+	let bytes = fs::read(path)?;
+	let image = if bytes.starts_with(&KTX2_IDENTIFIER)
+	{
+		transcode_ktx2(&bytes, target)?
+	}
+	else
+	{
+		transcode(&bytes, target)?
+	};
+
+	// every level one after the other
+	let mut offsets = Vec::with_capacity(image.levels.len());
+	let mut offset = 0;
+	for level in &image.levels
+	{
+		offsets.push(offset as u64);
+		offset += level.len();
+	}
+
+	let staging = create_staging_buffer(instance, device, data, &image.levels.concat())?;
+
+	data.mip_levels = image.levels.len() as u32;
+	data.texture_format = target.vk_format();
+
+	let (texture_image, texture_image_memory) = create_image(
+		instance,
+		device,
+		data,
+		image.width,
+		image.height,
+		data.mip_levels,
+		vk::SampleCountFlags::_1,
+		data.texture_format,
+		vk::ImageTiling::OPTIMAL,
+		vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+	data.texture_image = texture_image;
+	data.texture_image_memory = texture_image_memory;
+
+	transition_image_layout(
+		device,
+		data,
+		data.texture_image,
+		data.texture_format,
+		vk::ImageLayout::UNDEFINED,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		data.mip_levels,
+	)?;
+
+	data.layouts.expect(
+		data.texture_image,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		"copy destination",
+	);
+
+	let command_buffer = begin_single_time_commands(device, data, data.transfer_command_pool)?;
+
+	let regions = offsets
+		.iter()
+		.enumerate()
+		.map(|(level, offset)|
+			{
+				let subresource = vk::ImageSubresourceLayers::builder()
+					.aspect_mask(vk::ImageAspectFlags::COLOR)
+					.mip_level(level as u32)
+					.base_array_layer(0)
+					.layer_count(1);
+
+				vk::BufferImageCopy::builder()
+					.buffer_offset(*offset)
+					.buffer_row_length(0)
+					.buffer_image_height(0)
+					.image_subresource(subresource)
+					.image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+					.image_extent(vk::Extent3D {
+						width: (image.width >> level).max(1),
+						height: (image.height >> level).max(1),
+						depth: 1,
+					})
+					.build()
+			})
+		.collect::<Vec<_>>();
+
+	device.cmd_copy_buffer_to_image(
+		command_buffer,
+		staging.0,
+		data.texture_image,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		&regions,
+	);
+
+	end_single_time_commands(
+		device,
+		data,
+		command_buffer,
+		data.transfer_queue,
+		data.transfer_command_pool,
+	)?;
+
+	destroy_staging_buffer(device, data, staging);
+
+	transition_image_layout(
+		device,
+		data,
+		data.texture_image,
+		data.texture_format,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+		data.mip_levels,
+	)?;
+
+	Ok(())
+}
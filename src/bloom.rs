@@ -0,0 +1,367 @@
+// Bloom
+//
+// Light brighter than the threshold bleeds into its surroundings. The HDR
+// scene color is prefiltered into the first mip of a half resolution chain,
+// downsampled mip by mip, then upsampled back with a tent filter, each mip
+// adding itself to the one above. The composite pass adds the first mip on
+// top of the scene. Everything runs in a compute shader with the chain kept
+// in GENERAL, and is sized from the render extent so it's recreated along
+// with the swapchain.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator;
+use crate::composite::SCENE_FORMAT;
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+use crate::samplers::{common_sampler, CommonSampler};
+use crate::stats::FrameStats;
+use crate::{
+	begin_single_time_commands,
+	create_image,
+	create_shader_module,
+	end_single_time_commands,
+	AppData,
+};
+
+const MAX_MIPS: u32 = 6;
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Brightness above which light starts to bloom, HDR values from emissive
+/// materials easily exceed it while regular lit surfaces stay below.
+const THRESHOLD: f32 = 1.0;
+/// Fraction of the threshold below it where bloom fades in rather than
+/// cutting off sharply.
+const KNEE: f32 = 0.5;
+/// How much of the bloom the composite pass adds to the scene.
+pub const INTENSITY: f32 = 0.6;
+
+/// Must match the modes in `bloom.comp`.
+#[derive(Copy, Clone, Debug)]
+enum Mode
+{
+	Prefilter = 0,
+	Downsample = 1,
+	Upsample = 2,
+}
+
+/// Every dispatch in recording order along with the mip it writes. Each one
+/// reads the scene color or the neighbouring mip.
+fn passes(mips: u32) -> Vec<(Mode, u32)>
+{
+	std::iter::once((Mode::Prefilter, 0))
+		.chain((1..mips).map(|mip| (Mode::Downsample, mip)))
+		.chain((0..mips - 1).rev().map(|mip| (Mode::Upsample, mip)))
+		.collect()
+}
+
+pub fn mip_extent(data: &AppData, mip: u32) -> vk::Extent2D
+{
+	vk::Extent2D {
+		width: (data.render_extent.width / 2 >> mip).max(1),
+		height: (data.render_extent.height / 2 >> mip).max(1),
+	}
+}
+
+/// Must match the push constants of `bloom.comp`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct BloomPushConstants
+{
+	mode: i32,
+	threshold: f32,
+	knee: f32,
+}
+
+pub unsafe fn create_bloom_objects(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let base = mip_extent(data, 0);
+	let mips = (32 - base.width.min(base.height).leading_zeros()).min(MAX_MIPS);
+
+	let (image, memory) = create_image(
+		instance,
+		device,
+		data,
+		base.width,
+		base.height,
+		mips,
+		vk::SampleCountFlags::_1,
+		SCENE_FORMAT,
+		vk::ImageTiling::OPTIMAL,
+		// copied out by captures
+		vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL,
+	)?;
+
+	data.bloom_image = image;
+	data.bloom_image_memory = memory;
+
+	data.bloom_mip_views = (0..mips)
+		.map(|mip|
+			{
+				let info = vk::ImageViewCreateInfo::builder()
+					.image(image)
+					.view_type(vk::ImageViewType::_2D)
+					.format(SCENE_FORMAT)
+					.subresource_range(mip_range(mip, 1));
+				device.create_image_view(&info, None)
+			})
+		.collect::<Result<Vec<_>, _>>()?;
+
+	let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(vk::ImageLayout::UNDEFINED)
+		.new_layout(vk::ImageLayout::GENERAL)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(mip_range(0, mips))
+		.src_access_mask(vk::AccessFlags::empty())
+		.dst_access_mask(vk::AccessFlags::SHADER_WRITE);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		vk::PipelineStageFlags::TOP_OF_PIPE,
+		vk::PipelineStageFlags::COMPUTE_SHADER,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+
+	end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)?;
+	data.layouts.transition(image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+
+	let source_samplers = &[common_sampler(data, CommonSampler::LinearClamp)];
+	let source_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::COMPUTE)
+		.immutable_samplers(source_samplers);
+
+	let destination_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(1)
+		.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+	let bindings = &[source_binding, destination_binding];
+	let info = vk::DescriptorSetLayoutCreateInfo::builder()
+		.bindings(bindings);
+
+	data.bloom_descriptor_set_layout = data.descriptors.layout(device, &info)?;
+
+	let push_constant_range = push_constant_range::<BloomPushConstants>(vk::ShaderStageFlags::COMPUTE);
+
+	let set_layouts = &[data.bloom_descriptor_set_layout];
+	let push_constant_ranges = &[push_constant_range];
+	let info = vk::PipelineLayoutCreateInfo::builder()
+		.set_layouts(set_layouts)
+		.push_constant_ranges(push_constant_ranges);
+
+	data.bloom_pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+	let comp = include_bytes!(concat!(env!("OUT_DIR"), "/bloom_comp.spv"));
+	let comp_sm = create_shader_module(device, comp)?;
+
+	let stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::COMPUTE)
+		.module(comp_sm)
+		.name(b"main\0");
+
+	let info = vk::ComputePipelineCreateInfo::builder()
+		.stage(stage)
+		.layout(data.bloom_pipeline_layout);
+
+	data.bloom_pipeline = device.create_compute_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(comp_sm, None);
+
+	let passes = passes(mips);
+
+	let layouts = vec![data.bloom_descriptor_set_layout; passes.len()];
+	data.bloom_descriptor_sets = data.descriptors.swapchain.allocate(device, &layouts)?;
+
+	for (&(mode, mip), set) in passes.iter().zip(&data.bloom_descriptor_sets)
+	{
+		// the scene color is left in SHADER_READ_ONLY by the render pass
+		let (source_view, source_layout) = match mode
+		{
+			Mode::Prefilter => (data.scene_image_view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+			Mode::Downsample => (data.bloom_mip_views[mip as usize - 1], vk::ImageLayout::GENERAL),
+			Mode::Upsample => (data.bloom_mip_views[mip as usize + 1], vk::ImageLayout::GENERAL),
+		};
+
+		let source_info = &[vk::DescriptorImageInfo::builder()
+			.image_layout(source_layout)
+			.image_view(source_view)
+			.build()];
+
+		let destination_info = &[vk::DescriptorImageInfo::builder()
+			.image_layout(vk::ImageLayout::GENERAL)
+			.image_view(data.bloom_mip_views[mip as usize])
+			.build()];
+
+		let source_write = vk::WriteDescriptorSet::builder()
+			.dst_set(*set)
+			.dst_binding(0)
+			.dst_array_element(0)
+			.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+			.image_info(source_info);
+
+		let destination_write = vk::WriteDescriptorSet::builder()
+			.dst_set(*set)
+			.dst_binding(1)
+			.dst_array_element(0)
+			.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+			.image_info(destination_info);
+
+		device.update_descriptor_sets(&[source_write, destination_write], &[] as &[vk::CopyDescriptorSet]);
+	}
+
+	Ok(())
+}
+
+fn mip_range(base_mip: u32, mips: u32) -> vk::ImageSubresourceRange
+{
+	vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(base_mip)
+		.level_count(mips)
+		.base_array_layer(0)
+		.layer_count(1)
+		.build()
+}
+
+unsafe fn chain_barrier(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	src_stage: vk::PipelineStageFlags,
+	src_access: vk::AccessFlags,
+	dst_stage: vk::PipelineStageFlags,
+	dst_access: vk::AccessFlags,
+	)
+{
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(vk::ImageLayout::GENERAL)
+		.new_layout(vk::ImageLayout::GENERAL)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(data.bloom_image)
+		.subresource_range(mip_range(0, data.bloom_mip_views.len() as u32))
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
+
+/// Records the bloom chain. Must be after the scene render pass and outside
+/// of any render pass.
+pub unsafe fn record_bloom(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	stats: &mut FrameStats,
+	)
+{
+	data.layouts.expect(data.bloom_image, vk::ImageLayout::GENERAL, "bloom");
+
+	// last frame's composite pass may still be reading the first mip
+	chain_barrier(
+		device,
+		data,
+		command_buffer,
+		vk::PipelineStageFlags::FRAGMENT_SHADER,
+		vk::AccessFlags::empty(),
+		vk::PipelineStageFlags::COMPUTE_SHADER,
+		vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+	);
+	stats.barriers += 1;
+
+	device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.bloom_pipeline);
+	stats.pipeline_binds += 1;
+
+	let passes = passes(data.bloom_mip_views.len() as u32);
+
+	for (&(mode, mip), set) in passes.iter().zip(&data.bloom_descriptor_sets)
+	{
+		device.cmd_bind_descriptor_sets(
+			command_buffer,
+			vk::PipelineBindPoint::COMPUTE,
+			data.bloom_pipeline_layout,
+			0,
+			&[*set],
+			&[]);
+		stats.descriptor_binds += 1;
+
+		cmd_push_constants(
+			device,
+			command_buffer,
+			data.bloom_pipeline_layout,
+			vk::ShaderStageFlags::COMPUTE,
+			&BloomPushConstants { mode: mode as i32, threshold: THRESHOLD, knee: KNEE },
+		);
+
+		let extent = mip_extent(data, mip);
+		device.cmd_dispatch(
+			command_buffer,
+			(extent.width + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+			(extent.height + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+			1,
+		);
+
+		// the next dispatch reads what this one wrote
+		chain_barrier(
+			device,
+			data,
+			command_buffer,
+			vk::PipelineStageFlags::COMPUTE_SHADER,
+			vk::AccessFlags::SHADER_WRITE,
+			vk::PipelineStageFlags::COMPUTE_SHADER,
+			vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+		);
+		stats.barriers += 1;
+	}
+
+	chain_barrier(
+		device,
+		data,
+		command_buffer,
+		vk::PipelineStageFlags::COMPUTE_SHADER,
+		vk::AccessFlags::SHADER_WRITE,
+		vk::PipelineStageFlags::FRAGMENT_SHADER,
+		vk::AccessFlags::SHADER_READ,
+	);
+	stats.barriers += 1;
+}
+
+pub unsafe fn destroy_bloom_objects(device: &Device, data: &AppData)
+{
+	device.destroy_pipeline(data.bloom_pipeline, None);
+	device.destroy_pipeline_layout(data.bloom_pipeline_layout, None);
+
+	data.bloom_mip_views
+		.iter()
+		.for_each(|view| device.destroy_image_view(*view, None));
+	device.destroy_image(data.bloom_image, None);
+	allocator::free(device, data.bloom_image_memory);
+}
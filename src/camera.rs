@@ -0,0 +1,127 @@
+// Camera
+//
+// Both projections share a zoom factor. The orthographic view volume is
+// sized to match what the perspective projection shows at the target's
+// distance, so switching between them keeps the focus the same size.
+
+use nalgebra_glm as glm;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Projection
+{
+	Perspective,
+	Orthographic,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Camera
+{
+	pub eye: glm::Vec3,
+	pub target: glm::Vec3,
+	pub up: glm::Vec3,
+	pub projection: Projection,
+	/// Vertical field of view in radians at a zoom of 1.
+	pub fov_y: f32,
+	pub zoom: f32,
+	pub near: f32,
+	pub far: f32,
+}
+
+impl Camera
+{
+	pub fn new(eye: glm::Vec3, target: glm::Vec3, up: glm::Vec3, near: f32, far: f32) -> Self
+	{
+		Self {
+			eye,
+			target,
+			up,
+			projection: Projection::Perspective,
+			fov_y: glm::radians(&glm::vec1(45.0))[0],
+			zoom: 1.0,
+			near,
+			far,
+		}
+	}
+
+	pub fn toggle_projection(&mut self)
+	{
+		self.projection = match self.projection
+		{
+			Projection::Perspective => Projection::Orthographic,
+			Projection::Orthographic => Projection::Perspective,
+		};
+	}
+
+	/// Multiplies the zoom, values above 1 zoom in.
+	pub fn zoom_by(&mut self, factor: f32)
+	{
+		self.zoom = (self.zoom * factor).clamp(0.1, 10.0);
+	}
+
+	pub fn view(&self) -> glm::Mat4
+	{
+		glm::look_at(&self.eye, &self.target, &self.up)
+	}
+
+	/// Projection for Vulkan clip space, with y pointing down.
+	pub fn proj(&self, aspect: f32) -> glm::Mat4
+	{
+		let half_tan = (self.fov_y / 2.0).tan() / self.zoom;
+
+		let mut proj = match self.projection
+		{
+			Projection::Perspective =>
+			{
+				glm::perspective_rh_zo(aspect, 2.0 * half_tan.atan(), self.near, self.far)
+			},
+			Projection::Orthographic =>
+			{
+				let half_height = glm::distance(&self.eye, &self.target) * half_tan;
+				let half_width = half_height * aspect;
+				glm::ortho_rh_zo(-half_width, half_width, -half_height, half_height, self.near, self.far)
+			},
+		};
+
+		proj[(1,1)] *= -1.0;
+
+		proj
+	}
+}
+
+/// Planes of a view-projection matrix's frustum with normals facing inwards,
+/// for culling bounding boxes.
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum
+{
+	planes: [glm::Vec4; 6],
+}
+
+impl Frustum
+{
+	/// Vulkan clip space, so depth runs from 0 to 1.
+	pub fn from_view_proj(view_proj: &glm::Mat4) -> Self
+	{
+		let row = |i: usize| glm::vec4(view_proj[(i, 0)], view_proj[(i, 1)], view_proj[(i, 2)], view_proj[(i, 3)]);
+		let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+		Self {
+			planes: [w + x, w - x, w + y, w - y, z, w - z],
+		}
+	}
+
+	/// False only if the box is entirely outside one of the planes.
+	pub fn intersects_aabb(&self, min: &glm::Vec3, max: &glm::Vec3) -> bool
+	{
+		self.planes.iter().all(|plane|
+			{
+				// the corner furthest along the plane's normal
+				let corner = glm::vec3(
+					if plane.x >= 0.0 { max.x } else { min.x },
+					if plane.y >= 0.0 { max.y } else { min.y },
+					if plane.z >= 0.0 { max.z } else { min.z },
+				);
+
+				plane.x * corner.x + plane.y * corner.y + plane.z * corner.z + plane.w >= 0.0
+			})
+	}
+}
@@ -0,0 +1,181 @@
+// Camera paths
+//
+// Keyframes are recorded at the current camera pose and played back along
+// a Catmull-Rom spline through both the eye and target positions, so the
+// camera's orientation is interpolated along with its position. Paths are
+// saved as `.ron` files so flythroughs can be repeated exactly, e.g.
+//
+// (
+//     seconds_per_keyframe: 2.0,
+//     keyframes: [
+//         (eye: (6.0, 0.0, 2.0), target: (0.0, 0.0, 0.0)),
+//         (eye: (0.0, 6.0, 2.0), target: (0.0, 0.0, 0.0)),
+//     ],
+// )
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use nalgebra_glm as glm;
+
+use std::fs;
+use std::path::Path;
+
+use crate::camera::Camera;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe
+{
+	eye: (f32, f32, f32),
+	target: (f32, f32, f32),
+}
+
+impl Keyframe
+{
+	fn eye(&self) -> glm::Vec3
+	{
+		glm::vec3(self.eye.0, self.eye.1, self.eye.2)
+	}
+
+	fn target(&self) -> glm::Vec3
+	{
+		glm::vec3(self.target.0, self.target.1, self.target.2)
+	}
+}
+
+fn default_seconds_per_keyframe() -> f32
+{
+	2.0
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CameraPath
+{
+	#[serde(default = "default_seconds_per_keyframe")]
+	pub seconds_per_keyframe: f32,
+	keyframes: Vec<Keyframe>,
+	#[serde(skip)]
+	playback_time: Option<f32>,
+}
+
+impl Default for CameraPath
+{
+	fn default() -> Self
+	{
+		Self {
+			seconds_per_keyframe: default_seconds_per_keyframe(),
+			keyframes: Vec::new(),
+			playback_time: None,
+		}
+	}
+}
+
+impl CameraPath
+{
+	pub fn load(path: &Path) -> Result<Self>
+	{
+		let contents = fs::read_to_string(path)?;
+		ron::from_str(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))
+	}
+
+	pub fn save(&self, path: &Path) -> Result<()>
+	{
+		let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+		fs::write(path, contents)?;
+		Ok(())
+	}
+
+	pub fn len(&self) -> usize
+	{
+		self.keyframes.len()
+	}
+
+	pub fn record(&mut self, camera: &Camera)
+	{
+		let v = |v: glm::Vec3| (v.x, v.y, v.z);
+		self.keyframes.push(Keyframe { eye: v(camera.eye), target: v(camera.target) });
+	}
+
+	pub fn clear(&mut self)
+	{
+		self.keyframes.clear();
+		self.playback_time = None;
+	}
+
+	pub fn is_playing(&self) -> bool
+	{
+		self.playback_time.is_some()
+	}
+
+	/// Starts playback from the beginning. Needs at least two keyframes.
+	pub fn play(&mut self) -> bool
+	{
+		self.playback_time = (self.keyframes.len() >= 2).then_some(0.0);
+		self.is_playing()
+	}
+
+	pub fn stop(&mut self)
+	{
+		self.playback_time = None;
+	}
+
+	/// Total playback time in seconds.
+	pub fn duration(&self) -> f32
+	{
+		self.keyframes.len().saturating_sub(1) as f32 * self.seconds_per_keyframe
+	}
+
+	/// Moves the camera `dt` seconds further along the path, stopping once
+	/// the end is reached.
+	pub fn update(&mut self, camera: &mut Camera, dt: f32)
+	{
+		let time = match &mut self.playback_time
+		{
+			Some(time) =>
+			{
+				*time += dt;
+				*time
+			},
+			None => return,
+		};
+
+		if time >= self.duration()
+		{
+			self.playback_time = None;
+		}
+
+		let (eye, target) = self.sample(time.min(self.duration()));
+		camera.eye = eye;
+		camera.target = target;
+	}
+
+	/// Eye and target positions `time` seconds into the path.
+	pub fn sample(&self, time: f32) -> (glm::Vec3, glm::Vec3)
+	{
+		let last = self.keyframes.len() - 1;
+		let position = (time / self.seconds_per_keyframe).clamp(0.0, last as f32);
+		let segment = (position as usize).min(last.saturating_sub(1));
+		let t = position - segment as f32;
+
+		// the ends are repeated so the curve passes through every keyframe
+		let key = |i: isize| &self.keyframes[i.clamp(0, last as isize) as usize];
+		let i = segment as isize;
+		let (k0, k1, k2, k3) = (key(i - 1), key(i), key(i + 1), key(i + 2));
+
+		(
+			catmull_rom(k0.eye(), k1.eye(), k2.eye(), k3.eye(), t),
+			catmull_rom(k0.target(), k1.target(), k2.target(), k3.target(), t),
+		)
+	}
+}
+
+fn catmull_rom(p0: glm::Vec3, p1: glm::Vec3, p2: glm::Vec3, p3: glm::Vec3, t: f32) -> glm::Vec3
+{
+	let t2 = t * t;
+	let t3 = t2 * t;
+
+	(p1 * 2.0
+		+ (p2 - p0) * t
+		+ (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+		+ (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3) * 0.5
+}
@@ -0,0 +1,228 @@
+// Device capabilities
+//
+// Everything worth knowing about the selected device is queried once when
+// it's picked: which formats work for the common kinds of image, how far
+// MSAA goes, which optional features exist and the device's limits. The rest
+// of the renderer, and anything embedding it, reads them from here instead
+// of asking Vulkan again.
+
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::InstanceV1_1;
+use vulkanalia::Version;
+
+use std::fmt;
+
+use crate::dynamic_rendering;
+use crate::subgroups::SubgroupSupport;
+
+#[derive(Clone, Debug, Default)]
+pub struct DeviceCapabilities
+{
+	pub device_name: String,
+	pub device_type: vk::PhysicalDeviceType,
+	pub api_version: Version,
+	/// The sample counts both color and depth attachments can have.
+	pub msaa_sample_counts: vk::SampleCountFlags,
+	pub max_msaa_samples: vk::SampleCountFlags,
+	pub depth_format: Option<vk::Format>,
+	pub depth_stencil_format: Option<vk::Format>,
+	/// Half float color that can be rendered to and blended, for HDR.
+	pub hdr_color_format: Option<vk::Format>,
+	pub bc_compression: bool,
+	pub etc2_compression: bool,
+	pub astc_compression: bool,
+	pub ray_tracing: bool,
+	pub mesh_shaders: bool,
+	/// Descriptor indexing, so a shader can index one large array of
+	/// textures with a dynamic, non-uniform index.
+	pub bindless: bool,
+	pub subgroups: SubgroupSupport,
+	/// Timestamps on the graphics and compute queues.
+	pub timestamps: bool,
+	pub pipeline_statistics: bool,
+	/// `VK_KHR_timeline_semaphore`, for `--timeline-sync`.
+	pub timeline_semaphores: bool,
+	/// `VK_KHR_dynamic_rendering`, used instead of render passes where the
+	/// renderer can.
+	pub dynamic_rendering: bool,
+	pub limits: vk::PhysicalDeviceLimits,
+}
+
+impl DeviceCapabilities
+{
+	pub unsafe fn query(instance: &Instance, physical_device: vk::PhysicalDevice) -> Self
+	{
+		let properties = instance.get_physical_device_properties(physical_device);
+		let features = instance.get_physical_device_features(physical_device);
+		let extensions = instance
+			.enumerate_device_extension_properties(physical_device, None)
+			.unwrap_or_default()
+			.iter()
+			.map(|e| e.extension_name)
+			.collect::<Vec<_>>();
+		let has_extension = |extension: &vk::Extension| extensions.contains(&extension.name);
+
+		let first_supported = |candidates: &[vk::Format], usage: vk::FormatFeatureFlags|
+		{
+			candidates.iter().cloned().find(|&format|
+			{
+				instance
+					.get_physical_device_format_properties(physical_device, format)
+					.optimal_tiling_features
+					.contains(usage)
+			})
+		};
+
+		let api_version = Version::from(properties.api_version);
+		let limits = properties.limits;
+
+		let msaa_sample_counts = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+		let max_msaa_samples = most_samples(msaa_sample_counts, u32::MAX);
+
+		Self {
+			device_name: properties.device_name.to_string(),
+			device_type: properties.device_type,
+			api_version,
+			msaa_sample_counts,
+			max_msaa_samples,
+			depth_format: first_supported(
+				&[vk::Format::D32_SFLOAT, vk::Format::X8_D24_UNORM_PACK32, vk::Format::D16_UNORM],
+				vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+			),
+			depth_stencil_format: first_supported(
+				&[vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT],
+				vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+			),
+			hdr_color_format: first_supported(
+				&[vk::Format::R16G16B16A16_SFLOAT],
+				vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND,
+			),
+			bc_compression: features.texture_compression_bc == vk::TRUE,
+			etc2_compression: features.texture_compression_etc2 == vk::TRUE,
+			astc_compression: features.texture_compression_astc_ldr == vk::TRUE,
+			ray_tracing: has_extension(&vk::KHR_RAY_TRACING_PIPELINE_EXTENSION)
+				&& has_extension(&vk::KHR_ACCELERATION_STRUCTURE_EXTENSION),
+			mesh_shaders: has_extension(&vk::EXT_MESH_SHADER_EXTENSION),
+			// core from 1.2, though still optional to support there
+			bindless: api_version >= Version::new(1, 2, 0) || has_extension(&vk::EXT_DESCRIPTOR_INDEXING_EXTENSION),
+			subgroups: SubgroupSupport::query(instance, physical_device, api_version),
+			timestamps: limits.timestamp_compute_and_graphics == vk::TRUE,
+			pipeline_statistics: features.pipeline_statistics_query == vk::TRUE,
+			timeline_semaphores: api_version >= Version::new(1, 1, 0)
+				&& has_extension(&vk::KHR_TIMELINE_SEMAPHORE_EXTENSION)
+				&& timeline_semaphore_feature(instance, physical_device),
+			dynamic_rendering: api_version >= Version::new(1, 1, 0)
+				&& dynamic_rendering::EXTENSIONS.iter().all(|e| has_extension(e))
+				&& dynamic_rendering_feature(instance, physical_device),
+			limits,
+		}
+	}
+}
+
+impl DeviceCapabilities
+{
+	/// The most samples up to `requested` that color and depth attachments
+	/// can both have, so a count the device lacks falls back to the next
+	/// one down.
+	pub fn msaa_samples_up_to(&self, requested: u32) -> vk::SampleCountFlags
+	{
+		most_samples(self.msaa_sample_counts, requested)
+	}
+
+	/// How well the device suits the renderer, higher is better. The kind of
+	/// device outweighs everything else, so a discrete GPU always wins over
+	/// an integrated one, then larger limits and more optional features
+	/// break ties between devices of the same kind.
+	pub fn score(&self) -> u32
+	{
+		let kind = match self.device_type
+		{
+			vk::PhysicalDeviceType::DISCRETE_GPU => 10_000,
+			vk::PhysicalDeviceType::INTEGRATED_GPU => 1_000,
+			vk::PhysicalDeviceType::VIRTUAL_GPU => 100,
+			vk::PhysicalDeviceType::CPU => 10,
+			_ => 0,
+		};
+
+		let limits = self.limits.max_image_dimension_2d / 1024
+			+ self.max_msaa_samples.bits().trailing_zeros()
+			+ self.limits.max_push_constants_size / 128;
+
+		let features = [
+			(self.hdr_color_format.is_some(), 50),
+			(self.bindless, 20),
+			(self.timestamps, 10),
+			(self.pipeline_statistics, 10),
+			(self.bc_compression, 10),
+			(self.ray_tracing, 10),
+			(self.mesh_shaders, 10),
+		]
+		.iter()
+		.filter(|(supported, _)| *supported)
+		.map(|(_, points)| points)
+		.sum::<u32>();
+
+		kind + limits + features
+	}
+}
+
+unsafe fn timeline_semaphore_feature(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool
+{
+	let mut timeline = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+	let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut timeline);
+	instance.get_physical_device_features2(physical_device, &mut features);
+	timeline.timeline_semaphore == vk::TRUE
+}
+
+unsafe fn dynamic_rendering_feature(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool
+{
+	let mut dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+	let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut dynamic_rendering);
+	instance.get_physical_device_features2(physical_device, &mut features);
+	dynamic_rendering.dynamic_rendering == vk::TRUE
+}
+
+fn most_samples(counts: vk::SampleCountFlags, limit: u32) -> vk::SampleCountFlags
+{
+	[
+		(64, vk::SampleCountFlags::_64),
+		(32, vk::SampleCountFlags::_32),
+		(16, vk::SampleCountFlags::_16),
+		(8, vk::SampleCountFlags::_8),
+		(4, vk::SampleCountFlags::_4),
+		(2, vk::SampleCountFlags::_2),
+	]
+	.iter()
+	.find(|&&(samples, count)| samples <= limit && counts.contains(count))
+	.map_or(vk::SampleCountFlags::_1, |&(_, count)| count)
+}
+
+impl fmt::Display for DeviceCapabilities
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		let compression = [("BC", self.bc_compression), ("ETC2", self.etc2_compression), ("ASTC", self.astc_compression)]
+			.iter()
+			.filter(|(_, supported)| *supported)
+			.map(|(name, _)| *name)
+			.collect::<Vec<_>>();
+
+		write!(
+			f,
+			"{} (Vulkan {}), {:?} MSAA, depth {:?}, depth-stencil {:?}, HDR {:?}, compression {:?}, ray tracing {}, mesh shaders {}, bindless {}, subgroups of {} (compute arithmetic {}), max 2D image {}",
+			self.device_name,
+			self.api_version,
+			self.max_msaa_samples,
+			self.depth_format,
+			self.depth_stencil_format,
+			self.hdr_color_format,
+			compression,
+			self.ray_tracing,
+			self.mesh_shaders,
+			self.bindless,
+			self.subgroups.size,
+			self.subgroups.compute_arithmetic(),
+			self.limits.max_image_dimension_2d,
+		)
+	}
+}
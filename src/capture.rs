@@ -0,0 +1,320 @@
+// Intermediate target capture
+//
+// Dumps what the last frame left in one of the renderer's targets to disk,
+// to inspect offline what the composite view can only show a slice of.
+// HDR targets keep their full range in EXR, PNG clamps them to [0, 1].
+//
+// Captures are requested with the Print Screen key, which saves every
+// target, or from the command line, e.g.
+//
+// vulkan-tutorial --capture scene,depth --capture-format png
+//
+// which saves them after the first frame. R saves a 360° panorama of the
+// scene around the camera instead, see cubemap.
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bloom;
+use crate::readback;
+use crate::AppData;
+
+pub const CAPTURE_DIRECTORY: &str = "captures";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CaptureTarget
+{
+	/// Lit scene color before bloom and tone mapping.
+	Scene,
+	/// Only when MSAA is off, multisampled images can't be copied out.
+	Depth,
+	/// Largest mip of the bloom chain.
+	Bloom,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CaptureFormat
+{
+	#[default]
+	Exr,
+	Png,
+}
+
+/// Targets waiting to be saved once the frame using them has finished.
+#[derive(Clone, Debug, Default)]
+pub struct CaptureRequest
+{
+	pub targets: Vec<CaptureTarget>,
+	pub format: CaptureFormat,
+}
+
+impl CaptureTarget
+{
+	pub const ALL: [CaptureTarget; 3] = [CaptureTarget::Scene, CaptureTarget::Depth, CaptureTarget::Bloom];
+
+	pub fn name(self) -> &'static str
+	{
+		match self
+		{
+			CaptureTarget::Scene => "scene",
+			CaptureTarget::Depth => "depth",
+			CaptureTarget::Bloom => "bloom",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self>
+	{
+		Self::ALL.into_iter().find(|t| t.name() == name)
+	}
+}
+
+impl CaptureFormat
+{
+	pub fn from_name(name: &str) -> Option<Self>
+	{
+		match name
+		{
+			"exr" => Some(CaptureFormat::Exr),
+			"png" => Some(CaptureFormat::Png),
+			_ => None,
+		}
+	}
+
+	fn extension(self) -> &'static str
+	{
+		match self
+		{
+			CaptureFormat::Exr => "exr",
+			CaptureFormat::Png => "png",
+		}
+	}
+}
+
+impl CaptureRequest
+{
+	/// Saves every requested target and clears the request. The frames that
+	/// wrote them must have finished.
+	pub unsafe fn save(&mut self, instance: &Instance, device: &Device, data: &AppData) -> Result<()>
+	{
+		let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+		std::fs::create_dir_all(CAPTURE_DIRECTORY)?;
+
+		for target in self.targets.drain(..)
+		{
+			let path = PathBuf::from(CAPTURE_DIRECTORY)
+				.join(format!("{}-{}.{}", target.name(), stamp, self.format.extension()));
+
+			match capture(instance, device, data, target, &path)
+			{
+				Ok(()) => info!("Saved {}", path.display()),
+				Err(e) => warn!("Failed to capture {}: {}", target.name(), e),
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Where a target lives and what it looks like between frames.
+struct Source
+{
+	image: vk::Image,
+	extent: vk::Extent2D,
+	aspect: vk::ImageAspectFlags,
+	/// The layout the frame leaves it in, it's put back afterwards.
+	layout: vk::ImageLayout,
+	texel_size: u64,
+	decode: fn(&[u8]) -> [f32; 4],
+}
+
+fn source(data: &AppData, target: CaptureTarget) -> Result<Source>
+{
+	match target
+	{
+		CaptureTarget::Scene => Ok(Source {
+			image: data.scene_image,
+			extent: data.render_extent,
+			aspect: vk::ImageAspectFlags::COLOR,
+			layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+			texel_size: 8,
+			decode: decode_half4,
+		}),
+		CaptureTarget::Bloom => Ok(Source {
+			image: data.bloom_image,
+			extent: bloom::mip_extent(data, 0),
+			aspect: vk::ImageAspectFlags::COLOR,
+			layout: vk::ImageLayout::GENERAL,
+			texel_size: 8,
+			decode: decode_half4,
+		}),
+		CaptureTarget::Depth =>
+		{
+			if data.msaa_samples != vk::SampleCountFlags::_1
+			{
+				return Err(anyhow!("the depth buffer is multisampled"));
+			}
+
+			// the depth aspect of D24S8 is copied out in the low bits of 32
+			let decode = if data.depth_format == vk::Format::D32_SFLOAT_S8_UINT { decode_depth32 } else { decode_depth24 };
+
+			Ok(Source {
+				image: data.depth_image,
+				extent: data.render_extent,
+				aspect: vk::ImageAspectFlags::DEPTH,
+				layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+				texel_size: 4,
+				decode,
+			})
+		},
+	}
+}
+
+unsafe fn capture(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	target: CaptureTarget,
+	path: &Path,
+	) -> Result<()>
+{
+	let source = source(data, target)?;
+	let texels = (source.extent.width * source.extent.height) as u64;
+	let size = texels * source.texel_size;
+
+	let bytes = readback::read_now(instance, device, data, size, |command_buffer, buffer|
+	{
+		let subresource = vk::ImageSubresourceRange::builder()
+			.aspect_mask(source.aspect)
+			.base_mip_level(0)
+			.level_count(1)
+			.base_array_layer(0)
+			.layer_count(1);
+
+		let to_transfer = vk::ImageMemoryBarrier::builder()
+			.old_layout(source.layout)
+			.new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+			.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+			.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+			.image(source.image)
+			.subresource_range(subresource)
+			.src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+			.dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+		device.cmd_pipeline_barrier(
+			command_buffer,
+			vk::PipelineStageFlags::ALL_COMMANDS,
+			vk::PipelineStageFlags::TRANSFER,
+			vk::DependencyFlags::empty(),
+			&[] as &[vk::MemoryBarrier],
+			&[] as &[vk::BufferMemoryBarrier],
+			&[to_transfer],
+		);
+
+		let region = vk::BufferImageCopy::builder()
+			.buffer_offset(0)
+			.buffer_row_length(0)
+			.buffer_image_height(0)
+			.image_subresource(vk::ImageSubresourceLayers {
+				aspect_mask: source.aspect,
+				mip_level: 0,
+				base_array_layer: 0,
+				layer_count: 1,
+			})
+			.image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+			.image_extent(vk::Extent3D { width: source.extent.width, height: source.extent.height, depth: 1 });
+
+		device.cmd_copy_image_to_buffer(command_buffer, source.image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, buffer, &[region]);
+
+		// back to where the next frame expects it
+		let to_source = vk::ImageMemoryBarrier::builder()
+			.old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+			.new_layout(source.layout)
+			.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+			.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+			.image(source.image)
+			.subresource_range(subresource)
+			.src_access_mask(vk::AccessFlags::TRANSFER_READ)
+			.dst_access_mask(vk::AccessFlags::MEMORY_READ);
+
+		device.cmd_pipeline_barrier(
+			command_buffer,
+			vk::PipelineStageFlags::TRANSFER,
+			vk::PipelineStageFlags::ALL_COMMANDS,
+			vk::DependencyFlags::empty(),
+			&[] as &[vk::MemoryBarrier],
+			&[] as &[vk::BufferMemoryBarrier],
+			&[to_source],
+		);
+	})?;
+
+	let rgba = bytes
+		.chunks_exact(source.texel_size as usize)
+		.flat_map(source.decode)
+		.collect::<Vec<_>>();
+
+	let image = image::Rgba32FImage::from_raw(source.extent.width, source.extent.height, rgba)
+		.ok_or_else(|| anyhow!("capture is the wrong size"))?;
+
+	save_image(image, path)
+}
+
+/// Saves an equirectangular panorama next to the other captures.
+pub fn save_panorama(image: image::Rgba32FImage, format: CaptureFormat) -> Result<PathBuf>
+{
+	let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+	std::fs::create_dir_all(CAPTURE_DIRECTORY)?;
+
+	let path = PathBuf::from(CAPTURE_DIRECTORY).join(format!("panorama-{}.{}", stamp, format.extension()));
+	save_image(image, &path)?;
+
+	Ok(path)
+}
+
+fn save_image(image: image::Rgba32FImage, path: &Path) -> Result<()>
+{
+	let image = image::DynamicImage::ImageRgba32F(image);
+
+	match path.extension().and_then(|e| e.to_str())
+	{
+		Some("exr") => image.save(path)?,
+		_ => image.into_rgba8().save(path)?,
+	}
+
+	Ok(())
+}
+
+fn decode_half4(texel: &[u8]) -> [f32; 4]
+{
+	[0, 1, 2, 3].map(|i| half_to_f32(u16::from_ne_bytes([texel[i * 2], texel[i * 2 + 1]])))
+}
+
+fn decode_depth32(texel: &[u8]) -> [f32; 4]
+{
+	let depth = f32::from_ne_bytes([texel[0], texel[1], texel[2], texel[3]]);
+	[depth, depth, depth, 1.0]
+}
+
+fn decode_depth24(texel: &[u8]) -> [f32; 4]
+{
+	let depth = (u32::from_ne_bytes([texel[0], texel[1], texel[2], texel[3]]) & 0xff_ffff) as f32 / 0xff_ffff as f32;
+	[depth, depth, depth, 1.0]
+}
+
+fn half_to_f32(half: u16) -> f32
+{
+	let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+	let exponent = ((half >> 10) & 0x1f) as i32;
+	let mantissa = (half & 0x3ff) as f32;
+
+	sign * match exponent
+	{
+		0 => mantissa * 2f32.powi(-24),
+		31 if mantissa == 0.0 => f32::INFINITY,
+		31 => f32::NAN,
+		_ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+	}
+}
@@ -0,0 +1,65 @@
+// Frame clock
+//
+// Everything that animates takes its time from here: the shaders' time,
+// the timestep handed to the camera, time of day, physics and demo logic,
+// and the playback of camera paths and demo sequences. Normally that's the
+// wall clock. With `--deterministic` every frame is a fixed timestep
+// instead, so a run plays out the same however long its frames take.
+
+use std::time::Instant;
+
+/// Timestep of a deterministic frame, in seconds.
+pub const FIXED_TIMESTEP: f32 = 1.0 / 60.0;
+
+#[derive(Clone, Debug)]
+pub struct Clock
+{
+	last_frame: Instant,
+	fixed_timestep: Option<f32>,
+	// seconds since startup, in f64 so it doesn't lose precision over a long run
+	time: f64,
+	// the last tick's timestep
+	dt: f32,
+}
+
+impl Clock
+{
+	pub fn new(deterministic: bool) -> Self
+	{
+		Self { last_frame: Instant::now(), fixed_timestep: deterministic.then_some(FIXED_TIMESTEP), time: 0.0, dt: 0.0 }
+	}
+
+	pub fn is_deterministic(&self) -> bool
+	{
+		self.fixed_timestep.is_some()
+	}
+
+	/// Moves on to a new frame and returns its timestep.
+	pub fn tick(&mut self) -> f32
+	{
+		let dt = self.fixed_timestep.unwrap_or_else(|| self.last_frame.elapsed().as_secs_f32());
+		self.last_frame = Instant::now();
+		self.time += dt as f64;
+		self.dt = dt;
+		dt
+	}
+
+	/// Starts the next frame's timestep from now, e.g. so time spent
+	/// minimized doesn't count as one long frame.
+	pub fn restart_frame(&mut self)
+	{
+		self.last_frame = Instant::now();
+	}
+
+	/// Seconds since startup, as of the last tick.
+	pub fn time(&self) -> f32
+	{
+		self.time as f32
+	}
+
+	/// Timestep of the frame being rendered, as of the last tick.
+	pub fn dt(&self) -> f32
+	{
+		self.dt
+	}
+}
@@ -0,0 +1,600 @@
+// Scene composition
+//
+// The scene is rendered into offscreen targets which a fullscreen pass then
+// draws into the swapchain image. Normally that's just the resolved scene
+// color, but any intermediate target can be inspected instead, or all of
+// them at once in a grid.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator;
+use crate::dynamic_rendering;
+use crate::headless;
+use crate::camera::{Camera, Projection};
+use crate::overlay::Overlay;
+use crate::post_process::{PostProcessChain, MAX_EFFECTS};
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+use crate::samplers::{common_sampler, CommonSampler};
+use crate::stereo;
+use crate::{create_image, create_image_view, create_shader_module, AppData};
+
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 2.0;
+pub const RENDER_SCALE_STEP: f32 = 0.125;
+pub const DEFAULT_SHARPNESS: f32 = 0.5;
+
+/// The scene is rendered in HDR so emissive surfaces keep their intensity
+/// through to the bloom pass, the composite pass brings it back down.
+pub const SCENE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Size of the scene targets for a given swapchain extent.
+pub fn scaled_extent(extent: vk::Extent2D, scale: f32, max_dimension: u32) -> vk::Extent2D
+{
+	vk::Extent2D {
+		width: ((extent.width as f32 * scale).round() as u32).clamp(1, max_dimension),
+		height: ((extent.height as f32 * scale).round() as u32).clamp(1, max_dimension),
+	}
+}
+
+/// Renders above window resolution and filters down in the composite pass.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Supersampling
+{
+	#[default]
+	Off,
+	X1_5,
+	X2,
+}
+
+impl Supersampling
+{
+	pub fn next(self) -> Self
+	{
+		match self
+		{
+			Supersampling::Off => Supersampling::X1_5,
+			Supersampling::X1_5 => Supersampling::X2,
+			Supersampling::X2 => Supersampling::Off,
+		}
+	}
+
+	pub fn render_scale(self) -> f32
+	{
+		match self
+		{
+			Supersampling::Off => 1.0,
+			Supersampling::X1_5 => 1.5,
+			Supersampling::X2 => 2.0,
+		}
+	}
+}
+
+/// Which intermediate render target the composite pass shows.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum InspectTarget
+{
+	#[default]
+	Final,
+	Depth,
+	/// Scene is drawn with the additive overdraw pipeline and shown as a heatmap.
+	Overdraw,
+	/// Scene with the fluid simulation's smoke over it, the simulation only
+	/// runs while this is selected.
+	Fluid,
+	Grid,
+}
+
+impl InspectTarget
+{
+	pub fn next(self) -> Self
+	{
+		match self
+		{
+			InspectTarget::Final => InspectTarget::Depth,
+			InspectTarget::Depth => InspectTarget::Overdraw,
+			InspectTarget::Overdraw => InspectTarget::Fluid,
+			InspectTarget::Fluid => InspectTarget::Grid,
+			InspectTarget::Grid => InspectTarget::Final,
+		}
+	}
+
+	/// Index of the target in `composite.frag`.
+	fn shader_index(self) -> i32
+	{
+		match self
+		{
+			InspectTarget::Final => 0,
+			InspectTarget::Depth => 1,
+			InspectTarget::Overdraw => 2,
+			InspectTarget::Fluid => 3,
+			InspectTarget::Grid => -1,
+		}
+	}
+}
+
+/// Resolved scene color, sampled by the composite pass.
+/// Sized by the render scale, the composite pass upsamples it to the window.
+pub unsafe fn create_scene_objects(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let (scene_image, scene_image_memory) = create_image(
+		instance,
+		device,
+		data,
+		data.render_extent.width,
+		data.render_extent.height,
+		1,
+		vk::SampleCountFlags::_1,
+		SCENE_FORMAT,
+		vk::ImageTiling::OPTIMAL,
+		// copied out for transmissive materials to refract
+		vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL,
+	)?;
+
+	data.scene_image = scene_image;
+	data.scene_image_memory = scene_image_memory;
+
+	data.scene_image_view = create_image_view(
+		device,
+		data.scene_image,
+		SCENE_FORMAT,
+		vk::ImageAspectFlags::COLOR,
+		1,
+	)?;
+
+	Ok(())
+}
+
+/// Not needed with dynamic rendering, which draws into the swapchain image
+/// without one.
+pub unsafe fn create_composite_render_pass(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	if data.dynamic_rendering
+	{
+		return Ok(());
+	}
+
+	// every pixel is overwritten so the previous contents don't matter
+	let color_attachment = vk::AttachmentDescription::builder()
+		.format(data.swapchain_format)
+		.samples(vk::SampleCountFlags::_1)
+		.load_op(vk::AttachmentLoadOp::DONT_CARE)
+		.store_op(vk::AttachmentStoreOp::STORE)
+		.stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+		.stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+		.initial_layout(vk::ImageLayout::UNDEFINED)
+		.final_layout(headless::final_layout(data));
+
+	let color_attachment_ref = vk::AttachmentReference::builder()
+		.attachment(0)
+		.layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+	let color_attachments = &[color_attachment_ref];
+	let subpass = vk::SubpassDescription::builder()
+		.pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+		.color_attachments(color_attachments);
+
+	let dependency = vk::SubpassDependency::builder()
+		.src_subpass(vk::SUBPASS_EXTERNAL)
+		.dst_subpass(0)
+		.src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+		.src_access_mask(vk::AccessFlags::empty())
+		.dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+		.dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+	let attachments = &[color_attachment];
+	let subpasses = &[subpass];
+	let dependencies = &[dependency];
+
+	let info = vk::RenderPassCreateInfo::builder()
+		.attachments(attachments)
+		.subpasses(subpasses)
+		.dependencies(dependencies);
+
+	data.composite_render_pass = device.create_render_pass(&info, None)?;
+
+	Ok(())
+}
+
+/// Must match the push constants of `composite.frag`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct CompositePushConstants
+{
+	target: i32,
+	near: f32,
+	far: f32,
+	sharpness: f32,
+	orthographic: i32,
+	exposure: f32,
+	effect_count: i32,
+	effects: [i32; MAX_EFFECTS],
+	effect_parameters: [f32; MAX_EFFECTS],
+	stereo: i32,
+}
+
+pub unsafe fn create_composite_pipeline(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let linear = &[common_sampler(data, CommonSampler::LinearClamp)];
+	// depths can't be blended meaningfully, and not every format can be filtered
+	let point = &[common_sampler(data, CommonSampler::PointClamp)];
+
+	let color_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+		.immutable_samplers(linear);
+
+	let depth_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(1)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+		.immutable_samplers(point);
+
+	let fluid_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(2)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+		.immutable_samplers(linear);
+
+	let bloom_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(3)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+		.immutable_samplers(linear);
+
+	let left_eye_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(4)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+		.immutable_samplers(linear);
+
+	let bindings = &[color_binding, depth_binding, fluid_binding, bloom_binding, left_eye_binding];
+	let info = vk::DescriptorSetLayoutCreateInfo::builder()
+		.bindings(bindings);
+
+	data.composite_descriptor_set_layout = data.descriptors.layout(device, &info)?;
+
+	let vert = include_bytes!(concat!(env!("OUT_DIR"), "/composite_vert.spv"));
+	let frag = include_bytes!(concat!(env!("OUT_DIR"), "/composite_frag.spv"));
+
+	let vert_sm = create_shader_module(device, vert)?;
+	let frag_sm = create_shader_module(device, frag)?;
+
+	let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::VERTEX)
+		.module(vert_sm)
+		.name(b"main\0");
+
+	let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::FRAGMENT)
+		.module(frag_sm)
+		.name(b"main\0");
+
+	let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+	let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+		.topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+		.primitive_restart_enable(false);
+
+	let viewport = vk::Viewport::builder()
+		.x(0.0)
+		.y(0.0)
+		.width(data.swapchain_extent.width as f32)
+		.height(data.swapchain_extent.height as f32)
+		.min_depth(0.0)
+		.max_depth(1.0);
+
+	let scissor = vk::Rect2D::builder()
+		.offset(vk::Offset2D { x: 0, y: 0 })
+		.extent(data.swapchain_extent);
+
+	let viewports = &[viewport];
+	let scissors = &[scissor];
+	let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+		.viewports(viewports)
+		.scissors(scissors);
+
+	let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+		.depth_clamp_enable(false)
+		.rasterizer_discard_enable(false)
+		.polygon_mode(vk::PolygonMode::FILL)
+		.line_width(1.0)
+		.cull_mode(vk::CullModeFlags::NONE)
+		.front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+		.depth_bias_enable(false);
+
+	let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+		.sample_shading_enable(false)
+		.rasterization_samples(vk::SampleCountFlags::_1);
+
+	let attachment = vk::PipelineColorBlendAttachmentState::builder()
+		.color_write_mask(vk::ColorComponentFlags::all())
+		.blend_enable(false);
+	let attachments = &[attachment];
+	let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+		.logic_op_enable(false)
+		.attachments(attachments);
+
+	let push_constant_range = push_constant_range::<CompositePushConstants>(vk::ShaderStageFlags::FRAGMENT);
+
+	let set_layouts = &[data.composite_descriptor_set_layout];
+	let push_constant_ranges = &[push_constant_range];
+	let layout_info = vk::PipelineLayoutCreateInfo::builder()
+		.set_layouts(set_layouts)
+		.push_constant_ranges(push_constant_ranges);
+
+	data.composite_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+	// with dynamic rendering it's made for the swapchain's format instead of
+	// the render pass, which is left null
+	let color_formats = &[data.swapchain_format];
+	let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+		.color_attachment_formats(color_formats);
+
+	let stages = &[vert_stage, frag_stage];
+	let mut info = vk::GraphicsPipelineCreateInfo::builder()
+		.stages(stages)
+		.vertex_input_state(&vertex_input_state)
+		.input_assembly_state(&input_assembly_state)
+		.viewport_state(&viewport_state)
+		.rasterization_state(&rasterization_state)
+		.multisample_state(&multisample_state)
+		.color_blend_state(&color_blend_state)
+		.layout(data.composite_pipeline_layout)
+		.render_pass(data.composite_render_pass)
+		.subpass(0);
+
+	if data.dynamic_rendering
+	{
+		info = info.push_next(&mut rendering_info);
+	}
+
+	data.composite_pipeline = device.create_graphics_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(vert_sm, None);
+	device.destroy_shader_module(frag_sm, None);
+
+	Ok(())
+}
+
+/// One framebuffer per swapchain image for the composite pass, or none with
+/// dynamic rendering.
+pub unsafe fn create_composite_framebuffers(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	if data.dynamic_rendering
+	{
+		data.framebuffers.clear();
+		return Ok(());
+	}
+
+	data.framebuffers = data.swapchain_image_views
+		.iter()
+		.map(|image_view|
+			{
+				let attachments = &[*image_view];
+				let info = vk::FramebufferCreateInfo::builder()
+					.render_pass(data.composite_render_pass)
+					.attachments(attachments)
+					.width(data.swapchain_extent.width)
+					.height(data.swapchain_extent.height)
+					.layers(1);
+				device.create_framebuffer(&info, None)
+			})
+		.collect::<Result<Vec<_>, _>>()?;
+
+	Ok(())
+}
+
+/// Allocates the frame's set from its transient descriptors and points it
+/// at the scene targets, so it always refers to the current ones.
+pub unsafe fn write_composite_descriptor_set(
+	device: &Device,
+	data: &mut AppData,
+	frame: usize,
+	) -> Result<vk::DescriptorSet>
+{
+	let set = data.descriptors.allocate_transient(device, frame, &[data.composite_descriptor_set_layout])?[0];
+
+	let color_info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(data.scene_image_view);
+
+	let depth_info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+		.image_view(data.depth_sample_view);
+
+	// the simulation keeps its images in GENERAL
+	let fluid_info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::GENERAL)
+		.image_view(data.fluid_image_views[0]);
+
+	// so is the bloom chain, only its first mip is shown
+	let bloom_info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::GENERAL)
+		.image_view(data.bloom_mip_views[0]);
+
+	let left_eye_info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(stereo::left_eye_view(data));
+
+	let color_image_info = &[color_info];
+	let color_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(0)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(color_image_info);
+
+	let depth_image_info = &[depth_info];
+	let depth_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(1)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(depth_image_info);
+
+	let fluid_image_info = &[fluid_info];
+	let fluid_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(2)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(fluid_image_info);
+
+	let bloom_image_info = &[bloom_info];
+	let bloom_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(3)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(bloom_image_info);
+
+	let left_eye_image_info = &[left_eye_info];
+	let left_eye_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(4)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(left_eye_image_info);
+
+	device.update_descriptor_sets(
+		&[color_write, depth_write, fluid_write, bloom_write, left_eye_write],
+		&[] as &[vk::CopyDescriptorSet]
+	);
+
+	Ok(set)
+}
+
+/// Records the composite pass into the swapchain image, through its
+/// framebuffer or with dynamic rendering, and the overlay over it.
+pub unsafe fn record_composite_pass(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	descriptor_set: vk::DescriptorSet,
+	image_index: usize,
+	target: InspectTarget,
+	camera: &Camera,
+	post_process: &PostProcessChain,
+	exposure: f32,
+	overlay: Option<&Overlay>,
+	)
+{
+	if data.dynamic_rendering
+	{
+		dynamic_rendering::begin_swapchain_rendering(
+			device,
+			command_buffer,
+			data.swapchain_images[image_index],
+			data.swapchain_image_views[image_index],
+			data.swapchain_extent,
+		);
+	}
+	else
+	{
+		let render_area = vk::Rect2D::builder()
+			.offset(vk::Offset2D::default())
+			.extent(data.swapchain_extent);
+
+		let info = vk::RenderPassBeginInfo::builder()
+			.render_pass(data.composite_render_pass)
+			.framebuffer(data.framebuffers[image_index])
+			.render_area(render_area);
+
+		device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+	}
+
+	device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.composite_pipeline);
+	device.cmd_bind_descriptor_sets(
+		command_buffer,
+		vk::PipelineBindPoint::GRAPHICS,
+		data.composite_pipeline_layout,
+		0,
+		&[descriptor_set],
+		&[]);
+
+	// there's nothing to sharpen when the scene isn't being upsampled
+	let sharpness = if data.render_scale < 1.0 { data.sharpness } else { 0.0 };
+
+	let orthographic = (camera.projection == Projection::Orthographic) as i32;
+	let (effect_count, effects, effect_parameters) = post_process.encode();
+
+	let push_constants = CompositePushConstants {
+		target: target.shader_index(),
+		near: camera.near,
+		far: camera.far,
+		sharpness,
+		orthographic,
+		exposure,
+		effect_count,
+		effects,
+		effect_parameters,
+		stereo: data.stereo.shader_index(),
+	};
+
+	cmd_push_constants(
+		device,
+		command_buffer,
+		data.composite_pipeline_layout,
+		vk::ShaderStageFlags::FRAGMENT,
+		&push_constants,
+	);
+
+	device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+	if let Some(overlay) = overlay
+	{
+		overlay.record(device, command_buffer, data.swapchain_extent);
+	}
+
+	if data.dynamic_rendering
+	{
+		dynamic_rendering::end_swapchain_rendering(
+			device,
+			command_buffer,
+			data.swapchain_images[image_index],
+			headless::final_layout(data),
+		);
+	}
+	else
+	{
+		device.cmd_end_render_pass(command_buffer);
+	}
+}
+
+pub unsafe fn destroy_composite_objects(device: &Device, data: &AppData)
+{
+	data.framebuffers
+		.iter()
+		.for_each(|fb| device.destroy_framebuffer(*fb, None));
+	device.destroy_pipeline(data.composite_pipeline, None);
+	device.destroy_pipeline_layout(data.composite_pipeline_layout, None);
+	device.destroy_render_pass(data.composite_render_pass, None);
+	device.destroy_image_view(data.scene_image_view, None);
+	device.destroy_image(data.scene_image, None);
+	allocator::free(device, data.scene_image_memory);
+}
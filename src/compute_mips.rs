@@ -0,0 +1,330 @@
+// Compute mipmap generation
+//
+// An alternative to blitting each mip from the one above, for formats that
+// can't be linearly blitted and for long chains where the blits' implicit
+// layout juggling costs more than a dispatch. Each mip is one dispatch that
+// reads the one above it through a sampler and writes through a storage
+// view, so the image needs STORAGE and SAMPLED usage and a format that's a
+// storage image on this device.
+//
+// The chain is still a dispatch per mip with a barrier between each. A
+// single pass that reduces several mips per dispatch through subgroup ops
+// would need the subgroup extended types and an atomic counter per image,
+// which isn't worth it at the chain lengths here.
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::samplers::{common_sampler, CommonSampler};
+use crate::{begin_single_time_commands, create_shader_module, end_single_time_commands, AppData};
+
+/// Formats with a `mipmap.comp` permutation, in the order of the pipelines.
+const FORMATS: [vk::Format; 3] = [
+	vk::Format::R8G8B8A8_UNORM,
+	vk::Format::R16G16B16A16_SFLOAT,
+	vk::Format::R32G32B32A32_SFLOAT,
+];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MipGeneration
+{
+	/// `cmd_blit_image` from each mip to the next, falling back to compute
+	/// when the format can't be linearly blitted.
+	#[default]
+	Blit,
+	/// Compute whenever the format supports it.
+	Compute,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ComputeMips
+{
+	descriptor_set_layout: vk::DescriptorSetLayout,
+	pipeline_layout: vk::PipelineLayout,
+	/// One per entry of `FORMATS`.
+	pipelines: Vec<vk::Pipeline>,
+}
+
+impl ComputeMips
+{
+	pub unsafe fn create(device: &Device, data: &AppData) -> Result<Self>
+	{
+		let source_samplers = &[common_sampler(data, CommonSampler::LinearClamp)];
+		let source_binding = vk::DescriptorSetLayoutBinding::builder()
+			.binding(0)
+			.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+			.descriptor_count(1)
+			.stage_flags(vk::ShaderStageFlags::COMPUTE)
+			.immutable_samplers(source_samplers);
+
+		let destination_binding = vk::DescriptorSetLayoutBinding::builder()
+			.binding(1)
+			.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+			.descriptor_count(1)
+			.stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+		let bindings = &[source_binding, destination_binding];
+		let info = vk::DescriptorSetLayoutCreateInfo::builder()
+			.bindings(bindings);
+
+		let descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+		let set_layouts = &[descriptor_set_layout];
+		let info = vk::PipelineLayoutCreateInfo::builder()
+			.set_layouts(set_layouts);
+
+		let pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+		let shaders: [&[u8]; 3] = [
+			include_bytes!(concat!(env!("OUT_DIR"), "/mipmap_rgba8_comp.spv")),
+			include_bytes!(concat!(env!("OUT_DIR"), "/mipmap_rgba16f_comp.spv")),
+			include_bytes!(concat!(env!("OUT_DIR"), "/mipmap_rgba32f_comp.spv")),
+		];
+
+		let pipelines = shaders
+			.iter()
+			.map(|comp|
+				{
+					let comp_sm = create_shader_module(device, comp)?;
+
+					let stage = vk::PipelineShaderStageCreateInfo::builder()
+						.stage(vk::ShaderStageFlags::COMPUTE)
+						.module(comp_sm)
+						.name(b"main\0");
+
+					let info = vk::ComputePipelineCreateInfo::builder()
+						.stage(stage)
+						.layout(pipeline_layout);
+
+					let pipeline = device.create_compute_pipelines(data.pipeline_cache, &[info], None)?.0[0];
+					device.destroy_shader_module(comp_sm, None);
+					Ok(pipeline)
+				})
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(Self { descriptor_set_layout, pipeline_layout, pipelines })
+	}
+
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		self.pipelines.iter().for_each(|p| device.destroy_pipeline(*p, None));
+		device.destroy_pipeline_layout(self.pipeline_layout, None);
+		device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+	}
+
+	/// Whether images of the format can have their mips generated here.
+	pub unsafe fn supports(instance: &Instance, data: &AppData, format: vk::Format) -> bool
+	{
+		let features = instance
+			.get_physical_device_format_properties(data.physical_device, format)
+			.optimal_tiling_features;
+
+		FORMATS.contains(&format)
+			&& features.contains(vk::FormatFeatureFlags::STORAGE_IMAGE | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+	}
+
+	/// Usage an image of the format needs for its mips to be generated, by
+	/// blits or here.
+	pub unsafe fn usage(instance: &Instance, data: &AppData, format: vk::Format) -> vk::ImageUsageFlags
+	{
+		if Self::supports(instance, data, format)
+		{
+			vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::STORAGE
+		}
+		else
+		{
+			vk::ImageUsageFlags::TRANSFER_SRC
+		}
+	}
+
+	/// Fills every mip below the first, which must have been written and
+	/// be in `TRANSFER_DST_OPTIMAL` like the rest. Leaves the whole image in
+	/// `SHADER_READ_ONLY_OPTIMAL`, as `generate_mipmaps` does.
+	pub unsafe fn generate(
+		&self,
+		device: &Device,
+		data: &AppData,
+		image: vk::Image,
+		format: vk::Format,
+		width: u32,
+		height: u32,
+		mip_levels: u32,
+		) -> Result<()>
+	{
+		let pipeline = FORMATS
+			.iter()
+			.position(|f| *f == format)
+			.map(|i| self.pipelines[i])
+			.ok_or_else(|| anyhow!("No compute mipmap shader for {:?}", format))?;
+
+		let views = (0..mip_levels)
+			.map(|mip|
+				{
+					let info = vk::ImageViewCreateInfo::builder()
+						.image(image)
+						.view_type(vk::ImageViewType::_2D)
+						.format(format)
+						.subresource_range(mip_range(mip, 1));
+					device.create_image_view(&info, None)
+				})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let dispatches = mip_levels - 1;
+
+		let sampler_size = vk::DescriptorPoolSize::builder()
+			.type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+			.descriptor_count(dispatches);
+
+		let storage_size = vk::DescriptorPoolSize::builder()
+			.type_(vk::DescriptorType::STORAGE_IMAGE)
+			.descriptor_count(dispatches);
+
+		let pool_sizes = &[sampler_size, storage_size];
+		let info = vk::DescriptorPoolCreateInfo::builder()
+			.pool_sizes(pool_sizes)
+			.max_sets(dispatches);
+
+		let descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+		let layouts = vec![self.descriptor_set_layout; dispatches as usize];
+		let info = vk::DescriptorSetAllocateInfo::builder()
+			.descriptor_pool(descriptor_pool)
+			.set_layouts(&layouts);
+
+		let sets = device.allocate_descriptor_sets(&info)?;
+
+		for (mip, set) in (1..mip_levels).zip(&sets)
+		{
+			let source_info = &[vk::DescriptorImageInfo::builder()
+				.image_layout(vk::ImageLayout::GENERAL)
+				.image_view(views[mip as usize - 1])
+				.build()];
+
+			let destination_info = &[vk::DescriptorImageInfo::builder()
+				.image_layout(vk::ImageLayout::GENERAL)
+				.image_view(views[mip as usize])
+				.build()];
+
+			let source_write = vk::WriteDescriptorSet::builder()
+				.dst_set(*set)
+				.dst_binding(0)
+				.dst_array_element(0)
+				.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+				.image_info(source_info);
+
+			let destination_write = vk::WriteDescriptorSet::builder()
+				.dst_set(*set)
+				.dst_binding(1)
+				.dst_array_element(0)
+				.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+				.image_info(destination_info);
+
+			device.update_descriptor_sets(&[source_write, destination_write], &[] as &[vk::CopyDescriptorSet]);
+		}
+
+		let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+
+		// the first mip's copy has to land before it's read, the rest are
+		// about to be overwritten
+		barrier(
+			device,
+			command_buffer,
+			image,
+			mip_range(0, mip_levels),
+			(vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::GENERAL),
+			(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+			(vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE),
+		);
+
+		device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+
+		for (mip, set) in (1..mip_levels).zip(&sets)
+		{
+			device.cmd_bind_descriptor_sets(
+				command_buffer,
+				vk::PipelineBindPoint::COMPUTE,
+				self.pipeline_layout,
+				0,
+				&[*set],
+				&[]);
+
+			let mip_width = (width >> mip).max(1);
+			let mip_height = (height >> mip).max(1);
+			device.cmd_dispatch(command_buffer, (mip_width + 7) / 8, (mip_height + 7) / 8, 1);
+
+			// the next dispatch reads what this one wrote
+			barrier(
+				device,
+				command_buffer,
+				image,
+				mip_range(mip, 1),
+				(vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL),
+				(vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE),
+				(vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_READ),
+			);
+		}
+
+		barrier(
+			device,
+			command_buffer,
+			image,
+			mip_range(0, mip_levels),
+			(vk::ImageLayout::GENERAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+			(vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE),
+			(vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+		);
+
+		end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)?;
+		data.layouts.transition(image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+		// a batch would otherwise still be recording with the views and sets
+		data.immediate.flush(device)?;
+
+		device.destroy_descriptor_pool(descriptor_pool, None);
+		views.iter().for_each(|v| device.destroy_image_view(*v, None));
+
+		Ok(())
+	}
+}
+
+fn mip_range(base_mip: u32, mips: u32) -> vk::ImageSubresourceRange
+{
+	vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(base_mip)
+		.level_count(mips)
+		.base_array_layer(0)
+		.layer_count(1)
+		.build()
+}
+
+unsafe fn barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	range: vk::ImageSubresourceRange,
+	(old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+	(src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	(dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	)
+{
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(old_layout)
+		.new_layout(new_layout)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(range)
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
@@ -0,0 +1,211 @@
+// Configuration file
+//
+// Startup settings are read from `config.toml` in the working directory,
+// e.g.
+//
+// width = 1920
+// height = 1080
+// vsync = true
+// msaa = 4
+// fov = 60.0
+// fullscreen_key = "F11"
+// validation = true
+// live_reload = true
+//
+// [assets]
+// material = "media/viking_room.mat.ron"
+// model = "media/viking_room.obj"
+// camera_path = "media/camera_path.ron"
+// scene = "media/scene.ron"
+// demo = "media/demo.ron"
+//
+// [jobs]
+// culling = 2
+//
+// Anything left out keeps its default, and the file is written out with
+// every default if it doesn't exist. Command line options win over the
+// file. With `live_reload` the file is polled while the app runs: the field
+// of view, MSAA and the fullscreen key apply straight away, everything else
+// is read once at startup and only logged as needing a restart.
+
+use anyhow::{anyhow, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use winit::event::VirtualKeyCode;
+
+use crate::jobs::WorkerCounts;
+
+pub const CONFIG_PATH: &str = "config.toml";
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config
+{
+	/// Window size in logical pixels.
+	pub width: u32,
+	pub height: u32,
+	/// False presents immediately, tearing included. True leaves the pick to
+	/// the renderer, MAILBOX if there is one and FIFO otherwise.
+	pub vsync: bool,
+	/// MSAA sample count, 1, 2, 4 or 8, clamped to what the device supports.
+	/// The device's most when left out. U cycles through them at runtime.
+	pub msaa: Option<u32>,
+	/// Vertical field of view in degrees.
+	pub fov: f32,
+	/// Cycles windowed, borderless and exclusive fullscreen, by winit's
+	/// name for the key.
+	pub fullscreen_key: VirtualKeyCode,
+	/// Enables the validation layer in debug builds, release builds never
+	/// have it.
+	pub validation: bool,
+	/// Polls the file for changes while the app runs.
+	pub live_reload: bool,
+	pub assets: AssetPaths,
+	/// Worker threads per job system.
+	pub jobs: WorkerCounts,
+}
+
+impl Default for Config
+{
+	fn default() -> Self
+	{
+		Self {
+			width: 1024,
+			height: 768,
+			vsync: true,
+			msaa: None,
+			fov: 45.0,
+			fullscreen_key: VirtualKeyCode::F11,
+			validation: true,
+			live_reload: true,
+			assets: AssetPaths::default(),
+			jobs: WorkerCounts::default(),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AssetPaths
+{
+	pub material: PathBuf,
+	pub model: PathBuf,
+	pub camera_path: PathBuf,
+	pub scene: PathBuf,
+	/// The sequence Y plays.
+	pub demo: PathBuf,
+}
+
+impl Default for AssetPaths
+{
+	fn default() -> Self
+	{
+		Self {
+			material: "media/viking_room.mat.ron".into(),
+			model: "media/viking_room.obj".into(),
+			camera_path: "media/camera_path.ron".into(),
+			scene: "media/scene.ron".into(),
+			demo: "media/demo.ron".into(),
+		}
+	}
+}
+
+impl Config
+{
+	pub fn load(path: &Path) -> Result<Self>
+	{
+		let contents = fs::read_to_string(path)?;
+		toml::from_str(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))
+	}
+
+	/// Loads the file, writing the defaults out first if it doesn't exist.
+	/// Falls back to the defaults if it can't be read, leaving it alone so
+	/// it can be fixed.
+	pub fn load_or_create(path: &Path) -> Self
+	{
+		if !path.exists()
+		{
+			let config = Self::default();
+			match toml::to_string_pretty(&config).map_err(|e| anyhow!(e)).and_then(|s| Ok(fs::write(path, s)?))
+			{
+				Ok(()) => info!("Wrote default config to {}", path.display()),
+				Err(e) => warn!("Failed to write default config to {}: {}", path.display(), e),
+			}
+			return config;
+		}
+
+		Self::load(path).unwrap_or_else(|e|
+			{
+				warn!("Failed to load config ({}), using defaults", e);
+				Self::default()
+			})
+	}
+
+	/// Logs the settings that differ from `self` but only apply at startup.
+	pub fn warn_restart_needed(&self, new: &Config)
+	{
+		let changed = [
+			("width", self.width != new.width),
+			("height", self.height != new.height),
+			("vsync", self.vsync != new.vsync),
+			("validation", self.validation != new.validation),
+			("assets", self.assets != new.assets),
+			("jobs", self.jobs != new.jobs),
+		];
+		for (name, _) in changed.iter().filter(|(_, changed)| *changed)
+		{
+			warn!("Config {} changed, it applies after a restart", name);
+		}
+	}
+}
+
+/// Tracks the config file so it can be reloaded when it changes.
+#[derive(Clone, Debug)]
+pub struct ConfigWatcher
+{
+	path: PathBuf,
+	modified: Option<SystemTime>,
+	last_poll: Instant,
+}
+
+impl ConfigWatcher
+{
+	pub fn new(path: impl Into<PathBuf>) -> Self
+	{
+		let path = path.into();
+		let modified = modified_time(&path);
+		Self { path, modified, last_poll: Instant::now() }
+	}
+
+	/// Returns the freshly parsed config if the file changed since the last
+	/// poll. Polling is throttled so this is cheap to call every frame.
+	pub fn poll(&mut self) -> Option<Result<Config>>
+	{
+		if self.last_poll.elapsed() < POLL_INTERVAL
+		{
+			return None;
+		}
+
+		self.last_poll = Instant::now();
+
+		let modified = modified_time(&self.path);
+		if modified == self.modified
+		{
+			return None;
+		}
+
+		self.modified = modified;
+		Some(Config::load(&self.path))
+	}
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime>
+{
+	fs::metadata(path).and_then(|m| m.modified()).ok()
+}
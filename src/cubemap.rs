@@ -0,0 +1,603 @@
+// Cubemap captures of the scene
+//
+// Renders the models into a small offscreen target once per cube face and
+// reads the result back, for baking lighting data. Faces follow
+// Vulkan's cube map convention so captures can be uploaded as cube images
+// as they are.
+//
+// The captured views use the regular scene shaders, but with the view and
+// projection folded into the model push constant so all six faces can be
+// recorded into one command buffer. Bakes light the models with emission
+// alone, the live scene can be captured too, lit like the frame, and
+// unwrapped into an equirectangular image for 360° screenshots.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use nalgebra_glm as glm;
+
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use crate::allocator::{self, Allocation};
+use crate::push_constants::cmd_push_constants;
+use crate::readback::{self, ReadbackBuffer};
+use crate::{
+	begin_single_time_commands,
+	bind_vertex_streams,
+	create_image,
+	create_image_view,
+	create_shader_module,
+	emissive_uniform,
+	end_single_time_commands,
+	get_depth_format,
+	primitive_draws,
+	uniform_offset,
+	write_uniforms,
+	light_probes::ShIrradiance,
+	AppData,
+	ObjectData,
+	UniformBufferObject,
+	MAX_MODELS,
+};
+
+pub const CAPTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+/// Forward and up vectors for +X, -X, +Y, -Y, +Z and -Z.
+const FACES: [([f32; 3], [f32; 3]); 6] = [
+	([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+	([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+	([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+	([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+	([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+	([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+fn face_basis(face: usize) -> (glm::Vec3, glm::Vec3, glm::Vec3)
+{
+	let (forward, up) = FACES[face];
+	let forward = glm::make_vec3(&forward);
+	let right = glm::cross(&forward, &glm::make_vec3(&up));
+	let up = glm::cross(&right, &forward);
+	(forward, right, up)
+}
+
+/// Unnormalized direction through a point on a face, with `s` and `t`
+/// running from -1 to 1 left to right and top to bottom.
+pub fn texel_direction(face: usize, s: f32, t: f32) -> glm::Vec3
+{
+	let (forward, right, up) = face_basis(face);
+	forward + right * s + up * t
+}
+
+/// View-projection for a face. Unlike the camera's projection y isn't
+/// flipped, which is what makes rows run down the face.
+pub fn face_view_proj(face: usize, eye: &glm::Vec3, near: f32, far: f32) -> glm::Mat4
+{
+	let (forward, _, up) = face_basis(face);
+	let view = glm::look_at(eye, &(eye + forward), &up);
+	let proj = glm::perspective_rh_zo(1.0, FRAC_PI_2, near, far);
+	proj * view
+}
+
+/// Linear colors of the six faces, row by row.
+pub type Faces = [Vec<glm::Vec3>; 6];
+
+/// What the bakes light the models with: only their own emission.
+fn baking_uniforms(data: &AppData) -> UniformBufferObject
+{
+	UniformBufferObject {
+		view: glm::identity(),
+		proj: glm::identity(),
+		debug_view: 0,
+		time: 0.0,
+		_padding: [0.0; 2],
+		irradiance: [ShIrradiance::default(); MAX_MODELS],
+		reflection_probes: Default::default(),
+		camera_position: [0.0; 4],
+		reflection_probe_count: 0,
+		reflectivity: 0.0,
+		_padding2: [0.0; 2],
+		// emissive surfaces light the probes too
+		emissive: emissive_uniform(data),
+		sun_direction: [0.0; 4],
+		height_scale: 0.0,
+		clearcoat: data.material.clearcoat,
+		transmission: 0.0,
+		ior: data.material.ior,
+		roughness: data.material.roughness,
+		specular: data.material.specular,
+		anisotropy_strength: data.material.anisotropy_strength,
+		anisotropy_rotation: data.material.anisotropy_rotation,
+		double_sided: data.material.double_sided as u32,
+		mirror: 0.0,
+		light_count: 0,
+		_padding3: 0.0,
+		lights: Default::default(),
+		shadow_views: Default::default(),
+	}
+}
+
+/// Unwraps the faces into a `width` by `width / 2` equirectangular image,
+/// +Z up and +X in the middle, for panorama viewers.
+pub fn equirectangular(faces: &Faces, size: u32, width: u32) -> image::Rgba32FImage
+{
+	let height = (width / 2).max(1);
+
+	image::Rgba32FImage::from_fn(width, height, |x, y|
+		{
+			// longitude turns right as x grows, like looking around from inside
+			let longitude = ((x as f32 + 0.5) / width as f32 - 0.5) * 2.0 * PI;
+			let latitude = (0.5 - (y as f32 + 0.5) / height as f32) * PI;
+			let direction = glm::vec3(
+				latitude.cos() * longitude.cos(),
+				-latitude.cos() * longitude.sin(),
+				latitude.sin(),
+			);
+
+			// the face the direction leaves the cube through, and where
+			let face = (0..6)
+				.max_by(|a, b| direction.dot(&face_basis(*a).0).total_cmp(&direction.dot(&face_basis(*b).0)))
+				.unwrap_or(0);
+			let (forward, right, up) = face_basis(face);
+			let depth = direction.dot(&forward);
+			let s = direction.dot(&right) / depth;
+			let t = direction.dot(&up) / depth;
+
+			let texel = |c: f32| (((c + 1.0) / 2.0 * size as f32) as u32).min(size - 1);
+			let color = faces[face][(texel(t) * size + texel(s)) as usize];
+			image::Rgba([color.x, color.y, color.z, 1.0])
+		})
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CubemapCapture
+{
+	size: u32,
+	render_pass: vk::RenderPass,
+	pipeline: vk::Pipeline,
+	color_image: vk::Image,
+	color_image_memory: Allocation,
+	color_image_view: vk::ImageView,
+	depth_image: vk::Image,
+	depth_image_memory: Allocation,
+	depth_image_view: vk::ImageView,
+	framebuffer: vk::Framebuffer,
+	readback: ReadbackBuffer,
+}
+
+impl CubemapCapture
+{
+	pub unsafe fn create(
+		instance: &Instance,
+		device: &Device,
+		data: &AppData,
+		size: u32,
+		) -> Result<Self>
+	{
+		let mut capture = Self { size, ..Default::default() };
+
+		capture.create_render_pass(instance, device, data)?;
+		capture.create_pipeline(device, data)?;
+
+		let (color_image, color_image_memory) = create_image(
+			instance,
+			device,
+			data,
+			size,
+			size,
+			1,
+			vk::SampleCountFlags::_1,
+			CAPTURE_FORMAT,
+			vk::ImageTiling::OPTIMAL,
+			vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+			vk::MemoryPropertyFlags::DEVICE_LOCAL,
+		)?;
+
+		capture.color_image = color_image;
+		capture.color_image_memory = color_image_memory;
+		capture.color_image_view = create_image_view(device, color_image, CAPTURE_FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+
+		let depth_format = get_depth_format(instance, data)?;
+		let (depth_image, depth_image_memory) = create_image(
+			instance,
+			device,
+			data,
+			size,
+			size,
+			1,
+			vk::SampleCountFlags::_1,
+			depth_format,
+			vk::ImageTiling::OPTIMAL,
+			vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+			vk::MemoryPropertyFlags::DEVICE_LOCAL,
+		)?;
+
+		capture.depth_image = depth_image;
+		capture.depth_image_memory = depth_image_memory;
+		capture.depth_image_view = create_image_view(device, depth_image, depth_format, vk::ImageAspectFlags::DEPTH, 1)?;
+
+		let attachments = &[capture.color_image_view, capture.depth_image_view];
+		let info = vk::FramebufferCreateInfo::builder()
+			.render_pass(capture.render_pass)
+			.attachments(attachments)
+			.width(size)
+			.height(size)
+			.layers(1);
+
+		capture.framebuffer = device.create_framebuffer(&info, None)?;
+
+		capture.readback = ReadbackBuffer::create(instance, device, data, capture.face_bytes() * 6)?;
+
+		Ok(capture)
+	}
+
+	pub fn size(&self) -> u32
+	{
+		self.size
+	}
+
+	fn face_bytes(&self) -> vk::DeviceSize
+	{
+		(self.size * self.size * 4) as vk::DeviceSize
+	}
+
+	unsafe fn create_render_pass(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &AppData,
+		) -> Result<()>
+	{
+		let color_attachment = vk::AttachmentDescription::builder()
+			.format(CAPTURE_FORMAT)
+			.samples(vk::SampleCountFlags::_1)
+			.load_op(vk::AttachmentLoadOp::CLEAR)
+			.store_op(vk::AttachmentStoreOp::STORE)
+			.stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+			.stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+			.initial_layout(vk::ImageLayout::UNDEFINED)
+			.final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+		let color_attachment_ref = vk::AttachmentReference::builder()
+			.attachment(0)
+			.layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+		let depth_stencil_attachment = vk::AttachmentDescription::builder()
+			.format(get_depth_format(instance, data)?)
+			.samples(vk::SampleCountFlags::_1)
+			.load_op(vk::AttachmentLoadOp::CLEAR)
+			.store_op(vk::AttachmentStoreOp::DONT_CARE)
+			.stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+			.stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+			.initial_layout(vk::ImageLayout::UNDEFINED)
+			.final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+		let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
+			.attachment(1)
+			.layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+		let color_attachments = &[color_attachment_ref];
+		let subpass = vk::SubpassDescription::builder()
+			.pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+			.color_attachments(color_attachments)
+			.depth_stencil_attachment(&depth_stencil_attachment_ref);
+
+		// the previous face's copy has to finish before it's overwritten
+		let dependency = vk::SubpassDependency::builder()
+			.src_subpass(vk::SUBPASS_EXTERNAL)
+			.dst_subpass(0)
+			.src_stage_mask(vk::PipelineStageFlags::TRANSFER
+				| vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+			.src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+			.dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+				| vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+			.dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+				| vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+
+		let copy_dependency = vk::SubpassDependency::builder()
+			.src_subpass(0)
+			.dst_subpass(vk::SUBPASS_EXTERNAL)
+			.src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+			.src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+			.dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+			.dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+		let attachments = &[color_attachment, depth_stencil_attachment];
+		let subpasses = &[subpass];
+		let dependencies = &[dependency, copy_dependency];
+
+		let info = vk::RenderPassCreateInfo::builder()
+			.attachments(attachments)
+			.subpasses(subpasses)
+			.dependencies(dependencies);
+
+		self.render_pass = device.create_render_pass(&info, None)?;
+
+		Ok(())
+	}
+
+	unsafe fn create_pipeline(
+		&mut self,
+		device: &Device,
+		data: &AppData,
+		) -> Result<()>
+	{
+		let vert = include_bytes!(concat!(env!("OUT_DIR"), "/vert.spv"));
+
+		let vert_sm = create_shader_module(device, vert)?;
+		let frag_sm = create_shader_module(device, data.material.shader.fragment_spirv())?;
+
+		let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+			.stage(vk::ShaderStageFlags::VERTEX)
+			.module(vert_sm)
+			.name(b"main\0");
+
+		let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+			.stage(vk::ShaderStageFlags::FRAGMENT)
+			.module(frag_sm)
+			.name(b"main\0");
+
+		let binding_descriptions = data.vertex_layout.binding_descriptions(data.vertex_streams);
+		let attribute_descriptions = data.vertex_layout.attribute_descriptions(data.vertex_streams);
+		let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+			.vertex_binding_descriptions(&binding_descriptions)
+			.vertex_attribute_descriptions(&attribute_descriptions);
+
+		let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+			.topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+			.primitive_restart_enable(false);
+
+		let viewport = vk::Viewport::builder()
+			.x(0.0)
+			.y(0.0)
+			.width(self.size as f32)
+			.height(self.size as f32)
+			.min_depth(0.0)
+			.max_depth(1.0);
+
+		let scissor = vk::Rect2D::builder()
+			.offset(vk::Offset2D { x: 0, y: 0 })
+			.extent(vk::Extent2D { width: self.size, height: self.size });
+
+		let viewports = &[viewport];
+		let scissors = &[scissor];
+		let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+			.viewports(viewports)
+			.scissors(scissors);
+
+		// without the y flip the winding is mirrored compared to the scene
+		let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+			.depth_clamp_enable(false)
+			.rasterizer_discard_enable(false)
+			.polygon_mode(vk::PolygonMode::FILL)
+			.line_width(1.0)
+			.cull_mode(data.material.cull_mode().flags())
+			.front_face(vk::FrontFace::CLOCKWISE)
+			.depth_bias_enable(false);
+
+		let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+			.sample_shading_enable(false)
+			.rasterization_samples(vk::SampleCountFlags::_1);
+
+		let attachment = vk::PipelineColorBlendAttachmentState::builder()
+			.color_write_mask(vk::ColorComponentFlags::all())
+			.blend_enable(false);
+		let attachments = &[attachment];
+		let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+			.logic_op_enable(false)
+			.attachments(attachments);
+
+		let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+			.depth_test_enable(true)
+			.depth_write_enable(true)
+			.depth_compare_op(vk::CompareOp::LESS)
+			.depth_bounds_test_enable(false)
+			.stencil_test_enable(false);
+
+		let stages = &[vert_stage, frag_stage];
+
+		let info = vk::GraphicsPipelineCreateInfo::builder()
+			.stages(stages)
+			.vertex_input_state(&vertex_input_state)
+			.input_assembly_state(&input_assembly_state)
+			.viewport_state(&viewport_state)
+			.rasterization_state(&rasterization_state)
+			.multisample_state(&multisample_state)
+			.depth_stencil_state(&depth_stencil_state)
+			.color_blend_state(&color_blend_state)
+			.layout(data.pipeline_layout)
+			.render_pass(self.render_pass)
+			.subpass(0);
+
+		self.pipeline = device.create_graphics_pipelines(
+			data.pipeline_cache,
+			&[info],
+			None
+			)?.0[0];
+
+		device.destroy_shader_module(vert_sm, None);
+		device.destroy_shader_module(frag_sm, None);
+
+		Ok(())
+	}
+
+	/// Renders the models around `eye` for baking and waits for the faces to
+	/// be read back. Clobbers the first uniform buffer, so only call this
+	/// between frames with the device idle.
+	pub unsafe fn render(
+		&self,
+		device: &Device,
+		data: &AppData,
+		eye: &glm::Vec3,
+		near: f32,
+		far: f32,
+		models: &[glm::Mat4],
+		) -> Result<()>
+	{
+		self.render_scene(device, data, baking_uniforms(data), eye, near, far, models)
+	}
+
+	/// Like `render`, but lit by `ubo`, e.g. the frame's own uniforms to
+	/// capture the scene as it's currently shown.
+	pub unsafe fn render_scene(
+		&self,
+		device: &Device,
+		data: &AppData,
+		mut ubo: UniformBufferObject,
+		eye: &glm::Vec3,
+		near: f32,
+		far: f32,
+		models: &[glm::Mat4],
+		) -> Result<()>
+	{
+		// each face's view and projection go in the push constants
+		ubo.view = glm::identity();
+		ubo.proj = glm::identity();
+		// the refraction and mirror images only hold the main view
+		ubo.transmission = 0.0;
+		ubo.mirror = 0.0;
+
+		write_uniforms(device, data, 0, &ubo)?;
+
+		let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+
+		let clear_values = &[
+			vk::ClearValue { color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } },
+			vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+		];
+
+		let render_area = vk::Rect2D::builder()
+			.offset(vk::Offset2D::default())
+			.extent(vk::Extent2D { width: self.size, height: self.size });
+
+		for face in 0..6
+		{
+			let view_proj = face_view_proj(face, eye, near, far);
+
+			let info = vk::RenderPassBeginInfo::builder()
+				.render_pass(self.render_pass)
+				.framebuffer(self.framebuffer)
+				.render_area(render_area)
+				.clear_values(clear_values);
+
+			device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+			device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+			bind_vertex_streams(device, data, command_buffer);
+			device.cmd_bind_index_buffer(command_buffer, data.index_buffer, 0, data.index_type);
+
+			let draws = primitive_draws(data);
+			for model in models
+			{
+				// opaque and unlit, whatever the material says
+				let object = ObjectData::new(view_proj * model, 1.0, -1, false);
+				cmd_push_constants(device, command_buffer, data.pipeline_layout, vk::ShaderStageFlags::VERTEX, &object);
+
+				for &(set, first_index, index_count) in &draws
+				{
+					device.cmd_bind_descriptor_sets(
+						command_buffer,
+						vk::PipelineBindPoint::GRAPHICS,
+						data.pipeline_layout,
+						0,
+						&[set],
+						&[uniform_offset(data, 0)]);
+					device.cmd_draw_indexed(command_buffer, index_count, 1, first_index, 0, 0);
+				}
+			}
+
+			device.cmd_end_render_pass(command_buffer);
+			data.layouts.transition(self.color_image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+			let subresource = vk::ImageSubresourceLayers::builder()
+				.aspect_mask(vk::ImageAspectFlags::COLOR)
+				.mip_level(0)
+				.base_array_layer(0)
+				.layer_count(1);
+
+			let region = vk::BufferImageCopy::builder()
+				.buffer_offset(self.face_bytes() * face as vk::DeviceSize)
+				.buffer_row_length(0)
+				.buffer_image_height(0)
+				.image_subresource(subresource)
+				.image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+				.image_extent(vk::Extent3D { width: self.size, height: self.size, depth: 1 });
+
+			data.layouts.expect(self.color_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, "capture readback");
+			device.cmd_copy_image_to_buffer(
+				command_buffer,
+				self.color_image,
+				vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+				self.readback.buffer(),
+				&[region],
+			);
+		}
+
+		readback::record_host_barrier(device, command_buffer);
+
+		end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)
+	}
+
+	/// The last capture's faces converted to floats.
+	pub unsafe fn faces(&self, device: &Device) -> Result<Faces>
+	{
+		let bytes = self.readback.read(device)?;
+
+		let texels = (self.size * self.size) as usize;
+		let faces = [0, 1, 2, 3, 4, 5].map(|face|
+			{
+				bytes[face * texels * 4..(face + 1) * texels * 4]
+					.chunks_exact(4)
+					.map(|texel| glm::vec3(texel[0] as f32, texel[1] as f32, texel[2] as f32) / 255.0)
+					.collect::<Vec<_>>()
+			});
+
+		Ok(faces)
+	}
+
+	/// Records a copy of the last capture into the six layers of a cube
+	/// image in `TRANSFER_DST_OPTIMAL`, which must be the capture's size.
+	pub unsafe fn copy_to_cubemap(
+		&self,
+		device: &Device,
+		command_buffer: vk::CommandBuffer,
+		image: vk::Image,
+		)
+	{
+		let subresource = vk::ImageSubresourceLayers::builder()
+			.aspect_mask(vk::ImageAspectFlags::COLOR)
+			.mip_level(0)
+			.base_array_layer(0)
+			.layer_count(6);
+
+		let region = vk::BufferImageCopy::builder()
+			.buffer_offset(0)
+			.buffer_row_length(0)
+			.buffer_image_height(0)
+			.image_subresource(subresource)
+			.image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+			.image_extent(vk::Extent3D { width: self.size, height: self.size, depth: 1 });
+
+		device.cmd_copy_buffer_to_image(
+			command_buffer,
+			self.readback.buffer(),
+			image,
+			vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+			&[region],
+		);
+	}
+
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		self.readback.destroy(device);
+		device.destroy_framebuffer(self.framebuffer, None);
+		device.destroy_image_view(self.depth_image_view, None);
+		device.destroy_image(self.depth_image, None);
+		allocator::free(device, self.depth_image_memory);
+		device.destroy_image_view(self.color_image_view, None);
+		device.destroy_image(self.color_image, None);
+		allocator::free(device, self.color_image_memory);
+		device.destroy_pipeline(self.pipeline, None);
+		device.destroy_render_pass(self.render_pass, None);
+	}
+}
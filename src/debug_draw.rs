@@ -0,0 +1,392 @@
+// Debug line rendering
+//
+// Systems push world-space lines into `DebugDraw` each frame under a
+// category. Lines for disabled categories are dropped immediately, the rest
+// are uploaded to a host-visible vertex buffer and drawn as a line list on
+// top of the scene.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use nalgebra_glm as glm;
+
+use std::collections::HashSet;
+use std::f32::consts::TAU;
+use std::mem::size_of;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use crate::allocator;
+use crate::{create_buffer, create_shader_module, AppData};
+
+/// Anything past this many vertices in a frame is dropped.
+const MAX_VERTICES: usize = 65536;
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DebugCategory
+{
+	Frustum,
+	LightVolumes,
+	Colliders,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct DebugVertex
+{
+	pos: glm::Vec3,
+	color: glm::Vec3,
+}
+
+impl DebugVertex
+{
+	fn binding_description() -> vk::VertexInputBindingDescription
+	{
+		vk::VertexInputBindingDescription::builder()
+			.binding(0)
+			.stride(size_of::<DebugVertex>() as u32)
+			.input_rate(vk::VertexInputRate::VERTEX)
+			.build()
+	}
+
+	fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2]
+	{
+		let pos = vk::VertexInputAttributeDescription::builder()
+			.binding(0)
+			.location(0)
+			.format(vk::Format::R32G32B32_SFLOAT)
+			.offset(0)
+			.build();
+
+		let color = vk::VertexInputAttributeDescription::builder()
+			.binding(0)
+			.location(1)
+			.format(vk::Format::R32G32B32_SFLOAT)
+			.offset(size_of::<glm::Vec3>() as u32)
+			.build();
+
+		[pos, color]
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DebugDraw
+{
+	enabled: HashSet<DebugCategory>,
+	vertices: Vec<DebugVertex>,
+}
+
+impl DebugDraw
+{
+	/// Toggles a category, returning whether it's now enabled.
+	pub fn toggle(&mut self, category: DebugCategory) -> bool
+	{
+		if !self.enabled.remove(&category)
+		{
+			self.enabled.insert(category);
+		}
+
+		self.is_enabled(category)
+	}
+
+	pub fn is_enabled(&self, category: DebugCategory) -> bool
+	{
+		self.enabled.contains(&category)
+	}
+
+	pub fn clear(&mut self)
+	{
+		self.vertices.clear();
+	}
+
+	pub fn vertices(&self) -> &[DebugVertex]
+	{
+		&self.vertices
+	}
+
+	pub fn line(&mut self, category: DebugCategory, a: glm::Vec3, b: glm::Vec3, color: glm::Vec3)
+	{
+		if self.is_enabled(category) && self.vertices.len() + 2 <= MAX_VERTICES
+		{
+			self.vertices.push(DebugVertex { pos: a, color });
+			self.vertices.push(DebugVertex { pos: b, color });
+		}
+	}
+
+	/// Draws the frustum described by a view-projection matrix
+	/// (Vulkan clip space, so depth runs from 0 to 1).
+	pub fn frustum(&mut self, category: DebugCategory, view_proj: &glm::Mat4, color: glm::Vec3)
+	{
+		let inverse = glm::inverse(view_proj);
+
+		let corner = |x: f32, y: f32, z: f32|
+		{
+			let p = inverse * glm::vec4(x, y, z, 1.0);
+			glm::vec3(p.x, p.y, p.z) / p.w
+		};
+
+		let near = [corner(-1.0, -1.0, 0.0), corner(1.0, -1.0, 0.0), corner(1.0, 1.0, 0.0), corner(-1.0, 1.0, 0.0)];
+		let far = [corner(-1.0, -1.0, 1.0), corner(1.0, -1.0, 1.0), corner(1.0, 1.0, 1.0), corner(-1.0, 1.0, 1.0)];
+
+		for i in 0..4
+		{
+			let j = (i + 1) % 4;
+			self.line(category, near[i], near[j], color);
+			self.line(category, far[i], far[j], color);
+			self.line(category, near[i], far[i], color);
+		}
+	}
+
+	pub fn circle(
+		&mut self,
+		category: DebugCategory,
+		center: glm::Vec3,
+		axis_a: glm::Vec3,
+		axis_b: glm::Vec3,
+		radius: f32,
+		color: glm::Vec3,
+		)
+	{
+		let point = |i: usize|
+		{
+			let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+			center + (axis_a * angle.cos() + axis_b * angle.sin()) * radius
+		};
+
+		for i in 0..CIRCLE_SEGMENTS
+		{
+			self.line(category, point(i), point(i + 1), color);
+		}
+	}
+
+	/// Box with the given half extents, transformed by `transform`.
+	pub fn cuboid(&mut self, category: DebugCategory, transform: &glm::Mat4, half_extents: glm::Vec3, color: glm::Vec3)
+	{
+		let corner = |i: usize|
+		{
+			let sign = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+			let p = transform * glm::vec4(sign(1) * half_extents.x, sign(2) * half_extents.y, sign(4) * half_extents.z, 1.0);
+			glm::vec3(p.x, p.y, p.z)
+		};
+
+		// corners are indexed by their bits, so each edge joins corners
+		// that differ in exactly one bit
+		for i in 0..8
+		{
+			for bit in [1, 2, 4]
+			{
+				if i & bit == 0
+				{
+					self.line(category, corner(i), corner(i | bit), color);
+				}
+			}
+		}
+	}
+
+	/// Point light range.
+	pub fn sphere(&mut self, category: DebugCategory, center: glm::Vec3, radius: f32, color: glm::Vec3)
+	{
+		let x = glm::vec3(1.0, 0.0, 0.0);
+		let y = glm::vec3(0.0, 1.0, 0.0);
+		let z = glm::vec3(0.0, 0.0, 1.0);
+
+		self.circle(category, center, x, y, radius, color);
+		self.circle(category, center, y, z, radius, color);
+		self.circle(category, center, z, x, radius, color);
+	}
+
+	/// Spot light cone, `angle` is the half angle in radians.
+	pub fn cone(
+		&mut self,
+		category: DebugCategory,
+		apex: glm::Vec3,
+		direction: glm::Vec3,
+		range: f32,
+		angle: f32,
+		color: glm::Vec3,
+		)
+	{
+		let direction = glm::normalize(&direction);
+		let up = if direction.z.abs() < 0.99 { glm::vec3(0.0, 0.0, 1.0) } else { glm::vec3(1.0, 0.0, 0.0) };
+		let a = glm::normalize(&glm::cross(&direction, &up));
+		let b = glm::cross(&direction, &a);
+
+		let center = apex + direction * range;
+		let radius = range * angle.tan();
+
+		self.circle(category, center, a, b, radius, color);
+
+		for edge in [a, -a, b, -b]
+		{
+			self.line(category, apex, center + edge * radius, color);
+		}
+	}
+}
+
+pub unsafe fn create_debug_pipeline(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let vert = include_bytes!(concat!(env!("OUT_DIR"), "/debug_line_vert.spv"));
+	let frag = include_bytes!(concat!(env!("OUT_DIR"), "/debug_line_frag.spv"));
+
+	let vert_sm = create_shader_module(device, vert)?;
+	let frag_sm = create_shader_module(device, frag)?;
+
+	let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::VERTEX)
+		.module(vert_sm)
+		.name(b"main\0");
+
+	let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::FRAGMENT)
+		.module(frag_sm)
+		.name(b"main\0");
+
+	let binding_descriptions = &[DebugVertex::binding_description()];
+	let attribute_descriptions = DebugVertex::attribute_descriptions();
+	let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+		.vertex_binding_descriptions(binding_descriptions)
+		.vertex_attribute_descriptions(&attribute_descriptions);
+
+	let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+		.topology(vk::PrimitiveTopology::LINE_LIST)
+		.primitive_restart_enable(false);
+
+	let viewport = vk::Viewport::builder()
+		.x(0.0)
+		.y(0.0)
+		.width(data.render_extent.width as f32)
+		.height(data.render_extent.height as f32)
+		.min_depth(0.0)
+		.max_depth(1.0);
+
+	let scissor = vk::Rect2D::builder()
+		.offset(vk::Offset2D { x: 0, y: 0 })
+		.extent(data.render_extent);
+
+	let viewports = &[viewport];
+	let scissors = &[scissor];
+	let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+		.viewports(viewports)
+		.scissors(scissors);
+
+	let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+		.depth_clamp_enable(false)
+		.rasterizer_discard_enable(false)
+		.polygon_mode(vk::PolygonMode::FILL)
+		.line_width(1.0)
+		.cull_mode(vk::CullModeFlags::NONE)
+		.front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+		.depth_bias_enable(false);
+
+	let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+		.sample_shading_enable(false)
+		.rasterization_samples(data.msaa_samples);
+
+	let attachment = vk::PipelineColorBlendAttachmentState::builder()
+		.color_write_mask(vk::ColorComponentFlags::all())
+		.blend_enable(false);
+	let attachments = &[attachment];
+	let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+		.logic_op_enable(false)
+		.attachments(attachments);
+
+	// lines are depth tested against the scene but don't occlude each other
+	let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+		.depth_test_enable(true)
+		.depth_write_enable(false)
+		.depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+		.depth_bounds_test_enable(false)
+		.stencil_test_enable(false);
+
+	let stages = &[vert_stage, frag_stage];
+
+	// shares the main pipeline layout so the frame's descriptor set can be reused
+	let info = vk::GraphicsPipelineCreateInfo::builder()
+		.stages(stages)
+		.vertex_input_state(&vertex_input_state)
+		.input_assembly_state(&input_assembly_state)
+		.viewport_state(&viewport_state)
+		.rasterization_state(&rasterization_state)
+		.multisample_state(&multisample_state)
+		.depth_stencil_state(&depth_stencil_state)
+		.color_blend_state(&color_blend_state)
+		.layout(data.pipeline_layout)
+		.render_pass(data.render_pass)
+		.subpass(0);
+
+	data.debug_pipeline = device.create_graphics_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(vert_sm, None);
+	device.destroy_shader_module(frag_sm, None);
+
+	Ok(())
+}
+
+/// One host-visible vertex buffer per swapchain image, rewritten every frame.
+pub unsafe fn create_debug_buffers(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	data.debug_vertex_buffers.clear();
+	data.debug_vertex_buffers_memory.clear();
+
+	for _ in 0..data.swapchain_images.len()
+	{
+		let (buffer, buffer_memory) = create_buffer(
+			instance,
+			device,
+			data,
+			(size_of::<DebugVertex>() * MAX_VERTICES) as u64,
+			vk::BufferUsageFlags::VERTEX_BUFFER,
+			vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+		)?;
+
+		data.debug_vertex_buffers.push(buffer);
+		data.debug_vertex_buffers_memory.push(buffer_memory);
+	}
+
+	Ok(())
+}
+
+/// Copies this frame's debug lines into the image's vertex buffer.
+pub unsafe fn upload_debug_vertices(
+	device: &Device,
+	data: &AppData,
+	image_index: usize,
+	) -> Result<()>
+{
+	let vertices = data.debug_draw.vertices();
+
+	let memory = device.map_memory(
+		data.debug_vertex_buffers_memory[image_index].memory,
+		data.debug_vertex_buffers_memory[image_index].offset,
+		(size_of::<DebugVertex>() * vertices.len()) as u64,
+		vk::MemoryMapFlags::empty(),
+		)?;
+
+	memcpy(vertices.as_ptr(), memory.cast(), vertices.len());
+
+	device.unmap_memory(data.debug_vertex_buffers_memory[image_index].memory);
+
+	Ok(())
+}
+
+pub unsafe fn destroy_debug_objects(device: &Device, data: &AppData)
+{
+	data.debug_vertex_buffers
+		.iter()
+		.for_each(|b| device.destroy_buffer(*b, None));
+	data.debug_vertex_buffers_memory
+		.iter()
+		.for_each(|m| allocator::free(device, *m));
+	device.destroy_pipeline(data.debug_pipeline, None);
+}
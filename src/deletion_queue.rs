@@ -0,0 +1,76 @@
+// Deferred destruction
+//
+// Objects the GPU or the presentation engine may still be using when
+// they're replaced are retired here instead of destroyed, and destroyed
+// once enough frames have gone by that nothing in flight can refer to them.
+
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrSwapchainExtension;
+
+use crate::allocator::{self, Allocation};
+
+#[derive(Copy, Clone, Debug)]
+pub enum Retired
+{
+	/// Replaced by a swapchain created from it, whose images may still be
+	/// queued for presentation.
+	Swapchain(vk::SwapchainKHR),
+	/// Used by the frame it was recorded into, e.g. a screenshot's blit
+	/// target.
+	Image(vk::Image, Allocation),
+}
+
+impl Retired
+{
+	unsafe fn destroy(self, device: &Device)
+	{
+		match self
+		{
+			Retired::Swapchain(swapchain) => device.destroy_swapchain_khr(swapchain, None),
+			Retired::Image(image, memory) =>
+			{
+				device.destroy_image(image, None);
+				allocator::free(device, memory);
+			},
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DeletionQueue
+{
+	frame: u64,
+	retired: Vec<(u64, Retired)>,
+}
+
+impl DeletionQueue
+{
+	pub fn retire(&mut self, item: Retired)
+	{
+		self.retired.push((self.frame, item));
+	}
+
+	/// Called once a frame, after waiting for the frame's fence. Destroys
+	/// whatever was retired more than `frames` frames ago.
+	pub unsafe fn collect(&mut self, device: &Device, frames: usize)
+	{
+		self.frame += 1;
+
+		let frame = self.frame;
+		self.retired.retain(|&(retired_frame, item)|
+			{
+				let done = frame > retired_frame + frames as u64;
+				if done
+				{
+					item.destroy(device);
+				}
+				!done
+			});
+	}
+
+	/// Destroys everything, once the device is idle.
+	pub unsafe fn flush(&mut self, device: &Device)
+	{
+		self.retired.drain(..).for_each(|(_, item)| item.destroy(device));
+	}
+}
@@ -0,0 +1,137 @@
+// Depth-only rendering
+//
+// Slim permutations of the material's pipeline that only write depth, for
+// a depth pre-pass and, later, shadow maps. Opaque materials fetch nothing
+// but positions and run no fragment shader; alpha-tested ones need their UVs
+// and a fragment shader that discards the cut out texels.
+//
+// With the pre-pass on, opaque models lay down their depth first so the
+// full material shader only runs once per pixel, for the visible surface.
+// The main pipeline tests with LESS_OR_EQUAL and `gl_Position` is invariant
+// so both passes land on exactly the same depth.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::material::DepthVariant;
+use crate::{create_shader_module, AppData};
+
+pub unsafe fn create_depth_pipeline(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let variant = data.material.depth_variant();
+
+	let vert_sm = create_shader_module(device, variant.vertex_spirv())?;
+	let frag_sm = variant.fragment_spirv().map(|frag| create_shader_module(device, frag)).transpose()?;
+
+	let mut stages = vec![vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::VERTEX)
+		.module(vert_sm)
+		.name(b"main\0")
+		.build()];
+
+	if let Some(frag_sm) = frag_sm
+	{
+		stages.push(vk::PipelineShaderStageCreateInfo::builder()
+			.stage(vk::ShaderStageFlags::FRAGMENT)
+			.module(frag_sm)
+			.name(b"main\0")
+			.build());
+	}
+
+	// alpha testing needs the UVs from the second stream
+	let (binding_descriptions, attribute_descriptions) = match variant
+	{
+		DepthVariant::PositionOnly => (
+			vec![data.vertex_layout.position_binding_description(data.vertex_streams)],
+			vec![data.vertex_layout.position_attribute_description()],
+		),
+		DepthVariant::AlphaTested => (
+			data.vertex_layout.binding_descriptions(data.vertex_streams),
+			data.vertex_layout.attribute_descriptions(data.vertex_streams),
+		),
+	};
+	let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+		.vertex_binding_descriptions(&binding_descriptions)
+		.vertex_attribute_descriptions(&attribute_descriptions);
+
+	let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+		.topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+		.primitive_restart_enable(false);
+
+	let viewport = vk::Viewport::builder()
+		.x(0.0)
+		.y(0.0)
+		.width(data.render_extent.width as f32)
+		.height(data.render_extent.height as f32)
+		.min_depth(0.0)
+		.max_depth(1.0);
+
+	let scissor = vk::Rect2D::builder()
+		.offset(vk::Offset2D { x: 0, y: 0 })
+		.extent(data.render_extent);
+
+	let viewports = &[viewport];
+	let scissors = &[scissor];
+	let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+		.viewports(viewports)
+		.scissors(scissors);
+
+	let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+		.depth_clamp_enable(false)
+		.rasterizer_discard_enable(false)
+		.polygon_mode(vk::PolygonMode::FILL)
+		.line_width(1.0)
+		.cull_mode(data.material.cull_mode().flags())
+		.front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+		.depth_bias_enable(false);
+
+	let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+		.sample_shading_enable(false)
+		.rasterization_samples(data.msaa_samples);
+
+	// the scene pass has a color attachment, it's just never written
+	let attachment = vk::PipelineColorBlendAttachmentState::builder()
+		.color_write_mask(vk::ColorComponentFlags::empty())
+		.blend_enable(false);
+	let attachments = &[attachment];
+	let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+		.logic_op_enable(false)
+		.attachments(attachments);
+
+	let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+		.depth_test_enable(true)
+		.depth_write_enable(true)
+		.depth_compare_op(vk::CompareOp::LESS)
+		.depth_bounds_test_enable(false)
+		.stencil_test_enable(false);
+
+	let info = vk::GraphicsPipelineCreateInfo::builder()
+		.stages(&stages)
+		.vertex_input_state(&vertex_input_state)
+		.input_assembly_state(&input_assembly_state)
+		.viewport_state(&viewport_state)
+		.rasterization_state(&rasterization_state)
+		.multisample_state(&multisample_state)
+		.depth_stencil_state(&depth_stencil_state)
+		.color_blend_state(&color_blend_state)
+		.layout(data.pipeline_layout)
+		.render_pass(data.render_pass)
+		.subpass(0);
+
+	data.depth_pipeline = device.create_graphics_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(vert_sm, None);
+	if let Some(frag_sm) = frag_sm
+	{
+		device.destroy_shader_module(frag_sm, None);
+	}
+
+	Ok(())
+}
@@ -0,0 +1,224 @@
+// Descriptor management
+//
+// Descriptor sets come from allocators that grow their own pools instead of
+// pools sized by hand for whatever gets allocated from them. An allocator
+// keeps a list of pools, and when the current one runs out it makes another
+// with room for half as many sets again, up to a limit. Sets are never
+// freed one at a time: an allocator resets all of its pools at once, which
+// is cheap and can't fragment them.
+//
+// There's an allocator for sets that last as long as the device, one for
+// sets written against the swapchain's targets, reset when it's recreated,
+// and one per frame in flight for transient sets. A frame's allocator is
+// reset once its fence has signaled, so a transient set only has to last
+// the frame and can be allocated and written while recording.
+//
+// Layouts are cached by their bindings, so asking for the same layout again,
+// e.g. when the swapchain is recreated, returns the one already created.
+// They're kept until the device is destroyed.
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::collections::HashMap;
+use std::slice;
+
+const INITIAL_SETS_PER_POOL: u32 = 16;
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+// descriptors of each type a pool has room for, per set
+const POOL_RATIOS: &[(vk::DescriptorType, u32)] = &[
+	(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4),
+	(vk::DescriptorType::STORAGE_IMAGE, 2),
+	(vk::DescriptorType::UNIFORM_BUFFER, 1),
+	(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1),
+	(vk::DescriptorType::STORAGE_BUFFER, 1),
+];
+
+/// Hands out descriptor sets from pools it creates as it needs them.
+#[derive(Clone, Debug, Default)]
+pub struct DescriptorAllocator
+{
+	/// Pools with room left, the last is allocated from first.
+	ready: Vec<vk::DescriptorPool>,
+	/// Pools that have run out, until they're reset.
+	full: Vec<vk::DescriptorPool>,
+	sets_per_pool: u32,
+}
+
+impl DescriptorAllocator
+{
+	/// Allocates a set for each layout.
+	pub unsafe fn allocate(&mut self, device: &Device, layouts: &[vk::DescriptorSetLayout]) -> Result<Vec<vk::DescriptorSet>>
+	{
+		let mut pool = self.take_pool(device)?;
+		let result = match allocate_from(device, pool, layouts)
+		{
+			Err(vk::ErrorCode::OUT_OF_POOL_MEMORY | vk::ErrorCode::FRAGMENTED_POOL) =>
+			{
+				// a fresh pool has room for anything a full one didn't
+				self.full.push(pool);
+				pool = self.take_pool(device)?;
+				allocate_from(device, pool, layouts)
+			},
+			result => result,
+		};
+
+		self.ready.push(pool);
+		Ok(result?)
+	}
+
+	/// Resets every pool, which frees every set allocated from them. Only
+	/// once nothing in flight uses them.
+	pub unsafe fn reset(&mut self, device: &Device)
+	{
+		self.ready.append(&mut self.full);
+		for pool in &self.ready
+		{
+			// resetting a pool can't fail
+			let _ = device.reset_descriptor_pool(*pool, vk::DescriptorPoolResetFlags::empty());
+		}
+	}
+
+	pub unsafe fn destroy(&mut self, device: &Device)
+	{
+		self.ready
+			.drain(..)
+			.chain(self.full.drain(..))
+			.for_each(|pool| device.destroy_descriptor_pool(pool, None));
+	}
+
+	unsafe fn take_pool(&mut self, device: &Device) -> Result<vk::DescriptorPool>
+	{
+		if let Some(pool) = self.ready.pop()
+		{
+			return Ok(pool);
+		}
+
+		let sets = self.sets_per_pool.max(INITIAL_SETS_PER_POOL);
+		self.sets_per_pool = (sets + sets / 2).min(MAX_SETS_PER_POOL);
+
+		let pool_sizes = POOL_RATIOS
+			.iter()
+			.map(|&(type_, ratio)|
+				{
+					vk::DescriptorPoolSize::builder()
+						.type_(type_)
+						.descriptor_count(ratio * sets)
+						.build()
+				})
+			.collect::<Vec<_>>();
+
+		let info = vk::DescriptorPoolCreateInfo::builder()
+			.pool_sizes(&pool_sizes)
+			.max_sets(sets);
+
+		let pool = device.create_descriptor_pool(&info, None)?;
+		debug!("New descriptor pool for {} sets ({} pools)", sets, self.ready.len() + self.full.len() + 1);
+		Ok(pool)
+	}
+}
+
+unsafe fn allocate_from(
+	device: &Device,
+	pool: vk::DescriptorPool,
+	layouts: &[vk::DescriptorSetLayout],
+	) -> VkResult<Vec<vk::DescriptorSet>>
+{
+	let info = vk::DescriptorSetAllocateInfo::builder()
+		.descriptor_pool(pool)
+		.set_layouts(layouts);
+
+	device.allocate_descriptor_sets(&info)
+}
+
+// binding, type, count, stages and immutable samplers
+type BindingKey = (u32, vk::DescriptorType, u32, vk::ShaderStageFlags, Vec<vk::Sampler>);
+
+/// The layout cache and every allocator.
+#[derive(Clone, Debug, Default)]
+pub struct Descriptors
+{
+	layouts: HashMap<(vk::DescriptorSetLayoutCreateFlags, Vec<BindingKey>), vk::DescriptorSetLayout>,
+	/// Sets that last as long as the device.
+	pub persistent: DescriptorAllocator,
+	/// Sets that refer to the swapchain's targets, reset when it's recreated.
+	pub swapchain: DescriptorAllocator,
+	frames: Vec<DescriptorAllocator>,
+}
+
+impl Descriptors
+{
+	pub fn new(frames: usize) -> Self
+	{
+		Self { frames: vec![DescriptorAllocator::default(); frames], ..Default::default() }
+	}
+
+	/// The layout `info` describes, creating it the first time it's asked for.
+	pub unsafe fn layout(&mut self, device: &Device, info: &vk::DescriptorSetLayoutCreateInfo) -> Result<vk::DescriptorSetLayout>
+	{
+		let bindings = if info.binding_count == 0
+		{
+			&[]
+		}
+		else
+		{
+			slice::from_raw_parts(info.bindings, info.binding_count as usize)
+		};
+
+		let mut key = bindings
+			.iter()
+			.map(|b|
+				{
+					let samplers = if b.immutable_samplers.is_null()
+					{
+						vec![]
+					}
+					else
+					{
+						slice::from_raw_parts(b.immutable_samplers, b.descriptor_count as usize).to_vec()
+					};
+					(b.binding, b.descriptor_type, b.descriptor_count, b.stage_flags, samplers)
+				})
+			.collect::<Vec<_>>();
+		key.sort_by_key(|b| b.0);
+
+		if let Some(layout) = self.layouts.get(&(info.flags, key.clone()))
+		{
+			return Ok(*layout);
+		}
+
+		let layout = device.create_descriptor_set_layout(info, None)?;
+		self.layouts.insert((info.flags, key), layout);
+		Ok(layout)
+	}
+
+	/// Allocates sets that are only valid until the frame comes round again.
+	pub unsafe fn allocate_transient(
+		&mut self,
+		device: &Device,
+		frame: usize,
+		layouts: &[vk::DescriptorSetLayout],
+		) -> Result<Vec<vk::DescriptorSet>>
+	{
+		self.frames[frame].allocate(device, layouts)
+	}
+
+	/// Frees the frame's transient sets, once its fence has signaled.
+	pub unsafe fn reset_frame(&mut self, device: &Device, frame: usize)
+	{
+		self.frames[frame].reset(device);
+	}
+
+	/// Destroys every pool and layout, once the device is idle.
+	pub unsafe fn destroy(&mut self, device: &Device)
+	{
+		self.persistent.destroy(device);
+		self.swapchain.destroy(device);
+		self.frames.iter_mut().for_each(|f| f.destroy(device));
+		self.layouts
+			.drain()
+			.for_each(|(_, layout)| device.destroy_descriptor_set_layout(layout, None));
+	}
+}
@@ -0,0 +1,120 @@
+// Dynamic rendering
+//
+// With `VK_KHR_dynamic_rendering` a pass renders straight into image views,
+// with no render pass object or framebuffers, and its pipelines are made for
+// the attachments' formats instead of a render pass. It's used whenever the
+// device has it, which is decided when the device is created, and the
+// classic render pass path stays for devices without it.
+//
+// So far the composite pass is the one that takes it. It draws into the
+// swapchain image, so there are no framebuffers to rebuild with every
+// swapchain, and the layout transitions its render pass made are barriers
+// around the pass instead. The scene and transmission passes still use
+// render passes, which their secondary command buffers inherit.
+
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrDynamicRenderingExtension;
+
+/// What dynamic rendering needs on a Vulkan 1.1 device, where it isn't core.
+pub const EXTENSIONS: &[vk::Extension] = &[
+	vk::KHR_DYNAMIC_RENDERING_EXTENSION,
+	vk::KHR_DEPTH_STENCIL_RESOLVE_EXTENSION,
+	vk::KHR_CREATE_RENDERPASS_2_EXTENSION,
+];
+
+/// Starts rendering into a swapchain image. Every pixel gets overwritten, so
+/// whatever it held when it was last presented is discarded.
+pub unsafe fn begin_swapchain_rendering(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	view: vk::ImageView,
+	extent: vk::Extent2D,
+	)
+{
+	// waits on the acquire semaphore's stage, like the render pass did
+	image_barrier(
+		device,
+		command_buffer,
+		image,
+		(vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+		(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::empty()),
+		(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+	);
+
+	let color_attachment = vk::RenderingAttachmentInfo::builder()
+		.image_view(view)
+		.image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+		.load_op(vk::AttachmentLoadOp::DONT_CARE)
+		.store_op(vk::AttachmentStoreOp::STORE);
+
+	let render_area = vk::Rect2D::builder()
+		.offset(vk::Offset2D::default())
+		.extent(extent);
+
+	let color_attachments = &[color_attachment];
+	let info = vk::RenderingInfo::builder()
+		.render_area(render_area)
+		.layer_count(1)
+		.color_attachments(color_attachments);
+
+	device.cmd_begin_rendering_khr(command_buffer, &info);
+}
+
+/// Ends rendering into a swapchain image and moves it to `final_layout`,
+/// ready for presentation or, headless, to be copied out.
+pub unsafe fn end_swapchain_rendering(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	final_layout: vk::ImageLayout,
+	)
+{
+	device.cmd_end_rendering_khr(command_buffer);
+
+	image_barrier(
+		device,
+		command_buffer,
+		image,
+		(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, final_layout),
+		(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+		(vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty()),
+	);
+}
+
+unsafe fn image_barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	(old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+	(src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	(dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	)
+{
+	let subresource_range = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(old_layout)
+		.new_layout(new_layout)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(subresource_range)
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
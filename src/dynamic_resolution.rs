@@ -0,0 +1,94 @@
+// Dynamic resolution
+//
+// Adjusts the render scale to hold a target GPU frame time. Frame times are
+// smoothed first, and the scale is only changed once the average strays
+// outside a band around the target and the previous change has settled,
+// since every change rebuilds the scene targets.
+
+use crate::composite::MIN_RENDER_SCALE;
+
+/// New scales are rounded to a multiple of this to avoid tiny adjustments.
+const SCALE_GRANULARITY: f32 = 1.0 / 32.0;
+/// Weight of the newest frame in the moving average.
+const SMOOTHING: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug)]
+pub struct DynamicResolution
+{
+	pub enabled: bool,
+	pub target_ms: f32,
+	pub min_scale: f32,
+	pub max_scale: f32,
+	/// Fraction of the target the average may differ by before rescaling.
+	pub hysteresis: f32,
+	/// Frames to wait after a change before making another one.
+	pub cooldown: u32,
+	average_ms: Option<f32>,
+	frames_since_change: u32,
+}
+
+impl Default for DynamicResolution
+{
+	fn default() -> Self
+	{
+		Self {
+			enabled: false,
+			target_ms: 1000.0 / 60.0,
+			min_scale: MIN_RENDER_SCALE,
+			// going above native resolution is left to supersampling
+			max_scale: 1.0,
+			hysteresis: 0.1,
+			cooldown: 30,
+			average_ms: None,
+			frames_since_change: 0,
+		}
+	}
+}
+
+impl DynamicResolution
+{
+	/// Feeds in a GPU frame time, returning a new render scale if it
+	/// should change.
+	pub fn update(&mut self, gpu_ms: f32, scale: f32) -> Option<f32>
+	{
+		let average = match self.average_ms
+		{
+			Some(average) => average + (gpu_ms - average) * SMOOTHING,
+			None => gpu_ms,
+		};
+		self.average_ms = Some(average);
+		self.frames_since_change += 1;
+
+		if !self.enabled || self.frames_since_change < self.cooldown
+		{
+			return None;
+		}
+
+		let error = (average - self.target_ms) / self.target_ms;
+		if error.abs() <= self.hysteresis
+		{
+			return None;
+		}
+
+		// cost scales with the pixel count, so with the square of the scale
+		let ideal = scale * (self.target_ms / average).sqrt();
+		let new_scale = ((ideal / SCALE_GRANULARITY).round() * SCALE_GRANULARITY)
+			.clamp(self.min_scale, self.max_scale);
+
+		if new_scale == scale
+		{
+			return None;
+		}
+
+		// the average was measured at the old scale
+		self.average_ms = None;
+		self.frames_since_change = 0;
+		Some(new_scale)
+	}
+
+	/// Smoothed GPU frame time in milliseconds.
+	pub fn average_ms(&self) -> Option<f32>
+	{
+		self.average_ms
+	}
+}
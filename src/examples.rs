@@ -0,0 +1,166 @@
+// Tutorial examples
+//
+// Every chapter runs on the same renderer, so an example is a preset for
+// it rather than a program of its own: the mesh and material tweaks it
+// starts with and which of the later features are switched on. Selected
+// with `--example <name>`, without one everything is on as usual.
+//
+// The features a chapter hasn't introduced yet are only switched off where
+// they can be, the final pipelines are used throughout.
+
+use crate::composite::InspectTarget;
+use crate::debug_draw::DebugCategory;
+use crate::fallback;
+use crate::material::{CullMode, Material, ShaderVariant};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Example
+{
+	/// A vertex colored triangle.
+	Triangle,
+	/// A quad with the tutorial's texture.
+	TexturedQuad,
+	/// Two quads, one behind the other, sorted by the depth buffer.
+	Depth,
+	/// The viking room.
+	Model,
+	/// The model under the sky, with the sun moving and a specular highlight.
+	Lighting,
+	/// Lighting with the light volumes drawn and parallax self-shadowing
+	/// when the material has a height map.
+	Shadows,
+	/// Rough specular, clearcoat and reflections.
+	Pbr,
+	/// The fluid simulation's compute shaders drawn over the scene, and
+	/// compute-driven particles in it.
+	Compute,
+}
+
+/// What an example changes when the app starts.
+#[derive(Copy, Clone, Debug)]
+pub struct ExampleSettings
+{
+	/// Replaces the model file.
+	pub mesh: Option<&'static [u8]>,
+	pub models: usize,
+	pub show_sky: bool,
+	pub show_particles: bool,
+	/// In-game hours per second.
+	pub sun_speed: f32,
+	pub inspect_target: InspectTarget,
+	pub debug_category: Option<DebugCategory>,
+}
+
+impl Example
+{
+	pub const ALL: &'static [Example] = &[
+		Example::Triangle,
+		Example::TexturedQuad,
+		Example::Depth,
+		Example::Model,
+		Example::Lighting,
+		Example::Shadows,
+		Example::Pbr,
+		Example::Compute,
+	];
+
+	pub fn name(self) -> &'static str
+	{
+		match self
+		{
+			Example::Triangle => "triangle",
+			Example::TexturedQuad => "textured-quad",
+			Example::Depth => "depth",
+			Example::Model => "model",
+			Example::Lighting => "lighting",
+			Example::Shadows => "shadows",
+			Example::Pbr => "pbr",
+			Example::Compute => "compute",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self>
+	{
+		Self::ALL.iter().copied().find(|e| e.name() == name)
+	}
+
+	pub fn settings(self) -> ExampleSettings
+	{
+		let mut settings = ExampleSettings {
+			mesh: None,
+			models: 1,
+			show_sky: false,
+			show_particles: false,
+			sun_speed: 0.0,
+			inspect_target: InspectTarget::Final,
+			debug_category: None,
+		};
+
+		match self
+		{
+			Example::Triangle => settings.mesh = Some(fallback::TRIANGLE_MESH),
+			Example::TexturedQuad => settings.mesh = Some(fallback::QUAD_MESH),
+			Example::Depth =>
+			{
+				settings.mesh = Some(fallback::QUAD_MESH);
+				settings.models = 2;
+			},
+			Example::Model | Example::Pbr => {},
+			Example::Lighting =>
+			{
+				settings.show_sky = true;
+				settings.sun_speed = 1.0;
+			},
+			Example::Shadows =>
+			{
+				settings.show_sky = true;
+				settings.sun_speed = 1.0;
+				settings.debug_category = Some(DebugCategory::LightVolumes);
+			},
+			Example::Compute =>
+			{
+				settings.inspect_target = InspectTarget::Fluid;
+				settings.show_particles = true;
+			},
+		}
+
+		settings
+	}
+
+	/// Adjusts the material file's material to what the chapter covers.
+	pub fn adjust_material(self, material: &mut Material)
+	{
+		match self
+		{
+			Example::Triangle =>
+			{
+				material.shader = ShaderVariant::VertexColor;
+				material.cull_mode = CullMode::None;
+			},
+			Example::TexturedQuad | Example::Depth =>
+			{
+				material.texture = "media/texture.png".to_string();
+				material.shader = ShaderVariant::Textured;
+				material.cull_mode = CullMode::None;
+				material.lightmap = None;
+			},
+			Example::Model | Example::Compute => {},
+			Example::Lighting => material.specular = material.specular.max(0.5),
+			Example::Shadows =>
+			{
+				material.specular = material.specular.max(0.5);
+				if material.height_map.is_some()
+				{
+					material.shader = ShaderVariant::ParallaxShadowed;
+				}
+			},
+			Example::Pbr =>
+			{
+				material.specular = 1.0;
+				material.roughness = 0.3;
+				material.clearcoat = 0.5;
+				material.reflectivity = material.reflectivity.max(0.2);
+			},
+		}
+	}
+}
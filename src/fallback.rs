@@ -0,0 +1,39 @@
+// Embedded fallback assets
+//
+// These are baked into the binary so there's always something to render,
+// even when the media directory is missing or an asset fails to load.
+// The default shaders are already embedded by `create_pipeline`.
+
+use log::*;
+
+use std::fs;
+use std::path::Path;
+
+use crate::material::Material;
+
+/// Pink and black checkerboard, the classic "missing texture" look.
+pub const CHECKERBOARD_TEXTURE: &[u8] = include_bytes!("../media/fallback/checkerboard.png");
+pub const CUBE_MESH: &[u8] = include_bytes!("../media/fallback/cube.obj");
+pub const QUAD_MESH: &[u8] = include_bytes!("../media/fallback/quad.obj");
+pub const TRIANGLE_MESH: &[u8] = include_bytes!("../media/fallback/triangle.obj");
+const ERROR_MATERIAL: &str = include_str!("../media/fallback/error.mat.ron");
+
+pub fn error_material() -> Material
+{
+	ron::from_str(ERROR_MATERIAL).expect("Embedded error material is invalid")
+}
+
+/// Reads an asset from disk, falling back to the embedded bytes if it can't be read.
+pub fn read_or(path: impl AsRef<Path>, fallback: &[u8]) -> Vec<u8>
+{
+	let path = path.as_ref();
+	match fs::read(path)
+	{
+		Ok(bytes) => bytes,
+		Err(e) =>
+		{
+			warn!("Failed to read {} ({}), using embedded fallback", path.display(), e);
+			fallback.to_vec()
+		},
+	}
+}
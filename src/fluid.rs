@@ -0,0 +1,337 @@
+// 2D smoke simulation
+//
+// A stable fluids style Eulerian solver running in a compute shader. The
+// state lives in two storage images that are ping-ponged between
+// dispatches: advection, a number of Jacobi pressure iterations, then
+// projection. The dispatch count is even so the result always ends up in
+// the first image, which the composite pass samples to show the smoke.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator;
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+use crate::stats::FrameStats;
+use crate::{
+	begin_single_time_commands,
+	create_image,
+	create_image_view,
+	create_shader_module,
+	end_single_time_commands,
+	AppData,
+};
+
+const FLUID_SIZE: u32 = 256;
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Simulated with a fixed step so the solver stays stable.
+const TIMESTEP: f32 = 1.0 / 60.0;
+/// Odd, so that together with advection and projection the dispatch
+/// count is even.
+const PRESSURE_ITERATIONS: usize = 21;
+const WORKGROUP_SIZE: u32 = 16;
+
+/// Must match the stages in `fluid.comp`.
+#[derive(Copy, Clone, Debug)]
+enum Stage
+{
+	Advect = 0,
+	Jacobi = 1,
+	Project = 2,
+}
+
+/// Must match the push constants of `fluid.comp`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct FluidPushConstants
+{
+	stage: i32,
+	time: f32,
+	dt: f32,
+}
+
+pub unsafe fn create_fluid_objects(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	for i in 0..2
+	{
+		let (image, memory) = create_image(
+			instance,
+			device,
+			data,
+			FLUID_SIZE,
+			FLUID_SIZE,
+			1,
+			vk::SampleCountFlags::_1,
+			FORMAT,
+			vk::ImageTiling::OPTIMAL,
+			vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+			vk::MemoryPropertyFlags::DEVICE_LOCAL,
+		)?;
+
+		data.fluid_images[i] = image;
+		data.fluid_images_memory[i] = memory;
+		data.fluid_image_views[i] = create_image_view(device, image, FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+	}
+
+	clear_fluid_images(device, data)?;
+
+	let bindings = [0, 1].map(|binding|
+		vk::DescriptorSetLayoutBinding::builder()
+			.binding(binding)
+			.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+			.descriptor_count(1)
+			.stage_flags(vk::ShaderStageFlags::COMPUTE)
+			.build());
+
+	let info = vk::DescriptorSetLayoutCreateInfo::builder()
+		.bindings(&bindings);
+
+	data.fluid_descriptor_set_layout = data.descriptors.layout(device, &info)?;
+
+	let push_constant_range = push_constant_range::<FluidPushConstants>(vk::ShaderStageFlags::COMPUTE);
+
+	let set_layouts = &[data.fluid_descriptor_set_layout];
+	let push_constant_ranges = &[push_constant_range];
+	let info = vk::PipelineLayoutCreateInfo::builder()
+		.set_layouts(set_layouts)
+		.push_constant_ranges(push_constant_ranges);
+
+	data.fluid_pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+	let comp = include_bytes!(concat!(env!("OUT_DIR"), "/fluid_comp.spv"));
+	let comp_sm = create_shader_module(device, comp)?;
+
+	let stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::COMPUTE)
+		.module(comp_sm)
+		.name(b"main\0");
+
+	let info = vk::ComputePipelineCreateInfo::builder()
+		.stage(stage)
+		.layout(data.fluid_pipeline_layout);
+
+	data.fluid_pipeline = device.create_compute_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(comp_sm, None);
+
+	// one set per direction, reading one image and writing the other
+	let layouts = [data.fluid_descriptor_set_layout; 2];
+	let sets = data.descriptors.persistent.allocate(device, &layouts)?;
+
+	for (i, set) in sets.iter().enumerate()
+	{
+		let image_info = |view: vk::ImageView|
+			[vk::DescriptorImageInfo::builder()
+				.image_layout(vk::ImageLayout::GENERAL)
+				.image_view(view)
+				.build()];
+
+		let source_info = image_info(data.fluid_image_views[i]);
+		let destination_info = image_info(data.fluid_image_views[1 - i]);
+
+		let writes = [(0, &source_info), (1, &destination_info)].map(|(binding, info)|
+			vk::WriteDescriptorSet::builder()
+				.dst_set(*set)
+				.dst_binding(binding)
+				.dst_array_element(0)
+				.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+				.image_info(info)
+				.build());
+
+		device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+		data.fluid_descriptor_sets[i] = *set;
+	}
+
+	Ok(())
+}
+
+/// Moves both images to GENERAL, where they stay, and zeroes them.
+unsafe fn clear_fluid_images(device: &Device, data: &AppData) -> Result<()>
+{
+	let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+
+	let range = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1)
+		.build();
+
+	let barriers = data.fluid_images.map(|image|
+		vk::ImageMemoryBarrier::builder()
+			.old_layout(vk::ImageLayout::UNDEFINED)
+			.new_layout(vk::ImageLayout::GENERAL)
+			.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+			.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+			.image(image)
+			.subresource_range(range)
+			.src_access_mask(vk::AccessFlags::empty())
+			.dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+			.build());
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		vk::PipelineStageFlags::TOP_OF_PIPE,
+		vk::PipelineStageFlags::TRANSFER,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&barriers,
+	);
+
+	for image in data.fluid_images
+	{
+		data.layouts.transition(image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+
+		device.cmd_clear_color_image(
+			command_buffer,
+			image,
+			vk::ImageLayout::GENERAL,
+			&vk::ClearColorValue { float32: [0.0; 4] },
+			&[range],
+		);
+	}
+
+	end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)?;
+
+	Ok(())
+}
+
+unsafe fn image_barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	src_stage: vk::PipelineStageFlags,
+	src_access: vk::AccessFlags,
+	dst_stage: vk::PipelineStageFlags,
+	dst_access: vk::AccessFlags,
+	)
+{
+	let range = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(vk::ImageLayout::GENERAL)
+		.new_layout(vk::ImageLayout::GENERAL)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(range)
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
+
+/// Records one simulation step. Must be outside of a render pass.
+pub unsafe fn record_simulation(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	time: f32,
+	stats: &mut FrameStats,
+	)
+{
+	data.layouts.expect(data.fluid_images[0], vk::ImageLayout::GENERAL, "fluid simulation");
+
+	// last frame's composite pass may still be reading the result
+	image_barrier(
+		device,
+		command_buffer,
+		data.fluid_images[0],
+		vk::PipelineStageFlags::FRAGMENT_SHADER,
+		vk::AccessFlags::empty(),
+		vk::PipelineStageFlags::COMPUTE_SHADER,
+		vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+	);
+	stats.barriers += 1;
+
+	device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.fluid_pipeline);
+	stats.pipeline_binds += 1;
+
+	let stages = std::iter::once(Stage::Advect)
+		.chain(std::iter::repeat(Stage::Jacobi).take(PRESSURE_ITERATIONS))
+		.chain(std::iter::once(Stage::Project));
+
+	let groups = (FLUID_SIZE + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+
+	for (i, stage) in stages.enumerate()
+	{
+		let source = i % 2;
+
+		device.cmd_bind_descriptor_sets(
+			command_buffer,
+			vk::PipelineBindPoint::COMPUTE,
+			data.fluid_pipeline_layout,
+			0,
+			&[data.fluid_descriptor_sets[source]],
+			&[]);
+		stats.descriptor_binds += 1;
+
+		cmd_push_constants(
+			device,
+			command_buffer,
+			data.fluid_pipeline_layout,
+			vk::ShaderStageFlags::COMPUTE,
+			&FluidPushConstants { stage: stage as i32, time, dt: TIMESTEP },
+		);
+
+		device.cmd_dispatch(command_buffer, groups, groups, 1);
+
+		// the next dispatch reads what this one wrote
+		image_barrier(
+			device,
+			command_buffer,
+			data.fluid_images[1 - source],
+			vk::PipelineStageFlags::COMPUTE_SHADER,
+			vk::AccessFlags::SHADER_WRITE,
+			vk::PipelineStageFlags::COMPUTE_SHADER,
+			vk::AccessFlags::SHADER_READ,
+		);
+		stats.barriers += 1;
+	}
+
+	image_barrier(
+		device,
+		command_buffer,
+		data.fluid_images[0],
+		vk::PipelineStageFlags::COMPUTE_SHADER,
+		vk::AccessFlags::SHADER_WRITE,
+		vk::PipelineStageFlags::FRAGMENT_SHADER,
+		vk::AccessFlags::SHADER_READ,
+	);
+	stats.barriers += 1;
+}
+
+pub unsafe fn destroy_fluid_objects(device: &Device, data: &AppData)
+{
+	device.destroy_pipeline(data.fluid_pipeline, None);
+	device.destroy_pipeline_layout(data.fluid_pipeline_layout, None);
+
+	for i in 0..2
+	{
+		device.destroy_image_view(data.fluid_image_views[i], None);
+		device.destroy_image(data.fluid_images[i], None);
+		allocator::free(device, data.fluid_images_memory[i]);
+	}
+}
@@ -0,0 +1,148 @@
+// First-person camera controls
+//
+// WASD moves along the view and its right, E and Q straight up and down,
+// and Left Shift speeds everything up. Looking around follows the mouse
+// while the right button is held, so the cursor stays free for everything
+// else. The raw device motion is used rather than cursor positions, which
+// stop at the edges of the window and are scaled by the OS's acceleration.
+//
+// The controller only moves the camera's eye and target, so the camera
+// path and zoom keep working on top of it.
+
+use winit::event::{DeviceEvent, ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
+
+use nalgebra_glm as glm;
+
+use crate::camera::Camera;
+
+/// Just short of straight up or down, where the look-at would flip.
+const MAX_PITCH: f32 = 1.5;
+const FAST_MULTIPLIER: f32 = 4.0;
+
+#[derive(Copy, Clone, Debug)]
+pub struct FlyController
+{
+	/// Units per second.
+	pub speed: f32,
+	/// Radians per pixel of mouse motion.
+	pub sensitivity: f32,
+	forward: bool,
+	back: bool,
+	left: bool,
+	right: bool,
+	up: bool,
+	down: bool,
+	fast: bool,
+	looking: bool,
+	// mouse motion since the last update, in pixels
+	look_delta: (f32, f32),
+}
+
+impl Default for FlyController
+{
+	fn default() -> Self
+	{
+		Self {
+			speed: 2.0,
+			sensitivity: 0.003,
+			forward: false,
+			back: false,
+			left: false,
+			right: false,
+			up: false,
+			down: false,
+			fast: false,
+			looking: false,
+			look_delta: (0.0, 0.0),
+		}
+	}
+}
+
+impl FlyController
+{
+	/// Tracks the movement keys and the look button. Returns true if the
+	/// event was one of them.
+	pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool
+	{
+		match event
+		{
+			WindowEvent::KeyboardInput { input: KeyboardInput { state, virtual_keycode: Some(key), .. }, .. } =>
+			{
+				let pressed = *state == ElementState::Pressed;
+				let held = match key
+				{
+					VirtualKeyCode::W => &mut self.forward,
+					VirtualKeyCode::S => &mut self.back,
+					VirtualKeyCode::A => &mut self.left,
+					VirtualKeyCode::D => &mut self.right,
+					VirtualKeyCode::E => &mut self.up,
+					VirtualKeyCode::Q => &mut self.down,
+					VirtualKeyCode::LShift => &mut self.fast,
+					_ => return false,
+				};
+				*held = pressed;
+				true
+			},
+			WindowEvent::MouseInput { state, button: MouseButton::Right, .. } =>
+			{
+				self.looking = *state == ElementState::Pressed;
+				true
+			},
+			// keys can be released while another window has focus
+			WindowEvent::Focused(false) =>
+			{
+				*self = Self { speed: self.speed, sensitivity: self.sensitivity, ..Self::default() };
+				false
+			},
+			_ => false,
+		}
+	}
+
+	/// Accumulates mouse motion while looking around.
+	pub fn handle_device_event(&mut self, event: &DeviceEvent)
+	{
+		if let DeviceEvent::MouseMotion { delta: (x, y) } = event
+		{
+			if self.looking
+			{
+				self.look_delta.0 += *x as f32;
+				self.look_delta.1 += *y as f32;
+			}
+		}
+	}
+
+	/// Applies the mouse motion since the last update and moves for `dt`
+	/// seconds at the held keys' velocity.
+	pub fn update(&mut self, camera: &mut Camera, dt: f32)
+	{
+		let distance = glm::distance(&camera.eye, &camera.target);
+		let up = glm::normalize(&camera.up);
+		let mut forward = glm::normalize(&(camera.target - camera.eye));
+
+		let (yaw, pitch) = self.look_delta;
+		self.look_delta = (0.0, 0.0);
+		if yaw != 0.0 || pitch != 0.0
+		{
+			forward = glm::rotate_vec3(&forward, -yaw * self.sensitivity, &up);
+
+			let right = glm::normalize(&glm::cross(&forward, &up));
+			let current = glm::dot(&forward, &up).clamp(-1.0, 1.0).asin();
+			let target = (current - pitch * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+			forward = glm::rotate_vec3(&forward, target - current, &right);
+		}
+
+		let right = glm::normalize(&glm::cross(&forward, &up));
+		let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+		let direction = forward * axis(self.forward, self.back)
+			+ right * axis(self.right, self.left)
+			+ up * axis(self.up, self.down);
+
+		if direction != glm::Vec3::zeros()
+		{
+			let speed = if self.fast { self.speed * FAST_MULTIPLIER } else { self.speed };
+			camera.eye += glm::normalize(&direction) * speed * dt;
+		}
+
+		camera.target = camera.eye + forward * distance;
+	}
+}
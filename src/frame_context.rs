@@ -0,0 +1,78 @@
+// Per-frame command buffers
+//
+// Every frame in flight has a context owning a command pool of its own,
+// which is reset whole at the start of the frame, once the frame's fence
+// has been waited on. Command buffers are handed out by the context for
+// the frame being recorded rather than kept by whoever records them, so
+// they're only good until the frame comes round again, when they're reset
+// and handed out again. Nothing is freed one command buffer at a time.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameContext
+{
+	command_pool: vk::CommandPool,
+	// allocated the first time they're needed, reused by every frame after
+	primary: Vec<vk::CommandBuffer>,
+	secondary: Vec<vk::CommandBuffer>,
+	// primaries handed out since the last reset
+	primary_used: usize,
+}
+
+impl FrameContext
+{
+	pub fn new(command_pool: vk::CommandPool) -> Self
+	{
+		Self { command_pool, ..Default::default() }
+	}
+
+	/// Resets every command buffer handed out for the frame. Only once its
+	/// fence has signaled.
+	pub unsafe fn reset(&mut self, device: &Device) -> Result<()>
+	{
+		device.reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())?;
+		self.primary_used = 0;
+		Ok(())
+	}
+
+	/// A primary command buffer that isn't being used for the frame yet.
+	pub unsafe fn primary(&mut self, device: &Device) -> Result<vk::CommandBuffer>
+	{
+		if self.primary_used == self.primary.len()
+		{
+			self.primary.push(allocate(device, self.command_pool, vk::CommandBufferLevel::PRIMARY)?);
+		}
+
+		self.primary_used += 1;
+		Ok(self.primary[self.primary_used - 1])
+	}
+
+	/// The frame's `index`th secondary command buffer.
+	pub unsafe fn secondary(&mut self, device: &Device, index: usize) -> Result<vk::CommandBuffer>
+	{
+		while index >= self.secondary.len()
+		{
+			self.secondary.push(allocate(device, self.command_pool, vk::CommandBufferLevel::SECONDARY)?);
+		}
+
+		Ok(self.secondary[index])
+	}
+
+	/// Destroys the pool along with every command buffer from it.
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		device.destroy_command_pool(self.command_pool, None);
+	}
+}
+
+unsafe fn allocate(device: &Device, pool: vk::CommandPool, level: vk::CommandBufferLevel) -> Result<vk::CommandBuffer>
+{
+	let info = vk::CommandBufferAllocateInfo::builder()
+		.command_pool(pool)
+		.level(level)
+		.command_buffer_count(1);
+
+	Ok(device.allocate_command_buffers(&info)?[0])
+}
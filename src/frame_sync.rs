@@ -0,0 +1,171 @@
+// Frame synchronization
+//
+// Normally each frame in flight has a fence that its submission signals,
+// and starting the frame again waits for it. With `--timeline-sync`, on a
+// device with `VK_KHR_timeline_semaphore`, a single timeline semaphore
+// replaces the fences: every frame's submission signals it with the next
+// value in turn, so the value it has reached says how many frames have
+// finished, and waiting for any one frame, or all of them, is waiting for
+// the semaphore to reach a value. There's nothing to reset.
+//
+// Acquiring and presenting still use binary semaphores either way, the
+// swapchain can't wait on or signal a timeline.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrTimelineSemaphoreExtension;
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameSync
+{
+	fences: Vec<vk::Fence>,
+	timeline: Option<vk::Semaphore>,
+	// the value each frame's last submission signals the timeline with
+	frame_values: Vec<u64>,
+	// the value of the latest submission
+	value: u64,
+}
+
+impl FrameSync
+{
+	pub unsafe fn create(device: &Device, frames: usize, timeline: bool) -> Result<Self>
+	{
+		if timeline
+		{
+			let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+				.semaphore_type(vk::SemaphoreType::TIMELINE)
+				.initial_value(0);
+
+			let info = vk::SemaphoreCreateInfo::builder()
+				.push_next(&mut type_info);
+
+			let semaphore = device.create_semaphore(&info, None)?;
+			return Ok(Self { timeline: Some(semaphore), frame_values: vec![0; frames], ..Default::default() });
+		}
+
+		let info = vk::FenceCreateInfo::builder()
+			.flags(vk::FenceCreateFlags::SIGNALED);
+
+		let fences = (0..frames)
+			.map(|_| device.create_fence(&info, None))
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Ok(Self { fences, ..Default::default() })
+	}
+
+	pub fn is_timeline(&self) -> bool
+	{
+		self.timeline.is_some()
+	}
+
+	/// Waits until the frame's last submission has completed.
+	pub unsafe fn wait_for_frame(&self, device: &Device, frame: usize) -> Result<()>
+	{
+		match self.timeline
+		{
+			Some(_) => self.wait_for_value(device, self.frame_values[frame]),
+			None =>
+			{
+				device.wait_for_fences(&[self.fences[frame]], true, u64::MAX)?;
+				Ok(())
+			},
+		}
+	}
+
+	/// Waits until every frame submitted so far has completed.
+	pub unsafe fn wait_for_all(&self, device: &Device) -> Result<()>
+	{
+		match self.timeline
+		{
+			Some(_) => self.wait_for_value(device, self.value),
+			None =>
+			{
+				device.wait_for_fences(&self.fences, true, u64::MAX)?;
+				Ok(())
+			},
+		}
+	}
+
+	/// Waits until the timeline reaches `value`, which is once every frame
+	/// submitted with a value up to it has completed. Returns straight away
+	/// without a timeline, where there are only the frames' fences.
+	pub unsafe fn wait_for_value(&self, device: &Device, value: u64) -> Result<()>
+	{
+		if let Some(timeline) = self.timeline
+		{
+			let semaphores = &[timeline];
+			let values = &[value];
+			let info = vk::SemaphoreWaitInfo::builder()
+				.semaphores(semaphores)
+				.values(values);
+
+			device.wait_semaphores_khr(&info, u64::MAX)?;
+		}
+
+		Ok(())
+	}
+
+	/// Submits the frame's work, which signals the frame's fence or the
+	/// timeline's next value once it completes.
+	pub unsafe fn submit(
+		&mut self,
+		device: &Device,
+		queue: vk::Queue,
+		frame: usize,
+		wait_semaphores: &[vk::Semaphore],
+		wait_stages: &[vk::PipelineStageFlags],
+		command_buffers: &[vk::CommandBuffer],
+		signal_semaphores: &[vk::Semaphore],
+		) -> Result<()>
+	{
+		let timeline = match self.timeline
+		{
+			Some(timeline) => timeline,
+			None =>
+			{
+				let info = vk::SubmitInfo::builder()
+					.wait_semaphores(wait_semaphores)
+					.wait_dst_stage_mask(wait_stages)
+					.command_buffers(command_buffers)
+					.signal_semaphores(signal_semaphores);
+
+				device.reset_fences(&[self.fences[frame]])?;
+				device.queue_submit(queue, &[info], self.fences[frame])?;
+				return Ok(());
+			},
+		};
+
+		self.value += 1;
+		self.frame_values[frame] = self.value;
+
+		let signal = signal_semaphores.iter().copied().chain([timeline]).collect::<Vec<_>>();
+
+		// binary semaphores ignore their values, but still need one each
+		let wait_values = vec![0; wait_semaphores.len()];
+		let mut signal_values = vec![0; signal_semaphores.len()];
+		signal_values.push(self.value);
+
+		let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+			.wait_semaphore_values(&wait_values)
+			.signal_semaphore_values(&signal_values);
+
+		let info = vk::SubmitInfo::builder()
+			.wait_semaphores(wait_semaphores)
+			.wait_dst_stage_mask(wait_stages)
+			.command_buffers(command_buffers)
+			.signal_semaphores(&signal)
+			.push_next(&mut timeline_info);
+
+		device.queue_submit(queue, &[info], vk::Fence::null())?;
+		Ok(())
+	}
+
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		self.fences.iter().for_each(|f| device.destroy_fence(*f, None));
+		if let Some(timeline) = self.timeline
+		{
+			device.destroy_semaphore(timeline, None);
+		}
+	}
+}
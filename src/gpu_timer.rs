@@ -0,0 +1,65 @@
+// GPU frame timing
+//
+// Each swapchain image's command buffer writes a timestamp at the start and
+// end of the frame into its slot of the timestamp queries. The results are
+// read back the next time that image is used, after its fence has been
+// waited on, so reading never stalls.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::queries::{QueryKind, QueryPool};
+use crate::AppData;
+
+/// Creates a pair of timestamp queries per swapchain image. The timer does
+/// nothing if the graphics queue can't write timestamps.
+pub unsafe fn create_timestamp_queries(device: &Device, data: &mut AppData) -> Result<()>
+{
+	data.timestamp_queries = QueryPool::create(
+		device,
+		&data.capabilities,
+		QueryKind::Timestamp,
+		data.swapchain_images.len(),
+		2,
+	)?;
+
+	Ok(())
+}
+
+/// Must be recorded outside of a render pass.
+pub unsafe fn begin_frame_timer(
+	device: &Device,
+	data: &mut AppData,
+	command_buffer: vk::CommandBuffer,
+	image_index: usize,
+	)
+{
+	data.timestamp_queries.begin_slot(device, command_buffer, image_index);
+	data.timestamp_queries.write_timestamp(device, command_buffer, image_index, vk::PipelineStageFlags::TOP_OF_PIPE);
+}
+
+pub unsafe fn end_frame_timer(
+	device: &Device,
+	data: &mut AppData,
+	command_buffer: vk::CommandBuffer,
+	image_index: usize,
+	)
+{
+	data.timestamp_queries.write_timestamp(device, command_buffer, image_index, vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+}
+
+/// GPU time in milliseconds of the last frame rendered to the image,
+/// if it's available.
+pub unsafe fn read_frame_time(
+	device: &Device,
+	data: &AppData,
+	image_index: usize,
+	) -> Result<Option<f32>>
+{
+	data.timestamp_queries.elapsed_ms(device, image_index)
+}
+
+pub unsafe fn destroy_timestamp_queries(device: &Device, data: &AppData)
+{
+	data.timestamp_queries.destroy(device);
+}
@@ -0,0 +1,213 @@
+// Headless rendering
+//
+// `--headless` renders without a window, so the renderer can run in CI or
+// on a server without a display. There's no surface or swapchain: an
+// offscreen color image per frame in flight stands in for the swapchain's
+// images, the composite pass renders into it the same way, and nothing is
+// presented. Once the requested frames are done the last one is read back
+// and saved as a PNG, e.g.
+//
+// vulkan-tutorial --headless --width 1280 --height 720 --frames 60 --output frame.png
+//
+// Without a window there are no key bindings or resizing, the frames are
+// driven by the clock and whatever demo is playing. With --deterministic
+// every run saves the same image.
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::path::Path;
+
+use crate::allocator;
+use crate::config::Config;
+use crate::options::Options;
+use crate::readback;
+use crate::validation;
+use crate::{create_image, App, AppData, MAX_FRAMES_IN_FLIGHT};
+
+/// sRGB like the swapchain's, so the PNG gets the same bytes a window
+/// would have shown.
+pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Renders `--frames` frames without a window, saves the last to
+/// `--output` and tears everything down.
+pub unsafe fn run(options: &Options, config: Config) -> Result<()>
+{
+	let mut app = App::create(None, options, config)?;
+	let result = render_frames(&mut app, options.frames.max(1), &options.output);
+
+	app.wait_for_frames()?;
+	app.destroy();
+	result?;
+
+	if options.fail_on_validation && validation::report_failures() > 0
+	{
+		return Err(anyhow!("Validation reported failures"));
+	}
+
+	Ok(())
+}
+
+unsafe fn render_frames(app: &mut App, frames: u32, path: &Path) -> Result<()>
+{
+	let mut last = 0;
+	for _ in 0..frames
+	{
+		last = app.frame;
+		app.render_headless()?;
+	}
+
+	app.wait_for_frames()?;
+	save_png(&app.instance, &app.device, &app.data, last, path)?;
+	info!("Saved {} after {} frames", path.display(), frames);
+
+	if !app.captures.targets.is_empty()
+	{
+		app.save_captures()?;
+	}
+
+	Ok(())
+}
+
+/// The layout the composite pass leaves its target in, ready to present or,
+/// without a swapchain, to be copied out.
+pub fn final_layout(data: &AppData) -> vk::ImageLayout
+{
+	if data.headless { vk::ImageLayout::TRANSFER_SRC_OPTIMAL } else { vk::ImageLayout::PRESENT_SRC_KHR }
+}
+
+/// Creates the images standing in for the swapchain's, one per frame in
+/// flight, so a frame's target is free once the frame is.
+pub unsafe fn create_targets(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	extent: vk::Extent2D,
+	) -> Result<()>
+{
+	data.swapchain_format = FORMAT;
+	data.swapchain_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC;
+	data.swapchain_extent = extent;
+	data.swapchain_images.clear();
+	data.headless_images_memory.clear();
+
+	for _ in 0..MAX_FRAMES_IN_FLIGHT
+	{
+		let (image, memory) = create_image(
+			instance,
+			device,
+			data,
+			extent.width,
+			extent.height,
+			1,
+			vk::SampleCountFlags::_1,
+			FORMAT,
+			vk::ImageTiling::OPTIMAL,
+			data.swapchain_usage,
+			vk::MemoryPropertyFlags::DEVICE_LOCAL,
+		)?;
+
+		data.swapchain_images.push(image);
+		data.headless_images_memory.push(memory);
+	}
+
+	info!("Headless: {} targets of {}x{}", MAX_FRAMES_IN_FLIGHT, extent.width, extent.height);
+	Ok(())
+}
+
+/// Copies a finished target out and writes it as a PNG. The frame that
+/// rendered it must have completed.
+pub unsafe fn save_png(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	image_index: usize,
+	path: &Path,
+	) -> Result<()>
+{
+	let extent = data.swapchain_extent;
+	let size = (extent.width * extent.height * 4) as u64;
+
+	let pixels = readback::read_now(instance, device, data, size, |command_buffer, buffer|
+		record_copy(device, data, command_buffer, image_index, buffer))?;
+
+	let image = image::RgbaImage::from_raw(extent.width, extent.height, pixels)
+		.ok_or_else(|| anyhow!("headless frame is the wrong size"))?;
+	image.save(path)?;
+
+	Ok(())
+}
+
+/// Records the copy of the target into `buffer`.
+unsafe fn record_copy(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	image_index: usize,
+	buffer: vk::Buffer,
+	)
+{
+	let extent = data.swapchain_extent;
+
+	let subresource = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	// already in the layout the copy needs, only the pass's writes have to
+	// be made visible to it
+	let to_transfer = vk::ImageMemoryBarrier::builder()
+		.old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+		.new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(data.swapchain_images[image_index])
+		.subresource_range(subresource)
+		.src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+		.dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+		vk::PipelineStageFlags::TRANSFER,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[to_transfer],
+	);
+
+	let region = vk::BufferImageCopy::builder()
+		.buffer_offset(0)
+		.buffer_row_length(0)
+		.buffer_image_height(0)
+		.image_subresource(vk::ImageSubresourceLayers {
+			aspect_mask: vk::ImageAspectFlags::COLOR,
+			mip_level: 0,
+			base_array_layer: 0,
+			layer_count: 1,
+		})
+		.image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+		.image_extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 });
+
+	device.cmd_copy_image_to_buffer(
+		command_buffer,
+		data.swapchain_images[image_index],
+		vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+		buffer,
+		&[region],
+	);
+}
+
+/// Destroys the stand-in images, their views go with the swapchain's.
+pub unsafe fn destroy_targets(device: &Device, data: &AppData)
+{
+	data.swapchain_images
+		.iter()
+		.for_each(|i| device.destroy_image(*i, None));
+	data.headless_images_memory
+		.iter()
+		.for_each(|m| allocator::free(device, *m));
+}
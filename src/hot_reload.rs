@@ -0,0 +1,142 @@
+// Hot-reloaded demo logic (enabled with the `hot-reload` feature)
+//
+// The demo's per-frame logic lives in the `demo` crate, built as a dynamic
+// library next to the app. Rebuilding it while the app runs, e.g. with
+//
+// cargo build -p demo
+//
+// swaps the new logic in on the next poll while the renderer keeps its
+// device, pipelines and uploaded assets. The library is copied before it's
+// loaded so the build can overwrite the original, and a copy is only
+// swapped in once it loads and its ABI version matches, so a bad build
+// leaves the old logic running.
+
+use anyhow::{anyhow, Result};
+use libloading::Library;
+use log::*;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+pub use demo::DemoState;
+
+type UpdateFn = extern "C" fn(&mut DemoState);
+type AbiVersionFn = extern "C" fn() -> u32;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Where cargo puts the library for the profile the app was built with.
+fn library_path() -> PathBuf
+{
+	let profile = if cfg!(debug_assertions) { "debug" } else { "release" };
+	Path::new("target").join(profile).join(libloading::library_filename("demo"))
+}
+
+/// A loaded copy of the library, deleted when it's unloaded.
+#[derive(Debug)]
+struct Loaded
+{
+	// taken on drop so it's unloaded before the copy is deleted
+	library: Option<Library>,
+	update: UpdateFn,
+	copy: PathBuf,
+}
+
+impl Drop for Loaded
+{
+	fn drop(&mut self)
+	{
+		self.library.take();
+		let _ = fs::remove_file(&self.copy);
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct DemoLibrary
+{
+	path: PathBuf,
+	modified: Option<SystemTime>,
+	last_poll: Instant,
+	loaded: Option<Rc<Loaded>>,
+	reloads: u32,
+}
+
+impl DemoLibrary
+{
+	pub fn new() -> Self
+	{
+		Self { path: library_path(), modified: None, last_poll: Instant::now() - POLL_INTERVAL, loaded: None, reloads: 0 }
+	}
+
+	/// Loads the library if it was rebuilt since the last poll. Polling is
+	/// throttled so this is cheap to call every frame.
+	pub unsafe fn poll(&mut self)
+	{
+		if self.last_poll.elapsed() < POLL_INTERVAL
+		{
+			return;
+		}
+
+		self.last_poll = Instant::now();
+
+		let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+		if modified.is_none() || modified == self.modified
+		{
+			return;
+		}
+
+		self.modified = modified;
+		let file_name = self.path.file_name().unwrap_or_default().to_string_lossy();
+		let copy = std::env::temp_dir().join(format!("{}-{}-{}", std::process::id(), self.reloads, file_name));
+		match self.load(&copy)
+		{
+			Ok(loaded) =>
+			{
+				info!("Loaded demo logic from {} (reload {})", self.path.display(), self.reloads);
+				self.loaded = Some(Rc::new(loaded));
+				self.reloads += 1;
+			},
+			Err(e) =>
+			{
+				let _ = fs::remove_file(&copy);
+				warn!("Failed to load demo logic, keeping the old one: {}", e);
+			},
+		}
+	}
+
+	unsafe fn load(&self, copy: &Path) -> Result<Loaded>
+	{
+		fs::copy(&self.path, copy)?;
+
+		let library = Library::new(copy)?;
+		let abi_version = *library.get::<AbiVersionFn>(b"demo_abi_version\0")?;
+		if abi_version() != demo::ABI_VERSION
+		{
+			return Err(anyhow!("ABI version {}, expected {}", abi_version(), demo::ABI_VERSION));
+		}
+
+		let update = *library.get::<UpdateFn>(b"demo_update\0")?;
+		Ok(Loaded { library: Some(library), update, copy: copy.to_path_buf() })
+	}
+
+	pub fn is_loaded(&self) -> bool
+	{
+		self.loaded.is_some()
+	}
+
+	/// Runs the loaded logic, returning false if nothing is loaded.
+	pub fn update(&self, state: &mut DemoState) -> bool
+	{
+		match &self.loaded
+		{
+			Some(loaded) =>
+			{
+				(loaded.update)(state);
+				true
+			},
+			None => false,
+		}
+	}
+}
@@ -0,0 +1,33 @@
+// Image decoding
+//
+// Textures are decoded to 8 bit RGBA whatever they were saved as, PNG or
+// JPEG, with or without alpha, since RGB formats are rarely sampleable and
+// grayscale would need a swizzle per material slot. Whether the values are
+// sRGB or linear is up to the format the pixels are uploaded with.
+
+use anyhow::{anyhow, Result};
+
+/// Tightly packed RGBA8 rows, top to bottom.
+#[derive(Clone, Debug)]
+pub struct Pixels
+{
+	pub width: u32,
+	pub height: u32,
+	pub rgba: Vec<u8>,
+}
+
+/// Decodes an image of any supported format, guessed from its contents.
+/// `name` is only used in errors.
+pub fn decode_rgba(bytes: &[u8], name: &str) -> Result<Pixels>
+{
+	let image = image::load_from_memory(bytes)
+		.map_err(|e| anyhow!("Failed to decode {}: {}", name, e))?
+		.into_rgba8();
+
+	Ok(Pixels { width: image.width(), height: image.height(), rgba: image.into_raw() })
+}
+
+pub fn load_rgba(path: &str) -> Result<Pixels>
+{
+	decode_rgba(&std::fs::read(path)?, path)
+}
@@ -0,0 +1,175 @@
+// Immediate submission
+//
+// One-time command buffers for uploads, layout transitions and other setup
+// work. Each is normally submitted as soon as it's ended and waited on with
+// a fence. Between `begin_batch` and `end_batch`, consecutive ones for the
+// same queue are recorded into a single command buffer and submitted
+// together, so loading a handful of resources waits on the GPU a few times
+// rather than once per resource. Staging buffers freed during a batch are
+// kept until it's been submitted and finished with them.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use std::cell::RefCell;
+
+use crate::allocator::{self, Allocation};
+
+#[derive(Copy, Clone, Debug)]
+struct OpenCommands
+{
+	command_buffer: vk::CommandBuffer,
+	queue: vk::Queue,
+	command_pool: vk::CommandPool,
+}
+
+#[derive(Clone, Debug, Default)]
+struct BatchState
+{
+	batching: bool,
+	open: Option<OpenCommands>,
+	staging: Vec<(vk::Buffer, Allocation)>,
+}
+
+/// Interior mutability lets helpers that only borrow `AppData` immutably
+/// record into the open batch.
+#[derive(Clone, Debug, Default)]
+pub struct ImmediateSubmit
+{
+	fence: vk::Fence,
+	state: RefCell<BatchState>,
+}
+
+impl ImmediateSubmit
+{
+	pub unsafe fn create(device: &Device) -> Result<Self>
+	{
+		let info = vk::FenceCreateInfo::builder();
+		Ok(Self { fence: device.create_fence(&info, None)?, state: RefCell::default() })
+	}
+
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		device.destroy_fence(self.fence, None);
+	}
+
+	/// A command buffer to record into, the batch's own if one is open for
+	/// the queue. Work for another queue submits the open batch first so
+	/// everything still runs in the order it was recorded.
+	pub unsafe fn begin(
+		&self,
+		device: &Device,
+		command_pool: vk::CommandPool,
+		queue: vk::Queue,
+		) -> Result<vk::CommandBuffer>
+	{
+		let open = self.state.borrow().open;
+		match open
+		{
+			Some(open) if open.queue == queue && open.command_pool == command_pool => return Ok(open.command_buffer),
+			Some(_) => self.flush(device)?,
+			None => (),
+		}
+
+		let info = vk::CommandBufferAllocateInfo::builder()
+			.level(vk::CommandBufferLevel::PRIMARY)
+			.command_pool(command_pool)
+			.command_buffer_count(1);
+
+		let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+		let info = vk::CommandBufferBeginInfo::builder()
+			.flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+		device.begin_command_buffer(command_buffer, &info)?;
+
+		let mut state = self.state.borrow_mut();
+		if state.batching
+		{
+			state.open = Some(OpenCommands { command_buffer, queue, command_pool });
+		}
+
+		Ok(command_buffer)
+	}
+
+	/// Submits and waits for the commands, unless they're part of a batch,
+	/// which is submitted when it ends.
+	pub unsafe fn end(
+		&self,
+		device: &Device,
+		command_buffer: vk::CommandBuffer,
+		queue: vk::Queue,
+		command_pool: vk::CommandPool,
+		) -> Result<()>
+	{
+		if self.state.borrow().batching
+		{
+			return Ok(());
+		}
+
+		self.submit(device, OpenCommands { command_buffer, queue, command_pool })
+	}
+
+	unsafe fn submit(&self, device: &Device, commands: OpenCommands) -> Result<()>
+	{
+		device.end_command_buffer(commands.command_buffer)?;
+
+		let command_buffers = &[commands.command_buffer];
+		let info = vk::SubmitInfo::builder()
+			.command_buffers(command_buffers);
+
+		device.queue_submit(commands.queue, &[info], self.fence)?;
+		device.wait_for_fences(&[self.fence], true, u64::max_value())?;
+		device.reset_fences(&[self.fence])?;
+		device.free_command_buffers(commands.command_pool, command_buffers);
+
+		Ok(())
+	}
+
+	/// Submits whatever the batch has recorded so far and frees the staging
+	/// buffers it was waiting on.
+	pub unsafe fn flush(&self, device: &Device) -> Result<()>
+	{
+		let open = self.state.borrow_mut().open.take();
+		if let Some(open) = open
+		{
+			self.submit(device, open)?;
+		}
+
+		let staging = std::mem::take(&mut self.state.borrow_mut().staging);
+		for (buffer, memory) in staging
+		{
+			device.destroy_buffer(buffer, None);
+			allocator::free(device, memory);
+		}
+
+		Ok(())
+	}
+
+	pub fn begin_batch(&self)
+	{
+		self.state.borrow_mut().batching = true;
+	}
+
+	pub unsafe fn end_batch(&self, device: &Device) -> Result<()>
+	{
+		self.flush(device)?;
+		self.state.borrow_mut().batching = false;
+		Ok(())
+	}
+
+	/// Frees a staging buffer once the commands copying from it are done.
+	pub unsafe fn free_staging(&self, device: &Device, (buffer, memory): (vk::Buffer, Allocation))
+	{
+		let mut state = self.state.borrow_mut();
+		if state.batching
+		{
+			state.staging.push((buffer, memory));
+		}
+		else
+		{
+			device.destroy_buffer(buffer, None);
+			allocator::free(device, memory);
+		}
+	}
+}
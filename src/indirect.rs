@@ -0,0 +1,187 @@
+// Indirect dispatch
+//
+// Compute work whose size is only known on the GPU, e.g. how many particles
+// were emitted or how many clusters survived culling, is dispatched with
+// `cmd_dispatch_indirect` from arguments the GPU writes itself, so it never
+// waits on a readback to learn how much to launch.
+//
+// The producing pass writes a count into a storage buffer, `DispatchArgs`
+// turns it into workgroup counts, and the consumer is dispatched from them:
+//
+//     producer writes count
+//     dispatch_args.record(count_buffer, args)   one invocation
+//     args.barrier()                             shader write -> indirect read
+//     args.record_dispatch()                     the real workload
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use std::mem::size_of;
+
+use crate::allocator::{self, Allocation};
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+use crate::{create_buffer, create_shader_module, AppData};
+
+/// Must match the push constants of `dispatch_args.comp`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct DispatchArgsPushConstants
+{
+	workgroup_size: u32,
+	max_groups: u32,
+}
+
+/// A device local `vk::DispatchIndirectCommand` the GPU fills in.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IndirectArgs
+{
+	pub buffer: vk::Buffer,
+	pub memory: Allocation,
+}
+
+impl IndirectArgs
+{
+	pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self>
+	{
+		let (buffer, memory) = create_buffer(
+			instance,
+			device,
+			data,
+			size_of::<vk::DispatchIndirectCommand>() as u64,
+			vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+			vk::MemoryPropertyFlags::DEVICE_LOCAL,
+		)?;
+
+		Ok(Self { buffer, memory })
+	}
+
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		device.destroy_buffer(self.buffer, None);
+		allocator::free(device, self.memory);
+	}
+
+	/// Makes the arguments a shader just wrote visible to the dispatch.
+	pub unsafe fn barrier(&self, device: &Device, command_buffer: vk::CommandBuffer)
+	{
+		let barrier = vk::BufferMemoryBarrier::builder()
+			.src_access_mask(vk::AccessFlags::SHADER_WRITE)
+			.dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ)
+			.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+			.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+			.buffer(self.buffer)
+			.offset(0)
+			.size(vk::WHOLE_SIZE);
+
+		device.cmd_pipeline_barrier(
+			command_buffer,
+			vk::PipelineStageFlags::COMPUTE_SHADER,
+			vk::PipelineStageFlags::DRAW_INDIRECT,
+			vk::DependencyFlags::empty(),
+			&[] as &[vk::MemoryBarrier],
+			&[barrier],
+			&[] as &[vk::ImageMemoryBarrier],
+		);
+	}
+
+	/// Dispatches the bound compute pipeline with however many workgroups
+	/// the arguments hold.
+	pub unsafe fn record_dispatch(&self, device: &Device, command_buffer: vk::CommandBuffer)
+	{
+		device.cmd_dispatch_indirect(command_buffer, self.buffer, 0);
+	}
+}
+
+/// Builds `IndirectArgs` from a count in another buffer.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DispatchArgs
+{
+	descriptor_set_layout: vk::DescriptorSetLayout,
+	pipeline_layout: vk::PipelineLayout,
+	pipeline: vk::Pipeline,
+}
+
+impl DispatchArgs
+{
+	pub unsafe fn create(device: &Device, pipeline_cache: vk::PipelineCache) -> Result<Self>
+	{
+		let bindings = [0, 1].map(|binding|
+			{
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(binding)
+					.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build()
+			});
+
+		let info = vk::DescriptorSetLayoutCreateInfo::builder()
+			.bindings(&bindings);
+
+		let descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+		let set_layouts = &[descriptor_set_layout];
+		let push_constant_ranges = &[push_constant_range::<DispatchArgsPushConstants>(vk::ShaderStageFlags::COMPUTE)];
+		let info = vk::PipelineLayoutCreateInfo::builder()
+			.set_layouts(set_layouts)
+			.push_constant_ranges(push_constant_ranges);
+
+		let pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+		let comp_sm = create_shader_module(device, include_bytes!(concat!(env!("OUT_DIR"), "/dispatch_args_comp.spv")))?;
+
+		let stage = vk::PipelineShaderStageCreateInfo::builder()
+			.stage(vk::ShaderStageFlags::COMPUTE)
+			.module(comp_sm)
+			.name(b"main\0");
+
+		let info = vk::ComputePipelineCreateInfo::builder()
+			.stage(stage)
+			.layout(pipeline_layout);
+
+		let pipeline = device.create_compute_pipelines(pipeline_cache, &[info], None)?.0[0];
+		device.destroy_shader_module(comp_sm, None);
+
+		Ok(Self { descriptor_set_layout, pipeline_layout, pipeline })
+	}
+
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		device.destroy_pipeline(self.pipeline, None);
+		device.destroy_pipeline_layout(self.pipeline_layout, None);
+		device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+	}
+
+	/// The layout of the sets passed to `record`, storage buffers with the
+	/// count in binding 0 and the `IndirectArgs` buffer in binding 1.
+	pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout
+	{
+		self.descriptor_set_layout
+	}
+
+	/// Records writing enough workgroups of `workgroup_size` to cover the
+	/// count, capped at `max_groups` so a runaway count can't hang the GPU.
+	/// The count must already be visible to compute shaders.
+	pub unsafe fn record(
+		&self,
+		device: &Device,
+		command_buffer: vk::CommandBuffer,
+		set: vk::DescriptorSet,
+		workgroup_size: u32,
+		max_groups: u32,
+		)
+	{
+		device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+		device.cmd_bind_descriptor_sets(
+			command_buffer,
+			vk::PipelineBindPoint::COMPUTE,
+			self.pipeline_layout,
+			0,
+			&[set],
+			&[]);
+
+		let push_constants = DispatchArgsPushConstants { workgroup_size, max_groups };
+		cmd_push_constants(device, command_buffer, self.pipeline_layout, vk::ShaderStageFlags::COMPUTE, &push_constants);
+		device.cmd_dispatch(command_buffer, 1, 1, 1);
+	}
+}
@@ -0,0 +1,118 @@
+// Job system
+//
+// CPU work that splits into independent pieces runs on rayon's
+// work-stealing thread pools, one per system so a burst of asset decoding
+// can't hold up the culling a frame is waiting on. How many workers each
+// pool gets is set under `[jobs]` in config.toml, e.g.
+//
+// [jobs]
+// culling = 2
+// meshing = 4
+// decoding = 0
+// encoding = 1
+//
+// where 0 leaves it to rayon, a worker per core. Everything touching Vulkan
+// stays on the main thread: jobs produce plain data (visible chunks, meshes,
+// decoded pixels) and the caller uploads or records from it afterwards.
+
+use anyhow::Result;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum System
+{
+	/// Frustum culling.
+	Culling,
+	/// Building meshes, e.g. voxel chunks.
+	Meshing,
+	/// Decoding images read from disk.
+	Decoding,
+	/// Encoding images to save, e.g. screenshots.
+	Encoding,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkerCounts
+{
+	pub culling: usize,
+	pub meshing: usize,
+	pub decoding: usize,
+	pub encoding: usize,
+}
+
+/// A thread pool per system. The default has none, and runs jobs on
+/// rayon's global pool instead.
+#[derive(Clone, Debug, Default)]
+pub struct Jobs
+{
+	culling: Option<Arc<ThreadPool>>,
+	meshing: Option<Arc<ThreadPool>>,
+	decoding: Option<Arc<ThreadPool>>,
+	encoding: Option<Arc<ThreadPool>>,
+}
+
+impl Jobs
+{
+	pub fn new(workers: &WorkerCounts) -> Result<Self>
+	{
+		let pool = |name: &'static str, count: usize| -> Result<_>
+		{
+			let pool = ThreadPoolBuilder::new()
+				.num_threads(count)
+				.thread_name(move |i| format!("{}-{}", name, i))
+				.build()?;
+			Ok(Some(Arc::new(pool)))
+		};
+
+		Ok(Self {
+			culling: pool("culling", workers.culling)?,
+			meshing: pool("meshing", workers.meshing)?,
+			decoding: pool("decoding", workers.decoding)?,
+			encoding: pool("encoding", workers.encoding)?,
+		})
+	}
+
+	fn pool(&self, system: System) -> Option<&ThreadPool>
+	{
+		match system
+		{
+			System::Culling => self.culling.as_deref(),
+			System::Meshing => self.meshing.as_deref(),
+			System::Decoding => self.decoding.as_deref(),
+			System::Encoding => self.encoding.as_deref(),
+		}
+	}
+
+	/// Runs `f` on the system's pool, so any rayon iterators inside it are
+	/// split across that pool's workers. Blocks until it's done.
+	pub fn install<R: Send>(&self, system: System, f: impl FnOnce() -> R + Send) -> R
+	{
+		match self.pool(system)
+		{
+			Some(pool) => pool.install(f),
+			None => f(),
+		}
+	}
+
+	/// Runs `f` on one of the system's workers without waiting for it, for
+	/// work whose result nothing waits on.
+	pub fn spawn(&self, system: System, f: impl FnOnce() + Send + 'static)
+	{
+		match self.pool(system)
+		{
+			Some(pool) => pool.spawn(f),
+			None => rayon::spawn(f),
+		}
+	}
+
+	/// Maps every item in parallel on the system's pool, keeping their order.
+	pub fn map<T: Sync, R: Send>(&self, system: System, items: &[T], f: impl Fn(&T) -> R + Sync + Send) -> Vec<R>
+	{
+		self.install(system, || items.par_iter().map(f).collect())
+	}
+}
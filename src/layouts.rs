@@ -0,0 +1,99 @@
+// Debug-only image layout tracking
+//
+// Every layout transition we record goes through here so that misuse,
+// like sampling an image that's still in TRANSFER_DST_OPTIMAL, panics at
+// the call site that got it wrong instead of surfacing later as a vague
+// validation layer message. Release builds skip all of this.
+
+use vulkanalia::prelude::v1_0::*;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::panic::Location;
+
+const ENABLED: bool = cfg!(debug_assertions);
+
+#[derive(Copy, Clone, Debug)]
+struct TrackedLayout
+{
+	layout: vk::ImageLayout,
+	location: &'static Location<'static>,
+}
+
+/// Interior mutability lets the tracker live in `AppData` and be updated
+/// from helpers that only borrow it immutably.
+#[derive(Clone, Debug, Default)]
+pub struct LayoutTracker
+{
+	images: RefCell<HashMap<vk::Image, TrackedLayout>>,
+}
+
+impl LayoutTracker
+{
+	/// Records a transition, checking the image really was in `old_layout`.
+	/// Transitions from `UNDEFINED` discard the contents and are always valid.
+	#[track_caller]
+	pub fn transition(&self, image: vk::Image, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout)
+	{
+		if !ENABLED
+		{
+			return;
+		}
+
+		let mut images = self.images.borrow_mut();
+
+		if old_layout != vk::ImageLayout::UNDEFINED
+		{
+			match images.get(&image)
+			{
+				Some(tracked) if tracked.layout != old_layout => panic!(
+					"Image {:?} transitioned from {:?} to {:?} but it is in {:?} (set at {})",
+					image,
+					old_layout,
+					new_layout,
+					tracked.layout,
+					tracked.location,
+				),
+				None => panic!(
+					"Image {:?} transitioned from {:?} to {:?} but its layout was never recorded",
+					image,
+					old_layout,
+					new_layout,
+				),
+				_ => {},
+			}
+		}
+
+		images.insert(image, TrackedLayout { layout: new_layout, location: Location::caller() });
+	}
+
+	/// Checks an image is in the layout required for `usage`.
+	#[track_caller]
+	pub fn expect(&self, image: vk::Image, layout: vk::ImageLayout, usage: &str)
+	{
+		if !ENABLED
+		{
+			return;
+		}
+
+		match self.images.borrow().get(&image)
+		{
+			Some(tracked) if tracked.layout == layout => {},
+			Some(tracked) => panic!(
+				"Image {:?} used as {} requires {:?} but it is in {:?} (set at {})",
+				image,
+				usage,
+				layout,
+				tracked.layout,
+				tracked.location,
+			),
+			None => panic!(
+				"Image {:?} used as {} requires {:?} but its layout was never recorded",
+				image,
+				usage,
+				layout,
+			),
+		}
+	}
+
+}
@@ -0,0 +1,244 @@
+// Light probes
+//
+// A regular grid of probes stores the light arriving at each point as
+// second order spherical harmonics. They're baked by capturing a small
+// cubemap of the scene around every probe and projecting it, already
+// convolved with the cosine lobe so evaluating the harmonics in a direction
+// gives the irradiance for a surface facing that way. Models get indirect
+// diffuse lighting from the probes around them, interpolated trilinearly.
+
+use anyhow::Result;
+use log::*;
+use serde::{Deserialize, Serialize};
+use vulkanalia::prelude::v1_0::*;
+
+use nalgebra_glm as glm;
+
+use std::f32::consts::PI;
+
+use crate::cubemap::{self, CubemapCapture, Faces};
+use crate::AppData;
+
+pub const SH_COEFFICIENTS: usize = 9;
+
+/// Face size of the captured cubemaps. Irradiance is low frequency, so
+/// there's no point going much higher.
+const CAPTURE_SIZE: u32 = 32;
+
+/// Cosine lobe convolution per band.
+const BAND_SCALE: [f32; 3] = [PI, 2.0 * PI / 3.0, PI / 4.0];
+
+/// Irradiance coefficients as they're laid out in the uniform buffer.
+pub type ShIrradiance = [[f32; 4]; SH_COEFFICIENTS];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShProbe
+{
+	coefficients: [(f32, f32, f32); SH_COEFFICIENTS],
+}
+
+impl ShProbe
+{
+	/// Projects a captured cubemap, weighting each texel by the solid
+	/// angle it covers.
+	pub fn project(faces: &Faces, size: u32) -> Self
+	{
+		let mut coefficients = [glm::Vec3::zeros(); SH_COEFFICIENTS];
+		let mut total_weight = 0.0;
+
+		for (face, texels) in faces.iter().enumerate()
+		{
+			for (i, color) in texels.iter().enumerate()
+			{
+				let s = ((i as u32 % size) as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+				let t = ((i as u32 / size) as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+
+				let direction = cubemap::texel_direction(face, s, t);
+				let length_squared = glm::dot(&direction, &direction);
+				let weight = 1.0 / (length_squared * length_squared.sqrt());
+
+				let basis = sh_basis(&(direction / length_squared.sqrt()));
+				for (coefficient, b) in coefficients.iter_mut().zip(basis)
+				{
+					*coefficient += color * b * weight;
+				}
+
+				total_weight += weight;
+			}
+		}
+
+		let normalization = 4.0 * PI / total_weight;
+		let mut probe = Self::default();
+		for (i, coefficient) in coefficients.iter().enumerate()
+		{
+			let c = coefficient * normalization * BAND_SCALE[band(i)];
+			probe.coefficients[i] = (c.x, c.y, c.z);
+		}
+
+		probe
+	}
+}
+
+fn band(coefficient: usize) -> usize
+{
+	match coefficient
+	{
+		0 => 0,
+		1..=3 => 1,
+		_ => 2,
+	}
+}
+
+/// Real spherical harmonics up to the second band, in the same order as
+/// `shader.frag` evaluates them.
+fn sh_basis(d: &glm::Vec3) -> [f32; SH_COEFFICIENTS]
+{
+	[
+		0.282095,
+		0.488603 * d.y,
+		0.488603 * d.z,
+		0.488603 * d.x,
+		1.092548 * d.x * d.y,
+		1.092548 * d.y * d.z,
+		0.315392 * (3.0 * d.z * d.z - 1.0),
+		1.092548 * d.x * d.z,
+		0.546274 * (d.x * d.x - d.y * d.y),
+	]
+}
+
+/// Probes placed `spacing` apart starting from `origin`, x varying fastest.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightProbeGrid
+{
+	origin: (f32, f32, f32),
+	spacing: f32,
+	counts: (u32, u32, u32),
+	#[serde(default)]
+	probes: Vec<ShProbe>,
+}
+
+impl Default for LightProbeGrid
+{
+	/// Covers the spots models are placed at.
+	fn default() -> Self
+	{
+		Self {
+			origin: (-1.5, -3.0, -2.0),
+			spacing: 1.5,
+			counts: (3, 5, 4),
+			probes: Vec::new(),
+		}
+	}
+}
+
+impl LightProbeGrid
+{
+	fn len(&self) -> usize
+	{
+		(self.counts.0 * self.counts.1 * self.counts.2) as usize
+	}
+
+	pub fn is_baked(&self) -> bool
+	{
+		!self.probes.is_empty() && self.probes.len() == self.len()
+	}
+
+	fn index(&self, x: u32, y: u32, z: u32) -> usize
+	{
+		(x + self.counts.0 * (y + self.counts.1 * z)) as usize
+	}
+
+	pub fn positions(&self) -> Vec<glm::Vec3>
+	{
+		let origin = glm::vec3(self.origin.0, self.origin.1, self.origin.2);
+		let mut positions = Vec::with_capacity(self.len());
+		for z in 0..self.counts.2
+		{
+			for y in 0..self.counts.1
+			{
+				for x in 0..self.counts.0
+				{
+					positions.push(origin + glm::vec3(x as f32, y as f32, z as f32) * self.spacing);
+				}
+			}
+		}
+		positions
+	}
+
+	/// Trilinearly blends the eight probes around `position`, clamped to
+	/// the grid. None until the grid has been baked.
+	pub fn sample(&self, position: &glm::Vec3) -> Option<ShIrradiance>
+	{
+		if !self.is_baked()
+		{
+			return None;
+		}
+
+		let counts = [self.counts.0, self.counts.1, self.counts.2];
+		let origin = [self.origin.0, self.origin.1, self.origin.2];
+
+		let mut base = [0u32; 3];
+		let mut fraction = [0.0f32; 3];
+		for axis in 0..3
+		{
+			let cell = ((position[axis] - origin[axis]) / self.spacing)
+				.clamp(0.0, (counts[axis] - 1) as f32);
+			base[axis] = (cell as u32).min(counts[axis].saturating_sub(2));
+			fraction[axis] = (cell - base[axis] as f32).min(1.0);
+		}
+
+		let mut irradiance = [[0.0; 4]; SH_COEFFICIENTS];
+		for corner in 0..8
+		{
+			let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+
+			let mut weight = 1.0;
+			let mut cell = [0u32; 3];
+			for axis in 0..3
+			{
+				weight *= if offset[axis] == 1 { fraction[axis] } else { 1.0 - fraction[axis] };
+				cell[axis] = (base[axis] + offset[axis]).min(counts[axis] - 1);
+			}
+
+			let probe = &self.probes[self.index(cell[0], cell[1], cell[2])];
+			for (out, c) in irradiance.iter_mut().zip(probe.coefficients)
+			{
+				out[0] += c.0 * weight;
+				out[1] += c.1 * weight;
+				out[2] += c.2 * weight;
+			}
+		}
+
+		Some(irradiance)
+	}
+
+	/// Captures the scene from every probe. Only call this with the device idle.
+	pub unsafe fn bake(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &AppData,
+		near: f32,
+		far: f32,
+		models: &[glm::Mat4],
+		) -> Result<()>
+	{
+		let capture = CubemapCapture::create(instance, device, data, CAPTURE_SIZE)?;
+
+		let probes = self.positions()
+			.iter()
+			.map(|position|
+				{
+					capture.render(device, data, position, near, far, models)?;
+					Ok(ShProbe::project(&capture.faces(device)?, capture.size()))
+				})
+			.collect::<Result<Vec<_>>>();
+
+		capture.destroy(device);
+
+		self.probes = probes?;
+		info!("Baked {} light probes", self.probes.len());
+
+		Ok(())
+	}
+}
@@ -0,0 +1,41 @@
+// Baked lightmaps
+//
+// Static geometry can use lighting baked offline, e.g. in Blender, instead
+// of the light probes. The lightmap is sampled with the vertices' second UV
+// set and multiplied into the albedo; dynamic objects keep using the probes
+// since their baked lighting would be wrong as soon as they move.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{load_unmipped_texture, material_map_view, AppData};
+
+/// Binds the material's lightmap, loading it into the texture cache if it
+/// isn't resident already. Clears the binding if the material has none.
+pub unsafe fn load_lightmap(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	data.lightmap = None;
+
+	let path = match &data.material.lightmap
+	{
+		Some(path) => path.clone(),
+		None => return Ok(()),
+	};
+
+	// lightmaps are low resolution and stretched over large surfaces, so
+	// they're magnified far more often than minified and need no mipmaps
+	data.lightmap = Some(load_unmipped_texture(instance, device, data, &path, vk::Format::R8G8B8A8_SRGB)?);
+
+	Ok(())
+}
+
+/// The lightmap's view, or the material texture's when there isn't one so
+/// the descriptor is always valid.
+pub fn lightmap_view(data: &AppData) -> vk::ImageView
+{
+	material_map_view(data, &data.lightmap)
+}
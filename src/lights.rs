@@ -0,0 +1,287 @@
+// Punctual lights
+//
+// Spot and point lights placed in the scene file, e.g.
+//
+// lights: [
+//     (
+//         position: (0.0, 0.0, 3.0),
+//         color: (1.0, 0.9, 0.8),
+//         range: 8.0,
+//         kind: Spot(direction: (0.0, 0.0, -1.0), angle: 0.6),
+//         shadows: Some((resolution: 2048, slope_bias: 2.5, pcf_kernel: 5)),
+//     ),
+// ]
+//
+// Shadow quality and biasing are per light since no one set of constants
+// suits both a wide spot light far from a floor and a point light inches
+// from a wall. Anything left out of a light's shadow settings falls back to
+// the default, and lights without any cast no shadows.
+//
+// The main fragment shader lights surfaces with the first MAX_LIGHTS of
+// them. Each shadow casting light's faces are rendered into their atlas
+// tiles every frame and looked up through the frame's uniforms, the rest
+// are dropped.
+
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+
+use std::f32::consts::FRAC_PI_2;
+
+use crate::cubemap;
+use crate::shadow_atlas::{ShadowAtlas, ShadowTile};
+
+/// Lights the main fragment shader loops over, must match `shader.frag`.
+pub const MAX_LIGHTS: usize = 8;
+/// Shadow faces it can look up, six of them for each point light.
+pub const MAX_SHADOW_VIEWS: usize = 16;
+
+/// Where the shadow views' depth starts, close enough that a light placed
+/// against a wall still shadows what's in front of it.
+const SHADOW_NEAR: f32 = 0.05;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightKind
+{
+	/// A cone around `direction`, `angle` radians from its axis to its edge.
+	Spot { direction: (f32, f32, f32), angle: f32 },
+	/// Shines every way, so its shadow map is a cube of six tiles.
+	Point,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Light
+{
+	pub position: (f32, f32, f32),
+	pub color: (f32, f32, f32),
+	pub kind: LightKind,
+	/// Distance at which the light has faded out completely, and where its
+	/// shadow views end.
+	#[serde(default = "default_range")]
+	pub range: f32,
+	#[serde(default)]
+	pub shadows: Option<ShadowSettings>,
+}
+
+fn default_range() -> f32
+{
+	10.0
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowSettings
+{
+	/// Size of the light's tile in the shadow atlas, per face for point
+	/// lights. It gets a smaller one when the atlas is full.
+	pub resolution: u32,
+	/// Constant depth bias, in units of the depth format's precision.
+	pub depth_bias: f32,
+	/// Depth bias scaled by how steeply the surface slopes away from the
+	/// light.
+	pub slope_bias: f32,
+	/// How far along their normal surfaces are pushed before looking up the
+	/// shadow map, in texels of the light's tile, to hide acne on surfaces
+	/// nearly edge-on to the light.
+	pub normal_offset: f32,
+	/// Width of the square of texels filtered for soft edges, rounded up to
+	/// an odd number.
+	pub pcf_kernel: u32,
+}
+
+impl Default for ShadowSettings
+{
+	fn default() -> Self
+	{
+		Self {
+			resolution: 1024,
+			depth_bias: 1.25,
+			slope_bias: 1.75,
+			normal_offset: 1.0,
+			pcf_kernel: 3,
+		}
+	}
+}
+
+impl ShadowSettings
+{
+	/// The filter width the shader runs, odd so it centers on the texel.
+	pub fn pcf_kernel(&self) -> u32
+	{
+		self.pcf_kernel.clamp(1, 7) | 1
+	}
+}
+
+impl LightKind
+{
+	pub fn shadow_faces(self) -> usize
+	{
+		match self
+		{
+			LightKind::Spot { .. } => 1,
+			LightKind::Point => 6,
+		}
+	}
+}
+
+/// Allocates every shadow casting light's tiles from the atlas, one per
+/// face. Lights that cast no shadows get none, as do faces the atlas had
+/// no room left for.
+pub fn allocate_shadow_tiles(lights: &[Light], atlas: &mut ShadowAtlas) -> Vec<Vec<Option<ShadowTile>>>
+{
+	let sizes = lights
+		.iter()
+		.flat_map(|light|
+		{
+			let faces = light.shadows.map_or(0, |_| light.kind.shadow_faces());
+			std::iter::repeat(light.shadows.map_or(0, |s| s.resolution)).take(faces)
+		})
+		.collect::<Vec<_>>();
+
+	let mut tiles = atlas.allocate_all(&sizes).into_iter();
+	lights
+		.iter()
+		.map(|light|
+		{
+			let faces = light.shadows.map_or(0, |_| light.kind.shadow_faces());
+			tiles.by_ref().take(faces).collect()
+		})
+		.collect()
+}
+
+/// A light as `shader.frag` sees it, must match its `Light`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LightUniform
+{
+	/// w is the range
+	pub position: [f32; 4],
+	/// w is 1 for point lights and 0 for spot lights
+	pub color: [f32; 4],
+	/// w is the cosine of the cone's angle
+	pub direction: [f32; 4],
+	/// x is the first of its shadow views, -1 if it casts none
+	pub shadow: [f32; 4],
+}
+
+/// One face of a light's shadow, must match `ShadowView` in `shader.frag`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ShadowViewUniform
+{
+	pub view_proj: [[f32; 4]; 4],
+	/// Its tile's `uv_transform`, all zero when it didn't get one.
+	pub uv_transform: [f32; 4],
+	/// x is the width of one of its texels a unit from the light, y the
+	/// normal offset in texels and z the PCF kernel width.
+	pub filtering: [f32; 4],
+}
+
+/// A face of a shadow casting light, to render into its tile.
+#[derive(Copy, Clone, Debug)]
+pub struct ShadowView
+{
+	pub view_proj: glm::Mat4,
+	/// Its field of view, square.
+	pub fov: f32,
+	pub tile: Option<ShadowTile>,
+	pub settings: ShadowSettings,
+}
+
+impl ShadowView
+{
+	pub fn uniform(&self) -> ShadowViewUniform
+	{
+		let texel = self.tile.map_or(0.0, |tile| 2.0 * (self.fov / 2.0).tan() / tile.size as f32);
+		ShadowViewUniform {
+			view_proj: self.view_proj.into(),
+			uv_transform: self.tile.map_or([0.0; 4], ShadowTile::uv_transform),
+			filtering: [texel, self.settings.normal_offset, self.settings.pcf_kernel() as f32, 0.0],
+		}
+	}
+}
+
+impl Light
+{
+	fn position(&self) -> glm::Vec3
+	{
+		let (x, y, z) = self.position;
+		glm::vec3(x, y, z)
+	}
+
+	/// The field of view of each of its shadow faces.
+	fn shadow_fov(&self) -> f32
+	{
+		match self.kind
+		{
+			LightKind::Spot { angle, .. } => (angle * 2.0).clamp(0.01, 3.0),
+			LightKind::Point => FRAC_PI_2,
+		}
+	}
+
+	/// The view-projection of each of the light's shadow faces, in the
+	/// order of its tiles. A point light's are the cube faces in
+	/// `cubemap`'s order, which the shader picks between by direction.
+	pub fn shadow_view_projs(&self) -> Vec<glm::Mat4>
+	{
+		let position = self.position();
+		match self.kind
+		{
+			LightKind::Spot { direction: (x, y, z), .. } =>
+			{
+				let forward = glm::normalize(&glm::vec3(x, y, z));
+				let up = if forward.z.abs() < 0.99 { glm::vec3(0.0, 0.0, 1.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+				let view = glm::look_at(&position, &(position + forward), &up);
+				let proj = glm::perspective_rh_zo(1.0, self.shadow_fov(), SHADOW_NEAR, self.range);
+				vec![proj * view]
+			},
+			LightKind::Point => (0..6)
+				.map(|face| cubemap::face_view_proj(face, &position, SHADOW_NEAR, self.range))
+				.collect(),
+		}
+	}
+}
+
+/// The first MAX_LIGHTS lights' uniforms, and the shadow views they index.
+/// A light whose faces don't all fit in MAX_SHADOW_VIEWS casts no shadows.
+pub fn light_uniforms(lights: &[Light], tiles: &[Vec<Option<ShadowTile>>]) -> (Vec<LightUniform>, Vec<ShadowView>)
+{
+	let mut uniforms = Vec::new();
+	let mut views = Vec::new();
+
+	for (light, tiles) in lights.iter().zip(tiles).take(MAX_LIGHTS)
+	{
+		let (direction, cos_angle, point) = match light.kind
+		{
+			LightKind::Spot { direction: (x, y, z), angle } =>
+			{
+				let direction = glm::normalize(&glm::vec3(x, y, z));
+				([direction.x, direction.y, direction.z], angle.cos(), 0.0)
+			},
+			LightKind::Point => ([0.0; 3], -1.0, 1.0),
+		};
+
+		let mut first_view = -1.0;
+		if let Some(settings) = light.shadows
+		{
+			if views.len() + tiles.len() <= MAX_SHADOW_VIEWS
+			{
+				first_view = views.len() as f32;
+				views.extend(light.shadow_view_projs().into_iter().zip(tiles).map(|(view_proj, &tile)|
+				{
+					ShadowView { view_proj, fov: light.shadow_fov(), tile, settings }
+				}));
+			}
+		}
+
+		let (x, y, z) = light.position;
+		let (r, g, b) = light.color;
+		uniforms.push(LightUniform {
+			position: [x, y, z, light.range],
+			color: [r, g, b, point],
+			direction: [direction[0], direction[1], direction[2], cos_angle],
+			shadow: [first_view, 0.0, 0.0, 0.0],
+		});
+	}
+
+	(uniforms, views)
+}
@@ -6,11 +6,12 @@
 )]
 
 use winit::dpi::LogicalSize;
-use winit::event::{Event, WindowEvent, ElementState, VirtualKeyCode};
+use winit::event::{DeviceEvent, Event, WindowEvent, ElementState, MouseScrollDelta, VirtualKeyCode};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{Window, WindowBuilder};
 
 use anyhow::{anyhow, Result};
+use clap::Parser;
 use log::*;
 use vulkanalia::loader::{LibloadingLoader, LIBRARY};
 use vulkanalia::window as vk_window;
@@ -20,13 +21,13 @@ use vulkanalia::Version;
 use std::collections::HashSet;
 use std::ffi::CStr;
 use std::os::raw::c_void;
-use std::mem::size_of;
+use std::mem::{size_of, size_of_val};
 use std::ptr::copy_nonoverlapping as memcpy;
-use std::time::Instant;
-use std::fs::File;
+use std::ptr::NonNull;
+use std::time::{Duration, Instant};
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
-use std::io::BufReader;
+use std::path::Path;
 
 use thiserror::Error;
 
@@ -35,58 +36,497 @@ use vulkanalia::vk::
 	ExtDebugUtilsExtension,
 	DebugUtilsMessageTypeFlagsEXT,
 	DebugUtilsMessageSeverityFlagsEXT,
+	Handle,
 	KhrSurfaceExtension,
 	KhrSwapchainExtension,
 };
 
 use nalgebra_glm as glm;
 
+mod allocator;
+mod assets;
+mod basis;
+mod bloom;
+mod camera;
+mod camera_path;
+mod capabilities;
+mod capture;
+mod clock;
+mod composite;
+mod compute_mips;
+mod config;
+mod cubemap;
+mod debug_draw;
+mod deletion_queue;
+mod descriptors;
+mod depth_prepass;
+mod dynamic_rendering;
+mod dynamic_resolution;
+mod examples;
+mod fallback;
+mod fluid;
+mod fly_camera;
+mod frame_context;
+mod frame_sync;
+mod gpu_timer;
+mod headless;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+mod images;
+mod indirect;
+mod jobs;
+mod immediate;
+mod layouts;
+mod light_probes;
+mod lightmap;
+mod lights;
+mod material;
+mod memory_report;
+mod model;
+mod noise;
+mod options;
+mod overlay;
+mod particles;
+#[cfg(feature = "physics")]
+mod physics;
+mod pipeline_cache;
+mod planar_reflection;
+mod portals;
+mod post_process;
+mod push_constants;
+mod queries;
+mod readback;
+mod recording;
+mod reflection_probes;
+mod render_queue;
+mod samplers;
+mod scene;
+mod screenshot;
+mod sdf;
+mod sequence;
+mod shadow_atlas;
+mod sky;
+mod stats;
+mod stereo;
+mod stencil;
+mod stress;
+mod subgroups;
+mod time_of_day;
+mod transmission;
+mod validation;
+mod vertex_format;
+mod voxel;
+mod window_mode;
+
+use allocator::Allocation;
+use assets::{AssetHandle, Texture, TextureCache};
+use camera::{Camera, Frustum};
+use camera_path::CameraPath;
+use capabilities::DeviceCapabilities;
+use capture::{CaptureRequest, CaptureTarget};
+use clock::Clock;
+use composite::{InspectTarget, Supersampling, DEFAULT_SHARPNESS, MAX_RENDER_SCALE, MIN_RENDER_SCALE, RENDER_SCALE_STEP, SCENE_FORMAT};
+use compute_mips::{ComputeMips, MipGeneration};
+use config::{Config, ConfigWatcher, CONFIG_PATH};
+use cubemap::CubemapCapture;
+use debug_draw::{DebugCategory, DebugDraw};
+use deletion_queue::{DeletionQueue, Retired};
+use descriptors::Descriptors;
+use dynamic_resolution::DynamicResolution;
+use examples::Example;
+use fly_camera::FlyController;
+use frame_context::FrameContext;
+use frame_sync::FrameSync;
+use immediate::ImmediateSubmit;
+use indirect::DispatchArgs;
+use jobs::{Jobs, System};
+use layouts::LayoutTracker;
+use light_probes::ShIrradiance;
+use lights::{LightKind, LightUniform, ShadowViewUniform, MAX_LIGHTS, MAX_SHADOW_VIEWS};
+use material::{BlendMode, DepthVariant, Material, MaterialWatcher};
+use options::{GpuSelector, Options};
+use overlay::{Overlay, Settings};
+use planar_reflection::MirrorPlane;
+use portals::PortalPair;
+use post_process::ChainWatcher;
+use push_constants::{cmd_push_constants, push_constant_range};
+use queries::QueryPool;
+use readback::Readbacks;
+use recording::Recorder;
+use reflection_probes::MAX_REFLECTION_PROBES;
+use render_queue::{Draw, DrawState, Pass, RenderQueue};
+use samplers::{common_sampler, CommonSampler};
+use scene::Scene;
+use screenshot::Screenshots;
+use sequence::{Action, Sequence};
+use shadow_atlas::{ShadowAtlas, ShadowTile};
+use stats::FrameStats;
+use stereo::{Eye, StereoMode};
+use stencil::{StencilMode, OUTLINE_REFERENCE, OUTLINE_SCALE};
+use stress::{StressStep, StressTest};
+use subgroups::Reductions;
+use time_of_day::TimeOfDay;
+use vertex_format::{IndexWidth, VertexLayout, VertexStreams};
+use voxel::VoxelWorld;
+use window_mode::{WindowMode, WindowModeState};
+
 const PORTABILITY_MACOS_VERSION: Version = Version::new(1, 3, 216);
 const VALIDATION_ENABLED: bool = cfg!(debug_assertions);
 const VALIDATION_LAYER: vk::ExtensionName =
 	vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
 const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
-const MAX_FRAMES_IN_FLIGHT: usize = 2;
+// sync objects are created for the most that can be chosen at runtime
+const MAX_FRAMES_IN_FLIGHT: usize = 3;
+const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+/// Must match `MAX_MODELS` in the demo crate.
+const MAX_MODELS: usize = 4;
+// per swapchain image in the object buffer
+const MAX_OBJECTS: usize = 256;
+const WINDOW_TITLE: &str = "Vulkan Tutorial (Rust)";
+// what V cycles through, vsync'd, vsync'd without blocking, and torn
+const PRESENT_MODES: &[vk::PresentModeKHR] = &[
+	vk::PresentModeKHR::FIFO,
+	vk::PresentModeKHR::MAILBOX,
+	vk::PresentModeKHR::IMMEDIATE,
+];
+// what U cycles through, skipping counts the device doesn't support
+const MSAA_SAMPLES: &[u32] = &[1, 2, 4, 8];
+// positions on their own let depth-only passes skip the other attributes
+const VERTEX_STREAMS: VertexStreams = VertexStreams::Deinterleaved;
+const INDEX_WIDTH: IndexWidth = IndexWidth::Smallest;
+// how far the texture LOD knobs move per key press
+const LOD_BIAS_STEP: f32 = 0.5;
+const MIP_GENERATION: MipGeneration = MipGeneration::Blit;
+const Z_NEAR: f32 = 0.1;
+const Z_FAR: f32 = 10.0;
+// of each cube face R captures, the panorama is four times as wide
+const PANORAMA_FACE_SIZE: u32 = 512;
 
 fn main() -> Result<()>
 {
 	pretty_env_logger::init();
 
+	let options = Options::parse();
+	if options.list_gpus
+	{
+		return unsafe { list_gpus() };
+	}
+
+	let mut config = Config::load_or_create(Path::new(CONFIG_PATH));
+	if let Some(key) = options.fullscreen_key
+	{
+		config.fullscreen_key = key;
+	}
+	if options.headless
+	{
+		return unsafe { headless::run(&options, config) };
+	}
+
 	// Window
 
 	let event_loop = EventLoop::new();
 	let window = WindowBuilder::new()
-		.with_title("Vulkan Tutorial (Rust)")
-		.with_inner_size(LogicalSize::new(1024, 768))
+		.with_title(WINDOW_TITLE)
+		.with_inner_size(LogicalSize::new(options.width.unwrap_or(config.width), options.height.unwrap_or(config.height)))
 		.build(&event_loop)?;
 
 	// App
 
-	let mut app = unsafe { App::create(&window)? };
+	let mut app = unsafe { App::create(Some(&window), &options, config)? };
+	app.overlay = Some(unsafe { Overlay::create(&event_loop, &window, &app.device, &mut app.data)? });
+	if options.fullscreen
+	{
+		app.window_mode.set(&window, WindowMode::Borderless);
+	}
+	let mut stress = options.stress.map(StressTest::new);
+	if stress.is_some() && !app.data.validation
+	{
+		unsafe { app.destroy(); }
+		return Err(anyhow!("--stress needs the validation layer, which release builds and --no-validation leave out"));
+	}
+	if options.fail_on_validation && !app.data.validation
+	{
+		warn!("--fail-on-validation has nothing to check without the validation layer");
+	}
+	let fail_on_validation = options.fail_on_validation;
 	let mut destroying = false;
 	let mut minimized = false;
 	event_loop.run(move |event, _, control_flow|
 	{
-		*control_flow = ControlFlow::Poll;
+		// nothing is rendered while minimized, so sleep until the window is
+		// restored, unless a stress test is going to restore it
+		*control_flow = if minimized && stress.is_none() { ControlFlow::Wait } else { ControlFlow::Poll };
 		match event
 		{
+			Event::MainEventsCleared if !destroying && stress.as_ref().map_or(false, |s| s.is_done()) =>
+			{
+				destroying = true;
+				unsafe { app.wait_for_frames().unwrap(); }
+				unsafe { app.destroy(); }
+
+				// teardown counts too, so failures are only totalled now
+				if validation::report_failures() == 0
+				{
+					info!("Stress test passed");
+					*control_flow = ControlFlow::Exit;
+				}
+				else
+				{
+					error!("Stress test failed");
+					*control_flow = ControlFlow::ExitWithCode(1);
+				}
+			},
+			Event::MainEventsCleared if !destroying && minimized =>
+			{
+				if let Some(step) = stress.as_mut().and_then(|s| s.next())
+				{
+					unsafe { app.run_stress_step(&window, step) }.unwrap();
+				}
+			},
 			// Render a frame if our Vulkan app is not being destroyed.
 			Event::MainEventsCleared if !destroying && !minimized =>
 			{
-				unsafe { app.render(&window) }.unwrap()
+				if let Some(step) = stress.as_mut().and_then(|s| s.next())
+				{
+					unsafe { app.run_stress_step(&window, step) }.unwrap();
+				}
+				unsafe { app.render(&window) }.unwrap();
+				if !app.captures.targets.is_empty()
+				{
+					unsafe { app.save_captures() }.unwrap();
+				}
 			},
+			// while it's shown the overlay gets first pick of the input
+			Event::WindowEvent { ref event, .. } if app.overlay.as_mut().map_or(false, |o| o.handle_window_event(event)) => {},
+			// movement keys and mouse look are held rather than pressed
+			Event::WindowEvent { ref event, .. } if app.fly.handle_window_event(event) => {},
+			Event::DeviceEvent { event, .. } => app.fly.handle_device_event(&event),
 			Event::WindowEvent {event: WindowEvent::KeyboardInput { input, .. }, .. } =>
 			{
 				if input.state == ElementState::Pressed
 				{
 					match input.virtual_keycode
 					{
+						Some(key) if key == app.config.fullscreen_key =>
+						{
+							app.window_mode.cycle(&window);
+							app.resized = true;
+						},
+						Some(VirtualKeyCode::V) => app.cycle_present_mode(),
+						Some(VirtualKeyCode::U) => app.cycle_msaa_samples(),
+						Some(VirtualKeyCode::M) => app.report_memory(),
+						Some(VirtualKeyCode::Y) => app.toggle_sequence(&app.config.assets.demo.clone()),
+						// time of day: T starts or stops the clock, comma and period slow it down and speed it up
+						Some(VirtualKeyCode::T) =>
+						{
+							app.time_of_day.toggle_paused();
+							info!("Time of day: {:.1}h at {} hours/s", app.time_of_day.hours, app.time_of_day.speed);
+						},
+						Some(VirtualKeyCode::Comma) | Some(VirtualKeyCode::Period) =>
+						{
+							let factor = if input.virtual_keycode == Some(VirtualKeyCode::Period) { 2.0 } else { 0.5 };
+							app.time_of_day.scale_speed(factor);
+							info!("Time of day: {:.1}h at {} hours/s", app.time_of_day.hours, app.time_of_day.speed);
+						},
 						Some(VirtualKeyCode::Left) if app.models > 1 => app.models -= 1,
-						Some(VirtualKeyCode::Right) if app.models < 4 => app.models += 1,
+						Some(VirtualKeyCode::Right) if app.models < MAX_MODELS => app.models += 1,
+						Some(VirtualKeyCode::F5) => app.toggle_debug_category(DebugCategory::Frustum),
+						Some(VirtualKeyCode::F6) => app.toggle_debug_category(DebugCategory::LightVolumes),
+						Some(VirtualKeyCode::F1) => app.toggle_debug_category(DebugCategory::Colliders),
+						#[cfg(feature = "physics")]
+						Some(VirtualKeyCode::F2) => app.toggle_physics(),
+						#[cfg(feature = "physics")]
+						Some(VirtualKeyCode::Space) =>
+						{
+							if let Some(physics) = &mut app.physics
+							{
+								physics.launch();
+							}
+						},
+						Some(VirtualKeyCode::F4) =>
+						{
+							app.debug_view = app.debug_view.next();
+							info!("Debug view: {:?}", app.debug_view);
+						},
+						Some(VirtualKeyCode::F9) =>
+						{
+							app.inspect_target = app.inspect_target.next();
+							info!("Inspecting {:?}", app.inspect_target);
+						},
+						Some(VirtualKeyCode::PageUp) | Some(VirtualKeyCode::PageDown) =>
+						{
+							let step = if input.virtual_keycode == Some(VirtualKeyCode::PageUp) { RENDER_SCALE_STEP } else { -RENDER_SCALE_STEP };
+							let scale = (app.data.render_scale + step).clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+							if scale != app.data.render_scale
+							{
+								app.data.render_scale = scale;
+								app.resized = true;
+								info!("Render scale: {:.0}%", scale * 100.0);
+							}
+						},
+						Some(VirtualKeyCode::F10) =>
+						{
+							app.data.sharpness = if app.data.sharpness > 0.0 { 0.0 } else { DEFAULT_SHARPNESS };
+							info!("Upsampling sharpness: {}", app.data.sharpness);
+						},
+						// next to Home, PageUp and PageDown, which also change the render scale
+						Some(VirtualKeyCode::End) if app.clock.is_deterministic() =>
+						{
+							info!("Dynamic resolution is off in deterministic mode");
+						},
+						Some(VirtualKeyCode::End) =>
+						{
+							app.dynamic_resolution.enabled = !app.dynamic_resolution.enabled;
+							if app.dynamic_resolution.enabled && app.supersampling != Supersampling::Off
+							{
+								app.supersampling = Supersampling::Off;
+								app.data.render_scale = 1.0;
+								app.resized = true;
+							}
+							info!("Dynamic resolution: {}", app.dynamic_resolution.enabled);
+						},
+						Some(VirtualKeyCode::F12) => app.screenshots.request(),
+						// next to PageUp and PageDown, which step the render scale
+						Some(VirtualKeyCode::Home) =>
+						{
+							// the two would fight over the render scale
+							app.dynamic_resolution.enabled = false;
+							app.supersampling = app.supersampling.next();
+							app.data.render_scale = app.supersampling.render_scale();
+							app.resized = true;
+							info!("Supersampling: {:?}", app.supersampling);
+						},
+						// camera path: K records a keyframe, L plays or stops, J clears
+						Some(VirtualKeyCode::K) =>
+						{
+							app.camera_path.record(&app.camera);
+							info!("Recorded camera keyframe {}", app.camera_path.len());
+							if let Err(e) = app.camera_path.save(&app.config.assets.camera_path)
+							{
+								warn!("Failed to save camera path: {}", e);
+							}
+						},
+						Some(VirtualKeyCode::L) =>
+						{
+							if app.camera_path.is_playing()
+							{
+								app.camera_path.stop();
+							}
+							else if !app.camera_path.play()
+							{
+								warn!("Camera path needs at least two keyframes");
+							}
+						},
+						Some(VirtualKeyCode::J) => app.camera_path.clear(),
+						Some(VirtualKeyCode::X) =>
+						{
+							app.voxels.enabled = !app.voxels.enabled;
+							info!("Voxel terrain: {}", app.voxels.enabled);
+						},
+						Some(VirtualKeyCode::B) =>
+						{
+							if let Err(e) = unsafe { app.bake_lighting() }
+							{
+								warn!("Failed to bake lighting: {}", e);
+							}
+						},
+						Some(VirtualKeyCode::C) =>
+						{
+							app.show_sky = !app.show_sky;
+							info!("Sky and clouds: {}", app.show_sky);
+						},
+						Some(VirtualKeyCode::H) =>
+						{
+							app.data.stereo = app.data.stereo.next();
+							// for the left eye's target
+							app.resized = true;
+							info!("Stereo: {:?}", app.data.stereo);
+						},
+						Some(VirtualKeyCode::I) =>
+						{
+							app.show_particles = !app.show_particles;
+							info!("Compute particles: {}", app.show_particles);
+						},
+						Some(VirtualKeyCode::N) =>
+						{
+							// every frame's fence is waited on before reuse, so the
+							// ones dropped out of the cycle are just left idle
+							app.data.frames_in_flight = app.data.frames_in_flight % MAX_FRAMES_IN_FLIGHT + 1;
+							app.frame %= app.data.frames_in_flight;
+							info!("Frames in flight: {}", app.data.frames_in_flight);
+						},
+						Some(VirtualKeyCode::Minus) | Some(VirtualKeyCode::Equals) =>
+						{
+							let step = if input.virtual_keycode == Some(VirtualKeyCode::Equals) { LOD_BIAS_STEP } else { -LOD_BIAS_STEP };
+							if let Err(e) = unsafe { app.adjust_texture_lod(window, step, 0.0) }
+							{
+								warn!("Failed to change the texture LOD: {}", e);
+							}
+						},
+						Some(VirtualKeyCode::LBracket) | Some(VirtualKeyCode::RBracket) =>
+						{
+							let step = if input.virtual_keycode == Some(VirtualKeyCode::RBracket) { 1.0 } else { -1.0 };
+							if let Err(e) = unsafe { app.adjust_texture_lod(window, 0.0, step) }
+							{
+								warn!("Failed to change the texture LOD: {}", e);
+							}
+						},
+						Some(VirtualKeyCode::Snapshot) => app.captures.targets = CaptureTarget::ALL.to_vec(),
+						Some(VirtualKeyCode::R) =>
+						{
+							if let Err(e) = unsafe { app.save_panorama() }
+							{
+								warn!("Failed to save panorama: {}", e);
+							}
+						},
+						Some(VirtualKeyCode::O) =>
+						{
+							app.show_outline = !app.show_outline;
+							info!("Outline: {}", app.show_outline);
+						},
+						Some(VirtualKeyCode::Z) =>
+						{
+							app.depth_prepass = !app.depth_prepass;
+							info!("Depth pre-pass: {}", app.depth_prepass);
+						},
+						Some(VirtualKeyCode::G) =>
+						{
+							app.show_sdf = !app.show_sdf;
+							info!("SDF demo: {}", app.show_sdf);
+						},
+						Some(VirtualKeyCode::P) =>
+						{
+							app.camera.toggle_projection();
+							info!("Projection: {:?}", app.camera.projection);
+						},
+						Some(VirtualKeyCode::F3) =>
+						{
+							app.show_stats = !app.show_stats;
+							if !app.show_stats
+							{
+								window.set_title(WINDOW_TITLE);
+							}
+						},
+						Some(VirtualKeyCode::Grave) =>
+						{
+							if let Some(overlay) = &mut app.overlay
+							{
+								overlay.toggle(&window);
+							}
+						},
 						_ => {}
 					}
 				}
 			},
+			Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } =>
+			{
+				let lines = match delta
+				{
+					MouseScrollDelta::LineDelta(_, y) => y,
+					MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+				};
+				app.camera.zoom_by(1.1f32.powf(lines));
+			},
 			// Check for resize
 			Event::WindowEvent {event: WindowEvent::Resized(size), ..} =>
 			{
@@ -96,6 +536,11 @@ fn main() -> Result<()>
 				}
 				else
 				{
+					if minimized
+					{
+						// don't let the time spent minimized count as one long frame
+						app.clock.restart_frame();
+					}
 					minimized = false;
 					app.resized = true;
 				}
@@ -105,8 +550,12 @@ fn main() -> Result<()>
 			{
 				destroying = true;
 				*control_flow = ControlFlow::Exit;
-				unsafe { app.device.device_wait_idle().unwrap(); }
+				unsafe { app.wait_for_frames().unwrap(); }
 				unsafe { app.destroy(); }
+				if fail_on_validation && validation::report_failures() > 0
+				{
+					*control_flow = ControlFlow::ExitWithCode(1);
+				}
 			}
 			_ => {}
 		}
@@ -123,52 +572,259 @@ struct App
 	device: Device,
 	frame: usize,
 	resized: bool,
-	start: Instant,
+	clock: Clock,
 	models: usize,
+	material_watcher: MaterialWatcher,
+	chain_watcher: ChainWatcher,
+	config: Config,
+	config_watcher: Option<ConfigWatcher>,
+	stats: FrameStats,
+	show_stats: bool,
+	stats_shown: Instant,
+	camera: Camera,
+	camera_path: CameraPath,
+	fly: FlyController,
+	window_mode: WindowModeState,
+	time_of_day: TimeOfDay,
+	sequence: Sequence,
+	// shown in the title until the instant passes
+	caption: Option<(String, Instant)>,
+	scene: Scene,
+	show_sdf: bool,
+	depth_prepass: bool,
+	show_outline: bool,
+	show_sky: bool,
+	show_particles: bool,
+	voxels: VoxelWorld,
+	#[cfg(feature = "physics")]
+	physics: Option<physics::PhysicsWorld>,
+	#[cfg(feature = "hot-reload")]
+	demo: hot_reload::DemoLibrary,
+	// what the demo logic left after the last frame, while it's loaded
+	#[cfg(feature = "hot-reload")]
+	demo_state: Option<hot_reload::DemoState>,
+	frozen_frustum: Option<glm::Mat4>,
+	inspect_target: InspectTarget,
+	captures: CaptureRequest,
+	readbacks: Readbacks,
+	screenshots: Screenshots,
+	// with --record or --record-ffmpeg
+	recorder: Option<Recorder>,
+	debug_view: DebugView,
+	dynamic_resolution: DynamicResolution,
+	supersampling: Supersampling,
+	// GPU time of the most recently completed frame in milliseconds
+	gpu_time: Option<f32>,
+	// the tutorial chapter being shown, everything when there's none
+	example: Option<Example>,
+	// the debug overlay, with a window
+	overlay: Option<Overlay>,
 }
 
 impl App
 {
-	/// Creates our Vulkan app.
-	unsafe fn create(window: &Window) -> Result<Self>
+	/// What the selected device supports, as queried when it was picked.
+	pub fn capabilities(&self) -> &DeviceCapabilities
+	{
+		&self.data.capabilities
+	}
+
+	/// Creates the app for a window, or without one to render offscreen,
+	/// see headless.
+	unsafe fn create(window: Option<&Window>, options: &Options, config: Config) -> Result<Self>
 	{
 		let loader = LibloadingLoader::new(LIBRARY)?;
 		let entry = Entry::new(loader).map_err(|error| anyhow!(error))?;
 		let mut data = AppData::default();
+		data.render_scale = 1.0;
+		data.validation = VALIDATION_ENABLED && config.validation && !options.no_validation;
+		data.headless = window.is_none();
+		data.gpu = options.gpu.clone();
+		data.jobs = Jobs::new(&config.jobs)?;
+		data.material = Material::load(&config.assets.material).unwrap_or_else(|e|
+			{
+				warn!("Failed to load {} ({}), using error material", config.assets.material.display(), e);
+				fallback::error_material()
+			});
+		if let Some(example) = options.example
+		{
+			info!("Example: {}", example.name());
+			example.adjust_material(&mut data.material);
+		}
+		// the pipelines' vertex layout depends on the mesh
+		load_model(&mut data, &config.assets.model, options.example.and_then(|e| e.settings().mesh))?;
 		let instance = create_instance(window, &entry, &mut data)?;
-		data.surface = vk_window::create_surface(&instance, &window, &window)?;
+		if let Some(window) = window
+		{
+			data.surface = vk_window::create_surface(&instance, &window, &window)?;
+		}
 		select_physical_device(&instance, &mut data)?;
+		if let Some(samples) = options.msaa_samples.or(config.msaa)
+		{
+			data.msaa_samples = data.capabilities.msaa_samples_up_to(samples);
+			info!("MSAA: {:?} ({}x requested)", data.msaa_samples, samples);
+		}
+		data.requested_present_mode = options.present_mode.or((!config.vsync).then_some(vk::PresentModeKHR::IMMEDIATE));
+		data.stereo = options.stereo.unwrap_or_default();
+		data.textures.set_budget_from_device(&instance, data.physical_device);
+		if options.timeline_sync && !data.capabilities.timeline_semaphores
+		{
+			warn!("No timeline semaphores, synchronizing frames with fences");
+		}
+		data.timeline_sync = options.timeline_sync && data.capabilities.timeline_semaphores;
+		data.dynamic_rendering = data.capabilities.dynamic_rendering;
+		info!("Composite pass: {}", if data.dynamic_rendering { "dynamic rendering" } else { "render pass" });
 		let device = create_logical_device(&entry, &instance, &mut data)?;
-		create_swapchain(window, &instance, &device, &mut data)?;
+		pipeline_cache::create_pipeline_cache(&instance, &device, &mut data)?;
+		data.descriptors = Descriptors::new(MAX_FRAMES_IN_FLIGHT);
+		data.immediate = ImmediateSubmit::create(&device)?;
+		samplers::create_common_samplers(&device, &mut data)?;
+		data.compute_mips = ComputeMips::create(&device, &data)?;
+		data.reductions = Reductions::create(&device, &data.capabilities.subgroups, data.pipeline_cache)?;
+		data.dispatch_args = DispatchArgs::create(&device, data.pipeline_cache)?;
+		match window
+		{
+			Some(window) => create_swapchain(window, &instance, &device, &mut data)?,
+			None =>
+			{
+				let extent = vk::Extent2D {
+					width: options.width.unwrap_or(config.width),
+					height: options.height.unwrap_or(config.height),
+				};
+				headless::create_targets(&instance, &device, &mut data, extent)?;
+			},
+		}
 		create_swapchain_image_views(&device, &mut data)?;
 		create_render_pass(&instance, &device, &mut data)?;
+		composite::create_composite_render_pass(&device, &mut data)?;
 		create_descriptor_set_layout(&device, &mut data)?;
 		create_pipeline(&device, &mut data)?;
+		debug_draw::create_debug_pipeline(&device, &mut data)?;
+		depth_prepass::create_depth_pipeline(&device, &mut data)?;
+		sdf::create_sdf_pipeline(&device, &mut data)?;
+		composite::create_composite_pipeline(&device, &mut data)?;
 		create_command_pools(&instance, &device, &mut data)?;
+		fluid::create_fluid_objects(&instance, &device, &mut data)?;
+		particles::create_particle_objects(&instance, &device, &mut data)?;
+		particles::create_particle_pipeline(&device, &mut data)?;
+		noise::create_noise_textures(&instance, &device, &mut data)?;
+		sky::create_sky_objects(&device, &mut data)?;
+		sky::create_sky_pipeline(&device, &mut data)?;
+		portals::create_portal_layouts(&device, &mut data)?;
+		reflection_probes::create_reflection_cubemaps(&instance, &device, &mut data)?;
+		shadow_atlas::create_shadow_atlas(&instance, &device, &mut data)?;
+		shadow_atlas::create_shadow_pipeline(&device, &mut data)?;
 		create_color_objects(&instance, &device, &mut data)?;
 		create_depth_objects(&instance, &device, &mut data)?;
+		composite::create_scene_objects(&instance, &device, &mut data)?;
+		stereo::create_stereo_objects(&instance, &device, &mut data)?;
+		bloom::create_bloom_objects(&instance, &device, &mut data)?;
+		transmission::create_transmission_objects(&instance, &device, &mut data)?;
+		planar_reflection::create_planar_reflection_objects(&instance, &device, &mut data)?;
+		portals::create_portal_objects(&instance, &device, &mut data)?;
+		portals::create_portal_pipelines(&device, &mut data)?;
 		create_framebuffers(&device, &mut data)?;
-		create_texture_image(&instance, &device, &mut data)?;
-		create_texture_image_views(&device, &mut data)?;
+		composite::create_composite_framebuffers(&device, &mut data)?;
+		// the model's uploads share submissions instead of each waiting on its own
+		data.immediate.begin_batch();
+		load_primitive_textures(&instance, &device, &mut data)?;
+		load_texture(&instance, &device, &mut data)?;
 		create_texture_sampler(&device, &mut data)?;
-		load_model(&mut data)?;
+		lightmap::load_lightmap(&instance, &device, &mut data)?;
+		load_material_maps(&instance, &device, &mut data)?;
 		create_vertex_buffer(&instance, &device, &mut data)?;
 		create_index_buffer(&instance, &device, &mut data)?;
+		data.immediate.end_batch(&device)?;
 		create_uniform_buffers(&instance, &device, &mut data)?;
-		create_descriptor_pool(&device, &mut data)?;
+		create_object_buffer(&instance, &device, &mut data)?;
+		debug_draw::create_debug_buffers(&instance, &device, &mut data)?;
 		create_descriptor_sets(&device, &mut data)?;
-		create_command_buffers(&device, &mut data)?;
+		gpu_timer::create_timestamp_queries(&device, &mut data)?;
 		create_sync_objects(&device, &mut data)?;
-		Ok(Self {entry, instance, data, device, frame: 0, resized: false, start: Instant::now(), models: 1})
+		let material_watcher = MaterialWatcher::new(config.assets.material.clone());
+		let camera_path = if config.assets.camera_path.exists()
+		{
+			CameraPath::load(&config.assets.camera_path).unwrap_or_else(|e|
+				{
+					warn!("Failed to load camera path: {}", e);
+					CameraPath::default()
+				})
+		}
+		else
+		{
+			CameraPath::default()
+		};
+		let scene = if config.assets.scene.exists()
+		{
+			Scene::load(&config.assets.scene).unwrap_or_else(|e|
+				{
+					warn!("Failed to load scene: {}", e);
+					Scene::default()
+				})
+		}
+		else
+		{
+			Scene::default()
+		};
+		data.shadow_tiles = lights::allocate_shadow_tiles(&scene.lights, &mut data.shadow_atlas);
+		let models = [glm::translate(&glm::identity(), &Self::model_position(0))];
+		reflection_probes::bake_reflection_probes(&instance, &device, &data, &scene.reflection_probes, Z_NEAR, Z_FAR, &models)?;
+		let mut sequence = Sequence::default();
+		if let Some(path) = &options.demo
+		{
+			sequence = Sequence::load(path)?;
+			sequence.play();
+		}
+		let mut camera = Camera::new(
+			glm::vec3(6.0,0.0,2.0),
+			glm::vec3(0.0,0.0,0.0),
+			glm::vec3(0.0,0.0,1.0),
+			Z_NEAR,
+			Z_FAR,
+		);
+		camera.fov_y = config.fov.to_radians();
+		let chain_watcher = ChainWatcher::new(config.assets.scene.clone());
+		let recorder = options.record_target().map(|target| Recorder::start(target, options.record_every)).transpose()?;
+		let config_watcher = config.live_reload.then(|| ConfigWatcher::new(CONFIG_PATH));
+		let mut app = Self {entry, instance, data, device, frame: 0, resized: false, clock: Clock::new(options.deterministic), models: 1, material_watcher, chain_watcher, config, config_watcher, stats: FrameStats::default(), show_stats: false, stats_shown: Instant::now(), camera, camera_path, fly: FlyController::default(), window_mode: WindowModeState::default(), time_of_day: TimeOfDay::default(), sequence, caption: None, scene, show_sdf: false, show_sky: false, show_particles: false, depth_prepass: false, show_outline: false, voxels: VoxelWorld::default(), #[cfg(feature = "physics")] physics: None, #[cfg(feature = "hot-reload")] demo: hot_reload::DemoLibrary::new(), #[cfg(feature = "hot-reload")] demo_state: None, frozen_frustum: None, inspect_target: InspectTarget::Final, captures: options.captures(), readbacks: Readbacks::default(), screenshots: Screenshots::default(), recorder, debug_view: DebugView::None, dynamic_resolution: DynamicResolution::default(), supersampling: Supersampling::Off, gpu_time: None, example: options.example, overlay: None};
+
+		if let Some(example) = options.example
+		{
+			app.apply_example(example);
+		}
+		if options.deterministic
+		{
+			info!("Deterministic mode, {:.4} s per frame", clock::FIXED_TIMESTEP);
+		}
+
+		Ok(app)
 	}
 
 	/// Renders a frame for our Vulkan app.
 	unsafe fn render(&mut self, window: &Window) -> Result<()>
 	{
-		let in_flight_fence = self.data.in_flight_fences[self.frame];
+		if self.reload_material(window)?
+		{
+			return Ok(());
+		}
+		self.reload_post_process();
+		self.reload_config();
+		let dt = self.clock.tick();
+		for action in self.sequence.update(dt)
+		{
+			self.run_action(Some(window), action)?;
+		}
+		if self.caption.as_ref().map_or(false, |(_, until)| Instant::now() >= *until)
+		{
+			self.caption = None;
+			window.set_title(WINDOW_TITLE);
+		}
 
-		self.device
-			.wait_for_fences(&[in_flight_fence], true, u64::max_value())?;
+		self.data.frame_sync.wait_for_frame(&self.device, self.frame)?;
+		self.data.deletion_queue.collect(&self.device, MAX_FRAMES_IN_FLIGHT);
+		self.readbacks.collect(&self.device, self.frame);
+		self.screenshots.collect(&mut self.readbacks, &self.data.jobs);
+		self.update_overlay(window)?;
 
 		let result = self
 			.device
@@ -179,9 +835,10 @@ impl App
 				vk::Fence::null(),
 				);
 
-		let image_index = match result
+		// a suboptimal image can still be presented, the swapchain is recreated after
+		let (image_index, acquired_suboptimal) = match result
 		{
-			Ok((image_index, _)) => image_index as usize,
+			Ok((image_index, code)) => (image_index as usize, code == vk::SuccessCode::SUBOPTIMAL_KHR),
 			Err(vk::ErrorCode::OUT_OF_DATE_KHR) => return self.recreate_swapchain(window),
 			Err(e) => return Err(anyhow!(e)),
 		};
@@ -193,22 +850,22 @@ impl App
 				.wait_for_fences(&[image_in_flight], true, u64::max_value())?;
 		}
 
-		self.update_command_buffer(image_index)?;
-		self.update_uniform_buffer(image_index)?;
+		let command_buffer = self.update_frame(image_index, dt)?;
 
 		let wait_semaphores = &[self.data.image_available_semaphores[self.frame]];
 		let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-		let command_buffers = &[self.data.graphics_command_buffers[image_index]];
+		let command_buffers = &[command_buffer];
 		let signal_semaphores = &[self.data.render_finished_semaphores[self.frame]];
 
-		let submit_info = vk::SubmitInfo::builder()
-			.wait_semaphores(wait_semaphores)
-			.wait_dst_stage_mask(wait_stages)
-			.command_buffers(command_buffers)
-			.signal_semaphores(signal_semaphores);
-
-		self.device.reset_fences(&[in_flight_fence])?;
-		self.device.queue_submit(self.data.graphics_queue, &[submit_info], in_flight_fence)?;
+		self.data.frame_sync.submit(
+			&self.device,
+			self.data.graphics_queue,
+			self.frame,
+			wait_semaphores,
+			wait_stages,
+			command_buffers,
+			signal_semaphores,
+		)?;
 
 		let swapchains = &[self.data.swapchain];
 		let image_indices = &[image_index as u32];
@@ -219,7 +876,8 @@ impl App
 
 		let result = self.device.queue_present_khr(self.data.presentation_queue, &present_info);
 
-		let changed = result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR)
+		let changed = acquired_suboptimal
+			|| result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR)
 			|| result == Err(vk::ErrorCode::OUT_OF_DATE_KHR);
 
 		if changed || self.resized
@@ -229,263 +887,1635 @@ impl App
 		}
 		else if let Err(e) = result
 		{
-			return Err(anyhow!(e));
+			return Err(anyhow!(e));
+		}
+
+		self.frame = (self.frame + 1) % self.data.frames_in_flight;
+
+		if self.show_stats && self.stats_shown.elapsed().as_secs_f32() >= 1.0
+		{
+			self.stats_shown = Instant::now();
+			let gpu_time = self.gpu_time.map_or("-".to_string(), |ms| format!("{:.2}", ms));
+			window.set_title(&format!(
+				"{} - {}, {} ms GPU, {:.0}% scale",
+				WINDOW_TITLE,
+				self.stats,
+				gpu_time,
+				self.data.render_scale * 100.0,
+			));
+		}
+
+		Ok(())
+	}
+
+	/// Renders a frame into one of the offscreen targets standing in for the
+	/// swapchain's images, see headless. Nothing waits to be presented.
+	unsafe fn render_headless(&mut self) -> Result<()>
+	{
+		self.reload_post_process();
+		let dt = self.clock.tick();
+		for action in self.sequence.update(dt)
+		{
+			self.run_action(None, action)?;
+		}
+
+		self.data.frame_sync.wait_for_frame(&self.device, self.frame)?;
+		self.data.deletion_queue.collect(&self.device, MAX_FRAMES_IN_FLIGHT);
+		self.readbacks.collect(&self.device, self.frame);
+
+		// every frame in flight has a target of its own, which is free once the frame is
+		let image_index = self.frame;
+		let command_buffer = self.update_frame(image_index, dt)?;
+
+		self.data.frame_sync.submit(
+			&self.device,
+			self.data.graphics_queue,
+			self.frame,
+			&[],
+			&[],
+			&[command_buffer],
+			&[],
+		)?;
+
+		self.frame = (self.frame + 1) % self.data.frames_in_flight;
+
+		Ok(())
+	}
+
+	/// Moves everything on by `dt` and records the frame into the image's
+	/// target, returning the command buffer to submit.
+	unsafe fn update_frame(&mut self, image_index: usize, dt: f32) -> Result<vk::CommandBuffer>
+	{
+		self.update_render_scale(image_index)?;
+		self.camera_path.update(&mut self.camera, dt);
+		// a deterministic run only moves the camera along paths it replays
+		if !self.camera_path.is_playing() && !self.clock.is_deterministic()
+		{
+			self.fly.update(&mut self.camera, dt);
+		}
+		self.time_of_day.update(dt);
+		#[cfg(feature = "hot-reload")]
+		self.update_demo(dt);
+		self.voxels.update(&self.instance, &self.device, &mut self.data, self.camera.eye)?;
+
+		#[cfg(feature = "physics")]
+		if let Some(physics) = &mut self.physics
+		{
+			physics.step(dt);
+		}
+		self.update_debug_draw();
+		let command_buffer = self.update_command_buffer(image_index)?;
+		self.update_uniform_buffer(image_index)?;
+
+		Ok(command_buffer)
+	}
+
+	/// Runs the overlay's UI for the frame and applies whatever was changed
+	/// in it, the frame's fence must have been waited on.
+	unsafe fn update_overlay(&mut self, window: &Window) -> Result<()>
+	{
+		let Some(overlay) = &mut self.overlay else
+		{
+			return Ok(());
+		};
+
+		let supported = |samples: u32| self.data.capabilities.msaa_samples_up_to(samples).bits() == samples;
+		let settings = Settings {
+			eye: self.camera.eye,
+			target: self.camera.target,
+			lights: self.scene.lights.clone(),
+			msaa_samples: self.data.msaa_samples.bits(),
+			msaa_choices: MSAA_SAMPLES.iter().copied().filter(|&s| supported(s)).collect(),
+			present_mode: self.data.present_mode,
+			present_modes: PRESENT_MODES.iter().copied().filter(|m| self.data.present_modes.contains(m)).collect(),
+		};
+
+		let mut changed = settings.clone();
+		overlay.update(window, &self.instance, &self.device, &mut self.data, self.frame, &mut changed)?;
+		if changed == settings
+		{
+			return Ok(());
+		}
+
+		self.camera.eye = changed.eye;
+		self.camera.target = changed.target;
+		if changed.lights != settings.lights
+		{
+			self.data.shadow_tiles = lights::allocate_shadow_tiles(&changed.lights, &mut self.data.shadow_atlas);
+			self.scene.lights = changed.lights;
+		}
+		if changed.msaa_samples != settings.msaa_samples
+		{
+			self.set_msaa_samples(changed.msaa_samples);
+		}
+		if changed.present_mode != settings.present_mode
+		{
+			info!("Present mode: {:?}", changed.present_mode);
+			self.data.requested_present_mode = Some(changed.present_mode);
+			self.resized = true;
+		}
+
+		Ok(())
+	}
+
+	/// Statistics for the most recently recorded frame.
+	fn stats(&self) -> FrameStats
+	{
+		self.stats
+	}
+
+	/// Picks up the post-processing chain if the scene file changed on disk.
+	/// Command buffers are recorded every frame, so it applies from the next.
+	fn reload_post_process(&mut self)
+	{
+		match self.chain_watcher.poll()
+		{
+			Some(Ok(chain)) if chain != self.scene.post_process =>
+			{
+				info!("Post-processing: {:?}", chain.effects());
+				self.scene.post_process = chain;
+			},
+			Some(Err(e)) => warn!("Failed to reload the post-processing chain: {}", e),
+			_ => {},
+		}
+	}
+
+	/// Applies what can change while running from an edited config file.
+	fn reload_config(&mut self)
+	{
+		let config = match self.config_watcher.as_mut().and_then(|w| w.poll())
+		{
+			Some(Ok(config)) if config != self.config => config,
+			Some(Err(e)) =>
+			{
+				warn!("Failed to reload config: {}", e);
+				return;
+			},
+			_ => return,
+		};
+
+		self.config.warn_restart_needed(&config);
+		if config.fov != self.config.fov
+		{
+			info!("Field of view: {}", config.fov);
+			self.camera.fov_y = config.fov.to_radians();
+		}
+		if config.msaa != self.config.msaa
+		{
+			// left out means the device's most
+			self.set_msaa_samples(config.msaa.unwrap_or(u32::MAX));
+		}
+		if config.fullscreen_key != self.config.fullscreen_key
+		{
+			info!("Fullscreen key: {:?}", config.fullscreen_key);
+		}
+		if !config.live_reload
+		{
+			info!("Stopped watching {}", CONFIG_PATH);
+			self.config_watcher = None;
+		}
+
+		// keep what was started with, only the live settings move on
+		self.config.fov = config.fov;
+		self.config.msaa = config.msaa;
+		self.config.fullscreen_key = config.fullscreen_key;
+	}
+
+	/// Logs every live GPU allocation and writes them out as a treemap.
+	fn report_memory(&self)
+	{
+		let properties = unsafe { self.instance.get_physical_device_memory_properties(self.data.physical_device) };
+		memory_report::log_report(&properties);
+		match memory_report::write_treemap(&properties)
+		{
+			Ok(path) => info!("Wrote memory treemap to {}", path.display()),
+			Err(e) => warn!("Failed to write memory treemap: {}", e),
+		}
+	}
+
+	/// Switches to the next present mode the surface supports, in the order
+	/// of `PRESENT_MODES`. The swapchain is recreated after the next present.
+	fn cycle_present_mode(&mut self)
+	{
+		let current = PRESENT_MODES.iter().position(|m| *m == self.data.present_mode).unwrap_or(0);
+		let next = (1..=PRESENT_MODES.len())
+			.map(|offset| PRESENT_MODES[(current + offset) % PRESENT_MODES.len()])
+			.find(|mode| self.data.present_modes.contains(mode));
+
+		if let Some(mode) = next
+		{
+			info!("Present mode: {:?}", mode);
+			self.data.requested_present_mode = Some(mode);
+			self.resized = true;
+		}
+	}
+
+	/// Switches to the next MSAA sample count the device supports.
+	fn cycle_msaa_samples(&mut self)
+	{
+		let supported = |samples: u32| self.data.capabilities.msaa_samples_up_to(samples).bits() == samples;
+		let current = MSAA_SAMPLES.iter().position(|&s| s == self.data.msaa_samples.bits()).unwrap_or(0);
+		let next = (1..=MSAA_SAMPLES.len())
+			.map(|offset| MSAA_SAMPLES[(current + offset) % MSAA_SAMPLES.len()])
+			.find(|&samples| supported(samples));
+
+		if let Some(samples) = next
+		{
+			self.set_msaa_samples(samples);
+		}
+	}
+
+	/// Switches MSAA to the most samples up to `requested` that the device
+	/// supports. Everything multisampled, the attachments, render passes
+	/// and pipelines, is rebuilt along with the swapchain after the frame.
+	fn set_msaa_samples(&mut self, requested: u32)
+	{
+		let samples = self.data.capabilities.msaa_samples_up_to(requested);
+		if samples != self.data.msaa_samples
+		{
+			info!("MSAA: {:?} ({}x requested)", samples, requested);
+			self.data.msaa_samples = samples;
+			self.resized = true;
+		}
+	}
+
+	/// Reloads the material if its file changed on disk.
+	/// Returns true if anything was rebuilt.
+	unsafe fn reload_material(&mut self, window: &Window) -> Result<bool>
+	{
+		let mut material = match self.material_watcher.poll()
+		{
+			Some(Ok(material)) => material,
+			Some(Err(e)) =>
+			{
+				warn!("Failed to reload {}: {}", self.material_watcher.path().display(), e);
+				return Ok(false);
+			},
+			None => return Ok(false),
+		};
+
+		if let Some(texture) = &self.data.model_texture
+		{
+			material.texture = texture.clone();
+		}
+		if let Some(example) = self.example
+		{
+			example.adjust_material(&mut material);
+		}
+
+		if material == self.data.material
+		{
+			return Ok(false);
+		}
+
+		info!("Reloading {}", self.material_watcher.path().display());
+
+		self.wait_for_frames()?;
+
+		let texture_changed = material.texture != self.data.material.texture;
+		let lightmap_changed = material.lightmap != self.data.material.lightmap;
+		let maps_changed = material.emissive_texture != self.data.material.emissive_texture
+			|| material.height_map != self.data.material.height_map;
+		let previous = std::mem::replace(&mut self.data.material, material);
+
+		if texture_changed
+		{
+			// the current texture is only let go once the new one has loaded,
+			// so one that fails leaves it showing
+			let current = (self.data.texture_image, self.data.texture_image_memory, self.data.texture_image_view, self.data.texture_format, self.data.mip_levels);
+			if let Err(e) = load_texture(&self.instance, &self.device, &mut self.data)
+			{
+				warn!("Failed to load {}: {}", self.data.material.texture, e);
+				(self.data.texture_image, self.data.texture_image_memory, self.data.texture_image_view, self.data.texture_format, self.data.mip_levels) = current;
+				self.data.material = previous;
+				return Ok(false);
+			}
+
+			self.device.destroy_sampler(self.data.texture_sampler, None);
+			create_texture_sampler(&self.device, &mut self.data)?;
+		}
+
+		if lightmap_changed
+		{
+			lightmap::load_lightmap(&self.instance, &self.device, &mut self.data)?;
+		}
+
+		if maps_changed
+		{
+			load_material_maps(&self.instance, &self.device, &mut self.data)?;
+		}
+
+		// the pipeline and descriptor sets are rebuilt along with the swapchain
+		self.recreate_swapchain(window)?;
+
+		// nothing references the previous texture anymore
+		self.data.textures.unload_unused(&self.device);
+
+		Ok(true)
+	}
+
+	/// Saves the requested targets as the last frame left them.
+	unsafe fn save_captures(&mut self) -> Result<()>
+	{
+		self.wait_for_frames()?;
+		self.captures.save(&self.instance, &self.device, &self.data)
+	}
+
+	/// Moves the texture sampler's LOD bias and minimum LOD, to check each
+	/// mip of the chain by eye.
+	unsafe fn adjust_texture_lod(&mut self, window: &Window, bias_step: f32, min_lod_step: f32) -> Result<()>
+	{
+		self.wait_for_frames()?;
+
+		self.data.mip_lod_bias += bias_step;
+		self.data.min_lod = (self.data.min_lod + min_lod_step).clamp(0.0, self.data.mip_levels.saturating_sub(1) as f32);
+		info!("Texture LOD bias: {}, min LOD: {}", self.data.mip_lod_bias, self.data.min_lod);
+
+		self.device.destroy_sampler(self.data.texture_sampler, None);
+		create_texture_sampler(&self.device, &mut self.data)?;
+
+		// the descriptor sets are rebuilt along with the swapchain
+		self.recreate_swapchain(window)
+	}
+
+	fn toggle_debug_category(&mut self, category: DebugCategory)
+	{
+		let enabled = self.data.debug_draw.toggle(category);
+		info!("Debug {:?}: {}", category, if enabled { "on" } else { "off" });
+
+		// the frustum is frozen when enabled so it can be inspected as the view moves
+		if category == DebugCategory::Frustum
+		{
+			self.frozen_frustum = if enabled
+			{
+				let (view, proj) = self.camera_matrices();
+				Some(proj * view)
+			}
+			else
+			{
+				None
+			};
+		}
+	}
+
+	/// Reads back the GPU time of the image's last frame and lets the
+	/// dynamic resolution controller react to it. Scale changes are applied
+	/// by the swapchain recreation after present.
+	unsafe fn update_render_scale(&mut self, image_index: usize) -> Result<()>
+	{
+		let gpu_time = match gpu_timer::read_frame_time(&self.device, &self.data, image_index)?
+		{
+			Some(gpu_time) => gpu_time,
+			None => return Ok(()),
+		};
+
+		self.gpu_time = Some(gpu_time);
+
+		if let Some(scale) = self.dynamic_resolution.update(gpu_time, self.data.render_scale)
+		{
+			debug!("Dynamic resolution: {:.0}% -> {:.0}%", self.data.render_scale * 100.0, scale * 100.0);
+			self.data.render_scale = scale;
+			self.resized = true;
+		}
+
+		Ok(())
+	}
+
+	/// Gathers this frame's debug lines.
+	fn update_debug_draw(&mut self)
+	{
+		self.data.debug_draw.clear();
+
+		if let Some(view_proj) = self.frozen_frustum
+		{
+			self.data.debug_draw.frustum(DebugCategory::Frustum, &view_proj, glm::vec3(1.0, 1.0, 0.0));
+		}
+
+		// the range each light reaches, in its own color so they can be told apart
+		for light in &self.scene.lights
+		{
+			let (x, y, z) = light.position;
+			let (r, g, b) = light.color;
+			let position = glm::vec3(x, y, z);
+			let color = glm::vec3(r, g, b) / r.max(g).max(b).max(f32::EPSILON);
+			match light.kind
+			{
+				LightKind::Spot { direction: (dx, dy, dz), angle } =>
+				{
+					self.data.debug_draw.cone(DebugCategory::LightVolumes, position, glm::vec3(dx, dy, dz), light.range, angle, color);
+				},
+				LightKind::Point => self.data.debug_draw.sphere(DebugCategory::LightVolumes, position, light.range, color),
+			}
+		}
+
+		#[cfg(feature = "physics")]
+		if let Some(physics) = &self.physics
+		{
+			physics.debug_draw(&mut self.data.debug_draw);
+		}
+	}
+
+	/// Captures the scene around the camera, lit as it's shown, and saves it
+	/// as a 360° panorama.
+	unsafe fn save_panorama(&mut self) -> Result<()>
+	{
+		self.wait_for_frames()?;
+
+		let models = (0..self.models)
+			.map(|i| self.model_matrix(i))
+			.collect::<Vec<_>>();
+
+		let capture = CubemapCapture::create(&self.instance, &self.device, &self.data, PANORAMA_FACE_SIZE)?;
+		let result = capture.render_scene(&self.device, &self.data, self.uniforms(), &self.camera.eye, Z_NEAR, Z_FAR, &models);
+		let faces = result.and_then(|_| capture.faces(&self.device));
+		capture.destroy(&self.device);
+
+		let image = cubemap::equirectangular(&faces?, PANORAMA_FACE_SIZE, PANORAMA_FACE_SIZE * 4);
+		let path = capture::save_panorama(image, self.captures.format)?;
+		info!("Saved {}", path.display());
+
+		Ok(())
+	}
+
+	/// Captures the scene from every light and reflection probe. The light
+	/// probes are saved with the scene, reflection probes are recaptured on
+	/// every load.
+	unsafe fn bake_lighting(&mut self) -> Result<()>
+	{
+		self.wait_for_frames()?;
+
+		let models = (0..self.models)
+			.map(|i| self.model_matrix(i))
+			.collect::<Vec<_>>();
+
+		self.scene.light_probes.bake(&self.instance, &self.device, &self.data, Z_NEAR, Z_FAR, &models)?;
+		reflection_probes::bake_reflection_probes(
+			&self.instance,
+			&self.device,
+			&self.data,
+			&self.scene.reflection_probes,
+			Z_NEAR,
+			Z_FAR,
+			&models,
+		)?;
+		self.scene.save(&self.config.assets.scene)
+	}
+
+	/// Switches on what the example starts with. Its mesh and material were
+	/// already picked when the app was created.
+	fn apply_example(&mut self, example: Example)
+	{
+		let settings = example.settings();
+		self.models = settings.models.clamp(1, MAX_MODELS);
+		self.show_sky = settings.show_sky;
+		self.show_particles = settings.show_particles;
+		self.time_of_day.speed = settings.sun_speed;
+		self.inspect_target = settings.inspect_target;
+		if let Some(category) = settings.debug_category
+		{
+			self.toggle_debug_category(category);
+		}
+	}
+
+	/// Plays the demo sequence from the file, or stops it if it's playing.
+	fn toggle_sequence(&mut self, path: &Path)
+	{
+		if self.sequence.is_playing()
+		{
+			self.sequence.stop();
+			return;
+		}
+
+		match Sequence::load(path)
+		{
+			Ok(sequence) =>
+			{
+				self.sequence = sequence;
+				self.sequence.play();
+			},
+			Err(e) => warn!("Failed to load demo sequence: {}", e),
+		}
+	}
+
+	/// Carries out a stress test step. Window changes recreate the swapchain
+	/// through the resize events they cause, like any others.
+	unsafe fn run_stress_step(&mut self, window: &Window, step: StressStep) -> Result<()>
+	{
+		debug!("Stress: {:?}", step);
+
+		match step
+		{
+			StressStep::Resize(size) => window.set_inner_size(size),
+			StressStep::Minimize => window.set_minimized(true),
+			StressStep::Restore => window.set_minimized(false),
+			StressStep::WindowMode(mode) =>
+			{
+				self.window_mode.set(window, mode);
+				self.resized = true;
+			},
+			StressStep::Recreate => self.recreate_swapchain(window)?,
+		}
+
+		Ok(())
+	}
+
+	/// Carries out one of the demo sequence's actions. Assets that fail to
+	/// load are skipped so the rest of the demo still plays.
+	unsafe fn run_action(&mut self, window: Option<&Window>, action: Action) -> Result<()>
+	{
+		debug!("Demo: {:?}", action);
+
+		match action
+		{
+			Action::CameraPath(path) => match CameraPath::load(Path::new(&path))
+			{
+				Ok(camera_path) =>
+				{
+					self.camera_path = camera_path;
+					self.camera_path.play();
+				},
+				Err(e) => warn!("Failed to load camera path: {}", e),
+			},
+			Action::Caption { text, seconds } =>
+			{
+				if let Some(window) = window
+				{
+					window.set_title(&format!("{} - {}", WINDOW_TITLE, text));
+				}
+				self.caption = Some((text, Instant::now() + Duration::from_secs_f32(seconds)));
+			},
+			Action::Sun { hours, speed } =>
+			{
+				self.time_of_day.hours = hours;
+				self.time_of_day.speed = speed;
+			},
+			Action::Scene(path) => match Scene::load(Path::new(&path))
+			{
+				Ok(scene) => self.swap_scene(scene)?,
+				Err(e) => warn!("Failed to load scene: {}", e),
+			},
+			Action::Models(models) => self.models = models.clamp(1, MAX_MODELS),
+			Action::Stop => self.sequence.stop(),
+		}
+
+		Ok(())
+	}
+
+	/// Replaces the scene, reallocating its lights' shadow tiles and
+	/// recapturing its reflection probes. Light probes are baked data and
+	/// come with the scene.
+	unsafe fn swap_scene(&mut self, scene: Scene) -> Result<()>
+	{
+		self.wait_for_frames()?;
+
+		self.data.shadow_tiles = lights::allocate_shadow_tiles(&scene.lights, &mut self.data.shadow_atlas);
+
+		let models = (0..self.models)
+			.map(|i| self.model_matrix(i))
+			.collect::<Vec<_>>();
+		reflection_probes::bake_reflection_probes(
+			&self.instance,
+			&self.device,
+			&self.data,
+			&scene.reflection_probes,
+			Z_NEAR,
+			Z_FAR,
+			&models,
+		)?;
+
+		self.scene = scene;
+		Ok(())
+	}
+
+	/// Starts a simulation from the models' current positions, or stops it.
+	#[cfg(feature = "physics")]
+	fn toggle_physics(&mut self)
+	{
+		self.physics = match self.physics
+		{
+			Some(_) => None,
+			None =>
+			{
+				let positions = (0..MAX_MODELS)
+					.map(|i| Self::model_position(i))
+					.collect::<Vec<_>>();
+				Some(physics::PhysicsWorld::new(&self.data.vertices, &positions))
+			},
+		};
+
+		info!("Physics: {}", self.physics.is_some());
+	}
+
+	/// Runs the demo logic if its library is loaded, picking up a rebuilt
+	/// one first, and applies what it changed.
+	#[cfg(feature = "hot-reload")]
+	fn update_demo(&mut self, dt: f32)
+	{
+		unsafe { self.demo.poll() };
+
+		let mut state = hot_reload::DemoState {
+			time: self.clock.time(),
+			dt,
+			models: self.models as u32,
+			sun_hours: self.time_of_day.hours,
+			sun_speed: self.time_of_day.speed,
+			model_positions: [[0.0; 3]; MAX_MODELS],
+			model_transforms: [[0.0; 16]; MAX_MODELS],
+		};
+		for (i, position) in state.model_positions.iter_mut().enumerate()
+		{
+			*position = Self::model_position(i).into();
+		}
+		for (i, transform) in state.model_transforms.iter_mut().enumerate()
+		{
+			transform.copy_from_slice(glm::translation(&Self::model_position(i)).as_slice());
+		}
+
+		if !self.demo.update(&mut state)
+		{
+			self.demo_state = None;
+			return;
+		}
+
+		self.models = (state.models as usize).clamp(1, MAX_MODELS);
+		self.time_of_day.hours = state.sun_hours.rem_euclid(24.0);
+		self.time_of_day.speed = state.sun_speed;
+		self.demo_state = Some(state);
+	}
+
+	/// Where a model sits when it isn't being simulated.
+	fn model_position(model_index: usize) -> glm::Vec3
+	{
+		let y = (((model_index % 2) as f32) * 2.5) - 1.25;
+		let z = (((model_index / 2) as f32) * -2.0) + 1.0;
+		glm::vec3(0.0, y, z)
+	}
+
+	/// Whether the model is being moved by the simulation, so baked
+	/// lighting doesn't apply to it.
+	fn is_dynamic(&self, model_index: usize) -> bool
+	{
+		#[cfg(feature = "physics")]
+		if let Some(physics) = &self.physics
+		{
+			return physics.model_matrix(model_index).is_some();
+		}
+
+		let _ = model_index;
+		false
+	}
+
+	fn model_distance(&self, model_index: usize) -> f32
+	{
+		let position = self.model_matrix(model_index).column(3).xyz();
+		glm::distance(&position, &self.camera.eye)
+	}
+
+	fn model_object(&self, model_index: usize) -> ObjectData
+	{
+		let opacity = (model_index + 1) as f32 * 0.25 * self.data.material.opacity;
+		let probe_index = if self.model_irradiance(model_index).is_some() { model_index as i32 } else { -1 };
+		let lightmapped = self.data.lightmap.is_some() && !self.is_dynamic(model_index);
+		ObjectData {
+			alpha_cutoff: self.data.material.alpha_cutoff.unwrap_or(0.0),
+			..ObjectData::new(self.model_matrix(model_index), opacity, probe_index, lightmapped)
+		}
+	}
+
+	fn model_pipeline(&self) -> vk::Pipeline
+	{
+		if self.inspect_target == InspectTarget::Overdraw
+		{
+			self.data.overdraw_pipeline
+		}
+		else
+		{
+			self.data.pipeline
+		}
+	}
+
+	/// What drawing the model binds, for sorting. Every model shares the one
+	/// material and mesh for now, so only the pipeline can differ.
+	fn model_state(&self) -> DrawState
+	{
+		DrawState { pipeline: self.model_pipeline().as_raw(), material: 0, mesh: 0 }
+	}
+
+	fn model_matrix(&self, model_index: usize) -> glm::Mat4
+	{
+		#[cfg(feature = "physics")]
+		if let Some(model) = self.physics.as_ref().and_then(|p| p.model_matrix(model_index))
+		{
+			return model;
+		}
+
+		#[cfg(feature = "hot-reload")]
+		if let Some(state) = &self.demo_state
+		{
+			return glm::make_mat4(&state.model_transforms[model_index]);
+		}
+
+		let time = self.clock.time();
+
+		let model = glm::translate(
+			&glm::identity(),
+			&Self::model_position(model_index),
+		);
+
+		glm::rotate(
+			&model,
+			time * glm::radians(&glm::vec1(90.0))[0],
+			&glm::vec3(0.0,0.0,1.0))
+	}
+
+	/// Indirect diffuse lighting at the model's origin, if the probes are baked.
+	fn model_irradiance(&self, model_index: usize) -> Option<ShIrradiance>
+	{
+		let position = self.model_matrix(model_index).column(3).xyz();
+		self.scene.light_probes.sample(&position)
+	}
+
+	/// View and projection matrices for the current frame.
+	fn aspect(&self) -> f32
+	{
+		self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32
+	}
+
+	fn camera_matrices(&self) -> (glm::Mat4, glm::Mat4)
+	{
+		(self.camera.view(), self.camera.proj(self.aspect()))
+	}
+
+	/// The scene's mirror, when anything shows it.
+	fn mirror(&self) -> Option<&MirrorPlane>
+	{
+		self.scene.mirror.as_ref().filter(|_| self.data.material.mirror > 0.0)
+	}
+
+	/// Secondaries are executed once per eye in stereo and again for the
+	/// mirror and portals, so they have to be allowed to be more than once
+	/// in the frame.
+	fn secondary_usage(&self) -> vk::CommandBufferUsageFlags
+	{
+		if self.data.stereo == StereoMode::Off && self.mirror().is_none() && self.scene.portals.is_none()
+		{
+			vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE
+		}
+		else
+		{
+			vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE | vk::CommandBufferUsageFlags::SIMULTANEOUS_USE
+		}
+	}
+
+	unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()>
+	{
+		write_uniforms(&self.device, &self.data, image_index, &self.uniforms())
+	}
+
+	/// The frame's uniforms, from the camera's view.
+	fn uniforms(&self) -> UniformBufferObject
+	{
+		let (view, proj) = self.camera_matrices();
+		let time = self.clock.time();
+
+		let mut irradiance = [ShIrradiance::default(); MAX_MODELS];
+		for (model_index, irradiance) in irradiance.iter_mut().enumerate().take(self.models)
+		{
+			if let Some(sh) = self.model_irradiance(model_index)
+			{
+				*irradiance = sh;
+			}
+		}
+
+		let mut reflection_probes = [[[0.0; 4]; 3]; MAX_REFLECTION_PROBES];
+		for (uniform, probe) in reflection_probes.iter_mut().zip(&self.scene.reflection_probes)
+		{
+			*uniform = probe.uniform();
+		}
+
+		let (light_uniforms, shadow_views) = lights::light_uniforms(&self.scene.lights, &self.data.shadow_tiles);
+		let mut lights = [LightUniform::default(); MAX_LIGHTS];
+		lights[..light_uniforms.len()].copy_from_slice(&light_uniforms);
+		let mut shadow_view_uniforms = [ShadowViewUniform::default(); MAX_SHADOW_VIEWS];
+		for (uniform, view) in shadow_view_uniforms.iter_mut().zip(&shadow_views)
+		{
+			*uniform = view.uniform();
+		}
+
+		let eye = self.camera.eye;
+		let sun = self.time_of_day.sun_direction();
+
+		UniformBufferObject {
+			view,
+			proj,
+			debug_view: self.debug_view as i32,
+			time,
+			_padding: [0.0; 2],
+			irradiance,
+			reflection_probes,
+			camera_position: [eye.x, eye.y, eye.z, 1.0],
+			reflection_probe_count: self.scene.reflection_probes.len().min(MAX_REFLECTION_PROBES) as i32,
+			reflectivity: self.data.material.reflectivity,
+			_padding2: [0.0; 2],
+			emissive: emissive_uniform(&self.data),
+			sun_direction: [sun.x, sun.y, sun.z, self.time_of_day.daylight()],
+			height_scale: height_scale(&self.data),
+			clearcoat: self.data.material.clearcoat,
+			transmission: self.data.material.transmission,
+			ior: self.data.material.ior,
+			roughness: self.data.material.roughness,
+			specular: self.data.material.specular,
+			anisotropy_strength: self.data.material.anisotropy_strength,
+			anisotropy_rotation: self.data.material.anisotropy_rotation,
+			double_sided: self.data.material.double_sided as u32,
+			mirror: if self.mirror().is_some() { self.data.material.mirror } else { 0.0 },
+			light_count: light_uniforms.len() as i32,
+			_padding3: 0.0,
+			lights,
+			shadow_views: shadow_view_uniforms,
+		}
+	}
+
+	/// Records the frame, returning the command buffer to submit.
+	unsafe fn update_command_buffer(
+		&mut self,
+		image_index: usize,
+		) -> Result<vk::CommandBuffer>
+	{
+		self.stats = FrameStats::default();
+
+		// the frame's fence has been waited on, so nothing from its pools is in
+		// flight and everything in them can be reset at once
+		self.data.frame_contexts[self.frame].reset(&self.device)?;
+		self.data.descriptors.reset_frame(&self.device, self.frame);
+
+		let command_buffer = self.data.frame_contexts[self.frame].primary(&self.device)?;
+
+		let info = vk::CommandBufferBeginInfo::builder()
+			.flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+		self.device.begin_command_buffer(command_buffer, &info)?;
+
+		gpu_timer::begin_frame_timer(&self.device, &mut self.data, command_buffer, image_index);
+
+		if self.inspect_target == InspectTarget::Fluid
+		{
+			let time = self.clock.time();
+			fluid::record_simulation(&self.device, &self.data, command_buffer, time, &mut self.stats);
+		}
+
+		if self.show_particles
+		{
+			let (time, dt) = (self.clock.time(), self.clock.dt());
+			particles::record_simulation(&self.device, &self.data, command_buffer, time, dt, &mut self.stats);
+		}
+
+		let render_area = vk::Rect2D::builder()
+			.offset(vk::Offset2D::default())
+			.extent(self.data.render_extent);
+
+		let color_clear_value = vk::ClearValue {
+			color: vk::ClearColorValue {
+				float32: [0.0,0.0,0.0,1.0],
+			}
+		};
+
+		let depth_clear_value = vk::ClearValue {
+			depth_stencil: vk::ClearDepthStencilValue {
+				depth: 1.0,
+				stencil: 0,
+			}
+		};
+		
+		let clear_values = &[color_clear_value, depth_clear_value];
+
+		let info = vk::RenderPassBeginInfo::builder()
+			.render_pass(self.data.render_pass)
+			.framebuffer(self.data.scene_framebuffer)
+			.render_area(render_area)
+			.clear_values(clear_values);
+
+		let model_queue = self.data.material.render_queue();
+		let mut models = (0..self.models)
+			.map(|model_index| (self.model_state(), self.model_distance(model_index), model_index))
+			.collect::<Vec<_>>();
+
+		// blended models have to be drawn one by one in the queue's order
+		let batches = if model_queue.is_opaque()
+		{
+			render_queue::batch_by_state(&mut models)
+		}
+		else
+		{
+			models.iter().map(|&(_, _, model_index)| vec![model_index]).collect()
+		};
+
+		// laid out batch after batch so each one is a single instanced draw,
+		// then the outlines' shells in one more
+		let mut objects = batches
+			.iter()
+			.flatten()
+			.map(|&model_index| self.model_object(model_index))
+			.collect::<Vec<_>>();
+		let first_outline = objects.len() as u32;
+		if self.show_outline
+		{
+			objects.extend((0..self.models).map(|model_index|
+			{
+				let shell = glm::scale(&self.model_matrix(model_index), &glm::vec3(OUTLINE_SCALE, OUTLINE_SCALE, OUTLINE_SCALE));
+				ObjectData::new(shell, 1.0, -1, false)
+			}));
+		}
+		write_objects(&self.device, &self.data, image_index, &objects)?;
+
+		// every model's object comes before the outlines' shells, whatever the batches
+		let (_, shadow_views) = lights::light_uniforms(&self.scene.lights, &self.data.shadow_tiles);
+		shadow_atlas::record_shadow_pass(
+			&self.device,
+			&self.data,
+			command_buffer,
+			image_index,
+			&shadow_views,
+			self.models as u32,
+			&mut self.stats,
+		);
+
+		let depth_prepass = self.depth_prepass && model_queue.is_opaque();
+
+		let mut draws = Vec::new();
+		let mut first_object = 0;
+		for batch in batches
+		{
+			let distance = self.model_distance(batch[0]);
+			let object_count = batch.len() as u32;
+			let command_buffer = self.update_secondary_command_buffer(image_index, draws.len(), first_object, object_count, ModelPass::Shaded)?;
+			draws.push(Draw { distance, ..Draw::new(model_queue, command_buffer) });
+
+			// its queue is drawn before every other, so recording it here is fine
+			if depth_prepass
+			{
+				let command_buffer = self.update_secondary_command_buffer(image_index, draws.len(), first_object, object_count, ModelPass::DepthOnly)?;
+				draws.push(Draw { distance, ..Draw::new(RenderQueue::DepthPrepass, command_buffer) });
+			}
+
+			first_object += object_count;
+		}
+
+		// after everything opaque has marked the stencil, and in the same pass
+		// since the stencil isn't kept for the next
+		if self.show_outline
+		{
+			let command_buffer = self.update_secondary_command_buffer(image_index, draws.len(), first_outline, self.models as u32, ModelPass::Outline)?;
+			draws.push(Draw::new(RenderQueue::Transparent, command_buffer));
+		}
+
+		if self.voxels.enabled
+		{
+			let index = draws.len();
+			draws.push(Draw::new(RenderQueue::Opaque, self.update_voxel_command_buffer(image_index, index)?));
+		}
+
+		if self.show_sdf
+		{
+			let index = draws.len();
+			draws.push(Draw::new(RenderQueue::Opaque, self.update_sdf_command_buffer(image_index, index)?));
+		}
+
+		if self.show_sky
+		{
+			let index = draws.len();
+			draws.push(Draw::new(RenderQueue::Sky, self.update_sky_command_buffer(image_index, index)?));
+		}
+
+		if self.show_particles
+		{
+			let index = draws.len();
+			draws.push(Draw::new(RenderQueue::Transparent, self.update_particle_command_buffer(image_index, index)?));
+		}
+
+		// after the opaque models, which may be in front of the portals
+		if let Some(pair) = self.scene.portals
+		{
+			let index = draws.len();
+			draws.push(Draw::new(RenderQueue::AlphaTest, self.update_portal_command_buffer(image_index, index, &pair)?));
+		}
+
+		if !self.data.debug_draw.vertices().is_empty()
+		{
+			let index = draws.len();
+			draws.push(Draw::new(RenderQueue::Overlay, self.update_debug_command_buffer(image_index, index)?));
+		}
+
+		let scene_command_buffers = self.scene.render_queues.draw_order(&draws, Pass::Scene);
+		// whatever refracts the finished scene, or has to be drawn over what does, gets a pass of its own
+		let transmission_command_buffers = self.scene.render_queues.draw_order(&draws, Pass::Transmission);
+
+		// the scene pass's secondaries again, from the other side of the mirror
+		let mirror = self.mirror().copied();
+		if let Some(plane) = &mirror
+		{
+			let matrices = planar_reflection::mirrored_matrices(&self.camera, self.aspect(), plane);
+			self.record_scene_view(command_buffer, image_index, &info, &scene_command_buffers, matrices, self.data.mirror_image);
+		}
+
+		// and through each portal, the deepest view first since the ones above show it
+		let portals = self.scene.portals;
+		if let Some(pair) = &portals
+		{
+			for index in 0..2
+			{
+				if !pair.ends(index).0.faces(&self.camera.eye)
+				{
+					continue;
+				}
+
+				for level in (1..=pair.levels()).rev()
+				{
+					let matrices = portals::portal_matrices(&self.camera, self.aspect(), pair, index, level);
+					self.record_scene_view(command_buffer, image_index, &info, &scene_command_buffers, matrices, self.data.portal_images[index]);
+				}
+			}
+		}
+
+		for &eye in self.data.stereo.eyes()
+		{
+			// the camera's own matrices have to be put back after the other views'
+			if eye != Eye::Center || mirror.is_some() || portals.is_some()
+			{
+				let matrices = stereo::eye_matrices(&self.camera, self.aspect(), eye);
+				stereo::record_eye_uniforms(&self.device, &self.data, command_buffer, image_index, matrices, &mut self.stats);
+			}
+
+			self.device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::SECONDARY_COMMAND_BUFFERS);
+			if !scene_command_buffers.is_empty()
+			{
+				self.device.cmd_execute_commands(command_buffer, &scene_command_buffers);
+			}
+			self.device.cmd_end_render_pass(command_buffer);
+
+			if !transmission_command_buffers.is_empty()
+			{
+				transmission::record_transmission_pass(
+					&self.device,
+					&self.data,
+					command_buffer,
+					&transmission_command_buffers,
+					&mut self.stats,
+				);
+			}
+
+			if eye == Eye::Left
+			{
+				stereo::record_copy_left_eye(&self.device, &self.data, command_buffer, &mut self.stats);
+			}
+		}
+
+		bloom::record_bloom(&self.device, &self.data, command_buffer, &mut self.stats);
+
+		let composite_descriptor_set = composite::write_composite_descriptor_set(&self.device, &mut self.data, self.frame)?;
+		composite::record_composite_pass(
+			&self.device,
+			&self.data,
+			command_buffer,
+			composite_descriptor_set,
+			image_index,
+			self.inspect_target,
+			&self.camera,
+			&self.scene.post_process,
+			self.time_of_day.exposure(),
+			self.overlay.as_ref(),
+		);
+		self.stats.pipeline_binds += 1;
+		self.stats.descriptor_binds += 1;
+		self.stats.draw_calls += 1;
+		if let Some(draws) = self.overlay.as_ref().map(Overlay::draw_count).filter(|&draws| draws > 0)
+		{
+			self.stats.pipeline_binds += 1;
+			self.stats.draw_calls += draws;
+		}
+
+		if let Err(e) = self.screenshots.record(&self.instance, &self.device, &mut self.data, &mut self.readbacks, command_buffer, self.frame, image_index)
+		{
+			warn!("Failed to take screenshot: {}", e);
+		}
+
+		if let Some(recorder) = &mut self.recorder
+		{
+			recorder.record(&self.instance, &self.device, &mut self.data, &mut self.readbacks, command_buffer, self.frame, image_index)?;
+		}
+
+		gpu_timer::end_frame_timer(&self.device, &mut self.data, command_buffer, image_index);
+
+		self.device.end_command_buffer(command_buffer)?;
+		Ok(command_buffer)
+	}
+
+	/// Returns the `index`th secondary command buffer for the frame,
+	/// allocating it on first use.
+	unsafe fn get_secondary_command_buffer(
+		&mut self,
+		index: usize,
+		) -> Result<vk::CommandBuffer>
+	{
+		self.data.frame_contexts[self.frame].secondary(&self.device, index)
+	}
+
+	unsafe fn update_debug_command_buffer(
+		&mut self,
+		image_index: usize,
+		index: usize,
+		) -> Result<vk::CommandBuffer>
+	{
+		let command_buffer = self.get_secondary_command_buffer(index)?;
+
+		debug_draw::upload_debug_vertices(&self.device, &self.data, image_index)?;
+
+		let inheritence_info = vk::CommandBufferInheritanceInfo::builder()
+			.render_pass(self.data.render_pass)
+			.subpass(0)
+			.framebuffer(self.data.scene_framebuffer);
+
+		let info = vk::CommandBufferBeginInfo::builder()
+			.flags(self.secondary_usage())
+			.inheritance_info(&inheritence_info);
+
+		self.device.begin_command_buffer(command_buffer, &info)?;
+
+		self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.data.debug_pipeline);
+		self.stats.pipeline_binds += 1;
+		self.device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.data.debug_vertex_buffers[image_index]], &[0]);
+		self.device.cmd_bind_descriptor_sets(
+			command_buffer,
+			vk::PipelineBindPoint::GRAPHICS,
+			self.data.pipeline_layout,
+			0,
+			&[self.data.descriptor_set],
+			&[uniform_offset(&self.data, image_index)]);
+		self.stats.descriptor_binds += 1;
+
+		let vertex_count = self.data.debug_draw.vertices().len() as u32;
+		self.device.cmd_draw(command_buffer, vertex_count, 1, 0, 0);
+		self.stats.draw_calls += 1;
+
+		self.device.end_command_buffer(command_buffer)?;
+
+		Ok(command_buffer)
+	}
+
+	unsafe fn update_voxel_command_buffer(
+		&mut self,
+		image_index: usize,
+		index: usize,
+		) -> Result<vk::CommandBuffer>
+	{
+		let command_buffer = self.get_secondary_command_buffer(index)?;
+
+		let (view, proj) = self.camera_matrices();
+		let (chunks, culled) = self.voxels.visible_chunks(&self.data.jobs, &Frustum::from_view_proj(&(proj * view)));
+		self.stats.culled += culled;
+
+		let inheritence_info = vk::CommandBufferInheritanceInfo::builder()
+			.render_pass(self.data.render_pass)
+			.subpass(0)
+			.framebuffer(self.data.scene_framebuffer);
+
+		let info = vk::CommandBufferBeginInfo::builder()
+			.flags(self.secondary_usage())
+			.inheritance_info(&inheritence_info);
+
+		self.device.begin_command_buffer(command_buffer, &info)?;
+
+		self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.data.voxel_pipeline);
+		self.stats.pipeline_binds += 1;
+		self.device.cmd_bind_descriptor_sets(
+			command_buffer,
+			vk::PipelineBindPoint::GRAPHICS,
+			self.data.pipeline_layout,
+			0,
+			&[self.data.descriptor_set],
+			&[uniform_offset(&self.data, image_index)]);
+		self.stats.descriptor_binds += 1;
+
+		// chunk vertices are already in world space
+		let object = ObjectData::new(glm::identity(), 1.0, -1, false);
+		cmd_push_constants(
+			&self.device,
+			command_buffer,
+			self.data.pipeline_layout,
+			vk::ShaderStageFlags::VERTEX,
+			&object,
+		);
+
+		for chunk in chunks
+		{
+			self.device.cmd_bind_vertex_buffers(command_buffer, 0, &[chunk.vertex_buffer], &[0]);
+			self.device.cmd_bind_index_buffer(command_buffer, chunk.index_buffer, 0, chunk.index_type);
+			self.device.cmd_draw_indexed(command_buffer, chunk.index_count, 1, 0, 0, 0);
+			self.stats.record_draw_indexed(chunk.index_count, 1);
+		}
+
+		self.device.end_command_buffer(command_buffer)?;
+
+		Ok(command_buffer)
+	}
+
+	/// Renders the scene pass's secondaries from another view, e.g. the
+	/// mirror's, and copies the result into `target` for the scene pass to
+	/// sample. The view's matrices are left in the frame's uniforms.
+	unsafe fn record_scene_view(
+		&mut self,
+		command_buffer: vk::CommandBuffer,
+		image_index: usize,
+		info: &vk::RenderPassBeginInfo,
+		scene_command_buffers: &[vk::CommandBuffer],
+		matrices: (glm::Mat4, glm::Mat4, glm::Vec3),
+		target: vk::Image,
+		)
+	{
+		stereo::record_eye_uniforms(&self.device, &self.data, command_buffer, image_index, matrices, &mut self.stats);
+
+		self.device.cmd_begin_render_pass(command_buffer, info, vk::SubpassContents::SECONDARY_COMMAND_BUFFERS);
+		if !scene_command_buffers.is_empty()
+		{
+			self.device.cmd_execute_commands(command_buffer, scene_command_buffers);
 		}
+		self.device.cmd_end_render_pass(command_buffer);
 
-		self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
-
-		Ok(())
+		planar_reflection::record_copy_scene(&self.device, &self.data, command_buffer, target, &mut self.stats);
 	}
 
-	unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()>
+	unsafe fn update_portal_command_buffer(
+		&mut self,
+		image_index: usize,
+		index: usize,
+		pair: &PortalPair,
+		) -> Result<vk::CommandBuffer>
 	{
-		let view = glm::look_at(
-			&glm::vec3(6.0,0.0,2.0),
-			&glm::vec3(0.0,0.0,0.0),
-			&glm::vec3(0.0,0.0,1.0),
-		);
-
-		let mut proj = glm::perspective_rh_zo(
-			self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32,
-			glm::radians(&glm::vec1(45.0))[0],
-			0.1,
-			10.0,
-		);
+		let command_buffer = self.get_secondary_command_buffer(index)?;
 
-		proj[(1,1)] *= -1.0;
+		let inheritence_info = vk::CommandBufferInheritanceInfo::builder()
+			.render_pass(self.data.render_pass)
+			.subpass(0)
+			.framebuffer(self.data.scene_framebuffer);
 
-		let ubo = UniformBufferObject { view, proj };
+		let info = vk::CommandBufferBeginInfo::builder()
+			.flags(self.secondary_usage())
+			.inheritance_info(&inheritence_info);
 
-		let memory = self.device.map_memory(
-			self.data.uniform_buffers_memory[image_index],
-			0,
-			size_of::<UniformBufferObject>() as u64,
-			vk::MemoryMapFlags::empty(),
-			)?;
+		self.device.begin_command_buffer(command_buffer, &info)?;
 
-		memcpy(&ubo, memory.cast(), 1);
+		portals::record_portals(&self.device, &self.data, command_buffer, image_index, pair, &mut self.stats);
 
-		self.device.unmap_memory(self.data.uniform_buffers_memory[image_index]);
+		self.device.end_command_buffer(command_buffer)?;
 
-		Ok(())
+		Ok(command_buffer)
 	}
 
-	unsafe fn update_command_buffer(
+	unsafe fn update_sky_command_buffer(
 		&mut self,
 		image_index: usize,
-		) -> Result<()>
+		index: usize,
+		) -> Result<vk::CommandBuffer>
 	{
-		let command_pool = self.data.graphics_command_pools[image_index];
-
-		self.device.reset_command_pool(command_pool, vk::CommandPoolResetFlags::empty())?;
+		let command_buffer = self.get_secondary_command_buffer(index)?;
 
-		let command_buffer = self.data.graphics_command_buffers[image_index];
+		let inheritence_info = vk::CommandBufferInheritanceInfo::builder()
+			.render_pass(self.data.render_pass)
+			.subpass(0)
+			.framebuffer(self.data.scene_framebuffer);
 
 		let info = vk::CommandBufferBeginInfo::builder()
-			.flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+			.flags(self.secondary_usage())
+			.inheritance_info(&inheritence_info);
 
 		self.device.begin_command_buffer(command_buffer, &info)?;
 
-		let render_area = vk::Rect2D::builder()
-			.offset(vk::Offset2D::default())
-			.extent(self.data.swapchain_extent);
+		sky::record_sky(&self.device, &self.data, command_buffer, image_index, &self.time_of_day);
+		self.stats.pipeline_binds += 1;
+		self.stats.descriptor_binds += 1;
+		self.stats.draw_calls += 1;
 
-		let color_clear_value = vk::ClearValue {
-			color: vk::ClearColorValue {
-				float32: [0.0,0.0,0.0,1.0],
-			}
-		};
+		self.device.end_command_buffer(command_buffer)?;
 
-		let depth_clear_value = vk::ClearValue {
-			depth_stencil: vk::ClearDepthStencilValue {
-				depth: 1.0,
-				stencil: 0,
-			}
-		};
-		
-		let clear_values = &[color_clear_value, depth_clear_value];
+		Ok(command_buffer)
+	}
 
-		let info = vk::RenderPassBeginInfo::builder()
+	unsafe fn update_particle_command_buffer(
+		&mut self,
+		image_index: usize,
+		index: usize,
+		) -> Result<vk::CommandBuffer>
+	{
+		let command_buffer = self.get_secondary_command_buffer(index)?;
+
+		let inheritence_info = vk::CommandBufferInheritanceInfo::builder()
 			.render_pass(self.data.render_pass)
-			.framebuffer(self.data.framebuffers[image_index])
-			.render_area(render_area)
-			.clear_values(clear_values);
+			.subpass(0)
+			.framebuffer(self.data.scene_framebuffer);
 
-		self.device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::SECONDARY_COMMAND_BUFFERS);
+		let info = vk::CommandBufferBeginInfo::builder()
+			.flags(self.secondary_usage())
+			.inheritance_info(&inheritence_info);
 
-		let secondary_command_buffers = (0..self.models)
-			.map(|model_index| self.update_secondary_command_buffer(image_index, model_index))
-			.collect::<Result<Vec<_>, _>>()?;
+		self.device.begin_command_buffer(command_buffer, &info)?;
 
-		self.device.cmd_execute_commands(command_buffer, &secondary_command_buffers);
+		particles::record_particles(&self.device, &self.data, command_buffer, image_index);
+		self.stats.pipeline_binds += 1;
+		self.stats.descriptor_binds += 1;
+		self.stats.draw_calls += 1;
 
-		self.device.cmd_end_render_pass(command_buffer);
 		self.device.end_command_buffer(command_buffer)?;
-		Ok(())
+
+		Ok(command_buffer)
 	}
 
-	unsafe fn update_secondary_command_buffer(
+	unsafe fn update_sdf_command_buffer(
 		&mut self,
 		image_index: usize,
-		model_index: usize,
+		index: usize,
 		) -> Result<vk::CommandBuffer>
 	{
-		self.data.secondary_command_buffers.resize_with(image_index + 1, Vec::new);
-		let command_buffers = &mut self.data.secondary_command_buffers[image_index];
-		while model_index >= command_buffers.len()
-		{
-			let allocate_info = vk::CommandBufferAllocateInfo::builder()
-				.command_pool(self.data.graphics_command_pools[image_index])
-				.level(vk::CommandBufferLevel::SECONDARY)
-				.command_buffer_count(1);
+		let command_buffer = self.get_secondary_command_buffer(index)?;
 
-			let command_buffer = self.device.allocate_command_buffers(&allocate_info)?[0];
-
-			command_buffers.push(command_buffer);
-		}
+		let inheritence_info = vk::CommandBufferInheritanceInfo::builder()
+			.render_pass(self.data.render_pass)
+			.subpass(0)
+			.framebuffer(self.data.scene_framebuffer);
 
-		let command_buffer = command_buffers[model_index];
+		let info = vk::CommandBufferBeginInfo::builder()
+			.flags(self.secondary_usage())
+			.inheritance_info(&inheritence_info);
 
-		let time = self.start.elapsed().as_secs_f32();
+		self.device.begin_command_buffer(command_buffer, &info)?;
 
-		let y = (((model_index % 2) as f32) * 2.5) - 1.25;
-		let z = (((model_index / 2) as f32) * -2.0) + 1.0;
+		self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.data.sdf_pipeline);
+		self.stats.pipeline_binds += 1;
+		self.device.cmd_bind_descriptor_sets(
+			command_buffer,
+			vk::PipelineBindPoint::GRAPHICS,
+			self.data.pipeline_layout,
+			0,
+			&[self.data.descriptor_set],
+			&[uniform_offset(&self.data, image_index)]);
+		self.stats.descriptor_binds += 1;
 
-		let model = glm::translate(
-			&glm::identity(),
-			&glm::vec3(0.0,y,z)
-		);
+		self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+		self.stats.draw_calls += 1;
 
-		let model = glm::rotate(
-			&model,
-			time * glm::radians(&glm::vec1(90.0))[0],
-			&glm::vec3(0.0,0.0,1.0));
+		self.device.end_command_buffer(command_buffer)?;
 
-		let (_, model_bytes, _) = model.as_slice().align_to::<u8>();
+		Ok(command_buffer)
+	}
 
-		let opacity = (model_index + 1) as f32 * 0.25;
-		let opacity_bytes = &opacity.to_ne_bytes();
+	/// Records one instanced draw of the models whose data is at
+	/// `first_object` in the image's part of the object buffer, with the
+	/// pipeline for the pass. They must all share the same state.
+	unsafe fn update_secondary_command_buffer(
+		&mut self,
+		image_index: usize,
+		index: usize,
+		first_object: u32,
+		object_count: u32,
+		pass: ModelPass,
+		) -> Result<vk::CommandBuffer>
+	{
+		let command_buffer = self.get_secondary_command_buffer(index)?;
 
 		let inheritence_info = vk::CommandBufferInheritanceInfo::builder()
 			.render_pass(self.data.render_pass)
 			.subpass(0)
-			.framebuffer(self.data.framebuffers[image_index]);
+			.framebuffer(self.data.scene_framebuffer);
 
 		let info = vk::CommandBufferBeginInfo::builder()
-			.flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+			.flags(self.secondary_usage())
 			.inheritance_info(&inheritence_info);
 
 		self.device.begin_command_buffer(command_buffer, &info)?;
 
-		self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.data.pipeline);
-		self.device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.data.vertex_buffer], &[0]);
-		self.device.cmd_bind_index_buffer(command_buffer, self.data.index_buffer, 0, vk::IndexType::UINT32);
-		self.device.cmd_bind_descriptor_sets(
-			command_buffer,
-			vk::PipelineBindPoint::GRAPHICS,
-			self.data.pipeline_layout,
-			0,
-			&[self.data.descriptor_sets[image_index]],
-			&[]);
-		self.device.cmd_push_constants(
-			command_buffer,
-			self.data.pipeline_layout,
-			vk::ShaderStageFlags::VERTEX,
-			0,
-			model_bytes,
-		);
-		self.device.cmd_push_constants(
-			command_buffer,
-			self.data.pipeline_layout,
-			vk::ShaderStageFlags::FRAGMENT,
-			64,
-			opacity_bytes,
-		);
-		self.device.cmd_draw_indexed(command_buffer, self.data.indices.len() as u32, 1, 0, 0, 0);
+		let pipeline = match pass
+		{
+			ModelPass::Shaded => self.model_pipeline(),
+			ModelPass::DepthOnly => self.data.depth_pipeline,
+			ModelPass::Outline => self.data.outline_pipeline,
+		};
+		self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+		self.stats.pipeline_binds += 1;
+
+		if pass == ModelPass::DepthOnly && self.data.material.depth_variant() == DepthVariant::PositionOnly
+		{
+			// the positions are first in the buffer whether they're split out or not
+			self.device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.data.vertex_buffer], &self.data.vertex_offsets[..1]);
+		}
+		else
+		{
+			bind_vertex_streams(&self.device, &self.data, command_buffer);
+		}
+		self.device.cmd_bind_index_buffer(command_buffer, self.data.index_buffer, 0, self.data.index_type);
+
+		// each of the model's primitives is drawn with its own texture
+		for (set, first_index, index_count) in primitive_draws(&self.data)
+		{
+			self.device.cmd_bind_descriptor_sets(
+				command_buffer,
+				vk::PipelineBindPoint::GRAPHICS,
+				self.data.pipeline_layout,
+				0,
+				&[set],
+				&[uniform_offset(&self.data, image_index)]);
+			self.stats.descriptor_binds += 1;
+			self.stats.binds_saved += 2 * (object_count - 1);
+
+			// the vertex shader looks each instance's object up by its instance index
+			self.device.cmd_draw_indexed(
+				command_buffer,
+				index_count,
+				object_count,
+				first_index,
+				0,
+				first_object_index(image_index) + first_object,
+			);
+			self.stats.record_draw_indexed(index_count, object_count);
+		}
 
 		self.device.end_command_buffer(command_buffer)?;
 
 		Ok(command_buffer)
 	}
 
-	/// Recreate swapchain
+	/// Recreate swapchain. Deferred while the window has no area, since a
+	/// swapchain can't be empty; it's retried once the window is resized.
 	unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()>
 	{
-		self.device.device_wait_idle()?;
+		let size = window.inner_size();
+		if size.width == 0 || size.height == 0
+		{
+			self.resized = true;
+			return Ok(());
+		}
+
+		self.wait_for_frames()?;
 		self.destroy_swapchain();
 		create_swapchain(window, &self.instance, &self.device, &mut self.data)?;
 		create_swapchain_image_views(&self.device, &mut self.data)?;
 		create_render_pass(&self.instance, &self.device, &mut self.data)?;
+		composite::create_composite_render_pass(&self.device, &mut self.data)?;
 		create_pipeline(&self.device, &mut self.data)?;
+		debug_draw::create_debug_pipeline(&self.device, &mut self.data)?;
+		depth_prepass::create_depth_pipeline(&self.device, &mut self.data)?;
+		shadow_atlas::create_shadow_pipeline(&self.device, &mut self.data)?;
+		sdf::create_sdf_pipeline(&self.device, &mut self.data)?;
+		sky::create_sky_pipeline(&self.device, &mut self.data)?;
+		particles::create_particle_pipeline(&self.device, &mut self.data)?;
+		composite::create_composite_pipeline(&self.device, &mut self.data)?;
+		if let Some(overlay) = &mut self.overlay
+		{
+			overlay.create_pipeline(&self.device, &self.data)?;
+		}
 		create_color_objects(&self.instance, &self.device, &mut self.data)?;
 		create_depth_objects(&self.instance, &self.device, &mut self.data)?;
+		composite::create_scene_objects(&self.instance, &self.device, &mut self.data)?;
+		stereo::create_stereo_objects(&self.instance, &self.device, &mut self.data)?;
+		bloom::create_bloom_objects(&self.instance, &self.device, &mut self.data)?;
+		transmission::create_transmission_objects(&self.instance, &self.device, &mut self.data)?;
+		planar_reflection::create_planar_reflection_objects(&self.instance, &self.device, &mut self.data)?;
+		portals::create_portal_objects(&self.instance, &self.device, &mut self.data)?;
+		portals::create_portal_pipelines(&self.device, &mut self.data)?;
 		create_framebuffers(&self.device, &mut self.data)?;
+		composite::create_composite_framebuffers(&self.device, &mut self.data)?;
 		create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
-		create_descriptor_pool(&self.device, &mut self.data)?;
+		create_object_buffer(&self.instance, &self.device, &mut self.data)?;
+		debug_draw::create_debug_buffers(&self.instance, &self.device, &mut self.data)?;
 		create_descriptor_sets(&self.device, &mut self.data)?;
-		create_command_buffers(&self.device, &mut self.data)?;
+		gpu_timer::create_timestamp_queries(&self.device, &mut self.data)?;
 		self.data
 			.images_in_flight
 			.resize(self.data.swapchain_images.len(), vk::Fence::null());
 		Ok(())
 	}
 
+	/// Waits for every frame still in flight. The GPU work outside of frames
+	/// waits on its own fence when it's submitted, so once these have
+	/// completed nothing is using the frames' resources.
+	unsafe fn wait_for_frames(&self) -> Result<()>
+	{
+		self.data.frame_sync.wait_for_all(&self.device)
+	}
+
 	unsafe fn destroy_swapchain(&mut self)
 	{
+		if self.data.headless
+		{
+			headless::destroy_targets(&self.device, &self.data);
+		}
+		debug_draw::destroy_debug_objects(&self.device, &self.data);
+		gpu_timer::destroy_timestamp_queries(&self.device, &self.data);
+		composite::destroy_composite_objects(&self.device, &self.data);
+		if let Some(overlay) = &self.overlay
+		{
+			overlay.destroy_pipeline(&self.device);
+		}
+		stereo::destroy_stereo_objects(&self.device, &mut self.data);
+		bloom::destroy_bloom_objects(&self.device, &self.data);
+		transmission::destroy_transmission_objects(&self.device, &self.data);
+		planar_reflection::destroy_planar_reflection_objects(&self.device, &self.data);
+		portals::destroy_portal_objects(&self.device, &self.data);
 		self.device.destroy_image_view(self.data.color_image_view, None);
 		self.device.destroy_image(self.data.color_image, None);
-		self.device.free_memory(self.data.color_image_memory, None);
-		self.device.destroy_descriptor_pool(self.data.descriptor_pool, None);
-		self.data.uniform_buffers
-			.iter()
-			.for_each(|ub| self.device.destroy_buffer(*ub, None));
-		self.data.uniform_buffers_memory
-			.iter()
-			.for_each(|ub| self.device.free_memory(*ub, None));
-		self.data.framebuffers
-			.iter()
-			.for_each(|fb| self.device.destroy_framebuffer(*fb, None));
+		allocator::free(&self.device, self.data.color_image_memory);
+		self.data.descriptors.swapchain.reset(&self.device);
+		self.device.destroy_buffer(self.data.uniform_buffer, None);
+		allocator::free(&self.device, self.data.uniform_buffer_memory);
+		self.device.destroy_buffer(self.data.object_buffer, None);
+		allocator::free(&self.device, self.data.object_buffer_memory);
+		self.device.destroy_framebuffer(self.data.scene_framebuffer, None);
 
 		self.device.destroy_image(self.data.depth_image, None);
-		self.device.free_memory(self.data.depth_image_memory, None);
+		allocator::free(&self.device, self.data.depth_image_memory);
 		self.device.destroy_image_view(self.data.depth_image_view, None);
+		self.device.destroy_image_view(self.data.depth_sample_view, None);
 
 		self.device.destroy_pipeline(self.data.pipeline, None);
+		self.device.destroy_pipeline(self.data.overdraw_pipeline, None);
+		self.device.destroy_pipeline(self.data.outline_pipeline, None);
+		self.device.destroy_pipeline(self.data.depth_pipeline, None);
+		self.device.destroy_pipeline(self.data.shadow_pipeline, None);
+		self.device.destroy_pipeline(self.data.sdf_pipeline, None);
+		self.device.destroy_pipeline(self.data.sky_pipeline, None);
+		self.device.destroy_pipeline(self.data.particle_pipeline, None);
+		self.device.destroy_pipeline(self.data.voxel_pipeline, None);
 		self.device.destroy_pipeline_layout(self.data.pipeline_layout, None);
 		self.device.destroy_render_pass(self.data.render_pass, None);
 		self.data.swapchain_image_views
 			.iter()
 			.for_each(|image_view| self.device.destroy_image_view(*image_view, None));
-		self.device.destroy_swapchain_khr(self.data.swapchain, None);
+	}
+
+	/// Releases our handle on the current texture. The image itself is
+	/// owned by the texture cache and freed once it's unused.
+	unsafe fn destroy_texture(&mut self)
+	{
+		self.device.destroy_sampler(self.data.texture_sampler, None);
+		self.data.texture = None;
+		self.data.primitive_textures.clear();
+		self.data.lightmap = None;
+		self.data.emissive_texture = None;
+		self.data.height_map = None;
 	}
 
 	/// Destroys our Vulkan app.
 	unsafe fn destroy(&mut self)
 	{
+		// the fences cover rendering but not presentation, which has to be
+		// done with the swapchain's images before it's destroyed
+		self.device.queue_wait_idle(self.data.presentation_queue).unwrap();
+
+		self.readbacks.flush(&self.device);
+		self.screenshots.flush(&mut self.readbacks);
+		if let Some(mut recorder) = self.recorder.take()
+		{
+			if let Err(e) = recorder.finish()
+			{
+				warn!("Failed to finish recording: {}", e);
+			}
+		}
 		self.destroy_swapchain();
+		if !self.data.headless
+		{
+			self.device.destroy_swapchain_khr(self.data.swapchain, None);
+		}
+		if let Some(mut overlay) = self.overlay.take()
+		{
+			overlay.destroy(&self.device);
+		}
+		self.data.deletion_queue.flush(&self.device);
 
-		self.data.graphics_command_pools
+		self.data.frame_contexts
 			.iter()
-			.for_each(|pool| self.device.destroy_command_pool(*pool, None));
-
-		self.device.destroy_sampler(self.data.texture_sampler, None);
-		self.device.destroy_image_view(self.data.texture_image_view, None);
-		self.device.destroy_image(self.data.texture_image, None);
-		self.device.free_memory(self.data.texture_image_memory, None);
-
-		self.device.destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
+			.for_each(|context| context.destroy(&self.device));
+
+		self.destroy_texture();
+		self.data.textures.destroy(&self.device);
+		self.voxels.destroy(&self.device);
+		fluid::destroy_fluid_objects(&self.device, &self.data);
+		particles::destroy_particle_objects(&self.device, &self.data);
+		sky::destroy_sky_objects(&self.device, &self.data);
+		portals::destroy_portal_layouts(&self.device, &self.data);
+		reflection_probes::destroy_reflection_cubemaps(&self.device, &mut self.data);
+		shadow_atlas::destroy_shadow_atlas(&self.device, &self.data);
+		noise::destroy_noise_textures(&self.device, &mut self.data);
+
+		self.data.descriptors.destroy(&self.device);
+		self.data.compute_mips.destroy(&self.device);
+		self.data.reductions.destroy(&self.device);
+		self.data.dispatch_args.destroy(&self.device);
+		samplers::destroy_common_samplers(&self.device, &mut self.data);
 
 		self.device.destroy_buffer(self.data.index_buffer, None);
-		self.device.free_memory(self.data.index_buffer_memory, None);
+		allocator::free(&self.device, self.data.index_buffer_memory);
 		self.device.destroy_buffer(self.data.vertex_buffer, None);
-		self.device.free_memory(self.data.vertex_buffer_memory, None);
+		allocator::free(&self.device, self.data.vertex_buffer_memory);
 
-		self.data.in_flight_fences
-			.iter()
-			.for_each(|f| self.device.destroy_fence(*f, None));
-		self.data.images_in_flight
-			.iter()
-			.for_each(|f| self.device.destroy_fence(*f, None));
+		self.data.frame_sync.destroy(&self.device);
 		self.data.render_finished_semaphores
 			.iter()
 			.for_each(|s| self.device.destroy_semaphore(*s, None));
@@ -495,10 +2525,16 @@ impl App
 
 		self.device.destroy_command_pool(self.data.graphics_command_pool, None);
 		self.device.destroy_command_pool(self.data.transfer_command_pool, None);
+		self.data.immediate.destroy(&self.device);
+		pipeline_cache::destroy_pipeline_cache(&self.device, &self.data);
+		allocator::destroy(&self.device);
 		self.device.destroy_device(None);
-		self.instance.destroy_surface_khr(self.data.surface, None);
+		if !self.data.headless
+		{
+			self.instance.destroy_surface_khr(self.data.surface, None);
+		}
 
-		if VALIDATION_ENABLED
+		if self.data.validation
 		{
 			self.instance.destroy_debug_utils_messenger_ext(self.data.messenger, None);
 		}
@@ -512,74 +2548,235 @@ impl App
 struct AppData
 {
 	messenger: vk::DebugUtilsMessengerEXT,
+	/// Whether the validation layer and debug messenger are enabled.
+	validation: bool,
+	/// The device asked for on the command line, if any.
+	gpu: Option<GpuSelector>,
 	physical_device: vk::PhysicalDevice,	
+	capabilities: DeviceCapabilities,
 	msaa_samples: vk::SampleCountFlags,
+	// of the scene's depth buffer, chosen with the device
+	depth_format: vk::Format,
 	graphics_queue: vk::Queue,
 	presentation_queue: vk::Queue,
 	transfer_queue: vk::Queue,
+	immediate: ImmediateSubmit,
+	/// Shared by every pipeline, saved to disk on shutdown.
+	pipeline_cache: vk::PipelineCache,
+	descriptors: Descriptors,
+	jobs: Jobs,
+	// no window, surface or swapchain, see headless
+	headless: bool,
 	surface: vk::SurfaceKHR,
 	swapchain: vk::SwapchainKHR,
+	// of the images standing in for the swapchain's when headless
+	headless_images_memory: Vec<Allocation>,
+	deletion_queue: DeletionQueue,
 	swapchain_images: Vec<vk::Image>,
 	swapchain_format: vk::Format,
+	// screenshots need TRANSFER_SRC, which the surface may not allow
+	swapchain_usage: vk::ImageUsageFlags,
+	// None picks MAILBOX when the surface has it, FIFO otherwise
+	requested_present_mode: Option<vk::PresentModeKHR>,
+	present_mode: vk::PresentModeKHR,
+	// what the surface supported when the swapchain was last created
+	present_modes: Vec<vk::PresentModeKHR>,
 	swapchain_extent: vk::Extent2D,
+	// fraction of the swapchain resolution the scene is rendered at
+	render_scale: f32,
+	render_extent: vk::Extent2D,
+	// strength of the sharpening applied when upsampling, 0 disables it
+	sharpness: f32,
+	// the start and end of each swapchain image's frame
+	timestamp_queries: QueryPool,
 	swapchain_image_views: Vec<vk::ImageView>,
 	render_pass: vk::RenderPass,
 	descriptor_set_layout: vk::DescriptorSetLayout,
 	pipeline_layout: vk::PipelineLayout,
 	pipeline: vk::Pipeline,
+	overdraw_pipeline: vk::Pipeline,
+	outline_pipeline: vk::Pipeline,
+	// the material's depth-only permutation
+	depth_pipeline: vk::Pipeline,
+	sdf_pipeline: vk::Pipeline,
+	voxel_pipeline: vk::Pipeline,
+	fluid_images: [vk::Image; 2],
+	fluid_images_memory: [Allocation; 2],
+	fluid_image_views: [vk::ImageView; 2],
+	fluid_descriptor_set_layout: vk::DescriptorSetLayout,
+	fluid_pipeline_layout: vk::PipelineLayout,
+	fluid_pipeline: vk::Pipeline,
+	// fluid_descriptor_sets[i] reads fluid_images[i] and writes the other
+	fluid_descriptor_sets: [vk::DescriptorSet; 2],
+	particle_buffer: vk::Buffer,
+	particle_buffer_memory: Allocation,
+	particle_descriptor_set_layout: vk::DescriptorSetLayout,
+	particle_descriptor_set: vk::DescriptorSet,
+	particle_compute_pipeline_layout: vk::PipelineLayout,
+	particle_compute_pipeline: vk::Pipeline,
+	particle_pipeline: vk::Pipeline,
+	// generated at startup, keyed by name in `noise::NOISE_TEXTURES`
+	noise_textures: HashMap<&'static str, Texture>,
+	sky_sampler: vk::Sampler,
+	sky_descriptor_set_layout: vk::DescriptorSetLayout,
+	sky_descriptor_set: vk::DescriptorSet,
+	sky_pipeline_layout: vk::PipelineLayout,
+	sky_pipeline: vk::Pipeline,
+	// one per reflection probe slot, black when the slot is unused
+	reflection_cubemaps: Vec<Texture>,
+	reflection_sampler: vk::Sampler,
+	// half resolution HDR mip chain, one view per mip
+	bloom_image: vk::Image,
+	bloom_image_memory: Allocation,
+	bloom_mip_views: Vec<vk::ImageView>,
+	bloom_descriptor_set_layout: vk::DescriptorSetLayout,
+	bloom_pipeline_layout: vk::PipelineLayout,
+	bloom_pipeline: vk::Pipeline,
+	// one per dispatch, in the order `bloom::record_bloom` runs them
+	bloom_descriptor_sets: Vec<vk::DescriptorSet>,
+	// opaque scene color that transmissive models refract
+	refraction_image: vk::Image,
+	refraction_image_memory: Allocation,
+	refraction_image_view: vk::ImageView,
+	// the scene seen in the mirror plane, for mirror materials
+	mirror_image: vk::Image,
+	mirror_image_memory: Allocation,
+	mirror_image_view: vk::ImageView,
+	// what each of the scene's portals shows, drawn inside its stencil marks
+	portal_images: [vk::Image; 2],
+	portal_images_memory: [Allocation; 2],
+	portal_image_views: [vk::ImageView; 2],
+	portal_descriptor_set_layout: vk::DescriptorSetLayout,
+	portal_descriptor_sets: [vk::DescriptorSet; 2],
+	portal_pipeline_layout: vk::PipelineLayout,
+	portal_mark_pipelines: [vk::Pipeline; 2],
+	portal_view_pipelines: [vk::Pipeline; 2],
+	shadow_atlas: ShadowAtlas,
+	// each of the scene's lights' tiles, one per shadow face
+	shadow_tiles: Vec<Vec<Option<ShadowTile>>>,
+	shadow_atlas_image: vk::Image,
+	shadow_atlas_image_memory: Allocation,
+	shadow_atlas_image_view: vk::ImageView,
+	shadow_render_pass: vk::RenderPass,
+	shadow_framebuffer: vk::Framebuffer,
+	shadow_pipeline: vk::Pipeline,
+	transmission_render_pass: vk::RenderPass,
 	framebuffers: Vec<vk::Framebuffer>,
+	scene_framebuffer: vk::Framebuffer,
 	graphics_command_pool: vk::CommandPool,
-	graphics_command_pools: Vec<vk::CommandPool>,
-	graphics_command_buffers: Vec<vk::CommandBuffer>,
-	secondary_command_buffers: Vec<Vec<vk::CommandBuffer>>,
+	frame_contexts: Vec<FrameContext>,
 	transfer_command_pool: vk::CommandPool,
 	image_available_semaphores: Vec<vk::Semaphore>,
 	render_finished_semaphores: Vec<vk::Semaphore>,
-	in_flight_fences: Vec<vk::Fence>,
+	frame_sync: FrameSync,
+	// whether frames are tracked with a timeline semaphore, see frame_sync
+	timeline_sync: bool,
+	// whether the composite pass uses dynamic rendering, see dynamic_rendering
+	dynamic_rendering: bool,
 	images_in_flight: Vec<vk::Fence>,
+	/// How many of the sync objects are cycled through, more trades latency
+	/// for keeping the GPU busy.
+	frames_in_flight: usize,
 	vertices: Vec<Vertex>,
 	indices: Vec<u32>,
+	// the index ranges of the model's parts and their materials
+	primitives: Vec<model::Primitive>,
+	// glTF models bring their own texture, which wins over the material file's
+	model_texture: Option<String>,
+	// encoded images from the model's file, by the paths its primitives use
+	embedded_images: HashMap<String, Vec<u8>>,
+	// the textures of primitives that don't use the material's, by path
+	primitive_textures: HashMap<String, AssetHandle>,
+	// the set each primitive is drawn with, the main one unless it has its
+	// own texture
+	primitive_descriptor_sets: Vec<vk::DescriptorSet>,
 	vertex_buffer: vk::Buffer,
-	vertex_buffer_memory: vk::DeviceMemory,
+	vertex_buffer_memory: Allocation,
 	index_buffer: vk::Buffer,
-	index_buffer_memory: vk::DeviceMemory,
-	uniform_buffers: Vec<vk::Buffer>,
-	uniform_buffers_memory: Vec<vk::DeviceMemory>,
-	descriptor_pool: vk::DescriptorPool,
-	descriptor_sets: Vec<vk::DescriptorSet>,
+	index_buffer_memory: Allocation,
+	// picked from the mesh when it's loaded
+	vertex_layout: VertexLayout,
+	vertex_streams: VertexStreams,
+	// where each of the streams starts in vertex_buffer
+	vertex_offsets: Vec<vk::DeviceSize>,
+	index_type: vk::IndexType,
+	// every swapchain image's uniforms, uniform_stride apart
+	uniform_buffer: vk::Buffer,
+	uniform_buffer_memory: Allocation,
+	uniform_buffer_mapped: Option<NonNull<c_void>>,
+	uniform_stride: vk::DeviceSize,
+	// every swapchain image's objects, MAX_OBJECTS apart
+	object_buffer: vk::Buffer,
+	object_buffer_memory: Allocation,
+	object_buffer_mapped: Option<NonNull<c_void>>,
+	descriptor_set: vk::DescriptorSet,
 	mip_levels: u32,
+	texture_format: vk::Format,
 	texture_image: vk::Image,
-	texture_image_memory: vk::DeviceMemory,
+	texture_image_memory: Allocation,
 	texture_image_view: vk::ImageView,
 	texture_sampler: vk::Sampler,
+	// added to the texture's computed LOD, and the sharpest mip it may use
+	mip_lod_bias: f32,
+	min_lod: f32,
+	common_samplers: Vec<vk::Sampler>,
+	compute_mips: ComputeMips,
+	reductions: Reductions,
+	dispatch_args: DispatchArgs,
+	texture: Option<AssetHandle>,
+	lightmap: Option<AssetHandle>,
+	emissive_texture: Option<AssetHandle>,
+	height_map: Option<AssetHandle>,
+	textures: TextureCache,
+	layouts: LayoutTracker,
 	depth_image: vk::Image,
-	depth_image_memory: vk::DeviceMemory,
+	depth_image_memory: Allocation,
 	depth_image_view: vk::ImageView,
+	/// Only the depth aspect, for sampling.
+	depth_sample_view: vk::ImageView,
 	color_image: vk::Image,
-	color_image_memory: vk::DeviceMemory,
+	color_image_memory: Allocation,
 	color_image_view: vk::ImageView,
+	material: Material,
+	debug_pipeline: vk::Pipeline,
+	debug_vertex_buffers: Vec<vk::Buffer>,
+	debug_vertex_buffers_memory: Vec<Allocation>,
+	debug_draw: DebugDraw,
+	scene_image: vk::Image,
+	scene_image_memory: Allocation,
+	scene_image_view: vk::ImageView,
+	stereo: StereoMode,
+	// the left eye's scene color, only while rendering in stereo
+	stereo_image: vk::Image,
+	stereo_image_memory: Allocation,
+	stereo_image_view: vk::ImageView,
+	composite_render_pass: vk::RenderPass,
+	composite_descriptor_set_layout: vk::DescriptorSetLayout,
+	composite_pipeline_layout: vk::PipelineLayout,
+	composite_pipeline: vk::Pipeline,
 }
 
-unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) -> Result<Instance>
+unsafe fn create_instance(window: Option<&Window>, entry: &Entry, data: &mut AppData) -> Result<Instance>
 {
 	let application_info = vk::ApplicationInfo::builder()
 		.application_name(b"Vulkan Tutorial (Rust)\0")
 		.application_version(vk::make_version(1, 0, 0))
 		.engine_name(b"No Engine\0")
 		.engine_version(vk::make_version(1, 0, 0))
-		.api_version(vk::make_version(1, 0, 0));
+		// 1.1 for querying subgroup support, devices still only need 1.0
+		.api_version(vk::make_version(1, 1, 0));
 
 	let available_layers = entry.enumerate_instance_layer_properties()?
 		.iter()
 		.map(|layer| layer.layer_name)
 		.collect::<HashSet<_>>();
 
-	if VALIDATION_ENABLED && !available_layers.contains(&VALIDATION_LAYER)
+	if data.validation && !available_layers.contains(&VALIDATION_LAYER)
 	{
 		return Err(anyhow!("Validation layer requested but not supported"));
 	}
 
-	let layers = if VALIDATION_ENABLED
+	let layers = if data.validation
 	{
 		vec![VALIDATION_LAYER.as_ptr()]
 	}
@@ -588,12 +2785,14 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
 		vec![]
 	};
 
-	let mut extensions = vk_window::get_required_instance_extensions(window)
+	// a surface needs the window system's extensions, headless needs none
+	let mut extensions = window
+		.map_or(&[][..], |window| vk_window::get_required_instance_extensions(window))
 		.iter()
 		.map(|extension| extension.as_ptr())
 		.collect::<Vec<_>>();
 
-	if VALIDATION_ENABLED
+	if data.validation
 	{
 		extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
 	}
@@ -624,14 +2823,14 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
 		.message_type(DebugUtilsMessageTypeFlagsEXT::all())
 		.user_callback(Some(debug_callback));
 
-	if VALIDATION_ENABLED
+	if data.validation
 	{
 		info = info.push_next(&mut debug_info);
 	}
 
 	let instance = entry.create_instance(&info, None)?;
 
-	if VALIDATION_ENABLED
+	if data.validation
 	{
 		let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
 			.message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
@@ -662,14 +2861,18 @@ impl QueueFamilyIndices
 	{
 		let properties = instance.get_physical_device_queue_family_properties(physical_device);
 
+		// the fluid and particle passes are recorded with the frame, so the
+		// graphics family has to run compute too. Vulkan guarantees one that
+		// does on any device with graphics
 		let graphics = properties
 			.iter()
-			.position(|properties| properties.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+			.position(|properties| properties.queue_flags.contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE))
 			.map(|index| index as u32);
 
-		let mut presentation = None;
+		// nothing is presented headless, the graphics queue stands in
+		let mut presentation = graphics.filter(|_| data.headless);
 
-		for(index, properties) in properties.iter().enumerate()
+		for(index, properties) in properties.iter().enumerate().filter(|_| !data.headless)
 		{
 			if instance.get_physical_device_surface_support_khr
 				(
@@ -769,6 +2972,10 @@ unsafe fn check_physical_device(
 		return Err(anyhow!(SuitabilityError("Device doesn't support Anisotropic Sampling")));
 	}
 	QueueFamilyIndices::get(instance, data, physical_device)?;
+	if data.headless
+	{
+		return Ok(());
+	}
 
 	let support = SwapchainSupport::get(instance, data, physical_device)?;
 	if support.formats.is_empty() || support.present_modes.is_empty()
@@ -778,26 +2985,102 @@ unsafe fn check_physical_device(
 	Ok(())
 }
 
+/// Picks the highest scoring suitable device, or the one asked for with
+/// `--gpu` if it's suitable.
 unsafe fn select_physical_device(instance: &Instance, data: &mut AppData) -> Result<()>
 {
-	for physical_device in instance.enumerate_physical_devices()?
+	let mut candidates = vec![];
+	for (index, physical_device) in instance.enumerate_physical_devices()?.into_iter().enumerate()
 	{
 		let properties = instance.get_physical_device_properties(physical_device);
 
-		if let Err(error) = check_physical_device(instance, physical_device, data)
+		if data.gpu.as_ref().map_or(false, |gpu| !gpu.matches(index, &properties.device_name.to_string()))
+		{
+			info!("Skipping device ({}): not the one asked for with --gpu", properties.device_name);
+		}
+		else if let Err(error) = check_physical_device(instance, physical_device, data)
 		{
 			warn!("Skipping device ({}): {}", properties.device_name, error);
 		}
 		else
 		{
-			info!("Selected device: {}", properties.device_name);
-			data.physical_device = physical_device;
-			data.msaa_samples = get_max_msaa_samples(instance, data);
-			return Ok(());
+			let capabilities = DeviceCapabilities::query(instance, physical_device);
+			candidates.push((capabilities.score(), physical_device, capabilities));
 		}
 	}
 
-	Err(anyhow!("No suitable physical device found"))
+	// stable, so equal scores keep the enumeration order
+	candidates.sort_by_key(|(score, ..)| std::cmp::Reverse(*score));
+	for (rank, (score, _, capabilities)) in candidates.iter().enumerate()
+	{
+		info!("Candidate {}: {} ({:?}), score {}", rank, capabilities.device_name, capabilities.device_type, score);
+	}
+
+	let (_, physical_device, capabilities) = match candidates.into_iter().next()
+	{
+		Some(candidate) => candidate,
+		None => return match &data.gpu
+		{
+			Some(gpu) => Err(anyhow!("No suitable physical device matches {:?}, see --list-gpus", gpu)),
+			None => Err(anyhow!("No suitable physical device found")),
+		},
+	};
+
+	info!("Selected device: {}", capabilities.device_name);
+	data.physical_device = physical_device;
+	data.capabilities = capabilities;
+	info!("Capabilities: {}", data.capabilities);
+	data.msaa_samples = data.capabilities.max_msaa_samples;
+	data.depth_format = get_depth_stencil_format(instance, data)?;
+	info!("Depth format: {:?}", data.depth_format);
+	Ok(())
+}
+
+/// Prints every physical device with the index `--gpu` takes, without
+/// opening a window.
+unsafe fn list_gpus() -> Result<()>
+{
+	let loader = LibloadingLoader::new(LIBRARY)?;
+	let entry = Entry::new(loader).map_err(|error| anyhow!(error))?;
+
+	let application_info = vk::ApplicationInfo::builder()
+		.application_name(b"Vulkan Tutorial (Rust)\0")
+		.api_version(vk::make_version(1, 1, 0));
+
+	let mut extensions = vec![];
+	let flags = if cfg!(target_os = "macos") && entry.version()? >= PORTABILITY_MACOS_VERSION
+	{
+		extensions.push(vk::KHR_PORTABILITY_ENUMERATION_EXTENSION.name.as_ptr());
+		vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+	}
+	else
+	{
+		vk::InstanceCreateFlags::empty()
+	};
+
+	let info = vk::InstanceCreateInfo::builder()
+		.application_info(&application_info)
+		.enabled_extension_names(&extensions)
+		.flags(flags);
+	let instance = entry.create_instance(&info, None)?;
+
+	for (index, physical_device) in instance.enumerate_physical_devices()?.into_iter().enumerate()
+	{
+		let properties = instance.get_physical_device_properties(physical_device);
+		let version = properties.api_version;
+		println!(
+			"{}: {} ({:?}, Vulkan {}.{}.{})",
+			index,
+			properties.device_name,
+			properties.device_type,
+			vk::version_major(version),
+			vk::version_minor(version),
+			vk::version_patch(version),
+		);
+	}
+
+	instance.destroy_instance(None);
+	Ok(())
 }
 
 unsafe fn create_logical_device(
@@ -823,7 +3106,7 @@ unsafe fn create_logical_device(
 					.queue_priorities(queue_priorities)
 			}).collect::<Vec<_>>();
 
-	let layers = if VALIDATION_ENABLED
+	let layers = if data.validation
 	{
 		vec![VALIDATION_LAYER.as_ptr()]
 	}
@@ -834,6 +3117,7 @@ unsafe fn create_logical_device(
 
 	let mut extensions = DEVICE_EXTENSIONS
 		.iter()
+		.filter(|_| !data.headless)
 		.map(|name| name.as_ptr())
 		.collect::<Vec<_>>();
 
@@ -843,16 +3127,49 @@ unsafe fn create_logical_device(
 		extensions.push(vk::KHR_PORTABILITY_SUBSET_EXTENSION.name.as_ptr());
 	}
 
+	// Block-compressed formats are only used for transcoded basis textures
+	// so they're enabled whenever the device happens to support them
+	let supported_features = instance.get_physical_device_features(data.physical_device);
+
 	let features = vk::PhysicalDeviceFeatures::builder()
 		.sampler_anisotropy(true)
-		.sample_rate_shading(true);
+		.sample_rate_shading(true)
+		.texture_compression_bc(supported_features.texture_compression_bc == vk::TRUE)
+		.texture_compression_astc_ldr(supported_features.texture_compression_astc_ldr == vk::TRUE)
+		.pipeline_statistics_query(data.capabilities.pipeline_statistics);
+
+	if data.timeline_sync
+	{
+		extensions.push(vk::KHR_TIMELINE_SEMAPHORE_EXTENSION.name.as_ptr());
+	}
+
+	if data.dynamic_rendering
+	{
+		extensions.extend(dynamic_rendering::EXTENSIONS.iter().map(|e| e.name.as_ptr()));
+	}
+
+	let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::builder()
+		.timeline_semaphore(true);
+
+	let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::builder()
+		.dynamic_rendering(true);
 
-	let info = vk::DeviceCreateInfo::builder()
+	let mut info = vk::DeviceCreateInfo::builder()
 		.queue_create_infos(&queue_infos)
 		.enabled_layer_names(&layers)
 		.enabled_features(&features)
 		.enabled_extension_names(&extensions);
 
+	if data.timeline_sync
+	{
+		info = info.push_next(&mut timeline_features);
+	}
+
+	if data.dynamic_rendering
+	{
+		info = info.push_next(&mut dynamic_rendering_features);
+	}
+
 	let device = instance.create_device(data.physical_device, &info, None)?;
 	data.graphics_queue = device.get_device_queue(indices.graphics, 0);
 	data.transfer_queue = device.get_device_queue(indices.transfer, 0);
@@ -862,19 +3179,43 @@ unsafe fn create_logical_device(
 
 fn get_swapchain_surface_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR
 {
-	formats
+	// the composite pass writes linear color and relies on the attachment to
+	// encode it, so any sRGB format beats an exact match on channel order
+	let preferred = [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB, vk::Format::A8B8G8R8_SRGB_PACK32];
+
+	preferred
 		.iter()
-		.cloned()
-		.find(|f|
+		.find_map(|&format|
+			{
+				formats
+					.iter()
+					.cloned()
+					.find(|f| f.format == format && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
+			})
+		.unwrap_or_else(||
 			{
-				f.format == vk::Format::B8G8R8A8_SRGB
-							&& f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+				warn!("No sRGB surface format, output will be too dark");
+				formats[0]
 			})
-		.unwrap_or_else(|| formats[0])
 }
 
-fn get_swapchain_present_mode(present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR
+/// The requested mode if the surface supports it, otherwise MAILBOX if it's
+/// there and FIFO, which every surface supports, if not.
+fn get_swapchain_present_mode(
+	present_modes: &[vk::PresentModeKHR],
+	requested: Option<vk::PresentModeKHR>,
+	) -> vk::PresentModeKHR
 {
+	if let Some(requested) = requested
+	{
+		if present_modes.contains(&requested)
+		{
+			return requested;
+		}
+
+		warn!("Present mode {:?} isn't supported by the surface", requested);
+	}
+
 	present_modes
 		.iter()
 		.cloned()
@@ -921,12 +3262,15 @@ unsafe fn create_swapchain(
 	let support = SwapchainSupport::get(instance, data, data.physical_device)?;
 
 	let surface_format = get_swapchain_surface_format(&support.formats);
-	let present_mode = get_swapchain_present_mode(&support.present_modes);
+	let present_mode = get_swapchain_present_mode(&support.present_modes, data.requested_present_mode);
 	let extent = get_swapchain_extent(window, support.capabilities);
 
 	// simply sticking to this minimum means that we may sometimes have to wait on the 
 	// driver to complete internal operations before we can acquire another image to render to.
 	// Therefore it is recommended to request at least one more image than the minimum
+	data.swapchain_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+		| (support.capabilities.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC);
+
 	let mut image_count = support.capabilities.min_image_count + 1;
 
 	if support.capabilities.max_image_count != 0
@@ -935,20 +3279,22 @@ unsafe fn create_swapchain(
 		image_count = support.capabilities.max_image_count;
 	}
 
-	let mut queue_family_indices = vec![];
+	// concurrent sharing needs at least two distinct families, with a single
+	// one exclusive ownership is both valid and faster
+	// the transfer queue never touches swapchain images, only these two do
+	let mut queue_family_indices = vec![indices.graphics];
+	if indices.presentation != indices.graphics
+	{
+		queue_family_indices.push(indices.presentation);
+	}
 
-	let image_sharing_mode = if indices.graphics != indices.presentation
+	let image_sharing_mode = if queue_family_indices.len() > 1
 		{
-			queue_family_indices.push(indices.graphics);
-			queue_family_indices.push(indices.transfer);
-			queue_family_indices.push(indices.presentation);
 			vk::SharingMode::CONCURRENT
 		}
 		else
 		{
-			queue_family_indices.push(indices.graphics);
-			queue_family_indices.push(indices.transfer);
-			vk::SharingMode::CONCURRENT
+			vk::SharingMode::EXCLUSIVE
 		};
 	
 	let info = vk::SwapchainCreateInfoKHR::builder()
@@ -957,7 +3303,7 @@ unsafe fn create_swapchain(
 		.image_color_space(surface_format.color_space)
 		.image_extent(extent)
 		.image_array_layers(1)
-		.image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+		.image_usage(data.swapchain_usage)
 		.image_sharing_mode(image_sharing_mode)
 		.queue_family_indices(&queue_family_indices)
 		.pre_transform(support.capabilities.current_transform)
@@ -965,12 +3311,22 @@ unsafe fn create_swapchain(
 		.present_mode(present_mode)
 		.clipped(true)
 		.surface(data.surface)
-		.old_swapchain(vk::SwapchainKHR::null());
+		// lets the presentation engine hand over from the old one without a gap
+		.old_swapchain(data.swapchain);
 
+	let old_swapchain = data.swapchain;
 	data.swapchain = device.create_swapchain_khr(&info, None)?;
+	if !old_swapchain.is_null()
+	{
+		data.deletion_queue.retire(Retired::Swapchain(old_swapchain));
+	}
 	data.swapchain_images = device.get_swapchain_images_khr(data.swapchain)?;
 	data.swapchain_format = surface_format.format;
 	data.swapchain_extent = extent;
+	data.present_modes = support.present_modes;
+	data.present_mode = present_mode;
+	let max_dimension = instance.get_physical_device_properties(data.physical_device).limits.max_image_dimension_2d;
+	data.render_extent = composite::scaled_extent(extent, data.render_scale, max_dimension);
 
 	Ok(())
 }
@@ -1024,7 +3380,7 @@ unsafe fn create_render_pass(
 	) -> Result<()>
 {
 	let color_attachment = vk::AttachmentDescription::builder()
-		.format(data.swapchain_format)
+		.format(SCENE_FORMAT)
 		.samples(vk::SampleCountFlags::_1)
 		.load_op(vk::AttachmentLoadOp::CLEAR)
 		.store_op(vk::AttachmentStoreOp::STORE)
@@ -1040,30 +3396,32 @@ unsafe fn create_render_pass(
 
 	let color_attachments = &[color_attachment_ref];
 
+	// depth is kept around so the composite pass can inspect it, stencil
+	// only lasts the pass
 	let depth_stencil_attachment = vk::AttachmentDescription::builder()
-		.format(get_depth_format(instance, data)?)
+		.format(data.depth_format)
 		.samples(vk::SampleCountFlags::_1)
 		.load_op(vk::AttachmentLoadOp::CLEAR)
-		.store_op(vk::AttachmentStoreOp::DONT_CARE)
-		.stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+		.store_op(vk::AttachmentStoreOp::STORE)
+		.stencil_load_op(vk::AttachmentLoadOp::CLEAR)
 		.stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
 		.initial_layout(vk::ImageLayout::UNDEFINED)
 		.samples(data.msaa_samples)
-		.final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+		.final_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL);
 
 	let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
 		.attachment(1)
 		.layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
 
 	let color_resolve_attachment = vk::AttachmentDescription::builder()
-		.format(data.swapchain_format)
+		.format(SCENE_FORMAT)
 		.samples(vk::SampleCountFlags::_1)
 		.load_op(vk::AttachmentLoadOp::DONT_CARE)
 		.store_op(vk::AttachmentStoreOp::STORE)
 		.stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
 		.stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
 		.initial_layout(vk::ImageLayout::UNDEFINED)
-		.final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+		.final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
 
 	let color_resolve_attachment_ref = vk::AttachmentReference::builder()
 		.attachment(2)
@@ -1077,39 +3435,144 @@ unsafe fn create_render_pass(
 		.depth_stencil_attachment(&depth_stencil_attachment_ref)
 		.resolve_attachments(resolve_attachments);
 
+	// the previous frame's composite pass may still be sampling our targets
 	let dependency = vk::SubpassDependency::builder()
 		.src_subpass(vk::SUBPASS_EXTERNAL)
 		.dst_subpass(0)
 		.src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
-			| vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+			| vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+			| vk::PipelineStageFlags::FRAGMENT_SHADER)
 		.src_access_mask(vk::AccessFlags::empty())
 		.dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
 			| vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
 		.dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE
 			| vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
 
-	let attachments = &[color_attachment, depth_stencil_attachment, color_resolve_attachment];
-	let subpasses = &[subpass];
-	let dependencies = &[dependency];
+	// make the resolved color and depth visible to the bloom and composite passes
+	let composite_dependency = vk::SubpassDependency::builder()
+		.src_subpass(0)
+		.dst_subpass(vk::SUBPASS_EXTERNAL)
+		.src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+			| vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+		.src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+			| vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+		.dst_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER
+			| vk::PipelineStageFlags::FRAGMENT_SHADER)
+		.dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+	let attachments = &[color_attachment, depth_stencil_attachment, color_resolve_attachment];
+	let subpasses = &[subpass];
+	let dependencies = &[dependency, composite_dependency];
+
+	let info = vk::RenderPassCreateInfo::builder()
+		.subpasses(subpasses)
+		.attachments(attachments)
+		.dependencies(dependencies);
+
+	data.render_pass = device.create_render_pass(&info, None)?;
+
+	Ok(())
+}
+
+unsafe fn create_pipeline(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	// pipelines without the object buffer push the one object they draw
+	let push_constant_range = push_constant_range::<ObjectData>(vk::ShaderStageFlags::VERTEX);
+
+	let set_layouts = &[data.descriptor_set_layout];
+	let push_constant_ranges = &[push_constant_range];
+	let layout_info = vk::PipelineLayoutCreateInfo::builder()
+		.set_layouts(set_layouts)
+		.push_constant_ranges(push_constant_ranges);
+	data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+	data.pipeline = create_scene_pipeline(
+		device,
+		data,
+		data.vertex_layout,
+		data.vertex_streams,
+		include_bytes!(concat!(env!("OUT_DIR"), "/object_vert.spv")),
+		data.material.shader.fragment_spirv(),
+		data.material.blend_mode.attachment_state(),
+		data.material.cull_mode().flags(),
+		true,
+		StencilMode::Mark(OUTLINE_REFERENCE),
+	)?;
 
-	let info = vk::RenderPassCreateInfo::builder()
-		.subpasses(subpasses)
-		.attachments(attachments)
-		.dependencies(dependencies);
+	// overdraw counts every fragment, hidden or not
+	let additive = vk::PipelineColorBlendAttachmentState::builder()
+		.color_write_mask(vk::ColorComponentFlags::all())
+		.blend_enable(true)
+		.src_color_blend_factor(vk::BlendFactor::ONE)
+		.dst_color_blend_factor(vk::BlendFactor::ONE)
+		.color_blend_op(vk::BlendOp::ADD)
+		.src_alpha_blend_factor(vk::BlendFactor::ONE)
+		.dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+		.alpha_blend_op(vk::BlendOp::ADD)
+		.build();
 
-	data.render_pass = device.create_render_pass(&info, None)?;
+	data.overdraw_pipeline = create_scene_pipeline(
+		device,
+		data,
+		data.vertex_layout,
+		data.vertex_streams,
+		include_bytes!(concat!(env!("OUT_DIR"), "/object_vert.spv")),
+		include_bytes!(concat!(env!("OUT_DIR"), "/overdraw_frag.spv")),
+		additive,
+		vk::CullModeFlags::BACK,
+		false,
+		StencilMode::Disabled,
+	)?;
+
+	// shows through anything in front, like a selection highlight
+	data.outline_pipeline = create_scene_pipeline(
+		device,
+		data,
+		data.vertex_layout,
+		data.vertex_streams,
+		include_bytes!(concat!(env!("OUT_DIR"), "/object_vert.spv")),
+		include_bytes!(concat!(env!("OUT_DIR"), "/outline_frag.spv")),
+		BlendMode::Opaque.attachment_state(),
+		vk::CullModeFlags::BACK,
+		false,
+		StencilMode::Outside(OUTLINE_REFERENCE),
+	)?;
+
+	// voxel terrain is opaque and colored per vertex regardless of the material
+	data.voxel_pipeline = create_scene_pipeline(
+		device,
+		data,
+		VertexLayout::Full,
+		VertexStreams::Interleaved,
+		include_bytes!(concat!(env!("OUT_DIR"), "/vert.spv")),
+		include_bytes!(concat!(env!("OUT_DIR"), "/vertex_color_frag.spv")),
+		BlendMode::Opaque.attachment_state(),
+		vk::CullModeFlags::BACK,
+		true,
+		StencilMode::Disabled,
+	)?;
 
 	Ok(())
 }
 
-unsafe fn create_pipeline(
+/// Builds a pipeline that draws models into the scene render pass
+/// using the main pipeline layout.
+unsafe fn create_scene_pipeline(
 	device: &Device,
-	data: &mut AppData,
-	) -> Result<()>
+	data: &AppData,
+	vertex_layout: VertexLayout,
+	vertex_streams: VertexStreams,
+	vert: &[u8],
+	frag: &[u8],
+	blend_attachment: vk::PipelineColorBlendAttachmentState,
+	cull_mode: vk::CullModeFlags,
+	depth_test: bool,
+	stencil: StencilMode,
+	) -> Result<vk::Pipeline>
 {
-	let vert = include_bytes!("../shaders/vert.spv");
-	let frag = include_bytes!("../shaders/frag.spv");
-
 	let vert_sm = create_shader_module(device, vert)?;
 	let frag_sm = create_shader_module(device, frag)?;
 
@@ -1123,10 +3586,10 @@ unsafe fn create_pipeline(
 		.module(frag_sm)
 		.name(b"main\0");
 
-	let binding_descriptions = &[Vertex::binding_description()];
-	let attribute_descriptions = Vertex::attribute_descriptions();
+	let binding_descriptions = vertex_layout.binding_descriptions(vertex_streams);
+	let attribute_descriptions = vertex_layout.attribute_descriptions(vertex_streams);
 	let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
-		.vertex_binding_descriptions(binding_descriptions)
+		.vertex_binding_descriptions(&binding_descriptions)
 		.vertex_attribute_descriptions(&attribute_descriptions);
 
 	let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
@@ -1136,14 +3599,14 @@ unsafe fn create_pipeline(
 	let viewport = vk::Viewport::builder()
 		.x(0.0)
 		.y(0.0)
-		.width(data.swapchain_extent.width as f32)
-		.height(data.swapchain_extent.height as f32)
+		.width(data.render_extent.width as f32)
+		.height(data.render_extent.height as f32)
 		.min_depth(0.0)
 		.max_depth(1.0);
 
 	let scissor = vk::Rect2D::builder()
 		.offset(vk::Offset2D {x: 0, y:0 })
-		.extent(data.swapchain_extent);
+		.extent(data.render_extent);
 
 	let viewports = &[viewport];
 	let scissors = &[scissor];
@@ -1157,7 +3620,7 @@ unsafe fn create_pipeline(
 		.rasterizer_discard_enable(false)
 		.polygon_mode(vk::PolygonMode::FILL)
 		.line_width(1.0)
-		.cull_mode(vk::CullModeFlags::BACK)
+		.cull_mode(cull_mode)
 		.front_face(vk::FrontFace::COUNTER_CLOCKWISE)
 		.depth_bias_enable(false);
 
@@ -1166,16 +3629,7 @@ unsafe fn create_pipeline(
 		.min_sample_shading(0.2)
 		.rasterization_samples(data.msaa_samples);
 
-	let attachment = vk::PipelineColorBlendAttachmentState::builder()
-		.color_write_mask(vk::ColorComponentFlags::all())
-		.blend_enable(true)
-		.src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-		.dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-		.color_blend_op(vk::BlendOp::ADD)
-		.src_alpha_blend_factor(vk::BlendFactor::ONE)
-		.dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-		.alpha_blend_op(vk::BlendOp::ADD);
-	let attachments = &[attachment];
+	let attachments = &[blend_attachment];
 	let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
 		.logic_op_enable(false)
 		.logic_op(vk::LogicOp::COPY)
@@ -1183,30 +3637,16 @@ unsafe fn create_pipeline(
 		.blend_constants([0.0,0.0,0.0,0.0]);
 
 	let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-		.depth_test_enable(true)
-		.depth_write_enable(true)
-		.depth_compare_op(vk::CompareOp::LESS)
+		.depth_test_enable(depth_test)
+		.depth_write_enable(depth_test)
+		// equal passes where the depth pre-pass already drew the same surface
+		.depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
 		.depth_bounds_test_enable(false)
 		.min_depth_bounds(0.0)
 		.max_depth_bounds(1.0)
-		.stencil_test_enable(false);
-
-	let vert_push_constant_range = vk::PushConstantRange::builder()
-		.stage_flags(vk::ShaderStageFlags::VERTEX)
-		.offset(0)
-		.size(64); // mat4 -- 16 4 byte floats -- 16*4
-
-	let frag_push_constant_range = vk::PushConstantRange::builder()
-		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
-		.offset(64) // offset from vertex push constant's input
-		.size(4); // float -- 4 bytes
-
-	let set_layouts = &[data.descriptor_set_layout];
-	let push_constant_ranges = &[vert_push_constant_range, frag_push_constant_range];
-	let layout_info = vk::PipelineLayoutCreateInfo::builder()
-		.set_layouts(set_layouts)
-		.push_constant_ranges(push_constant_ranges);
-	data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+		.stencil_test_enable(stencil.enabled())
+		.front(stencil.op_state())
+		.back(stencil.op_state());
 
 	/*
 	// causes configuration of these values to be ignored
@@ -1236,15 +3676,15 @@ unsafe fn create_pipeline(
 		.render_pass(data.render_pass)
 		.subpass(0);
 
-	data.pipeline = device.create_graphics_pipelines(
-		vk::PipelineCache::null(),
+	let pipeline = device.create_graphics_pipelines(
+		data.pipeline_cache,
 		&[info],
 		None
 		)?.0[0];
 
 	device.destroy_shader_module(vert_sm, None);
 	device.destroy_shader_module(frag_sm, None);
-	Ok(())
+	Ok(pipeline)
 }
 
 unsafe fn create_framebuffers(
@@ -1252,23 +3692,18 @@ unsafe fn create_framebuffers(
 	data: &mut AppData,
 	) -> Result<()>
 {
-	data.framebuffers = data.swapchain_image_views
-						.iter()
-						.map(|image_view|
-							{
-								let attachments = &[
-									data.color_image_view,
-									data.depth_image_view,
-									*image_view,];
-								let info = vk::FramebufferCreateInfo::builder()
-									.render_pass(data.render_pass)
-									.attachments(attachments)
-									.width(data.swapchain_extent.width)
-									.height(data.swapchain_extent.height)
-									.layers(1);
-								device.create_framebuffer(&info, None)
-							})
-						.collect::<Result<Vec<_>,_>>()?;
+	let attachments = &[
+		data.color_image_view,
+		data.depth_image_view,
+		data.scene_image_view,];
+	let info = vk::FramebufferCreateInfo::builder()
+		.render_pass(data.render_pass)
+		.attachments(attachments)
+		.width(data.render_extent.width)
+		.height(data.render_extent.height)
+		.layers(1);
+
+	data.scene_framebuffer = device.create_framebuffer(&info, None)?;
 
 	Ok(())
 }
@@ -1298,37 +3733,22 @@ unsafe fn create_command_pools(
 	data.graphics_command_pool = create_command_pool(instance, device, data, indices.graphics)?;
 	data.transfer_command_pool = create_command_pool(instance, device, data, indices.transfer)?;
 
-	let num_images = data.swapchain_images.len();
-	for _ in 0..num_images
+	// one per frame in flight, reset whole at the start of its frame
+	for _ in 0..MAX_FRAMES_IN_FLIGHT
 	{
-		let g_command_pool = create_command_pool(instance, device, data, indices.graphics)?;
-		data.graphics_command_pools.push(g_command_pool);
+		let command_pool = create_command_pool(instance, device, data, indices.graphics)?;
+		data.frame_contexts.push(FrameContext::new(command_pool));
 	}
 
 	Ok(())
 }
 
-unsafe fn create_command_buffers(
-	device: &Device,
-	data: &mut AppData,
-	) -> Result<()>
+/// Waits for the whole device to idle. Everything in flight is fenced, so
+/// nothing should need this, and debug builds treat it as a bug.
+unsafe fn device_wait_idle(device: &Device) -> Result<()>
 {
-	let num_images = data.swapchain_images.len();
-	for image_index in 0..num_images
-	{
-		let command_pool = data.graphics_command_pools[image_index];
-
-		let allocate_info = vk::CommandBufferAllocateInfo::builder()
-			.command_pool(command_pool)
-			.level(vk::CommandBufferLevel::PRIMARY)
-			.command_buffer_count(1);
-
-		let command_buffer = device.allocate_command_buffers(&allocate_info)?[0];
-		data.graphics_command_buffers.push(command_buffer);
-	}
-
-	data.secondary_command_buffers = vec![vec![]; data.swapchain_images.len()];
-
+	debug_assert!(false, "waited for the device to idle instead of a fence");
+	device.device_wait_idle()?;
 	Ok(())
 }
 
@@ -1338,17 +3758,18 @@ unsafe fn create_sync_objects(
 	) -> Result<()>
 {
 	let semaphore_info = vk::SemaphoreCreateInfo::builder();
-	let fence_info = vk::FenceCreateInfo::builder()
-					.flags(vk::FenceCreateFlags::SIGNALED);
 
 	for _ in 0..MAX_FRAMES_IN_FLIGHT
 	{
 		data.image_available_semaphores.push(device.create_semaphore(&semaphore_info, None)?);
 		data.render_finished_semaphores.push(device.create_semaphore(&semaphore_info, None)?);
-		data.in_flight_fences.push(device.create_fence(&fence_info, None)?);
 	}
 
+	data.frame_sync = FrameSync::create(device, MAX_FRAMES_IN_FLIGHT, data.timeline_sync)?;
+	info!("Frame sync: {}", if data.frame_sync.is_timeline() { "timeline semaphore" } else { "fences" });
+
 	data.images_in_flight = data.swapchain_images.iter().map(|_| vk::Fence::null()).collect();
+	data.frames_in_flight = DEFAULT_FRAMES_IN_FLIGHT;
 
 	Ok(())
 }
@@ -1363,6 +3784,8 @@ extern "system" fn debug_callback(
 	let data = unsafe { *data };
 	let message = unsafe { CStr::from_ptr(data.message) }.to_string_lossy();
 
+	validation::record(severity, type_, &message);
+
 	if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
 	{
 		error!("({:?}) {}", type_, message);
@@ -1390,48 +3813,28 @@ struct Vertex
 	pos: glm::Vec3,
 	color: glm::Vec3,
 	tex_coord: glm::Vec2,
+	// second UV set, unique across the mesh so baked lighting doesn't overlap
+	lightmap_coord: glm::Vec2,
 }
 
 impl Vertex
 {
-	fn new(pos: glm::Vec3, color: glm::Vec3, tex_coord: glm::Vec2) -> Self
+	fn new(pos: glm::Vec3, color: glm::Vec3, tex_coord: glm::Vec2, lightmap_coord: glm::Vec2) -> Self
 	{
-		Self {pos, color, tex_coord}
+		Self {pos, color, tex_coord, lightmap_coord}
 	}
 
+	/// The binding for vertices uploaded as they are, in one stream, like
+	/// voxel chunks. Meshes that may be packed or split go through
+	/// `VertexLayout` instead.
 	fn binding_description() -> vk::VertexInputBindingDescription
 	{
-		vk::VertexInputBindingDescription::builder()
-			.binding(0)
-			.stride(size_of::<Vertex>() as u32)
-			.input_rate(vk::VertexInputRate::VERTEX)
-			.build()
+		VertexLayout::Full.binding_descriptions(VertexStreams::Interleaved)[0]
 	}
 
-	fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3]
+	fn attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription>
 	{
-		let pos = vk::VertexInputAttributeDescription::builder()
-			.binding(0)
-			.location(0)
-			.format(vk::Format::R32G32B32_SFLOAT)
-			.offset(0)
-			.build();
-
-		let color = vk::VertexInputAttributeDescription::builder()
-			.binding(0)
-			.location(1)
-			.format(vk::Format::R32G32B32_SFLOAT)
-			.offset(size_of::<glm::Vec3>() as u32)
-			.build();
-
-		let tex_coord = vk::VertexInputAttributeDescription::builder()
-			.binding(0)
-			.location(2)
-			.format(vk::Format::R32G32_SFLOAT)
-			.offset((size_of::<glm::Vec3>() + size_of::<glm::Vec3>()) as u32)
-			.build();
-
-		[pos, color, tex_coord]
+		VertexLayout::Full.attribute_descriptions(VertexStreams::Interleaved)
 	}
 }
 
@@ -1442,6 +3845,7 @@ impl PartialEq for Vertex
 		self.pos == other.pos
 			&& self.color == other.color
 			&& self.tex_coord == other.tex_coord
+			&& self.lightmap_coord == other.lightmap_coord
 	}
 }
 
@@ -1462,6 +3866,8 @@ impl Hash for Vertex
 		self.color[2].to_bits().hash(state);
 		self.tex_coord[0].to_bits().hash(state);
 		self.tex_coord[1].to_bits().hash(state);
+		self.lightmap_coord[0].to_bits().hash(state);
+		self.lightmap_coord[1].to_bits().hash(state);
 	}
 }
 
@@ -1491,7 +3897,7 @@ unsafe fn create_buffer(
 	size: vk::DeviceSize,
 	usage: vk::BufferUsageFlags,
 	properties: vk::MemoryPropertyFlags,
-	) -> Result<(vk::Buffer, vk::DeviceMemory)>
+	) -> Result<(vk::Buffer, Allocation)>
 {
 	let buffer_info = vk::BufferCreateInfo::builder()
 		.size(size)
@@ -1500,20 +3906,7 @@ unsafe fn create_buffer(
 
 	let buffer = device.create_buffer(&buffer_info, None)?;
 
-	let requirements = device.get_buffer_memory_requirements(buffer);
-
-	let memory_info = vk::MemoryAllocateInfo::builder()
-		.allocation_size(requirements.size)
-		.memory_type_index(get_memory_type_index(
-				instance,
-				data,
-				properties,
-				requirements
-				)?);
-
-	let buffer_memory = device.allocate_memory(&memory_info, None)?;
-
-	device.bind_buffer_memory(buffer, buffer_memory, 0)?;
+	let buffer_memory = allocator::allocate_buffer(instance, device, data, buffer, properties, format!("buffer {:?}", usage))?;
 
 	Ok((buffer, buffer_memory))
 }
@@ -1524,19 +3917,8 @@ unsafe fn begin_single_time_commands(
 	command_pool: vk::CommandPool,
 	) -> Result<vk::CommandBuffer>
 {
-	let info = vk::CommandBufferAllocateInfo::builder()
-		.level(vk::CommandBufferLevel::PRIMARY)
-		.command_pool(command_pool)
-		.command_buffer_count(1);
-
-	let command_buffer = device.allocate_command_buffers(&info)?[0];
-
-	let info = vk::CommandBufferBeginInfo::builder()
-		.flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-
-	device.begin_command_buffer(command_buffer, &info)?;
-
-	Ok(command_buffer)
+	let queue = if command_pool == data.transfer_command_pool { data.transfer_queue } else { data.graphics_queue };
+	data.immediate.begin(device, command_pool, queue)
 }
 
 unsafe fn end_single_time_commands(
@@ -1547,17 +3929,7 @@ unsafe fn end_single_time_commands(
 	command_pool: vk::CommandPool,
 	) -> Result<()>
 {
-	device.end_command_buffer(command_buffer)?;
-
-	let command_buffers = &[command_buffer];
-	let info = vk::SubmitInfo::builder()
-		.command_buffers(command_buffers);
-
-	device.queue_submit(queue, &[info], vk::Fence::null())?;
-	device.queue_wait_idle(queue)?;
-	device.free_command_buffers(command_pool, command_buffers);
-
-	Ok(())
+	data.immediate.end(device, command_buffer, queue, command_pool)
 }
 
 unsafe fn copy_buffer(
@@ -1590,55 +3962,92 @@ unsafe fn create_vertex_buffer(
 	data: &mut AppData,
 	) -> Result<()>
 {
-	let size = (size_of::<Vertex>() * data.vertices.len()) as u64;
-
-	let (staging_buffer, staging_buffer_memory) = create_buffer(
+	let vertices = data.vertices.clone();
+	let (vertex_buffer, vertex_buffer_memory, vertex_offsets) = vertex_format::create_vertex_buffer(
 		instance,
 		device,
 		data,
-		size,
-		vk::BufferUsageFlags::TRANSFER_SRC,
-		vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+		&vertices,
+		data.vertex_layout,
+		data.vertex_streams,
 	)?;
 
-	let memory = device.map_memory(
-		staging_buffer_memory,
-		0,
-		size,
-		vk::MemoryMapFlags::empty()
-		)?;
+	data.vertex_buffer = vertex_buffer;
+	data.vertex_buffer_memory = vertex_buffer_memory;
+	data.vertex_offsets = vertex_offsets;
+
+	Ok(())
+}
+
+/// Binds every stream of the model's vertex buffer.
+unsafe fn bind_vertex_streams(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer)
+{
+	let buffers = vec![data.vertex_buffer; data.vertex_offsets.len()];
+	device.cmd_bind_vertex_buffers(command_buffer, 0, &buffers, &data.vertex_offsets);
+}
 
-	memcpy(data.vertices.as_ptr(), memory.cast(), data.vertices.len());
+/// The model's draws as the descriptor set, first index and index count of
+/// each. Neighbouring primitives drawn with the same set are merged, so a
+/// model with one texture stays a single draw.
+fn primitive_draws(data: &AppData) -> Vec<(vk::DescriptorSet, u32, u32)>
+{
+	let mut draws: Vec<(vk::DescriptorSet, u32, u32)> = Vec::new();
+
+	for (primitive, set) in data.primitives.iter().zip(&data.primitive_descriptor_sets)
+	{
+		match draws.last_mut()
+		{
+			Some((last_set, first_index, index_count))
+				if last_set == set && *first_index + *index_count == primitive.first_index =>
+			{
+				*index_count += primitive.index_count;
+			},
+			_ => draws.push((*set, primitive.first_index, primitive.index_count)),
+		}
+	}
 
-	device.unmap_memory(staging_buffer_memory);
+	if draws.is_empty()
+	{
+		draws.push((data.descriptor_set, 0, data.indices.len() as u32));
+	}
+
+	draws
+}
 
-	let (vertex_buffer, vertex_buffer_memory) = create_buffer(
+unsafe fn create_index_buffer(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let indices = data.indices.clone();
+	let (index_buffer, index_buffer_memory, index_type) = vertex_format::create_index_buffer(
 		instance,
 		device,
 		data,
-		size,
-		vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
-		vk::MemoryPropertyFlags::DEVICE_LOCAL,
+		&indices,
+		INDEX_WIDTH,
 	)?;
 
-	data.vertex_buffer = vertex_buffer;
-	data.vertex_buffer_memory = vertex_buffer_memory;
-
-	copy_buffer(device, data, staging_buffer, vertex_buffer, size)?;
-
-	device.destroy_buffer(staging_buffer, None);
-	device.free_memory(staging_buffer_memory, None);
+	data.index_buffer = index_buffer;
+	data.index_buffer_memory = index_buffer_memory;
+	data.index_type = index_type;
 
 	Ok(())
 }
 
-unsafe fn create_index_buffer(
+/// A host visible buffer holding a copy of `items`, for uploading them to
+/// device local memory with `copy_buffer` or `copy_buffer_to_image`. Every
+/// upload goes through one of these; free it with `destroy_staging_buffer`
+/// once the copy is done.
+unsafe fn create_staging_buffer<T: Copy>(
 	instance: &Instance,
 	device: &Device,
-	data: &mut AppData,
-	) -> Result<()>
+	data: &AppData,
+	items: &[T],
+	) -> Result<(vk::Buffer, Allocation)>
 {
-	let size = (size_of::<u32>() * data.indices.len()) as u64;
+	let size = size_of_val(items) as u64;
 
 	let (staging_buffer, staging_buffer_memory) = create_buffer(
 		instance,
@@ -1650,68 +4059,279 @@ unsafe fn create_index_buffer(
 	)?;
 
 	let memory = device.map_memory(
-		staging_buffer_memory,
-		0,
+		staging_buffer_memory.memory,
+		staging_buffer_memory.offset,
 		size,
 		vk::MemoryMapFlags::empty()
 		)?;
 
-	memcpy(data.indices.as_ptr(), memory.cast(), data.indices.len());
+	memcpy(items.as_ptr(), memory.cast(), items.len());
+
+	device.unmap_memory(staging_buffer_memory.memory);
+
+	Ok((staging_buffer, staging_buffer_memory))
+}
+
+unsafe fn destroy_staging_buffer(device: &Device, data: &AppData, staging: (vk::Buffer, Allocation))
+{
+	data.immediate.free_staging(device, staging);
+}
+
+/// Uploads `items` into a new device local buffer through a staging buffer.
+unsafe fn create_device_local_buffer<T: Copy>(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	items: &[T],
+	usage: vk::BufferUsageFlags,
+	) -> Result<(vk::Buffer, Allocation)>
+{
+	let size = size_of_val(items) as u64;
 
-	device.unmap_memory(staging_buffer_memory);
+	let staging = create_staging_buffer(instance, device, data, items)?;
 
-	let (index_buffer, index_buffer_memory) = create_buffer(
+	let (buffer, buffer_memory) = create_buffer(
 		instance,
 		device,
 		data,
 		size,
-		vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
-		vk::MemoryPropertyFlags::DEVICE_LOCAL
+		vk::BufferUsageFlags::TRANSFER_DST | usage,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL,
 	)?;
 
-	data.index_buffer = index_buffer;
-	data.index_buffer_memory = index_buffer_memory;
-
-	copy_buffer(device, data, staging_buffer, index_buffer, size)?;
+	copy_buffer(device, data, staging.0, buffer, size)?;
 
-	device.destroy_buffer(staging_buffer, None);
-	device.free_memory(staging_buffer_memory, None);
+	destroy_staging_buffer(device, data, staging);
 
-	Ok(())
+	Ok((buffer, buffer_memory))
 }
 
+/// Creates one uniform buffer for every swapchain image's uniforms, each
+/// in its own slice aligned for use as a dynamic offset.
 unsafe fn create_uniform_buffers(
 	instance: &Instance,
 	device: &Device,
 	data: &mut AppData,
 	) -> Result<()>
 {
-	data.uniform_buffers.clear();
-	data.uniform_buffers_memory.clear();
+	let alignment = instance
+		.get_physical_device_properties(data.physical_device)
+		.limits
+		.min_uniform_buffer_offset_alignment;
 
-	for _ in 0..data.swapchain_images.len()
-	{
-		let (uniform_buffer, uniform_buffer_memory) = create_buffer(
-			instance,
-			device,
-			data,
-			size_of::<UniformBufferObject>() as u64,
-			vk::BufferUsageFlags::UNIFORM_BUFFER,
-			vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-		)?;
+	let size = size_of::<UniformBufferObject>() as u64;
+	data.uniform_stride = size.div_ceil(alignment) * alignment;
 
-		data.uniform_buffers.push(uniform_buffer);
-		data.uniform_buffers_memory.push(uniform_buffer_memory);
-	}
+	(data.uniform_buffer, data.uniform_buffer_memory) = create_buffer(
+		instance,
+		device,
+		data,
+		data.uniform_stride * data.swapchain_images.len() as u64,
+		// stereo rewrites the eye's matrices between passes
+		vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+		vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+	)?;
+	data.uniform_buffer_mapped = map_persistently(device, data.uniform_buffer_memory)?;
+
+	Ok(())
+}
+
+/// Maps the whole of host visible memory for as long as it lives, it's
+/// written every frame. Freeing the memory unmaps it.
+unsafe fn map_persistently(device: &Device, memory: Allocation) -> Result<Option<NonNull<c_void>>>
+{
+	Ok(NonNull::new(device.map_memory(memory.memory, memory.offset, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())?))
+}
+
+/// The mapping at `offset` bytes in.
+unsafe fn mapped_at(mapped: Option<NonNull<c_void>>, offset: usize) -> Result<*mut c_void>
+{
+	let mapped = mapped.ok_or_else(|| anyhow!("Memory isn't mapped"))?;
+	Ok(mapped.as_ptr().cast::<u8>().add(offset).cast())
+}
+
+/// Dynamic offset of the swapchain image's uniforms, to bind the
+/// descriptor set with.
+fn uniform_offset(data: &AppData, image_index: usize) -> u32
+{
+	(data.uniform_stride * image_index as u64) as u32
+}
+
+/// Copies the uniforms into the swapchain image's slice of the buffer.
+unsafe fn write_uniforms(
+	device: &Device,
+	data: &AppData,
+	image_index: usize,
+	ubo: &UniformBufferObject,
+	) -> Result<()>
+{
+	let memory = mapped_at(data.uniform_buffer_mapped, uniform_offset(data, image_index) as usize)?;
+
+	memcpy(ubo, memory.cast(), 1);
 
 	Ok(())
 }
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 struct UniformBufferObject
 {
 	view: glm::Mat4,
 	proj: glm::Mat4,
+	debug_view: i32,
+	time: f32,
+	// std140 aligns the arrays below to 16 bytes
+	_padding: [f32; 2],
+	irradiance: [ShIrradiance; MAX_MODELS],
+	reflection_probes: [[[f32; 4]; 3]; MAX_REFLECTION_PROBES],
+	camera_position: [f32; 4],
+	reflection_probe_count: i32,
+	reflectivity: f32,
+	// std140 aligns emissive to 16 bytes
+	_padding2: [f32; 2],
+	emissive: [f32; 4],
+	// w is how much daylight there is
+	sun_direction: [f32; 4],
+	height_scale: f32,
+	clearcoat: f32,
+	transmission: f32,
+	ior: f32,
+	roughness: f32,
+	specular: f32,
+	anisotropy_strength: f32,
+	anisotropy_rotation: f32,
+	double_sided: u32,
+	mirror: f32,
+	light_count: i32,
+	// std140 aligns the lights to 16 bytes
+	_padding3: f32,
+	lights: [LightUniform; MAX_LIGHTS],
+	shadow_views: [ShadowViewUniform; MAX_SHADOW_VIEWS],
+}
+
+/// Debug outputs of the main fragment shader, must match `shader.frag`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DebugView
+{
+	None = 0,
+	Uvs = 1,
+	Normals = 2,
+	MipLevel = 3,
+	Tangents = 4,
+	Roughness = 5,
+}
+
+impl DebugView
+{
+	fn next(self) -> Self
+	{
+		match self
+		{
+			DebugView::None => DebugView::Uvs,
+			DebugView::Uvs => DebugView::Normals,
+			DebugView::Normals => DebugView::MipLevel,
+			DebugView::MipLevel => DebugView::Tangents,
+			DebugView::Tangents => DebugView::Roughness,
+			DebugView::Roughness => DebugView::None,
+		}
+	}
+}
+
+/// What a model draw renders.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ModelPass
+{
+	/// With the material.
+	Shaded,
+	/// Only its depth, for the pre-pass.
+	DepthOnly,
+	/// A solid shell around it, wherever it didn't mark the stencil.
+	Outline,
+}
+
+/// What the model shaders know about the object they're drawing, looked up
+/// by instance index in the object buffer or pushed as constants. Must match
+/// `Object` in `shader.vert`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ObjectData
+{
+	model: glm::Mat4,
+	opacity: f32,
+	probe_index: i32,
+	lightmapped: u32,
+	// 0 keeps every texel
+	alpha_cutoff: f32,
+	// multiplied into the albedo, w is padding
+	tint: glm::Vec4,
+}
+
+impl ObjectData
+{
+	fn new(model: glm::Mat4, opacity: f32, probe_index: i32, lightmapped: bool) -> Self
+	{
+		Self {
+			model,
+			opacity,
+			probe_index,
+			lightmapped: lightmapped as u32,
+			alpha_cutoff: 0.0,
+			tint: glm::vec4(1.0, 1.0, 1.0, 1.0),
+		}
+	}
+}
+
+/// Creates the buffer every swapchain image's objects are looked up in,
+/// MAX_OBJECTS apart.
+unsafe fn create_object_buffer(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	(data.object_buffer, data.object_buffer_memory) = create_buffer(
+		instance,
+		device,
+		data,
+		(size_of::<ObjectData>() * MAX_OBJECTS * data.swapchain_images.len()) as u64,
+		vk::BufferUsageFlags::STORAGE_BUFFER,
+		vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+	)?;
+	data.object_buffer_mapped = map_persistently(device, data.object_buffer_memory)?;
+
+	Ok(())
+}
+
+/// Index of the swapchain image's first object, draws add it to their
+/// first instance.
+fn first_object_index(image_index: usize) -> u32
+{
+	(image_index * MAX_OBJECTS) as u32
+}
+
+/// Copies the objects into the swapchain image's part of the object buffer.
+unsafe fn write_objects(
+	device: &Device,
+	data: &AppData,
+	image_index: usize,
+	objects: &[ObjectData],
+	) -> Result<()>
+{
+	if objects.is_empty()
+	{
+		return Ok(());
+	}
+
+	if objects.len() > MAX_OBJECTS
+	{
+		return Err(anyhow!("{} objects don't fit in the object buffer, the limit is {}", objects.len(), MAX_OBJECTS));
+	}
+
+	let memory = mapped_at(data.object_buffer_mapped, first_object_index(image_index) as usize * size_of::<ObjectData>())?;
+
+	memcpy(objects.as_ptr(), memory.cast(), objects.len());
+
+	Ok(())
 }
 
 unsafe fn create_descriptor_set_layout(
@@ -1721,9 +4341,9 @@ unsafe fn create_descriptor_set_layout(
 {
 	let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
 		.binding(0)
-		.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+		.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
 		.descriptor_count(1)
-		.stage_flags(vk::ShaderStageFlags::VERTEX);
+		.stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
 
 	let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
 		.binding(1)
@@ -1731,83 +4351,297 @@ unsafe fn create_descriptor_set_layout(
 		.descriptor_count(1)
 		.stage_flags(vk::ShaderStageFlags::FRAGMENT);
 
-	let bindings = &[ubo_binding, sampler_binding];
+	let reflection_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(2)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(MAX_REFLECTION_PROBES as u32)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+	let lightmap_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(3)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+	let emissive_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(4)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+	let height_map_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(5)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+	let refraction_samplers = &[common_sampler(data, CommonSampler::LinearClamp)];
+	let refraction_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(6)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+		.immutable_samplers(refraction_samplers);
+
+	let object_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(7)
+		.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::VERTEX);
+
+	let mirror_samplers = &[common_sampler(data, CommonSampler::LinearClamp)];
+	let mirror_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(8)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+		.immutable_samplers(mirror_samplers);
+
+	let shadow_samplers = &[common_sampler(data, CommonSampler::ShadowCompare)];
+	let shadow_binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(9)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+		.immutable_samplers(shadow_samplers);
+
+	let bindings = &[
+		ubo_binding,
+		sampler_binding,
+		reflection_binding,
+		lightmap_binding,
+		emissive_binding,
+		height_map_binding,
+		refraction_binding,
+		object_binding,
+		mirror_binding,
+		shadow_binding,
+	];
 	let info = vk::DescriptorSetLayoutCreateInfo::builder()
 		.bindings(bindings);
 
-	data.descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+	data.descriptor_set_layout = data.descriptors.layout(device, &info)?;
 
 	Ok(())
 }
 
-unsafe fn create_descriptor_pool(
+unsafe fn create_descriptor_sets(
 	device: &Device,
-	data: &mut AppData
+	data: &mut AppData,
 	) -> Result<()>
 {
-	let ubo_size = vk::DescriptorPoolSize::builder()
-		.type_(vk::DescriptorType::UNIFORM_BUFFER)
-		.descriptor_count(data.swapchain_images.len() as u32);
+	// a single set serves every frame, the uniforms' dynamic offset picks the frame's slice
+	data.descriptor_set = data.descriptors.swapchain.allocate(device, &[data.descriptor_set_layout])?[0];
+
+	data.layouts.expect(
+		data.texture_image,
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+		"sampled texture",
+	);
 
-	let sampler_size = vk::DescriptorPoolSize::builder()
-		.type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-		.descriptor_count(data.swapchain_images.len() as u32);
+	write_descriptor_set(device, data, data.descriptor_set, data.texture_image_view);
 
-	let pool_sizes = &[ubo_size, sampler_size];
-	let info = vk::DescriptorPoolCreateInfo::builder()
-		.pool_sizes(pool_sizes)
-		.max_sets(data.swapchain_images.len() as u32);
+	// primitives with a texture of their own get a set that only differs in
+	// it, shared by the primitives using the same one
+	let views = data.primitives
+		.iter()
+		.map(|primitive|
+			{
+				primitive.material.base_color_texture
+					.as_ref()
+					.and_then(|texture| data.primitive_textures.get(texture))
+					.map(|handle| data.textures.texture(handle).view)
+			})
+		.collect::<Vec<_>>();
+
+	let mut sets = HashMap::new();
+	data.primitive_descriptor_sets = Vec::with_capacity(views.len());
+	for view in views
+	{
+		let set = match view
+		{
+			Some(view) => match sets.get(&view)
+			{
+				Some(set) => *set,
+				None =>
+				{
+					let set = data.descriptors.swapchain.allocate(device, &[data.descriptor_set_layout])?[0];
+					write_descriptor_set(device, data, set, view);
+					sets.insert(view, set);
+					set
+				},
+			},
+			None => data.descriptor_set,
+		};
+		data.primitive_descriptor_sets.push(set);
+	}
 
-	data.descriptor_pool = device.create_descriptor_pool(&info, None)?;
 	Ok(())
 }
 
-unsafe fn create_descriptor_sets(
+/// Points every binding of a main descriptor set at its resource, with
+/// `texture_view` as the base color texture.
+unsafe fn write_descriptor_set(
 	device: &Device,
-	data: &mut AppData,
-	) -> Result<()>
+	data: &AppData,
+	set: vk::DescriptorSet,
+	texture_view: vk::ImageView,
+	)
 {
-	let layouts = vec![data.descriptor_set_layout; data.swapchain_images.len()];
-	let info = vk::DescriptorSetAllocateInfo::builder()
-		.descriptor_pool(data.descriptor_pool)
-		.set_layouts(&layouts);
-
-	data.descriptor_sets = device.allocate_descriptor_sets(&info)?;
-
-	for i in 0..data.swapchain_images.len()
-	{
-		let info = vk::DescriptorBufferInfo::builder()
-			.buffer(data.uniform_buffers[i])
-			.offset(0)
-			.range(size_of::<UniformBufferObject>() as u64);
-
-		let buffer_info = &[info];
-		let ubo_write = vk::WriteDescriptorSet::builder()
-			.dst_set(data.descriptor_sets[i])
-			.dst_binding(0)
-			.dst_array_element(0)
-			.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-			.buffer_info(buffer_info);
-
-		let info = vk::DescriptorImageInfo::builder()
-			.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-			.image_view(data.texture_image_view)
-			.sampler(data.texture_sampler);
-
-		let image_info = &[info];
-		let sampler_write = vk::WriteDescriptorSet::builder()
-			.dst_set(data.descriptor_sets[i])
-			.dst_binding(1)
-			.dst_array_element(0)
-			.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-			.image_info(image_info);
-
-		device.update_descriptor_sets(
-			&[ubo_write, sampler_write],
-			&[] as &[vk::CopyDescriptorSet]
-		);
-	}
-	Ok(())
+	let info = vk::DescriptorBufferInfo::builder()
+		.buffer(data.uniform_buffer)
+		.offset(0)
+		.range(size_of::<UniformBufferObject>() as u64);
+
+	let buffer_info = &[info];
+	let ubo_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(0)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+		.buffer_info(buffer_info);
+
+	let info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(texture_view)
+		.sampler(data.texture_sampler);
+
+	let image_info = &[info];
+	let sampler_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(1)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(image_info);
+
+	let reflection_infos = data.reflection_cubemaps
+		.iter()
+		.map(|cubemap|
+			{
+				vk::DescriptorImageInfo::builder()
+					.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+					.image_view(cubemap.view)
+					.sampler(data.reflection_sampler)
+					.build()
+			})
+		.collect::<Vec<_>>();
+
+	let reflection_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(2)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(&reflection_infos);
+
+	let info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(lightmap::lightmap_view(data))
+		.sampler(data.texture_sampler);
+
+	let lightmap_info = &[info];
+	let lightmap_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(3)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(lightmap_info);
+
+	let info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(material_map_view(data, &data.emissive_texture))
+		.sampler(data.texture_sampler);
+
+	let emissive_info = &[info];
+	let emissive_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(4)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(emissive_info);
+
+	let info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(material_map_view(data, &data.height_map))
+		.sampler(data.texture_sampler);
+
+	let height_map_info = &[info];
+	let height_map_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(5)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(height_map_info);
+
+	let info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(data.refraction_image_view);
+
+	let refraction_info = &[info];
+	let refraction_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(6)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(refraction_info);
+
+	let info = vk::DescriptorBufferInfo::builder()
+		.buffer(data.object_buffer)
+		.offset(0)
+		.range(vk::WHOLE_SIZE);
+
+	let object_info = &[info];
+	let object_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(7)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+		.buffer_info(object_info);
+
+	let info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(data.mirror_image_view);
+
+	let mirror_info = &[info];
+	let mirror_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(8)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(mirror_info);
+
+	data.layouts.expect(
+		data.shadow_atlas_image,
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+		"shadow atlas",
+	);
+
+	let info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(data.shadow_atlas_image_view);
+
+	let shadow_info = &[info];
+	let shadow_write = vk::WriteDescriptorSet::builder()
+		.dst_set(set)
+		.dst_binding(9)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(shadow_info);
+
+	device.update_descriptor_sets(
+		&[
+			ubo_write,
+			sampler_write,
+			reflection_write,
+			lightmap_write,
+			emissive_write,
+			height_map_write,
+			refraction_write,
+			object_write,
+			mirror_write,
+			shadow_write,
+		],
+		&[] as &[vk::CopyDescriptorSet]
+	);
 }
 
 unsafe fn create_image(
@@ -1822,7 +4656,7 @@ unsafe fn create_image(
 	tiling: vk::ImageTiling,
 	usage: vk::ImageUsageFlags,
 	properties: vk::MemoryPropertyFlags,
-	) -> Result<(vk::Image, vk::DeviceMemory)>
+	) -> Result<(vk::Image, Allocation)>
 {
 	let info = vk::ImageCreateInfo::builder()
 		.image_type(vk::ImageType::_2D)
@@ -1840,19 +4674,8 @@ unsafe fn create_image(
 
 	let image = device.create_image(&info, None)?;
 
-	let requirements = device.get_image_memory_requirements(image);
-
-	let info = vk::MemoryAllocateInfo::builder()
-		.allocation_size(requirements.size)
-		.memory_type_index(get_memory_type_index(
-				instance,
-				data,
-				vk::MemoryPropertyFlags::DEVICE_LOCAL,
-				requirements,
-				)?);
-	
-	let texture_image_memory = device.allocate_memory(&info, None)?;
-	device.bind_image_memory(image, texture_image_memory, 0)?;
+	let label = format!("image {:?} {}x{}", format, width, height);
+	let texture_image_memory = allocator::allocate_image(instance, device, data, image, properties, label)?;
 
 	Ok((image, texture_image_memory))
 }
@@ -1868,14 +4691,33 @@ unsafe fn generate_mipmaps(
 	mip_levels: u32,
 	) -> Result<()>
 {
-	if !instance
+	// nearest blits only need the format to be blittable, and still beat
+	// sampling the full resolution image from afar
+	let features = instance
 		.get_physical_device_format_properties(data.physical_device, format)
-		.optimal_tiling_features
-		.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+		.optimal_tiling_features;
+	let linear = features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR);
+
+	// images of the formats it supports are created with its usage
+	if mip_levels > 1 && ComputeMips::supports(instance, data, format) && (MIP_GENERATION == MipGeneration::Compute || !linear)
 	{
-		return Err(anyhow!("Linear blitting not supported by texture image format"));
+		return data.compute_mips.generate(device, data, image, format, width, height, mip_levels);
 	}
 
+	let filter = if linear
+	{
+		vk::Filter::LINEAR
+	}
+	else if features.contains(vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST)
+	{
+		warn!("{:?} can't be linearly filtered, generating mipmaps with nearest blits", format);
+		vk::Filter::NEAREST
+	}
+	else
+	{
+		return Err(anyhow!("Blitting not supported by texture image format {:?}", format));
+	};
+
 	let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
 
 	let subresource = vk::ImageSubresourceRange::builder()
@@ -1952,7 +4794,7 @@ unsafe fn generate_mipmaps(
 			image,
 			vk::ImageLayout::TRANSFER_DST_OPTIMAL,
 			&[blit],
-			vk::Filter::LINEAR,
+			filter,
 		);
 
 		barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
@@ -1981,6 +4823,12 @@ unsafe fn generate_mipmaps(
 		}
 	}
 
+	data.layouts.transition(
+		image,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+	);
+
 	barrier.subresource_range.base_mip_level = mip_levels - 1;
 	barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
 	barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
@@ -2011,48 +4859,28 @@ unsafe fn generate_mipmaps(
 unsafe fn create_texture_image(
 	instance: &Instance,
 	device: &Device,
-	data: &mut AppData
+	data: &mut AppData,
+	path: &str,
 	) -> Result<()>
 {
-	let image = File::open("media/viking_room.png")?;
-
-	let decoder = png::Decoder::new(image);
-	let mut reader = decoder.read_info()?;
-
-	//TODO handle png images that don't have an alpha channel
-	if reader.info().color_type != png::ColorType::Rgba
+	if (path.ends_with(".basis") || path.ends_with(".ktx2")) && Path::new(path).exists()
 	{
-		panic!("Invalid texture image. Make sure it has an alpha channel");
+		return basis::create_basis_texture_image(instance, device, data, path);
 	}
 
-	let mut pixels = vec![0; reader.info().raw_bytes()];
-	reader.next_frame(&mut pixels)?;
-
-	let size = reader.info().raw_bytes() as u64;
-
-	let (width, height) = reader.info().size();
-
-	let (staging_buffer, staging_buffer_memory) = create_buffer(
-		instance,
-		device,
-		data,
-		size,
-		vk::BufferUsageFlags::TRANSFER_SRC,
-		vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-	)?;
-
-	let memory = device.map_memory(
-		staging_buffer_memory,
-		0,
-		size,
-		vk::MemoryMapFlags::empty(),
-		)?;
+	// images embedded in a glTF file were kept by the model under their path
+	let image = match data.embedded_images.get(path)
+	{
+		Some(bytes) => bytes.clone(),
+		None => fallback::read_or(path, fallback::CHECKERBOARD_TEXTURE),
+	};
 
-	memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
+	let images::Pixels { width, height, rgba } = images::decode_rgba(&image, path)?;
 
-	device.unmap_memory(staging_buffer_memory);
+	let staging = create_staging_buffer(instance, device, data, &rgba)?;
 
 	data.mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+	data.texture_format = vk::Format::R8G8B8A8_SRGB;
 
 	let(texture_image, texture_image_memory) = create_image(
 		instance,
@@ -2065,8 +4893,8 @@ unsafe fn create_texture_image(
 		vk::Format::R8G8B8A8_SRGB,
 		vk::ImageTiling::OPTIMAL,
 		vk::ImageUsageFlags::SAMPLED
-			| vk::ImageUsageFlags::TRANSFER_SRC
-			| vk::ImageUsageFlags::TRANSFER_DST,
+			| vk::ImageUsageFlags::TRANSFER_DST
+			| ComputeMips::usage(instance, data, vk::Format::R8G8B8A8_SRGB),
 		vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
 
 	data.texture_image = texture_image;
@@ -2085,14 +4913,13 @@ unsafe fn create_texture_image(
 	copy_buffer_to_image(
 		device,
 		data,
-		staging_buffer,
+		staging.0,
 		data.texture_image,
 		width,
 		height,
 	)?;
 
-	device.destroy_buffer(staging_buffer, None);
-	device.free_memory(staging_buffer_memory, None);
+	destroy_staging_buffer(device, data, staging);
 
 	generate_mipmaps(
 		instance,
@@ -2108,6 +4935,7 @@ unsafe fn create_texture_image(
 	Ok(())
 }
 
+#[track_caller]
 unsafe fn copy_buffer_to_image(
 	device: &Device,
 	data: &AppData,
@@ -2117,6 +4945,8 @@ unsafe fn copy_buffer_to_image(
 	height: u32,
 	) -> Result<()>
 {
+	data.layouts.expect(image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, "copy destination");
+
 	let command_buffer = begin_single_time_commands(device, data, data.transfer_command_pool)?;
 
 	let subresource = vk::ImageSubresourceLayers::builder()
@@ -2151,6 +4981,7 @@ unsafe fn copy_buffer_to_image(
 	Ok(())
 }
 
+#[track_caller]
 unsafe fn transition_image_layout(
 	device: &Device,
 	data: &AppData,
@@ -2189,6 +5020,8 @@ unsafe fn transition_image_layout(
 		_ => return Err(anyhow!("ImageLayout transition not supported")),
 	};
 
+	data.layouts.transition(image, old_layout, new_layout);
+
 	let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
 
 	let subresource = vk::ImageSubresourceRange::builder()
@@ -2273,7 +5106,7 @@ unsafe fn create_texture_image_views(
 	data.texture_image_view = create_image_view(
 		device,
 		data.texture_image,
-		vk::Format::R8G8B8A8_SRGB,
+		data.texture_format,
 		vk::ImageAspectFlags::COLOR,
 		data.mip_levels,
 	)?;
@@ -2282,6 +5115,239 @@ unsafe fn create_texture_image_views(
 	Ok(())
 }
 
+/// Binds the material's texture, loading it into the texture cache
+/// if it isn't resident already.
+unsafe fn load_texture(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let path = data.material.texture.clone();
+	let handle = load_mipped_texture(instance, device, data, &path)?;
+
+	let texture = *data.textures.texture(&handle);
+	data.texture_image = texture.image;
+	data.texture_image_memory = texture.memory;
+	data.texture_image_view = texture.view;
+	data.texture_format = texture.format;
+	data.mip_levels = texture.mip_levels;
+	data.texture = Some(handle);
+
+	Ok(())
+}
+
+/// Loads a texture with mipmaps into the texture cache if it isn't resident
+/// already. Goes through the `texture_*` fields, which are left pointing at
+/// it.
+unsafe fn load_mipped_texture(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	path: &str,
+	) -> Result<AssetHandle>
+{
+	if let Some(handle) = data.textures.get(path)
+	{
+		return Ok(handle);
+	}
+
+	create_texture_image(instance, device, data, path)?;
+	create_texture_image_views(device, data)?;
+
+	let texture = Texture {
+		image: data.texture_image,
+		memory: data.texture_image_memory,
+		view: data.texture_image_view,
+		format: data.texture_format,
+		mip_levels: data.mip_levels,
+		size: device.get_image_memory_requirements(data.texture_image).size,
+	};
+
+	Ok(data.textures.insert(device, path, texture))
+}
+
+/// Loads the base color textures of the model's primitives that differ from
+/// the material's. Has to come before `load_texture`, since these loads
+/// leave the `texture_*` fields pointing at the last of them.
+unsafe fn load_primitive_textures(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let paths = data.primitives
+		.iter()
+		.filter_map(|primitive| primitive.material.base_color_texture.clone())
+		.filter(|path| *path != data.material.texture)
+		.collect::<HashSet<_>>();
+
+	data.primitive_textures.clear();
+	for path in paths
+	{
+		let handle = load_mipped_texture(instance, device, data, &path)?;
+		data.primitive_textures.insert(path, handle);
+	}
+
+	Ok(())
+}
+
+/// Loads a texture without mipmaps into the texture cache if it isn't
+/// resident already.
+unsafe fn load_unmipped_texture(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	path: &str,
+	format: vk::Format,
+	) -> Result<AssetHandle>
+{
+	Ok(load_unmipped_textures(instance, device, data, &[(path.to_string(), format)])?.remove(0))
+}
+
+/// Loads several textures like `load_unmipped_texture`, decoding the ones
+/// that aren't resident yet in parallel. The uploads stay one at a time.
+unsafe fn load_unmipped_textures(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	textures: &[(String, vk::Format)],
+	) -> Result<Vec<AssetHandle>>
+{
+	let missing = textures
+		.iter()
+		.map(|(path, _)| path.clone())
+		.filter(|path| data.textures.get(path).is_none())
+		.collect::<Vec<_>>();
+	let decoded = data.jobs.map(System::Decoding, &missing, |path| images::load_rgba(path));
+	let mut decoded = missing.into_iter().zip(decoded).collect::<HashMap<_, _>>();
+
+	textures
+		.iter()
+		.map(|(path, format)|
+			{
+				if let Some(handle) = data.textures.get(path)
+				{
+					return Ok(handle);
+				}
+
+				info!("Loading {}", path);
+				let pixels = decoded.remove(path).unwrap_or_else(|| images::load_rgba(path))?;
+				let texture = create_unmipped_texture_image(instance, device, data, &pixels, *format)?;
+				Ok(data.textures.insert(device, path, texture))
+			})
+		.collect()
+}
+
+/// Binds the material's emissive texture and height map, clearing the
+/// bindings for whichever it doesn't have.
+unsafe fn load_material_maps(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let emissive = data.material.emissive_texture.clone().map(|path| (path, vk::Format::R8G8B8A8_SRGB));
+	// heights aren't colors, so they're read back exactly as stored
+	let height = data.material.height_map.clone().map(|path| (path, vk::Format::R8G8B8A8_UNORM));
+
+	let maps = emissive.iter().chain(&height).cloned().collect::<Vec<_>>();
+	let mut handles = load_unmipped_textures(instance, device, data, &maps)?.into_iter();
+	data.emissive_texture = emissive.and_then(|_| handles.next());
+	data.height_map = height.and_then(|_| handles.next());
+
+	Ok(())
+}
+
+/// The map's view, or the material texture's when there isn't one so the
+/// descriptor is always valid.
+fn material_map_view(data: &AppData, map: &Option<AssetHandle>) -> vk::ImageView
+{
+	match map
+	{
+		Some(handle) => data.textures.texture(handle).view,
+		None => data.texture_image_view,
+	}
+}
+
+/// Emissive color scaled by its intensity, w is 1 when the emissive texture
+/// should be sampled.
+fn emissive_uniform(data: &AppData) -> [f32; 4]
+{
+	let (r, g, b) = data.material.emissive;
+	let intensity = data.material.emissive_intensity;
+	let textured = if data.emissive_texture.is_some() { 1.0 } else { 0.0 };
+	[r * intensity, g * intensity, b * intensity, textured]
+}
+
+/// Depth of the material's height map in UV units, 0 without one so the
+/// parallax shaders leave the UVs alone.
+fn height_scale(data: &AppData) -> f32
+{
+	if data.height_map.is_some() { data.material.height_scale } else { 0.0 }
+}
+
+/// Uploads decoded pixels as a single mip texture.
+unsafe fn create_unmipped_texture_image(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	pixels: &images::Pixels,
+	format: vk::Format,
+	) -> Result<Texture>
+{
+	let staging = create_staging_buffer(instance, device, data, &pixels.rgba)?;
+
+	let (image, image_memory) = create_image(
+		instance,
+		device,
+		data,
+		pixels.width,
+		pixels.height,
+		1,
+		vk::SampleCountFlags::_1,
+		format,
+		vk::ImageTiling::OPTIMAL,
+		vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL,
+	)?;
+
+	transition_image_layout(
+		device,
+		data,
+		image,
+		format,
+		vk::ImageLayout::UNDEFINED,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		1,
+	)?;
+
+	copy_buffer_to_image(device, data, staging.0, image, pixels.width, pixels.height)?;
+
+	destroy_staging_buffer(device, data, staging);
+
+	transition_image_layout(
+		device,
+		data,
+		image,
+		format,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+		1,
+	)?;
+
+	let view = create_image_view(device, image, format, vk::ImageAspectFlags::COLOR, 1)?;
+
+	Ok(Texture {
+		image,
+		memory: image_memory,
+		view,
+		format,
+		mip_levels: 1,
+		size: device.get_image_memory_requirements(image).size,
+	})
+}
+
 unsafe fn create_texture_sampler(
 	device: &Device,
 	data: &mut AppData,
@@ -2300,8 +5366,8 @@ unsafe fn create_texture_sampler(
 		.compare_enable(false)
 		.compare_op(vk::CompareOp::ALWAYS)
 		.mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-		.mip_lod_bias(0.0)
-		.min_lod(0.0)
+		.mip_lod_bias(data.mip_lod_bias)
+		.min_lod(data.min_lod.min(data.mip_levels as f32))
 		.max_lod(data.mip_levels as f32);
 
 	data.texture_sampler = device.create_sampler(&info, None)?;
@@ -2357,31 +5423,60 @@ unsafe fn get_depth_format(
 	)
 }
 
+/// A format with stencil for the scene's depth buffer. Every device supports
+/// at least one of these.
+unsafe fn get_depth_stencil_format(
+	instance: &Instance,
+	data: &AppData,
+	) -> Result<vk::Format>
+{
+	let candidates = &[
+		vk::Format::D32_SFLOAT_S8_UINT,
+		vk::Format::D24_UNORM_S8_UINT,
+	];
+
+	get_supported_format(
+		instance,
+		data,
+		candidates,
+		vk::ImageTiling::OPTIMAL,
+		vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+	)
+}
+
 unsafe fn create_depth_objects(
 	instance: &Instance,
 	device: &Device,
 	data: &mut AppData,
 	) -> Result<()>
 {
-	let format = get_depth_format(instance, data)?;
+	let format = data.depth_format;
 
 	let (depth_image, depth_image_memory) = create_image(
 		instance,
 		device,
 		data,
-		data.swapchain_extent.width,
-		data.swapchain_extent.height,
+		data.render_extent.width,
+		data.render_extent.height,
 		1,
 		data.msaa_samples,
 		format,
 		vk::ImageTiling::OPTIMAL,
-		vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+		// copied out by captures
+		vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC,
 		vk::MemoryPropertyFlags::DEVICE_LOCAL,
 	)?;
 
 	data.depth_image = depth_image;
 	data.depth_image_memory = depth_image_memory;
 	data.depth_image_view = create_image_view(
+		device,
+		data.depth_image,
+		format,
+		vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+		1,
+	)?;
+	data.depth_sample_view = create_image_view(
 		device,
 		data.depth_image,
 		format,
@@ -2392,78 +5487,38 @@ unsafe fn create_depth_objects(
 	Ok(())
 }
 
-fn load_model(data: &mut AppData) -> Result<()>
+/// Loads the model at `path`, or the embedded OBJ instead if there is one.
+fn load_model(data: &mut AppData, path: &Path, embedded: Option<&[u8]>) -> Result<()>
 {
-	let mut reader = BufReader::new(File::open("media/viking_room.obj")?);
-
-	let (models, _) = tobj::load_obj_buf(
-		&mut reader,
-		&tobj::LoadOptions { triangulate: true, ..Default::default() },
-		|_| Ok(Default::default()),
-	)?;
-
-	let mut unique_vertices = HashMap::new();
+	let mesh = match (embedded, path.extension().and_then(|e| e.to_str()))
+	{
+		(Some(bytes), _) => model::load_obj(bytes)?,
+		(None, Some("gltf" | "glb")) => model::load_gltf(path).or_else(|e|
+			{
+				warn!("Failed to load {} ({}), using embedded fallback", path.display(), e);
+				model::load_obj(fallback::CUBE_MESH)
+			})?,
+		_ => model::load_obj(&fallback::read_or(path, fallback::CUBE_MESH))?,
+	};
 
-	for model in &models
+	data.model_texture = mesh.base_color_texture().map(str::to_string);
+	if let Some(texture) = &data.model_texture
 	{
-		for index in &model.mesh.indices
-		{
-			let pos_offset = (3 * index) as usize;
-			let tex_coord_offset = (2 * index) as usize;
+		data.material.texture = texture.clone();
+	}
 
-			let vertex = Vertex {
-				pos: glm::vec3(
-						 model.mesh.positions[pos_offset],
-						 model.mesh.positions[pos_offset + 1],
-						 model.mesh.positions[pos_offset + 2],
-						 ),
-				color: glm::vec3(1.0,1.0,1.0),
-				tex_coord: glm::vec2(
-					model.mesh.texcoords[tex_coord_offset],
-					1.0 - model.mesh.texcoords[tex_coord_offset + 1],
-					)
-			};
+	data.vertices = mesh.vertices;
+	data.indices = mesh.indices;
+	data.primitives = mesh.primitives;
+	data.embedded_images = mesh.embedded_images;
 
-			if let Some(index) = unique_vertices.get(&vertex)
-			{
-				data.indices.push(*index as u32);
-			}
-			else
-			{
-				let index = data.vertices.len();
-				unique_vertices.insert(vertex, index);
-				data.vertices.push(vertex);
-				data.indices.push(index as u32);
-			}
-		}
-	}
+	data.vertex_layout = VertexLayout::select(&data.vertices);
+	data.vertex_streams = VERTEX_STREAMS;
+	info!("Loaded {} vertices in {} primitives as {:?}, {:?}", data.vertices.len(), data.primitives.len(), data.vertex_layout, data.vertex_streams);
 
 	Ok(())
 }
 
-unsafe fn get_max_msaa_samples(
-	instance: &Instance,
-	data: &AppData,
-	) -> vk::SampleCountFlags
-{
-	let properties = instance.get_physical_device_properties(data.physical_device);
-	let counts = properties.limits.framebuffer_color_sample_counts
-		& properties.limits.framebuffer_depth_sample_counts;
-
-	[
-		vk::SampleCountFlags::_64,
-		vk::SampleCountFlags::_32,
-		vk::SampleCountFlags::_16,
-		vk::SampleCountFlags::_8,
-		vk::SampleCountFlags::_4,
-		vk::SampleCountFlags::_2,
-	]
-	.iter()
-	.cloned()
-	.find(|count| counts.contains(*count))
-	.unwrap_or(vk::SampleCountFlags::_1)
-}
-
 unsafe fn create_color_objects(
 	instance: &Instance,
 	device: &Device,
@@ -2474,14 +5529,14 @@ unsafe fn create_color_objects(
 		instance,
 		device,
 		data,
-		data.swapchain_extent.width,
-		data.swapchain_extent.height,
+		data.render_extent.width,
+		data.render_extent.height,
 		1,
 		data.msaa_samples,
-		data.swapchain_format,
+		SCENE_FORMAT,
 		vk::ImageTiling::OPTIMAL,
-		vk::ImageUsageFlags::COLOR_ATTACHMENT
-			| vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+		// not transient, the transmission pass draws on top of what the scene pass left
+		vk::ImageUsageFlags::COLOR_ATTACHMENT,
 		vk::MemoryPropertyFlags::DEVICE_LOCAL,
 	)?;
 
@@ -2491,7 +5546,7 @@ unsafe fn create_color_objects(
 	data.color_image_view = create_image_view(
 		device,
 		data.color_image,
-		data.swapchain_format,
+		SCENE_FORMAT,
 		vk::ImageAspectFlags::COLOR,
 		1,
 	)?;
@@ -0,0 +1,377 @@
+// Material definitions
+//
+// Materials live in `.mat.ron` files next to the assets they describe, e.g.
+//
+// (
+//     shader: Textured,
+//     texture: "media/viking_room.png",
+//     opacity: 1.0,
+//     blend_mode: AlphaBlend,
+//     reflectivity: 0.2,
+//     lightmap: Some("media/viking_room_lightmap.png"),
+//     emissive: (1.0, 0.6, 0.2),
+//     emissive_intensity: 4.0,
+//     emissive_texture: Some("media/viking_room_emissive.png"),
+//     height_map: Some("media/viking_room_height.png"),
+//     height_scale: 0.04,
+//     clearcoat: 1.0,
+//     transmission: 0.0,
+//     ior: 1.5,
+//     mirror: 0.0,
+//     roughness: 0.3,
+//     specular: 1.0,
+//     anisotropy_strength: 0.8,
+//     anisotropy_rotation: 0.0,
+//     cull_mode: Back,
+//     double_sided: false,
+//     queue: Some(Transparent),
+//     alpha_cutoff: Some(0.5),
+// )
+//
+// The file is polled for changes while the app runs so materials can be
+// tweaked without recompiling.
+
+use anyhow::Result;
+use serde::Deserialize;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::render_queue::RenderQueue;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum ShaderVariant
+{
+	#[default]
+	Textured,
+	VertexColor,
+	/// Textured with parallax occlusion mapping from the height map.
+	Parallax,
+	/// Parallax that also shadows itself where the height map blocks the sun.
+	ParallaxShadowed,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum BlendMode
+{
+	Opaque,
+	#[default]
+	AlphaBlend,
+	Additive,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum CullMode
+{
+	#[default]
+	Back,
+	Front,
+	None,
+}
+
+/// Depth-only permutations of the material's shaders, picked from the
+/// material rather than set in it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepthVariant
+{
+	/// Positions only and no fragment shader.
+	PositionOnly,
+	/// Every attribute, with a fragment shader discarding below the cutoff.
+	AlphaTested,
+}
+
+impl DepthVariant
+{
+	pub fn vertex_spirv(self) -> &'static [u8]
+	{
+		match self
+		{
+			DepthVariant::PositionOnly => include_bytes!(concat!(env!("OUT_DIR"), "/depth_vert.spv")),
+			DepthVariant::AlphaTested => include_bytes!(concat!(env!("OUT_DIR"), "/object_vert.spv")),
+		}
+	}
+
+	pub fn fragment_spirv(self) -> Option<&'static [u8]>
+	{
+		match self
+		{
+			DepthVariant::PositionOnly => None,
+			DepthVariant::AlphaTested => Some(include_bytes!(concat!(env!("OUT_DIR"), "/depth_alpha_frag.spv"))),
+		}
+	}
+}
+
+impl ShaderVariant
+{
+	pub fn fragment_spirv(self) -> &'static [u8]
+	{
+		match self
+		{
+			ShaderVariant::Textured => include_bytes!(concat!(env!("OUT_DIR"), "/frag.spv")),
+			ShaderVariant::VertexColor => include_bytes!(concat!(env!("OUT_DIR"), "/vertex_color_frag.spv")),
+			ShaderVariant::Parallax => include_bytes!(concat!(env!("OUT_DIR"), "/parallax_frag.spv")),
+			ShaderVariant::ParallaxShadowed => include_bytes!(concat!(env!("OUT_DIR"), "/parallax_shadowed_frag.spv")),
+		}
+	}
+}
+
+impl CullMode
+{
+	pub fn flags(self) -> vk::CullModeFlags
+	{
+		match self
+		{
+			CullMode::Back => vk::CullModeFlags::BACK,
+			CullMode::Front => vk::CullModeFlags::FRONT,
+			CullMode::None => vk::CullModeFlags::NONE,
+		}
+	}
+}
+
+impl BlendMode
+{
+	pub fn attachment_state(self) -> vk::PipelineColorBlendAttachmentState
+	{
+		let builder = vk::PipelineColorBlendAttachmentState::builder()
+			.color_write_mask(vk::ColorComponentFlags::all())
+			.color_blend_op(vk::BlendOp::ADD)
+			.src_alpha_blend_factor(vk::BlendFactor::ONE)
+			.dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+			.alpha_blend_op(vk::BlendOp::ADD);
+
+		match self
+		{
+			BlendMode::Opaque => builder
+				.blend_enable(false)
+				.src_color_blend_factor(vk::BlendFactor::ONE)
+				.dst_color_blend_factor(vk::BlendFactor::ZERO),
+			BlendMode::AlphaBlend => builder
+				.blend_enable(true)
+				.src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+				.dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+			BlendMode::Additive => builder
+				.blend_enable(true)
+				.src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+				.dst_color_blend_factor(vk::BlendFactor::ONE),
+		}
+		.build()
+	}
+}
+
+fn default_opacity() -> f32
+{
+	1.0
+}
+
+fn default_emissive_intensity() -> f32
+{
+	1.0
+}
+
+fn default_height_scale() -> f32
+{
+	0.05
+}
+
+/// glTF's default, about right for glass and most plastics.
+fn default_ior() -> f32
+{
+	1.5
+}
+
+fn default_roughness() -> f32
+{
+	0.5
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct Material
+{
+	#[serde(default)]
+	pub shader: ShaderVariant,
+	pub texture: String,
+	/// Multiplied with the per-model opacity push constant.
+	#[serde(default = "default_opacity")]
+	pub opacity: f32,
+	#[serde(default)]
+	pub blend_mode: BlendMode,
+	/// How much of the reflection probes' cubemaps is blended in.
+	#[serde(default)]
+	pub reflectivity: f32,
+	/// Baked lighting for static geometry, sampled with the second UV set.
+	#[serde(default)]
+	pub lightmap: Option<String>,
+	/// Light the surface gives off regardless of lighting, in linear RGB.
+	#[serde(default)]
+	pub emissive: (f32, f32, f32),
+	/// Scales `emissive`, values pushing it above 1 make the surface bloom.
+	#[serde(default = "default_emissive_intensity")]
+	pub emissive_intensity: f32,
+	/// Multiplied with `emissive`, sampled with the regular UVs.
+	#[serde(default)]
+	pub emissive_texture: Option<String>,
+	/// Surface relief for the parallax shaders, white is highest.
+	#[serde(default)]
+	pub height_map: Option<String>,
+	/// How deep the height map's black goes below the surface, in UV units.
+	#[serde(default = "default_height_scale")]
+	pub height_scale: f32,
+	/// Strength of a smooth varnish layer reflecting the probes on top of
+	/// everything else, like glTF's `KHR_materials_clearcoat`. The probes'
+	/// cubemaps have no blurred mips, so the coat is always mirror smooth.
+	#[serde(default)]
+	pub clearcoat: f32,
+	/// How much of the scene behind shows through, refracted and tinted by
+	/// the texture, like glTF's `KHR_materials_transmission`. Transmissive
+	/// models are drawn in their own pass after everything opaque.
+	#[serde(default)]
+	pub transmission: f32,
+	/// Index of refraction for transmission and the Fresnel reflection on
+	/// transmissive surfaces, like glTF's `KHR_materials_ior`.
+	#[serde(default = "default_ior")]
+	pub ior: f32,
+	/// How much of the scene's mirror plane reflection replaces the surface,
+	/// for flat mirrors and water lying on the plane. Nothing without one.
+	#[serde(default)]
+	pub mirror: f32,
+	/// Perceptual roughness of the sun's specular highlight.
+	#[serde(default = "default_roughness")]
+	pub roughness: f32,
+	/// Strength of the sun's specular highlight, 0 leaves the surface matte.
+	#[serde(default)]
+	pub specular: f32,
+	/// Stretches the highlight along the anisotropy direction for brushed
+	/// metal and hair, from 0 to 1 like glTF's `KHR_materials_anisotropy`.
+	#[serde(default)]
+	pub anisotropy_strength: f32,
+	/// Counter-clockwise angle in radians from the texture's u axis to the
+	/// anisotropy direction.
+	#[serde(default)]
+	pub anisotropy_rotation: f32,
+	/// Which faces aren't drawn, `None` for thin surfaces seen from both sides.
+	#[serde(default)]
+	pub cull_mode: CullMode,
+	/// Draws back faces too and lights them with the normal flipped towards
+	/// the camera, like glTF's `doubleSided`. Overrides `cull_mode`, which is
+	/// what foliage and thin-walled assets want.
+	#[serde(default)]
+	pub double_sided: bool,
+	/// Where the models are drawn, picked from the blend mode and
+	/// transmission when left out.
+	#[serde(default)]
+	pub queue: Option<RenderQueue>,
+	/// Texels with less alpha than this are cut out rather than blended,
+	/// for foliage and fences. Puts the models in the alpha test queue.
+	#[serde(default)]
+	pub alpha_cutoff: Option<f32>,
+}
+
+impl Material
+{
+	pub fn load(path: &Path) -> Result<Self>
+	{
+		Ok(ron::from_str(&fs::read_to_string(path)?)?)
+	}
+
+	pub fn render_queue(&self) -> RenderQueue
+	{
+		if let Some(queue) = self.queue
+		{
+			queue
+		}
+		else if self.transmission > 0.0
+		{
+			RenderQueue::Transmissive
+		}
+		else if self.alpha_cutoff.is_some()
+		{
+			RenderQueue::AlphaTest
+		}
+		else if self.blend_mode == BlendMode::Opaque
+		{
+			RenderQueue::Opaque
+		}
+		else
+		{
+			RenderQueue::Transparent
+		}
+	}
+
+	pub fn depth_variant(&self) -> DepthVariant
+	{
+		if self.alpha_cutoff.is_some()
+		{
+			DepthVariant::AlphaTested
+		}
+		else
+		{
+			DepthVariant::PositionOnly
+		}
+	}
+
+	pub fn cull_mode(&self) -> CullMode
+	{
+		if self.double_sided
+		{
+			CullMode::None
+		}
+		else
+		{
+			self.cull_mode
+		}
+	}
+}
+
+/// Tracks a material file on disk so it can be reloaded when it changes.
+#[derive(Clone, Debug)]
+pub struct MaterialWatcher
+{
+	path: PathBuf,
+	modified: Option<SystemTime>,
+	last_poll: Instant,
+}
+
+impl MaterialWatcher
+{
+	pub fn new(path: impl Into<PathBuf>) -> Self
+	{
+		let path = path.into();
+		let modified = modified_time(&path);
+		Self { path, modified, last_poll: Instant::now() }
+	}
+
+	pub fn path(&self) -> &Path
+	{
+		&self.path
+	}
+
+	/// Returns the freshly parsed material if the file changed since the
+	/// last poll. Polling is throttled so this is cheap to call every frame.
+	pub fn poll(&mut self) -> Option<Result<Material>>
+	{
+		if self.last_poll.elapsed() < POLL_INTERVAL
+		{
+			return None;
+		}
+
+		self.last_poll = Instant::now();
+
+		let modified = modified_time(&self.path);
+		if modified == self.modified
+		{
+			return None;
+		}
+
+		self.modified = modified;
+		Some(Material::load(&self.path))
+	}
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime>
+{
+	fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,201 @@
+// GPU memory report
+//
+// The allocator records everything it hands out here so the live
+// allocations can be listed: on shutdown, where anything left when the
+// allocator is destroyed is a leak, and on demand with M. Debug builds also
+// keep where each allocation was made, which is usually all a leak needs.
+//
+// There's no overlay to draw it in, so the on-demand report also writes a
+// treemap of every live allocation to `captures/memory.svg`: a column per
+// memory type, as wide as its share of the total, split into a block per
+// allocation. Hovering a block shows what it is.
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::allocator::{self, Allocation};
+use crate::capture::CAPTURE_DIRECTORY;
+
+const TREEMAP_WIDTH: f64 = 1200.0;
+const TREEMAP_HEIGHT: f64 = 800.0;
+
+#[derive(Clone, Debug)]
+pub struct Record
+{
+	pub size: vk::DeviceSize,
+	pub memory_type: u32,
+	/// What the memory is for, e.g. the buffer usage or image format.
+	pub label: String,
+	/// Where it was allocated, only captured in debug builds.
+	pub backtrace: Option<Arc<Backtrace>>,
+}
+
+// keyed by the raw memory handle and offset
+static ALLOCATIONS: Mutex<BTreeMap<(u64, vk::DeviceSize), Record>> = Mutex::new(BTreeMap::new());
+
+pub fn record(allocation: &Allocation, label: impl Into<String>)
+{
+	let backtrace = cfg!(debug_assertions).then(|| Arc::new(Backtrace::force_capture()));
+	let record = Record { size: allocation.size, memory_type: allocation.memory_type, label: label.into(), backtrace };
+	ALLOCATIONS.lock().unwrap().insert((allocation.memory.as_raw(), allocation.offset), record);
+}
+
+pub fn forget(allocation: &Allocation)
+{
+	ALLOCATIONS.lock().unwrap().remove(&(allocation.memory.as_raw(), allocation.offset));
+}
+
+/// Every allocation that hasn't been freed yet.
+pub fn live() -> Vec<Record>
+{
+	ALLOCATIONS.lock().unwrap().values().cloned().collect()
+}
+
+/// Logs how much of each heap is in use, then the live allocations
+/// totalled per memory type.
+pub fn log_report(properties: &vk::PhysicalDeviceMemoryProperties)
+{
+	let allocations = live();
+	let total = allocations.iter().map(|a| a.size).sum::<vk::DeviceSize>();
+	info!("{} live GPU allocations, {}", allocations.len(), format_size(total));
+
+	let heap = |memory_type: u32| properties.memory_types[memory_type as usize].heap_index as usize;
+	let mut used = vec![0; properties.memory_heap_count as usize];
+	let mut spare = vec![0; properties.memory_heap_count as usize];
+	for allocation in &allocations
+	{
+		used[heap(allocation.memory_type)] += allocation.size;
+	}
+	// the blocks' live ranges are already counted as used
+	for (memory_type, _, unused) in allocator::blocks()
+	{
+		spare[heap(memory_type)] += unused;
+	}
+	for (i, (used, spare)) in used.iter().zip(&spare).enumerate()
+	{
+		let heap_size = properties.memory_heaps[i].size;
+		info!("  heap {}: {} used, {} more reserved in blocks, of {}", i, format_size(*used), format_size(*spare), format_size(heap_size));
+	}
+
+	for (memory_type, allocations) in by_memory_type(&allocations)
+	{
+		let size = allocations.iter().map(|a| a.size).sum::<vk::DeviceSize>();
+		info!("  {}: {} allocations, {}", memory_type_name(properties, memory_type), allocations.len(), format_size(size));
+		for allocation in allocations
+		{
+			info!("    {:>10}  {}", format_size(allocation.size), allocation.label);
+		}
+	}
+}
+
+/// Logs anything still allocated as a leak, with where it was allocated in
+/// debug builds. Call once everything has been destroyed.
+pub fn log_leaks()
+{
+	let allocations = live();
+	if allocations.is_empty()
+	{
+		return;
+	}
+
+	let total = allocations.iter().map(|a| a.size).sum::<vk::DeviceSize>();
+	warn!("{} GPU allocations leaked, {}", allocations.len(), format_size(total));
+	for allocation in &allocations
+	{
+		warn!("  {} ({}, memory type {})", allocation.label, format_size(allocation.size), allocation.memory_type);
+		if let Some(backtrace) = &allocation.backtrace
+		{
+			warn!("{}", backtrace);
+		}
+	}
+}
+
+/// Writes a treemap of the live allocations as an SVG, returning its path.
+pub fn write_treemap(properties: &vk::PhysicalDeviceMemoryProperties) -> Result<PathBuf>
+{
+	let allocations = live();
+	let total = allocations.iter().map(|a| a.size).sum::<vk::DeviceSize>().max(1) as f64;
+
+	let mut svg = String::new();
+	writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="monospace" font-size="11">"#, TREEMAP_WIDTH, TREEMAP_HEIGHT)?;
+
+	let mut x = 0.0;
+	for (i, (memory_type, allocations)) in by_memory_type(&allocations).into_iter().enumerate()
+	{
+		let type_size = allocations.iter().map(|a| a.size).sum::<vk::DeviceSize>() as f64;
+		let width = type_size / total * TREEMAP_WIDTH;
+		let hue = i * 67 % 360;
+
+		let mut y = 0.0;
+		for allocation in allocations
+		{
+			let height = allocation.size as f64 / type_size * TREEMAP_HEIGHT;
+			writeln!(
+				svg,
+				r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="hsl({}, 55%, 60%)" stroke="black" stroke-width="0.5"><title>{} ({})</title></rect>"#,
+				x, y, width, height, hue, escape(&allocation.label), format_size(allocation.size),
+			)?;
+			y += height;
+		}
+
+		writeln!(
+			svg,
+			r#"<text x="{:.1}" y="12">{} ({})</text>"#,
+			x + 2.0, escape(&memory_type_name(properties, memory_type)), format_size(type_size as vk::DeviceSize),
+		)?;
+		x += width;
+	}
+
+	writeln!(svg, "</svg>")?;
+
+	fs::create_dir_all(CAPTURE_DIRECTORY)?;
+	let path = Path::new(CAPTURE_DIRECTORY).join("memory.svg");
+	fs::write(&path, svg)?;
+	Ok(path)
+}
+
+/// The allocations grouped by memory type, largest first within each.
+fn by_memory_type(allocations: &[Record]) -> BTreeMap<u32, Vec<&Record>>
+{
+	let mut groups = BTreeMap::<u32, Vec<&Record>>::new();
+	for allocation in allocations
+	{
+		groups.entry(allocation.memory_type).or_default().push(allocation);
+	}
+
+	for allocations in groups.values_mut()
+	{
+		allocations.sort_by_key(|a| std::cmp::Reverse(a.size));
+	}
+
+	groups
+}
+
+fn memory_type_name(properties: &vk::PhysicalDeviceMemoryProperties, memory_type: u32) -> String
+{
+	let flags = properties.memory_types[memory_type as usize].property_flags;
+	format!("type {} {:?}", memory_type, flags)
+}
+
+fn format_size(size: vk::DeviceSize) -> String
+{
+	match size
+	{
+		s if s >= 1 << 20 => format!("{:.1} MiB", s as f64 / (1 << 20) as f64),
+		s if s >= 1 << 10 => format!("{:.1} KiB", s as f64 / (1 << 10) as f64),
+		s => format!("{} B", s),
+	}
+}
+
+fn escape(text: &str) -> String
+{
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
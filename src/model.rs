@@ -0,0 +1,303 @@
+// Models
+//
+// OBJ meshes are loaded with tobj, triangulated, and welded back into
+// indexed vertices: OBJ indexes positions and UVs separately, so every
+// corner is expanded to a full vertex and identical ones are shared through
+// a map. Models without UVs or vertex colors get zeros and white, rather
+// than failing to load, so any mesh can at least be looked at.
+//
+// glTF scenes are flattened into the same single mesh: each node's
+// transform is baked into its vertices, and each primitive keeps its index
+// range and material so it's drawn with its own base color texture. The
+// base color factor is multiplied into the vertex colors, which tint the
+// texture. Nothing else is read from the materials, the rest of the
+// shading still comes from the `.mat.ron` file.
+//
+// Images stored in the file's buffers, as in most `.glb` files, are kept
+// encoded in the mesh under a made up path, which the texture cache loads
+// them by like any other.
+
+use anyhow::{anyhow, Result};
+use log::*;
+use nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::Vertex;
+
+#[derive(Clone, Debug, Default)]
+pub struct Mesh
+{
+	pub vertices: Vec<Vertex>,
+	pub indices: Vec<u32>,
+	pub primitives: Vec<Primitive>,
+	/// Encoded images from the file's buffers, by the path their primitives
+	/// name them with.
+	pub embedded_images: HashMap<String, Vec<u8>>,
+}
+
+/// A range of the mesh's indices sharing a material.
+#[derive(Clone, Debug, Default)]
+pub struct Primitive
+{
+	pub first_index: u32,
+	pub index_count: u32,
+	pub material: PrimitiveMaterial,
+}
+
+#[derive(Clone, Debug)]
+pub struct PrimitiveMaterial
+{
+	/// Already multiplied into the vertex colors, which tint the texture.
+	pub base_color: glm::Vec4,
+	/// Resolved against the model's directory, or a key of the mesh's
+	/// `embedded_images`.
+	pub base_color_texture: Option<String>,
+}
+
+impl Default for PrimitiveMaterial
+{
+	fn default() -> Self
+	{
+		Self { base_color: glm::vec4(1.0, 1.0, 1.0, 1.0), base_color_texture: None }
+	}
+}
+
+impl Mesh
+{
+	/// Adds a corner, sharing the vertex with an identical earlier one.
+	fn push(&mut self, unique_vertices: &mut HashMap<Vertex, usize>, vertex: Vertex)
+	{
+		if let Some(index) = unique_vertices.get(&vertex)
+		{
+			self.indices.push(*index as u32);
+		}
+		else
+		{
+			let index = self.vertices.len();
+			unique_vertices.insert(vertex, index);
+			self.vertices.push(vertex);
+			self.indices.push(index as u32);
+		}
+	}
+
+	/// Ends a primitive at the current last index.
+	fn finish_primitive(&mut self, first_index: usize, material: PrimitiveMaterial)
+	{
+		self.primitives.push(Primitive {
+			first_index: first_index as u32,
+			index_count: (self.indices.len() - first_index) as u32,
+			material,
+		});
+	}
+
+	/// The first primitive's base color texture, which stands in for the
+	/// material's. Primitives with other textures are drawn with their own.
+	pub fn base_color_texture(&self) -> Option<&str>
+	{
+		self.primitives.iter().find_map(|p| p.material.base_color_texture.as_deref())
+	}
+}
+
+/// Loads every object in the OBJ into one mesh. Materials are ignored, the
+/// material file decides how it's shaded.
+pub fn load_obj(bytes: &[u8]) -> Result<Mesh>
+{
+	let mut reader = BufReader::new(bytes);
+
+	let (models, _) = tobj::load_obj_buf(
+		&mut reader,
+		&tobj::LoadOptions { triangulate: true, ..Default::default() },
+		|_| Ok(Default::default()),
+	)?;
+
+	let mut mesh = Mesh::default();
+	let mut unique_vertices = HashMap::new();
+
+	for model in &models
+	{
+		let obj = &model.mesh;
+
+		for (corner, &index) in obj.indices.iter().enumerate()
+		{
+			let index = index as usize;
+			let pos_offset = 3 * index;
+
+			// UVs and colors may be indexed separately from the positions
+			let tex_coord_index = obj.texcoord_indices.get(corner).map_or(index, |&i| i as usize);
+			let tex_coord = match obj.texcoords.get(2 * tex_coord_index..2 * tex_coord_index + 2)
+			{
+				Some(uv) => glm::vec2(uv[0], 1.0 - uv[1]),
+				None => glm::vec2(0.0, 0.0),
+			};
+
+			let color = match obj.vertex_color.get(pos_offset..pos_offset + 3)
+			{
+				Some(rgb) => glm::vec3(rgb[0], rgb[1], rgb[2]),
+				None => glm::vec3(1.0, 1.0, 1.0),
+			};
+
+			// OBJ only carries one UV set, so lightmaps for OBJ models have
+			// to be baked against the same unwrap as the texture
+			let vertex = Vertex {
+				pos: glm::vec3(
+						 obj.positions[pos_offset],
+						 obj.positions[pos_offset + 1],
+						 obj.positions[pos_offset + 2],
+						 ),
+				color,
+				tex_coord,
+				lightmap_coord: tex_coord,
+			};
+
+			mesh.push(&mut unique_vertices, vertex);
+		}
+	}
+
+	mesh.finish_primitive(0, PrimitiveMaterial::default());
+
+	Ok(mesh)
+}
+
+/// Loads the default scene of a `.gltf` or `.glb`, or its first scene if it
+/// doesn't name one.
+pub fn load_gltf(path: &Path) -> Result<Mesh>
+{
+	let gltf = gltf::Gltf::open(path)?;
+	let buffers = gltf::import_buffers(&gltf.document, path.parent(), gltf.blob.clone())?;
+
+	let scene = gltf
+		.document
+		.default_scene()
+		.or_else(|| gltf.document.scenes().next())
+		.ok_or_else(|| anyhow!("{} has no scenes", path.display()))?;
+
+	let mut mesh = Mesh::default();
+	let mut unique_vertices = HashMap::new();
+
+	for node in scene.nodes()
+	{
+		load_gltf_node(path, &buffers, &node, &glm::identity(), &mut mesh, &mut unique_vertices)?;
+	}
+
+	Ok(mesh)
+}
+
+fn load_gltf_node(
+	path: &Path,
+	buffers: &[gltf::buffer::Data],
+	node: &gltf::Node,
+	parent: &glm::Mat4,
+	mesh: &mut Mesh,
+	unique_vertices: &mut HashMap<Vertex, usize>,
+	) -> Result<()>
+{
+	let transform = parent * glm::Mat4::from(node.transform().matrix());
+
+	if let Some(node_mesh) = node.mesh()
+	{
+		for primitive in node_mesh.primitives()
+		{
+			if primitive.mode() != gltf::mesh::Mode::Triangles
+			{
+				warn!("Skipping {:?} primitive in {}", primitive.mode(), path.display());
+				continue;
+			}
+
+			let material = gltf_material(path, buffers, &primitive.material(), &mut mesh.embedded_images);
+			let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+			let positions = reader
+				.read_positions()
+				.ok_or_else(|| anyhow!("Primitive without positions in {}", path.display()))?
+				.collect::<Vec<_>>();
+			let tex_coords = reader
+				.read_tex_coords(0)
+				.map(|uvs| uvs.into_f32().collect::<Vec<_>>())
+				.unwrap_or_default();
+			let lightmap_coords = reader
+				.read_tex_coords(1)
+				.map(|uvs| uvs.into_f32().collect::<Vec<_>>())
+				.unwrap_or_default();
+			let colors = reader
+				.read_colors(0)
+				.map(|colors| colors.into_rgb_f32().collect::<Vec<_>>())
+				.unwrap_or_default();
+			let indices = match reader.read_indices()
+			{
+				Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+				None => (0..positions.len() as u32).collect(),
+			};
+
+			let first_index = mesh.indices.len();
+			for index in indices
+			{
+				let index = index as usize;
+				let pos = transform * glm::vec4(positions[index][0], positions[index][1], positions[index][2], 1.0);
+
+				// glTF UVs already start at the top left
+				let tex_coord = tex_coords.get(index).map_or(glm::vec2(0.0, 0.0), |uv| glm::vec2(uv[0], uv[1]));
+				let lightmap_coord = lightmap_coords.get(index).map_or(tex_coord, |uv| glm::vec2(uv[0], uv[1]));
+				let color = colors.get(index).map_or(glm::vec3(1.0, 1.0, 1.0), |rgb| glm::vec3(rgb[0], rgb[1], rgb[2]));
+
+				let vertex = Vertex {
+					pos: pos.xyz(),
+					color: color.component_mul(&material.base_color.xyz()),
+					tex_coord,
+					lightmap_coord,
+				};
+
+				mesh.push(unique_vertices, vertex);
+			}
+			mesh.finish_primitive(first_index, material);
+		}
+	}
+
+	for child in node.children()
+	{
+		load_gltf_node(path, buffers, &child, &transform, mesh, unique_vertices)?;
+	}
+
+	Ok(())
+}
+
+fn gltf_material(
+	path: &Path,
+	buffers: &[gltf::buffer::Data],
+	material: &gltf::Material,
+	embedded_images: &mut HashMap<String, Vec<u8>>,
+	) -> PrimitiveMaterial
+{
+	let pbr = material.pbr_metallic_roughness();
+	let base_color = glm::Vec4::from(pbr.base_color_factor());
+
+	let base_color_texture = pbr.base_color_texture().and_then(|info|
+	{
+		let image = info.texture().source();
+		match image.source()
+		{
+			gltf::image::Source::View { view, .. } =>
+			{
+				let key = format!("{}#image{}", path.display(), image.index());
+				let start = view.offset();
+				let bytes = &buffers[view.buffer().index()][start..start + view.length()];
+				embedded_images.entry(key.clone()).or_insert_with(|| bytes.to_vec());
+				Some(key)
+			},
+			gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") =>
+			{
+				let directory = path.parent().unwrap_or_else(|| Path::new(""));
+				Some(directory.join(uri).to_string_lossy().into_owned())
+			},
+			gltf::image::Source::Uri { .. } =>
+			{
+				warn!("Base64 data URI textures aren't supported, ignoring one of {}'s", path.display());
+				None
+			},
+		}
+	});
+
+	PrimitiveMaterial { base_color, base_color_texture }
+}
@@ -0,0 +1,334 @@
+// GPU noise textures
+//
+// Tileable noise is generated by a compute shader at startup instead of
+// being shipped as assets. Each texture stores four octaves, one per
+// channel, with each channel doubling the frequency of the last.
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::collections::HashMap;
+
+use crate::allocator;
+use crate::assets::Texture;
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+use crate::{
+	begin_single_time_commands,
+	create_shader_module,
+	end_single_time_commands,
+	AppData,
+};
+
+const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+/// Must match the kinds in `noise.comp`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoiseKind
+{
+	Perlin = 0,
+	/// Doesn't tile exactly, the simplex lattice is skewed.
+	Simplex = 1,
+	Worley = 2,
+	/// Perlin-Worley base shape in red, Worley octaves in the rest, for clouds.
+	PerlinWorley = 3,
+}
+
+struct NoiseDescription
+{
+	name: &'static str,
+	kind: NoiseKind,
+	size: u32,
+	/// 1 for 2D textures.
+	depth: u32,
+	/// Lattice cells across the texture in the lowest octave.
+	period: i32,
+}
+
+const NOISE_TEXTURES: &[NoiseDescription] = &[
+	NoiseDescription { name: "perlin", kind: NoiseKind::Perlin, size: 256, depth: 1, period: 8 },
+	NoiseDescription { name: "simplex", kind: NoiseKind::Simplex, size: 256, depth: 1, period: 8 },
+	NoiseDescription { name: "worley", kind: NoiseKind::Worley, size: 256, depth: 1, period: 8 },
+	NoiseDescription { name: "clouds", kind: NoiseKind::PerlinWorley, size: 64, depth: 64, period: 4 },
+];
+
+/// Must match the push constants of `noise.comp`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct NoisePushConstants
+{
+	kind: i32,
+	period: i32,
+	seed: u32,
+}
+
+/// Generates every texture in `NOISE_TEXTURES` into `data.noise_textures`.
+pub unsafe fn create_noise_textures(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(0)
+		.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+	let bindings = &[binding];
+	let info = vk::DescriptorSetLayoutCreateInfo::builder()
+		.bindings(bindings);
+
+	let set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+	let push_constant_range = push_constant_range::<NoisePushConstants>(vk::ShaderStageFlags::COMPUTE);
+
+	let set_layouts = &[set_layout];
+	let push_constant_ranges = &[push_constant_range];
+	let info = vk::PipelineLayoutCreateInfo::builder()
+		.set_layouts(set_layouts)
+		.push_constant_ranges(push_constant_ranges);
+
+	let pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+	let pipeline_2d = create_noise_pipeline(device, data.pipeline_cache, pipeline_layout, include_bytes!(concat!(env!("OUT_DIR"), "/noise_comp.spv")))?;
+	let pipeline_3d = create_noise_pipeline(device, data.pipeline_cache, pipeline_layout, include_bytes!(concat!(env!("OUT_DIR"), "/noise_3d_comp.spv")))?;
+
+	let pool_size = vk::DescriptorPoolSize::builder()
+		.type_(vk::DescriptorType::STORAGE_IMAGE)
+		.descriptor_count(NOISE_TEXTURES.len() as u32);
+
+	let pool_sizes = &[pool_size];
+	let info = vk::DescriptorPoolCreateInfo::builder()
+		.pool_sizes(pool_sizes)
+		.max_sets(NOISE_TEXTURES.len() as u32);
+
+	let descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+	let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+
+	for (seed, description) in NOISE_TEXTURES.iter().enumerate()
+	{
+		let texture = create_noise_image(instance, device, data, description)?;
+
+		let layouts = &[set_layout];
+		let info = vk::DescriptorSetAllocateInfo::builder()
+			.descriptor_pool(descriptor_pool)
+			.set_layouts(layouts);
+
+		let descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+		let image_info = vk::DescriptorImageInfo::builder()
+			.image_layout(vk::ImageLayout::GENERAL)
+			.image_view(texture.view);
+
+		let image_infos = &[image_info];
+		let write = vk::WriteDescriptorSet::builder()
+			.dst_set(descriptor_set)
+			.dst_binding(0)
+			.dst_array_element(0)
+			.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+			.image_info(image_infos);
+
+		device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+		image_barrier(
+			device,
+			data,
+			command_buffer,
+			texture.image,
+			(vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL),
+			(vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::COMPUTE_SHADER),
+			(vk::AccessFlags::empty(), vk::AccessFlags::SHADER_WRITE),
+		);
+
+		let (pipeline, groups) = if description.depth > 1
+		{
+			(pipeline_3d, [description.size / 4, description.size / 4, description.depth / 4])
+		}
+		else
+		{
+			(pipeline_2d, [description.size / 8, description.size / 8, 1])
+		};
+
+		device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+		device.cmd_bind_descriptor_sets(
+			command_buffer,
+			vk::PipelineBindPoint::COMPUTE,
+			pipeline_layout,
+			0,
+			&[descriptor_set],
+			&[]);
+
+		let push_constants = NoisePushConstants {
+			kind: description.kind as i32,
+			period: description.period,
+			seed: seed as u32,
+		};
+
+		cmd_push_constants(device, command_buffer, pipeline_layout, vk::ShaderStageFlags::COMPUTE, &push_constants);
+
+		device.cmd_dispatch(command_buffer, groups[0].max(1), groups[1].max(1), groups[2].max(1));
+
+		image_barrier(
+			device,
+			data,
+			command_buffer,
+			texture.image,
+			(vk::ImageLayout::GENERAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+			(vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::FRAGMENT_SHADER),
+			(vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_READ),
+		);
+
+		info!("Generated {} noise ({}x{}x{})", description.name, description.size, description.size, description.depth);
+		data.noise_textures.insert(description.name, texture);
+	}
+
+	end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)?;
+
+	device.destroy_descriptor_pool(descriptor_pool, None);
+	device.destroy_pipeline(pipeline_2d, None);
+	device.destroy_pipeline(pipeline_3d, None);
+	device.destroy_pipeline_layout(pipeline_layout, None);
+	device.destroy_descriptor_set_layout(set_layout, None);
+
+	Ok(())
+}
+
+unsafe fn create_noise_pipeline(
+	device: &Device,
+	pipeline_cache: vk::PipelineCache,
+	layout: vk::PipelineLayout,
+	bytecode: &[u8],
+	) -> Result<vk::Pipeline>
+{
+	let comp_sm = create_shader_module(device, bytecode)?;
+
+	let stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::COMPUTE)
+		.module(comp_sm)
+		.name(b"main\0");
+
+	let info = vk::ComputePipelineCreateInfo::builder()
+		.stage(stage)
+		.layout(layout);
+
+	let pipeline = device.create_compute_pipelines(
+		pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(comp_sm, None);
+
+	Ok(pipeline)
+}
+
+/// Like `create_image`, but 3D when the description has depth.
+unsafe fn create_noise_image(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	description: &NoiseDescription,
+	) -> Result<Texture>
+{
+	let (image_type, view_type) = if description.depth > 1
+	{
+		(vk::ImageType::_3D, vk::ImageViewType::_3D)
+	}
+	else
+	{
+		(vk::ImageType::_2D, vk::ImageViewType::_2D)
+	};
+
+	let info = vk::ImageCreateInfo::builder()
+		.image_type(image_type)
+		.extent(vk::Extent3D { width: description.size, height: description.size, depth: description.depth })
+		.mip_levels(1)
+		.array_layers(1)
+		.samples(vk::SampleCountFlags::_1)
+		.format(FORMAT)
+		.tiling(vk::ImageTiling::OPTIMAL)
+		.initial_layout(vk::ImageLayout::UNDEFINED)
+		.usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+		.sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+	let image = device.create_image(&info, None)?;
+
+	let label = format!("noise {}x{}x{}", description.size, description.size, description.depth);
+	let memory = allocator::allocate_image(instance, device, data, image, vk::MemoryPropertyFlags::DEVICE_LOCAL, label)?;
+
+	let subresource_range = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let info = vk::ImageViewCreateInfo::builder()
+		.image(image)
+		.view_type(view_type)
+		.format(FORMAT)
+		.subresource_range(subresource_range);
+
+	let view = device.create_image_view(&info, None)?;
+
+	Ok(Texture {
+		image,
+		memory,
+		view,
+		format: FORMAT,
+		mip_levels: 1,
+		size: requirements.size,
+	})
+}
+
+unsafe fn image_barrier(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	(old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+	(src_stage, dst_stage): (vk::PipelineStageFlags, vk::PipelineStageFlags),
+	(src_access, dst_access): (vk::AccessFlags, vk::AccessFlags),
+	)
+{
+	data.layouts.transition(image, old_layout, new_layout);
+
+	let subresource_range = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(old_layout)
+		.new_layout(new_layout)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(subresource_range)
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
+
+pub unsafe fn destroy_noise_textures(device: &Device, data: &mut AppData)
+{
+	for (_, texture) in data.noise_textures.drain()
+	{
+		device.destroy_image_view(texture.view, None);
+		device.destroy_image(texture.image, None);
+		allocator::free(device, texture.memory);
+	}
+}
@@ -0,0 +1,232 @@
+// Command line options
+//
+// Everything is optional and falls back to `config.toml`, or what the
+// renderer would pick on its own, e.g.
+//
+// vulkan-tutorial --width 1920 --height 1080 --gpu 1 --msaa 4 --present-mode immediate
+// vulkan-tutorial --fullscreen --fullscreen-key F10
+// vulkan-tutorial --list-gpus
+// VULKAN_TUTORIAL_GPU=radeon vulkan-tutorial
+// vulkan-tutorial --capture scene,depth --capture-format png
+// vulkan-tutorial --demo media/demo.ron
+// vulkan-tutorial --example textured-quad
+// vulkan-tutorial --deterministic --demo media/demo.ron --capture final
+// vulkan-tutorial --stress resize
+// vulkan-tutorial --headless --frames 60 --output frame.png
+// vulkan-tutorial --stereo anaglyph
+// vulkan-tutorial --deterministic --demo media/demo.ron --record-ffmpeg demo.mp4
+//
+// `--help` lists them all.
+
+use clap::Parser;
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use vulkanalia::prelude::v1_0::*;
+use winit::event::VirtualKeyCode;
+
+use std::path::PathBuf;
+
+use crate::capture::{CaptureFormat, CaptureRequest, CaptureTarget};
+use crate::examples::Example;
+use crate::recording::RecordTarget;
+use crate::stereo::StereoMode;
+use crate::stress::StressMode;
+
+#[derive(Clone, Debug, Parser)]
+#[command(about = "Vulkan tutorial renderer")]
+pub struct Options
+{
+	/// Window width in logical pixels, or the image's in pixels with
+	/// --headless.
+	#[arg(long)]
+	pub width: Option<u32>,
+	/// Window height in logical pixels, or the image's in pixels with
+	/// --headless.
+	#[arg(long)]
+	pub height: Option<u32>,
+	/// Starts in borderless fullscreen.
+	#[arg(long)]
+	pub fullscreen: bool,
+	/// Key that cycles windowed, borderless and exclusive fullscreen, by
+	/// winit's name for it, e.g. `F11` or `Return`.
+	#[arg(long, value_parser = parse_key)]
+	pub fullscreen_key: Option<VirtualKeyCode>,
+	/// Device to render with, by its index in --list-gpus or part of its
+	/// name. The highest scoring suitable one when not given, which prefers
+	/// discrete GPUs.
+	#[arg(long, env = "VULKAN_TUTORIAL_GPU", value_parser = GpuSelector::parse)]
+	pub gpu: Option<GpuSelector>,
+	/// Prints the devices --gpu can pick from and exits.
+	#[arg(long)]
+	pub list_gpus: bool,
+	/// Leaves the validation layer out of debug builds, e.g. for profiling.
+	#[arg(long)]
+	pub no_validation: bool,
+	/// Tracks frames with a timeline semaphore instead of a fence each, on
+	/// devices with VK_KHR_timeline_semaphore.
+	#[arg(long)]
+	pub timeline_sync: bool,
+	/// Exits with a failure if validation reported any errors, or warnings
+	/// about misusing the API, by the time the window is closed.
+	#[arg(long)]
+	pub fail_on_validation: bool,
+	/// MSAA sample count, 1, 2, 4 or 8, clamped to what the device supports.
+	/// The device's most when not given.
+	#[arg(long = "msaa", value_parser = parse_msaa_samples)]
+	pub msaa_samples: Option<u32>,
+	/// `fifo`, `mailbox` or `immediate`, falling back to the default if the
+	/// surface doesn't support it. MAILBOX if there is one when not given.
+	#[arg(long, value_parser = parse_present_mode)]
+	pub present_mode: Option<vk::PresentModeKHR>,
+	/// Tutorial chapter to show instead of everything at once.
+	#[arg(long, value_parser = parse_example)]
+	pub example: Option<Example>,
+	/// Demo sequence to play from startup.
+	#[arg(long)]
+	pub demo: Option<PathBuf>,
+	/// Renders the same frames on every run, for golden image and replay
+	/// tests: a fixed timestep, no dynamic resolution, and a camera that
+	/// only moves along the paths it's told to play. The noise textures are
+	/// always generated from fixed seeds.
+	#[arg(long)]
+	pub deterministic: bool,
+	/// `resize` or `recreate`: puts the swapchain through hundreds of
+	/// recreations, then quits, failing like --fail-on-validation.
+	#[arg(long, value_parser = parse_stress_mode)]
+	pub stress: Option<StressMode>,
+	/// Renders without a window or surface and saves the last frame to
+	/// --output, e.g. in CI or on a server without a display.
+	#[arg(long)]
+	pub headless: bool,
+	/// How many frames --headless renders before saving the last.
+	#[arg(long, default_value_t = 1)]
+	pub frames: u32,
+	/// The PNG --headless saves.
+	#[arg(long, default_value = "headless.png")]
+	pub output: PathBuf,
+	/// `side-by-side` or `anaglyph`: renders the scene once per eye and
+	/// shows them together, H cycles through them at runtime.
+	#[arg(long, value_parser = parse_stereo_mode)]
+	pub stereo: Option<StereoMode>,
+	/// Saves presented frames as numbered PNGs in this directory.
+	#[arg(long, conflicts_with = "record_ffmpeg")]
+	pub record: Option<PathBuf>,
+	/// Pipes presented frames into ffmpeg, which encodes them to this file.
+	#[arg(long)]
+	pub record_ffmpeg: Option<PathBuf>,
+	/// Only records every Nth presented frame.
+	#[arg(long, default_value_t = 1)]
+	pub record_every: u32,
+	/// Frame rate of the --record-ffmpeg video.
+	#[arg(long, default_value_t = 60)]
+	pub record_fps: u32,
+	/// Targets to save after the first frame, comma separated.
+	#[arg(long = "capture", value_delimiter = ',', value_parser = parse_capture_target)]
+	pub capture_targets: Vec<CaptureTarget>,
+	/// `exr` or `png`.
+	#[arg(long, value_parser = parse_capture_format, default_value = "exr")]
+	pub capture_format: CaptureFormat,
+}
+
+impl Options
+{
+	pub fn captures(&self) -> CaptureRequest
+	{
+		CaptureRequest { targets: self.capture_targets.clone(), format: self.capture_format }
+	}
+
+	pub fn record_target(&self) -> Option<RecordTarget>
+	{
+		match (&self.record, &self.record_ffmpeg)
+		{
+			(Some(directory), _) => Some(RecordTarget::Images(directory.clone())),
+			(_, Some(output)) => Some(RecordTarget::Ffmpeg { output: output.clone(), fps: self.record_fps }),
+			_ => None,
+		}
+	}
+}
+
+/// Which device `--gpu` asked for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuSelector
+{
+	Index(usize),
+	/// Matched case-insensitively against part of the device name.
+	Name(String),
+}
+
+impl GpuSelector
+{
+	fn parse(value: &str) -> Result<Self, String>
+	{
+		Ok(match value.parse()
+		{
+			Ok(index) => GpuSelector::Index(index),
+			Err(_) => GpuSelector::Name(value.to_lowercase()),
+		})
+	}
+
+	pub fn matches(&self, index: usize, name: &str) -> bool
+	{
+		match self
+		{
+			GpuSelector::Index(i) => *i == index,
+			GpuSelector::Name(part) => name.to_lowercase().contains(part.as_str()),
+		}
+	}
+}
+
+fn parse_msaa_samples(value: &str) -> Result<u32, String>
+{
+	value
+		.parse::<u32>()
+		.ok()
+		.filter(|s| s.is_power_of_two() && *s <= 64)
+		.ok_or_else(|| format!("Invalid MSAA sample count {}", value))
+}
+
+fn parse_present_mode(value: &str) -> Result<vk::PresentModeKHR, String>
+{
+	match value
+	{
+		"fifo" => Ok(vk::PresentModeKHR::FIFO),
+		"mailbox" => Ok(vk::PresentModeKHR::MAILBOX),
+		"immediate" => Ok(vk::PresentModeKHR::IMMEDIATE),
+		_ => Err(format!("Unknown present mode {}, expected fifo, mailbox or immediate", value)),
+	}
+}
+
+fn parse_key(value: &str) -> Result<VirtualKeyCode, String>
+{
+	VirtualKeyCode::deserialize(value.into_deserializer())
+		.map_err(|e: serde::de::value::Error| format!("Unknown key {}: {}", value, e))
+}
+
+fn parse_example(value: &str) -> Result<Example, String>
+{
+	Example::from_name(value).ok_or_else(||
+		{
+			let names = Example::ALL.iter().map(|e| e.name()).collect::<Vec<_>>();
+			format!("Unknown example {}, expected one of {}", value, names.join(", "))
+		})
+}
+
+fn parse_stress_mode(value: &str) -> Result<StressMode, String>
+{
+	StressMode::from_name(value).ok_or_else(|| format!("Unknown stress mode {}, expected resize or recreate", value))
+}
+
+fn parse_stereo_mode(value: &str) -> Result<StereoMode, String>
+{
+	StereoMode::from_name(value).ok_or_else(|| format!("Unknown stereo mode {}, expected side-by-side or anaglyph", value))
+}
+
+fn parse_capture_target(value: &str) -> Result<CaptureTarget, String>
+{
+	CaptureTarget::from_name(value).ok_or_else(|| format!("Unknown capture target {}", value))
+}
+
+fn parse_capture_format(value: &str) -> Result<CaptureFormat, String>
+{
+	CaptureFormat::from_name(value).ok_or_else(|| format!("Unknown capture format {}", value))
+}
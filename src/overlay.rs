@@ -0,0 +1,895 @@
+// Debug overlay
+//
+// An egui window over the finished frame for changing settings while the
+// app runs: the camera, the scene's lights and their shadows, MSAA and the
+// present mode. Grave, the key under Escape, shows and hides it. While it's
+// shown it gets the window's input first and keeps what it uses, a click on
+// its window or typing into a field, from the camera controls and key
+// bindings.
+//
+// egui lays the UI out on the CPU and hands back triangle meshes, which are
+// drawn at the end of the composite pass straight into the swapchain image.
+// Every frame in flight has vertex and index buffers of its own, written
+// once the frame's fence has been waited on, so a new UI every frame never
+// waits on the GPU. egui's textures, the font atlas in practice, change so
+// rarely that updating them just waits for the frames in flight. There's
+// no overlay headless, without a window to take input from.
+
+use anyhow::{anyhow, Result};
+use egui::epaint::{ClippedPrimitive, ImageDelta, Primitive, Vertex};
+use egui::{ImageData, TextureId, TexturesDelta};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+use winit::event::WindowEvent;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::Window;
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::os::raw::c_void;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::ptr::NonNull;
+
+use crate::allocator::{self, Allocation};
+use crate::lights::{Light, ShadowSettings};
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+use crate::samplers::{common_sampler, CommonSampler};
+use crate::shadow_atlas::{MIN_SHADOW_TILE_SIZE, SHADOW_ATLAS_SIZE};
+use crate::{
+	begin_single_time_commands,
+	create_buffer,
+	create_image,
+	create_image_view,
+	create_shader_module,
+	create_staging_buffer,
+	destroy_staging_buffer,
+	end_single_time_commands,
+	map_persistently,
+	mapped_at,
+	AppData,
+	MAX_FRAMES_IN_FLIGHT,
+};
+
+/// egui's texture colors are sRGB, uploaded as they are.
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Must match the push constants of `egui.vert`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct OverlayPushConstants
+{
+	screen_size: [f32; 2],
+}
+
+/// What the overlay shows and can change. The app fills it in before the
+/// UI runs and applies whatever is different afterwards.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings
+{
+	pub eye: glm::Vec3,
+	pub target: glm::Vec3,
+	pub lights: Vec<Light>,
+	pub msaa_samples: u32,
+	/// The sample counts the device supports.
+	pub msaa_choices: Vec<u32>,
+	pub present_mode: vk::PresentModeKHR,
+	/// The present modes the surface supports.
+	pub present_modes: Vec<vk::PresentModeKHR>,
+}
+
+struct OverlayTexture
+{
+	image: vk::Image,
+	memory: Allocation,
+	view: vk::ImageView,
+	descriptor_set: vk::DescriptorSet,
+}
+
+impl OverlayTexture
+{
+	unsafe fn destroy(&self, device: &Device)
+	{
+		device.destroy_image_view(self.view, None);
+		device.destroy_image(self.image, None);
+		allocator::free(device, self.memory);
+	}
+}
+
+/// A mapped host visible buffer, replaced by a bigger one whenever a
+/// frame's UI doesn't fit.
+#[derive(Copy, Clone, Debug, Default)]
+struct HostBuffer
+{
+	buffer: vk::Buffer,
+	memory: Allocation,
+	mapped: Option<NonNull<c_void>>,
+	size: vk::DeviceSize,
+}
+
+impl HostBuffer
+{
+	/// Makes room for `size` bytes. The frame that last used the buffer
+	/// must have finished.
+	unsafe fn reserve(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &AppData,
+		size: vk::DeviceSize,
+		usage: vk::BufferUsageFlags,
+		) -> Result<()>
+	{
+		if size <= self.size
+		{
+			return Ok(());
+		}
+
+		self.destroy(device);
+		let size = size.next_power_of_two();
+		let (buffer, memory) = create_buffer(
+			instance,
+			device,
+			data,
+			size,
+			usage,
+			vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+		)?;
+		*self = Self { buffer, memory, mapped: map_persistently(device, memory)?, size };
+
+		Ok(())
+	}
+
+	unsafe fn destroy(&self, device: &Device)
+	{
+		if self.size > 0
+		{
+			device.destroy_buffer(self.buffer, None);
+			allocator::free(device, self.memory);
+		}
+	}
+}
+
+/// One mesh of the UI, clipped to its part of the screen.
+#[derive(Copy, Clone, Debug)]
+struct OverlayDraw
+{
+	scissor: vk::Rect2D,
+	texture: TextureId,
+	first_index: u32,
+	index_count: u32,
+	vertex_offset: i32,
+}
+
+pub struct Overlay
+{
+	visible: bool,
+	context: egui::Context,
+	state: egui_winit::State,
+	descriptor_set_layout: vk::DescriptorSetLayout,
+	pipeline_layout: vk::PipelineLayout,
+	pipeline: vk::Pipeline,
+	textures: HashMap<TextureId, OverlayTexture>,
+	// the sets of replaced textures, descriptor sets go back to the pool
+	// only all at once
+	spare_sets: Vec<vk::DescriptorSet>,
+	// freed by egui after the last frame, which may still be drawing them
+	pending_free: Vec<TextureId>,
+	vertex_buffers: [HostBuffer; MAX_FRAMES_IN_FLIGHT],
+	index_buffers: [HostBuffer; MAX_FRAMES_IN_FLIGHT],
+	// what the frame being recorded draws, and from which buffers
+	frame: usize,
+	draws: Vec<OverlayDraw>,
+	pixels_per_point: f32,
+}
+
+impl Overlay
+{
+	/// Creates the overlay, hidden, for `window`. Its pipeline is made along
+	/// with the others and remade with the swapchain.
+	pub unsafe fn create<T>(
+		event_loop: &EventLoopWindowTarget<T>,
+		window: &Window,
+		device: &Device,
+		data: &mut AppData,
+		) -> Result<Self>
+	{
+		let mut state = egui_winit::State::new(event_loop);
+		state.set_pixels_per_point(window.scale_factor() as f32);
+
+		let binding = vk::DescriptorSetLayoutBinding::builder()
+			.binding(0)
+			.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+			.descriptor_count(1)
+			.stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+		let bindings = &[binding];
+		let info = vk::DescriptorSetLayoutCreateInfo::builder()
+			.bindings(bindings);
+
+		let descriptor_set_layout = data.descriptors.layout(device, &info)?;
+
+		let push_constant_range = push_constant_range::<OverlayPushConstants>(vk::ShaderStageFlags::VERTEX);
+
+		let set_layouts = &[descriptor_set_layout];
+		let push_constant_ranges = &[push_constant_range];
+		let info = vk::PipelineLayoutCreateInfo::builder()
+			.set_layouts(set_layouts)
+			.push_constant_ranges(push_constant_ranges);
+
+		let pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+		let mut overlay = Self {
+			visible: false,
+			context: egui::Context::default(),
+			state,
+			descriptor_set_layout,
+			pipeline_layout,
+			pipeline: vk::Pipeline::null(),
+			textures: HashMap::new(),
+			spare_sets: Vec::new(),
+			pending_free: Vec::new(),
+			vertex_buffers: Default::default(),
+			index_buffers: Default::default(),
+			frame: 0,
+			draws: Vec::new(),
+			pixels_per_point: window.scale_factor() as f32,
+		};
+		overlay.create_pipeline(device, data)?;
+
+		Ok(overlay)
+	}
+
+	/// Shows or hides the overlay.
+	pub fn toggle(&mut self, window: &Window)
+	{
+		self.visible = !self.visible;
+		if self.visible
+		{
+			// events aren't passed on while it's hidden
+			self.state.set_pixels_per_point(window.scale_factor() as f32);
+		}
+		else
+		{
+			self.draws.clear();
+		}
+	}
+
+	/// Passes a window event to egui while the overlay is shown. Returns
+	/// whether egui used it, so nothing else should.
+	pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool
+	{
+		self.visible && self.state.on_event(&self.context, event).consumed
+	}
+
+	/// Draws made for the frame being recorded.
+	pub fn draw_count(&self) -> u32
+	{
+		self.draws.len() as u32
+	}
+
+	/// Runs the UI over `settings` and gets its meshes ready for the frame,
+	/// whose fence must have been waited on.
+	pub unsafe fn update(
+		&mut self,
+		window: &Window,
+		instance: &Instance,
+		device: &Device,
+		data: &mut AppData,
+		frame: usize,
+		settings: &mut Settings,
+		) -> Result<()>
+	{
+		self.frame = frame;
+		self.draws.clear();
+		if !self.visible
+		{
+			return Ok(());
+		}
+
+		let input = self.state.take_egui_input(window);
+		let output = self.context.run(input, |context| settings_window(context, settings));
+		self.state.handle_platform_output(window, &self.context, output.platform_output);
+
+		self.update_textures(instance, device, data, output.textures_delta)?;
+
+		self.pixels_per_point = self.context.pixels_per_point();
+		let primitives = self.context.tessellate(output.shapes);
+		self.upload_meshes(instance, device, data, &primitives)
+	}
+
+	/// Records the frame's UI into the composite pass, over everything else.
+	pub unsafe fn record(&self, device: &Device, command_buffer: vk::CommandBuffer, extent: vk::Extent2D)
+	{
+		if self.draws.is_empty()
+		{
+			return;
+		}
+
+		device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+
+		let viewport = vk::Viewport::builder()
+			.x(0.0)
+			.y(0.0)
+			.width(extent.width as f32)
+			.height(extent.height as f32)
+			.min_depth(0.0)
+			.max_depth(1.0);
+
+		device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+		device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffers[self.frame].buffer], &[0]);
+		device.cmd_bind_index_buffer(command_buffer, self.index_buffers[self.frame].buffer, 0, vk::IndexType::UINT32);
+
+		// the vertices are in points, the viewport in pixels
+		let push_constants = OverlayPushConstants {
+			screen_size: [
+				extent.width as f32 / self.pixels_per_point,
+				extent.height as f32 / self.pixels_per_point,
+			],
+		};
+
+		cmd_push_constants(
+			device,
+			command_buffer,
+			self.pipeline_layout,
+			vk::ShaderStageFlags::VERTEX,
+			&push_constants,
+		);
+
+		let mut bound = None;
+		for draw in &self.draws
+		{
+			let Some(texture) = self.textures.get(&draw.texture) else
+			{
+				continue;
+			};
+
+			if bound != Some(draw.texture)
+			{
+				device.cmd_bind_descriptor_sets(
+					command_buffer,
+					vk::PipelineBindPoint::GRAPHICS,
+					self.pipeline_layout,
+					0,
+					&[texture.descriptor_set],
+					&[]);
+				bound = Some(draw.texture);
+			}
+
+			device.cmd_set_scissor(command_buffer, 0, &[draw.scissor]);
+			device.cmd_draw_indexed(command_buffer, draw.index_count, 1, draw.first_index, draw.vertex_offset, 0);
+		}
+	}
+
+	/// Creates the pipeline for the composite pass, with dynamic rendering
+	/// or its render pass. The viewport and scissor are set when drawing.
+	pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()>
+	{
+		let vert = include_bytes!(concat!(env!("OUT_DIR"), "/egui_vert.spv"));
+		let frag = include_bytes!(concat!(env!("OUT_DIR"), "/egui_frag.spv"));
+
+		let vert_sm = create_shader_module(device, vert)?;
+		let frag_sm = create_shader_module(device, frag)?;
+
+		let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+			.stage(vk::ShaderStageFlags::VERTEX)
+			.module(vert_sm)
+			.name(b"main\0");
+
+		let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+			.stage(vk::ShaderStageFlags::FRAGMENT)
+			.module(frag_sm)
+			.name(b"main\0");
+
+		// egui's Vertex, a position and texture coordinate in f32s and a color
+		// in bytes
+		let binding_description = vk::VertexInputBindingDescription::builder()
+			.binding(0)
+			.stride(size_of::<Vertex>() as u32)
+			.input_rate(vk::VertexInputRate::VERTEX);
+
+		let attribute = |location: u32, format: vk::Format, offset: u32|
+			vk::VertexInputAttributeDescription::builder()
+				.binding(0)
+				.location(location)
+				.format(format)
+				.offset(offset)
+				.build();
+
+		let binding_descriptions = &[binding_description];
+		let attribute_descriptions = &[
+			attribute(0, vk::Format::R32G32_SFLOAT, 0),
+			attribute(1, vk::Format::R32G32_SFLOAT, 8),
+			attribute(2, vk::Format::R8G8B8A8_UNORM, 16),
+		];
+		let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+			.vertex_binding_descriptions(binding_descriptions)
+			.vertex_attribute_descriptions(attribute_descriptions);
+
+		let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+			.topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+			.primitive_restart_enable(false);
+
+		// placeholders for the count, both are dynamic
+		let viewport = vk::Viewport::builder()
+			.width(data.swapchain_extent.width as f32)
+			.height(data.swapchain_extent.height as f32)
+			.max_depth(1.0);
+
+		let scissor = vk::Rect2D::builder()
+			.offset(vk::Offset2D { x: 0, y: 0 })
+			.extent(data.swapchain_extent);
+
+		let viewports = &[viewport];
+		let scissors = &[scissor];
+		let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+			.viewports(viewports)
+			.scissors(scissors);
+
+		let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+		let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+			.dynamic_states(dynamic_states);
+
+		let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+			.depth_clamp_enable(false)
+			.rasterizer_discard_enable(false)
+			.polygon_mode(vk::PolygonMode::FILL)
+			.line_width(1.0)
+			.cull_mode(vk::CullModeFlags::NONE)
+			.front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+			.depth_bias_enable(false);
+
+		let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+			.sample_shading_enable(false)
+			.rasterization_samples(vk::SampleCountFlags::_1);
+
+		// egui's colors are premultiplied
+		let attachment = vk::PipelineColorBlendAttachmentState::builder()
+			.color_write_mask(vk::ColorComponentFlags::all())
+			.blend_enable(true)
+			.src_color_blend_factor(vk::BlendFactor::ONE)
+			.dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+			.color_blend_op(vk::BlendOp::ADD)
+			.src_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_DST_ALPHA)
+			.dst_alpha_blend_factor(vk::BlendFactor::ONE)
+			.alpha_blend_op(vk::BlendOp::ADD);
+		let attachments = &[attachment];
+		let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+			.logic_op_enable(false)
+			.attachments(attachments);
+
+		let color_formats = &[data.swapchain_format];
+		let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+			.color_attachment_formats(color_formats);
+
+		let stages = &[vert_stage, frag_stage];
+		let mut info = vk::GraphicsPipelineCreateInfo::builder()
+			.stages(stages)
+			.vertex_input_state(&vertex_input_state)
+			.input_assembly_state(&input_assembly_state)
+			.viewport_state(&viewport_state)
+			.rasterization_state(&rasterization_state)
+			.multisample_state(&multisample_state)
+			.color_blend_state(&color_blend_state)
+			.dynamic_state(&dynamic_state)
+			.layout(self.pipeline_layout)
+			.render_pass(data.composite_render_pass)
+			.subpass(0);
+
+		if data.dynamic_rendering
+		{
+			info = info.push_next(&mut rendering_info);
+		}
+
+		self.pipeline = device.create_graphics_pipelines(
+			data.pipeline_cache,
+			&[info],
+			None
+			)?.0[0];
+
+		device.destroy_shader_module(vert_sm, None);
+		device.destroy_shader_module(frag_sm, None);
+
+		Ok(())
+	}
+
+	pub unsafe fn destroy_pipeline(&self, device: &Device)
+	{
+		device.destroy_pipeline(self.pipeline, None);
+	}
+
+	/// Destroys everything but the pipeline, which goes with the swapchain.
+	/// Every frame must have finished.
+	pub unsafe fn destroy(&mut self, device: &Device)
+	{
+		self.textures
+			.drain()
+			.for_each(|(_, texture)| texture.destroy(device));
+		self.vertex_buffers
+			.iter()
+			.chain(&self.index_buffers)
+			.for_each(|buffer| buffer.destroy(device));
+		device.destroy_pipeline_layout(self.pipeline_layout, None);
+	}
+
+	/// Applies egui's texture changes. Frees wait a frame, the last one may
+	/// still be drawing with them.
+	unsafe fn update_textures(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &mut AppData,
+		delta: TexturesDelta,
+		) -> Result<()>
+	{
+		let free = std::mem::replace(&mut self.pending_free, delta.free);
+		if delta.set.is_empty() && free.is_empty()
+		{
+			return Ok(());
+		}
+
+		// rare enough, mostly the font atlas, not to keep copies per frame
+		data.frame_sync.wait_for_all(device)?;
+
+		for id in free
+		{
+			self.free_texture(device, id);
+		}
+
+		for (id, image_delta) in &delta.set
+		{
+			self.set_texture(instance, device, data, *id, image_delta)?;
+		}
+
+		Ok(())
+	}
+
+	unsafe fn free_texture(&mut self, device: &Device, id: TextureId)
+	{
+		if let Some(texture) = self.textures.remove(&id)
+		{
+			texture.destroy(device);
+			self.spare_sets.push(texture.descriptor_set);
+		}
+	}
+
+	/// Uploads a whole texture, replacing any it had, or a part of one.
+	unsafe fn set_texture(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &mut AppData,
+		id: TextureId,
+		delta: &ImageDelta,
+		) -> Result<()>
+	{
+		let [width, height] = delta.image.size();
+		let pixels = match &delta.image
+		{
+			ImageData::Color(image) => image.pixels.iter().flat_map(|c| c.to_array()).collect::<Vec<_>>(),
+			ImageData::Font(image) => image.srgba_pixels(None).flat_map(|c| c.to_array()).collect::<Vec<_>>(),
+		};
+
+		let (offset, old_layout) = match delta.pos
+		{
+			Some(pos) => (pos, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+			None =>
+			{
+				self.free_texture(device, id);
+				let texture = self.create_texture(instance, device, data, width as u32, height as u32)?;
+				self.textures.insert(id, texture);
+				([0, 0], vk::ImageLayout::UNDEFINED)
+			},
+		};
+
+		let texture = self.textures
+			.get(&id)
+			.ok_or_else(|| anyhow!("egui updated part of texture {:?} before creating it", id))?;
+
+		let staging = create_staging_buffer(instance, device, data, &pixels)?;
+		let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+
+		data.layouts.transition(texture.image, old_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+		image_barrier(
+			device,
+			command_buffer,
+			texture.image,
+			(old_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+			(vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::empty()),
+			(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+		);
+
+		let subresource = vk::ImageSubresourceLayers::builder()
+			.aspect_mask(vk::ImageAspectFlags::COLOR)
+			.mip_level(0)
+			.base_array_layer(0)
+			.layer_count(1);
+
+		let region = vk::BufferImageCopy::builder()
+			.buffer_offset(0)
+			.buffer_row_length(0)
+			.buffer_image_height(0)
+			.image_subresource(subresource)
+			.image_offset(vk::Offset3D { x: offset[0] as i32, y: offset[1] as i32, z: 0 })
+			.image_extent(vk::Extent3D { width: width as u32, height: height as u32, depth: 1 });
+
+		device.cmd_copy_buffer_to_image(
+			command_buffer,
+			staging.0,
+			texture.image,
+			vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+			&[region],
+		);
+
+		data.layouts.transition(texture.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+		image_barrier(
+			device,
+			command_buffer,
+			texture.image,
+			(vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+			(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+			(vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+		);
+
+		end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)?;
+		destroy_staging_buffer(device, data, staging);
+
+		Ok(())
+	}
+
+	/// An empty texture with a descriptor set, a spare one if there is.
+	unsafe fn create_texture(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &mut AppData,
+		width: u32,
+		height: u32,
+		) -> Result<OverlayTexture>
+	{
+		let (image, memory) = create_image(
+			instance,
+			device,
+			data,
+			width,
+			height,
+			1,
+			vk::SampleCountFlags::_1,
+			TEXTURE_FORMAT,
+			vk::ImageTiling::OPTIMAL,
+			vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+			vk::MemoryPropertyFlags::DEVICE_LOCAL,
+		)?;
+
+		let view = create_image_view(device, image, TEXTURE_FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+
+		let descriptor_set = match self.spare_sets.pop()
+		{
+			Some(set) => set,
+			None => data.descriptors.persistent.allocate(device, &[self.descriptor_set_layout])?[0],
+		};
+
+		let image_info = vk::DescriptorImageInfo::builder()
+			.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+			.image_view(view)
+			.sampler(common_sampler(data, CommonSampler::LinearClamp));
+
+		let image_infos = &[image_info];
+		let write = vk::WriteDescriptorSet::builder()
+			.dst_set(descriptor_set)
+			.dst_binding(0)
+			.dst_array_element(0)
+			.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+			.image_info(image_infos);
+
+		device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+		Ok(OverlayTexture { image, memory, view, descriptor_set })
+	}
+
+	/// Writes the meshes into the frame's buffers, one after another, and
+	/// lists their draws.
+	unsafe fn upload_meshes(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &AppData,
+		primitives: &[ClippedPrimitive],
+		) -> Result<()>
+	{
+		// paint callbacks are for custom rendering, which the overlay doesn't do
+		let meshes = primitives
+			.iter()
+			.filter_map(|p| match &p.primitive
+			{
+				Primitive::Mesh(mesh) => Some((p.clip_rect, mesh)),
+				Primitive::Callback(_) => None,
+			})
+			.collect::<Vec<_>>();
+
+		let vertex_count = meshes.iter().map(|(_, mesh)| mesh.vertices.len()).sum::<usize>();
+		let index_count = meshes.iter().map(|(_, mesh)| mesh.indices.len()).sum::<usize>();
+		if index_count == 0
+		{
+			return Ok(());
+		}
+
+		let vertex_buffer = &mut self.vertex_buffers[self.frame];
+		let size = (vertex_count * size_of::<Vertex>()) as u64;
+		vertex_buffer.reserve(instance, device, data, size, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+		let vertices = mapped_at(vertex_buffer.mapped, 0)?.cast::<Vertex>();
+
+		let index_buffer = &mut self.index_buffers[self.frame];
+		let size = (index_count * size_of::<u32>()) as u64;
+		index_buffer.reserve(instance, device, data, size, vk::BufferUsageFlags::INDEX_BUFFER)?;
+		let indices = mapped_at(index_buffer.mapped, 0)?.cast::<u32>();
+
+		let (mut vertex_offset, mut first_index) = (0, 0);
+		for (clip_rect, mesh) in meshes
+		{
+			memcpy(mesh.vertices.as_ptr(), vertices.add(vertex_offset), mesh.vertices.len());
+			memcpy(mesh.indices.as_ptr(), indices.add(first_index), mesh.indices.len());
+
+			if let Some(scissor) = scissor(clip_rect, self.pixels_per_point, data.swapchain_extent)
+			{
+				self.draws.push(OverlayDraw {
+					scissor,
+					texture: mesh.texture_id,
+					first_index: first_index as u32,
+					index_count: mesh.indices.len() as u32,
+					vertex_offset: vertex_offset as i32,
+				});
+			}
+
+			vertex_offset += mesh.vertices.len();
+			first_index += mesh.indices.len();
+		}
+
+		Ok(())
+	}
+}
+
+/// The settings window.
+fn settings_window(context: &egui::Context, settings: &mut Settings)
+{
+	egui::Window::new("Settings").show(context, |ui|
+	{
+		ui.collapsing("Camera", |ui|
+		{
+			drag_row(ui, "Eye", settings.eye.iter_mut(), 0.05);
+			drag_row(ui, "Target", settings.target.iter_mut(), 0.05);
+		});
+
+		ui.collapsing("Lights", |ui|
+		{
+			if settings.lights.is_empty()
+			{
+				ui.label("The scene has no lights");
+			}
+
+			for (i, light) in settings.lights.iter_mut().enumerate()
+			{
+				ui.label(format!("Light {}", i));
+				let (x, y, z) = &mut light.position;
+				drag_row(ui, "Position", [x, y, z], 0.05);
+				// HDR, so not a color picker's 0 to 1
+				let (r, g, b) = &mut light.color;
+				drag_row(ui, "Color", [r, g, b], 0.01);
+
+				let mut casts_shadows = light.shadows.is_some();
+				ui.checkbox(&mut casts_shadows, "Shadows");
+				if casts_shadows != light.shadows.is_some()
+				{
+					light.shadows = casts_shadows.then(ShadowSettings::default);
+				}
+
+				if let Some(shadows) = &mut light.shadows
+				{
+					shadow_settings(ui, shadows);
+				}
+			}
+		});
+
+		ui.separator();
+
+		egui::ComboBox::from_label("MSAA")
+			.selected_text(format!("{}x", settings.msaa_samples))
+			.show_ui(ui, |ui|
+			{
+				for &samples in &settings.msaa_choices
+				{
+					ui.selectable_value(&mut settings.msaa_samples, samples, format!("{}x", samples));
+				}
+			});
+
+		egui::ComboBox::from_label("Present mode")
+			.selected_text(format!("{:?}", settings.present_mode))
+			.show_ui(ui, |ui|
+			{
+				for &mode in &settings.present_modes
+				{
+					ui.selectable_value(&mut settings.present_mode, mode, format!("{:?}", mode));
+				}
+			});
+	});
+}
+
+/// A label and a field to drag for each value.
+/// A light's shadow settings, in the ranges the atlas and shader take.
+fn shadow_settings(ui: &mut egui::Ui, shadows: &mut ShadowSettings)
+{
+	ui.horizontal(|ui|
+	{
+		ui.label("Resolution");
+		ui.add(egui::DragValue::new(&mut shadows.resolution).speed(16.0).clamp_range(MIN_SHADOW_TILE_SIZE..=SHADOW_ATLAS_SIZE));
+		ui.label("PCF kernel");
+		ui.add(egui::DragValue::new(&mut shadows.pcf_kernel).speed(0.05).clamp_range(1..=7));
+	});
+	drag_row(ui, "Depth bias", [&mut shadows.depth_bias], 0.01);
+	drag_row(ui, "Slope bias", [&mut shadows.slope_bias], 0.01);
+	drag_row(ui, "Normal offset", [&mut shadows.normal_offset], 0.01);
+}
+
+fn drag_row<'a>(ui: &mut egui::Ui, label: &str, values: impl IntoIterator<Item = &'a mut f32>, speed: f64)
+{
+	ui.horizontal(|ui|
+	{
+		ui.label(label);
+		for value in values
+		{
+			ui.add(egui::DragValue::new(value).speed(speed));
+		}
+	});
+}
+
+/// A clip rectangle in points as a scissor in pixels, if anything of it is
+/// on screen.
+fn scissor(clip_rect: egui::Rect, pixels_per_point: f32, extent: vk::Extent2D) -> Option<vk::Rect2D>
+{
+	let (width, height) = (extent.width as f32, extent.height as f32);
+	let min_x = (clip_rect.min.x * pixels_per_point).round().clamp(0.0, width);
+	let min_y = (clip_rect.min.y * pixels_per_point).round().clamp(0.0, height);
+	let max_x = (clip_rect.max.x * pixels_per_point).round().clamp(min_x, width);
+	let max_y = (clip_rect.max.y * pixels_per_point).round().clamp(min_y, height);
+
+	(max_x > min_x && max_y > min_y).then(|| vk::Rect2D {
+		offset: vk::Offset2D { x: min_x as i32, y: min_y as i32 },
+		extent: vk::Extent2D { width: (max_x - min_x) as u32, height: (max_y - min_y) as u32 },
+	})
+}
+
+unsafe fn image_barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	(old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+	(src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	(dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	)
+{
+	let subresource_range = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(old_layout)
+		.new_layout(new_layout)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(subresource_range)
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
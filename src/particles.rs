@@ -0,0 +1,416 @@
+// Compute particles
+//
+// A compute shader moves a few thousand particles through a storage buffer
+// every frame, respawning each at the emitter once its life runs out, and
+// the same buffer is then bound as the vertex buffer of a point list drawn
+// in the scene pass. Nothing goes through the CPU after the buffer is
+// zeroed, which has every particle respawn on the first dispatch.
+//
+// The dispatch is recorded into the frame's command buffer before the scene
+// pass, on the compute family, which is the graphics one. Two buffer
+// barriers order it: one against the previous frame's draw still reading
+// the vertices, one making the new positions visible to vertex input.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use nalgebra_glm as glm;
+
+use std::mem::size_of;
+
+use crate::allocator;
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+use crate::stats::FrameStats;
+use crate::{
+	begin_single_time_commands,
+	create_buffer,
+	create_shader_module,
+	end_single_time_commands,
+	uniform_offset,
+	AppData,
+};
+
+const PARTICLE_COUNT: u32 = 16384;
+const WORKGROUP_SIZE: u32 = 256;
+/// Longer frames are simulated as this long, so a hitch doesn't fling
+/// everything across the scene.
+const MAX_TIMESTEP: f32 = 0.1;
+
+/// Must match `Particle` in `particles.comp`, `position.w` is the life left
+/// in seconds.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Particle
+{
+	position: glm::Vec4,
+	velocity: glm::Vec4,
+}
+
+impl Particle
+{
+	fn binding_description() -> vk::VertexInputBindingDescription
+	{
+		vk::VertexInputBindingDescription::builder()
+			.binding(0)
+			.stride(size_of::<Particle>() as u32)
+			.input_rate(vk::VertexInputRate::VERTEX)
+			.build()
+	}
+
+	fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2]
+	{
+		let position = vk::VertexInputAttributeDescription::builder()
+			.binding(0)
+			.location(0)
+			.format(vk::Format::R32G32B32A32_SFLOAT)
+			.offset(0)
+			.build();
+
+		let velocity = vk::VertexInputAttributeDescription::builder()
+			.binding(0)
+			.location(1)
+			.format(vk::Format::R32G32B32A32_SFLOAT)
+			.offset(size_of::<glm::Vec4>() as u32)
+			.build();
+
+		[position, velocity]
+	}
+}
+
+/// Must match the push constants of `particles.comp`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ParticlePushConstants
+{
+	time: f32,
+	dt: f32,
+	count: u32,
+}
+
+/// The particle buffer and the compute pipeline moving it. These don't
+/// depend on the swapchain.
+pub unsafe fn create_particle_objects(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let size = (size_of::<Particle>() * PARTICLE_COUNT as usize) as u64;
+	let (buffer, buffer_memory) = create_buffer(
+		instance,
+		device,
+		data,
+		size,
+		vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL,
+	)?;
+
+	data.particle_buffer = buffer;
+	data.particle_buffer_memory = buffer_memory;
+
+	clear_particle_buffer(device, data, size)?;
+
+	let binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(0)
+		.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+	let bindings = &[binding];
+	let info = vk::DescriptorSetLayoutCreateInfo::builder()
+		.bindings(bindings);
+
+	data.particle_descriptor_set_layout = data.descriptors.layout(device, &info)?;
+
+	let push_constant_range = push_constant_range::<ParticlePushConstants>(vk::ShaderStageFlags::COMPUTE);
+
+	let set_layouts = &[data.particle_descriptor_set_layout];
+	let push_constant_ranges = &[push_constant_range];
+	let info = vk::PipelineLayoutCreateInfo::builder()
+		.set_layouts(set_layouts)
+		.push_constant_ranges(push_constant_ranges);
+
+	data.particle_compute_pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+	let comp = include_bytes!(concat!(env!("OUT_DIR"), "/particles_comp.spv"));
+	let comp_sm = create_shader_module(device, comp)?;
+
+	let stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::COMPUTE)
+		.module(comp_sm)
+		.name(b"main\0");
+
+	let info = vk::ComputePipelineCreateInfo::builder()
+		.stage(stage)
+		.layout(data.particle_compute_pipeline_layout);
+
+	data.particle_compute_pipeline = device.create_compute_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(comp_sm, None);
+
+	data.particle_descriptor_set = data.descriptors.persistent.allocate(device, &[data.particle_descriptor_set_layout])?[0];
+
+	let buffer_info = vk::DescriptorBufferInfo::builder()
+		.buffer(data.particle_buffer)
+		.offset(0)
+		.range(vk::WHOLE_SIZE);
+
+	let buffer_infos = &[buffer_info];
+	let write = vk::WriteDescriptorSet::builder()
+		.dst_set(data.particle_descriptor_set)
+		.dst_binding(0)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+		.buffer_info(buffer_infos);
+
+	device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+	Ok(())
+}
+
+/// Zeroes every particle, so they all start out dead.
+unsafe fn clear_particle_buffer(device: &Device, data: &AppData, size: u64) -> Result<()>
+{
+	let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+
+	device.cmd_fill_buffer(command_buffer, data.particle_buffer, 0, size, 0);
+
+	buffer_barrier(
+		device,
+		command_buffer,
+		data.particle_buffer,
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+		(vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE),
+	);
+
+	end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)?;
+
+	Ok(())
+}
+
+/// Draws the particle buffer as points in the scene pass, additively so the
+/// order they're drawn in doesn't matter.
+pub unsafe fn create_particle_pipeline(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let vert = include_bytes!(concat!(env!("OUT_DIR"), "/particle_vert.spv"));
+	let frag = include_bytes!(concat!(env!("OUT_DIR"), "/particle_frag.spv"));
+
+	let vert_sm = create_shader_module(device, vert)?;
+	let frag_sm = create_shader_module(device, frag)?;
+
+	let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::VERTEX)
+		.module(vert_sm)
+		.name(b"main\0");
+
+	let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::FRAGMENT)
+		.module(frag_sm)
+		.name(b"main\0");
+
+	let binding_descriptions = &[Particle::binding_description()];
+	let attribute_descriptions = Particle::attribute_descriptions();
+	let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+		.vertex_binding_descriptions(binding_descriptions)
+		.vertex_attribute_descriptions(&attribute_descriptions);
+
+	let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+		.topology(vk::PrimitiveTopology::POINT_LIST)
+		.primitive_restart_enable(false);
+
+	let viewport = vk::Viewport::builder()
+		.x(0.0)
+		.y(0.0)
+		.width(data.render_extent.width as f32)
+		.height(data.render_extent.height as f32)
+		.min_depth(0.0)
+		.max_depth(1.0);
+
+	let scissor = vk::Rect2D::builder()
+		.offset(vk::Offset2D { x: 0, y: 0 })
+		.extent(data.render_extent);
+
+	let viewports = &[viewport];
+	let scissors = &[scissor];
+	let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+		.viewports(viewports)
+		.scissors(scissors);
+
+	let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+		.depth_clamp_enable(false)
+		.rasterizer_discard_enable(false)
+		.polygon_mode(vk::PolygonMode::FILL)
+		.line_width(1.0)
+		.cull_mode(vk::CullModeFlags::NONE)
+		.front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+		.depth_bias_enable(false);
+
+	let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+		.sample_shading_enable(false)
+		.rasterization_samples(data.msaa_samples);
+
+	let attachment = vk::PipelineColorBlendAttachmentState::builder()
+		.color_write_mask(vk::ColorComponentFlags::all())
+		.blend_enable(true)
+		.src_color_blend_factor(vk::BlendFactor::ONE)
+		.dst_color_blend_factor(vk::BlendFactor::ONE)
+		.color_blend_op(vk::BlendOp::ADD)
+		.src_alpha_blend_factor(vk::BlendFactor::ZERO)
+		.dst_alpha_blend_factor(vk::BlendFactor::ONE)
+		.alpha_blend_op(vk::BlendOp::ADD);
+	let attachments = &[attachment];
+	let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+		.logic_op_enable(false)
+		.attachments(attachments);
+
+	// hidden by the scene but not by each other
+	let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+		.depth_test_enable(true)
+		.depth_write_enable(false)
+		.depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+		.depth_bounds_test_enable(false)
+		.stencil_test_enable(false);
+
+	let stages = &[vert_stage, frag_stage];
+
+	// shares the main pipeline layout so the frame's descriptor set can be reused
+	let info = vk::GraphicsPipelineCreateInfo::builder()
+		.stages(stages)
+		.vertex_input_state(&vertex_input_state)
+		.input_assembly_state(&input_assembly_state)
+		.viewport_state(&viewport_state)
+		.rasterization_state(&rasterization_state)
+		.multisample_state(&multisample_state)
+		.depth_stencil_state(&depth_stencil_state)
+		.color_blend_state(&color_blend_state)
+		.layout(data.pipeline_layout)
+		.render_pass(data.render_pass)
+		.subpass(0);
+
+	data.particle_pipeline = device.create_graphics_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(vert_sm, None);
+	device.destroy_shader_module(frag_sm, None);
+
+	Ok(())
+}
+
+unsafe fn buffer_barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	buffer: vk::Buffer,
+	(src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	(dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	)
+{
+	let barrier = vk::BufferMemoryBarrier::builder()
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.buffer(buffer)
+		.offset(0)
+		.size(vk::WHOLE_SIZE)
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[barrier],
+		&[] as &[vk::ImageMemoryBarrier],
+	);
+}
+
+/// Records one simulation step. Must be outside of a render pass.
+pub unsafe fn record_simulation(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	time: f32,
+	dt: f32,
+	stats: &mut FrameStats,
+	)
+{
+	// the previous frame's draw may still be reading the vertices
+	buffer_barrier(
+		device,
+		command_buffer,
+		data.particle_buffer,
+		(vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::empty()),
+		(vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE),
+	);
+	stats.barriers += 1;
+
+	device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.particle_compute_pipeline);
+	stats.pipeline_binds += 1;
+
+	device.cmd_bind_descriptor_sets(
+		command_buffer,
+		vk::PipelineBindPoint::COMPUTE,
+		data.particle_compute_pipeline_layout,
+		0,
+		&[data.particle_descriptor_set],
+		&[]);
+	stats.descriptor_binds += 1;
+
+	cmd_push_constants(
+		device,
+		command_buffer,
+		data.particle_compute_pipeline_layout,
+		vk::ShaderStageFlags::COMPUTE,
+		&ParticlePushConstants { time, dt: dt.min(MAX_TIMESTEP), count: PARTICLE_COUNT },
+	);
+
+	device.cmd_dispatch(command_buffer, (PARTICLE_COUNT + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE, 1, 1);
+
+	buffer_barrier(
+		device,
+		command_buffer,
+		data.particle_buffer,
+		(vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE),
+		(vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ),
+	);
+	stats.barriers += 1;
+}
+
+/// Records drawing the particles. Must be inside the scene pass.
+pub unsafe fn record_particles(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	image_index: usize,
+	)
+{
+	device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.particle_pipeline);
+	device.cmd_bind_vertex_buffers(command_buffer, 0, &[data.particle_buffer], &[0]);
+	device.cmd_bind_descriptor_sets(
+		command_buffer,
+		vk::PipelineBindPoint::GRAPHICS,
+		data.pipeline_layout,
+		0,
+		&[data.descriptor_set],
+		&[uniform_offset(data, image_index)]);
+
+	device.cmd_draw(command_buffer, PARTICLE_COUNT, 1, 0, 0);
+}
+
+pub unsafe fn destroy_particle_objects(device: &Device, data: &AppData)
+{
+	device.destroy_pipeline(data.particle_compute_pipeline, None);
+	device.destroy_pipeline_layout(data.particle_compute_pipeline_layout, None);
+	device.destroy_buffer(data.particle_buffer, None);
+	allocator::free(device, data.particle_buffer_memory);
+}
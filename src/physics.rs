@@ -0,0 +1,193 @@
+// Rigid body physics (enabled with the `physics` feature)
+//
+// Each model instance gets a dynamic rapier3d body with a box collider
+// around the mesh, dropped onto a fixed ground box. The simulation runs at
+// a fixed timestep and the models are drawn at their body transforms.
+
+use rapier3d::prelude::*;
+
+use nalgebra_glm as glm;
+
+use std::fmt;
+
+use crate::debug_draw::{DebugCategory, DebugDraw};
+use crate::Vertex;
+
+const TIMESTEP: f32 = 1.0 / 60.0;
+/// Frames longer than this are simulated as if they weren't, so a stall
+/// doesn't cause a burst of catch-up steps.
+const MAX_FRAME_TIME: f32 = 0.25;
+const GROUND_HEIGHT: f32 = -2.0;
+
+pub struct PhysicsWorld
+{
+	gravity: Vector<Real>,
+	integration_parameters: IntegrationParameters,
+	pipeline: PhysicsPipeline,
+	islands: IslandManager,
+	broad_phase: BroadPhase,
+	narrow_phase: NarrowPhase,
+	bodies: RigidBodySet,
+	colliders: ColliderSet,
+	impulse_joints: ImpulseJointSet,
+	multibody_joints: MultibodyJointSet,
+	ccd_solver: CCDSolver,
+	model_bodies: Vec<RigidBodyHandle>,
+	accumulator: f32,
+}
+
+impl PhysicsWorld
+{
+	/// Creates a body for each model, starting at the given transforms.
+	pub fn new(vertices: &[Vertex], model_positions: &[glm::Vec3]) -> Self
+	{
+		let mut bodies = RigidBodySet::new();
+		let mut colliders = ColliderSet::new();
+
+		let ground = ColliderBuilder::cuboid(20.0, 20.0, 0.5)
+			.translation(vector![0.0, 0.0, GROUND_HEIGHT - 0.5])
+			.build();
+		colliders.insert(ground);
+
+		let (min, max) = vertices.iter().fold(
+			(glm::vec3(f32::MAX, f32::MAX, f32::MAX), glm::vec3(f32::MIN, f32::MIN, f32::MIN)),
+			|(min, max), v| (glm::min2(&min, &v.pos), glm::max2(&max, &v.pos)),
+		);
+		let half_extents = (max - min) * 0.5;
+		let center = (max + min) * 0.5;
+
+		let model_bodies = model_positions
+			.iter()
+			.map(|position|
+				{
+					let body = RigidBodyBuilder::dynamic()
+						.translation(*position)
+						.build();
+					let handle = bodies.insert(body);
+
+					let collider = ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+						.translation(center)
+						.restitution(0.3)
+						.build();
+					colliders.insert_with_parent(collider, handle, &mut bodies);
+
+					handle
+				})
+			.collect();
+
+		Self {
+			gravity: vector![0.0, 0.0, -9.81],
+			integration_parameters: IntegrationParameters::default(),
+			pipeline: PhysicsPipeline::new(),
+			islands: IslandManager::new(),
+			broad_phase: BroadPhase::new(),
+			narrow_phase: NarrowPhase::new(),
+			bodies,
+			colliders,
+			impulse_joints: ImpulseJointSet::new(),
+			multibody_joints: MultibodyJointSet::new(),
+			ccd_solver: CCDSolver::new(),
+			model_bodies,
+			accumulator: 0.0,
+		}
+	}
+
+	/// Advances the simulation by however many fixed steps fit in `dt`.
+	pub fn step(&mut self, dt: f32)
+	{
+		self.integration_parameters.dt = TIMESTEP;
+		self.accumulator += dt.min(MAX_FRAME_TIME);
+
+		while self.accumulator >= TIMESTEP
+		{
+			self.accumulator -= TIMESTEP;
+			self.pipeline.step(
+				&self.gravity,
+				&self.integration_parameters,
+				&mut self.islands,
+				&mut self.broad_phase,
+				&mut self.narrow_phase,
+				&mut self.bodies,
+				&mut self.colliders,
+				&mut self.impulse_joints,
+				&mut self.multibody_joints,
+				&mut self.ccd_solver,
+				None,
+				&(),
+				&(),
+			);
+		}
+	}
+
+	/// Model matrix of a model's body, if it has one.
+	pub fn model_matrix(&self, model_index: usize) -> Option<glm::Mat4>
+	{
+		self.model_bodies
+			.get(model_index)
+			.map(|handle| self.bodies[*handle].position().to_homogeneous())
+	}
+
+	/// Throws every model upwards with a bit of spin.
+	pub fn launch(&mut self)
+	{
+		for (i, handle) in self.model_bodies.iter().enumerate()
+		{
+			let body = &mut self.bodies[*handle];
+			let spin = if i % 2 == 0 { 1.0 } else { -1.0 };
+			body.apply_impulse(vector![0.0, 0.0, 6.0] * body.mass(), true);
+			body.apply_torque_impulse(vector![spin, 0.5, 0.0] * body.mass(), true);
+		}
+	}
+
+	/// Draws every box collider.
+	pub fn debug_draw(&self, debug_draw: &mut DebugDraw)
+	{
+		for (_, collider) in self.colliders.iter()
+		{
+			if let Some(cuboid) = collider.shape().as_cuboid()
+			{
+				let color = if collider.parent().is_some() { glm::vec3(0.0, 1.0, 0.5) } else { glm::vec3(0.5, 0.5, 0.5) };
+				debug_draw.cuboid(
+					DebugCategory::Colliders,
+					&collider.position().to_homogeneous(),
+					cuboid.half_extents,
+					color,
+				);
+			}
+		}
+	}
+}
+
+// the pipeline only holds scratch buffers so a clone can start with a fresh one
+impl Clone for PhysicsWorld
+{
+	fn clone(&self) -> Self
+	{
+		Self {
+			gravity: self.gravity,
+			integration_parameters: self.integration_parameters,
+			pipeline: PhysicsPipeline::new(),
+			islands: self.islands.clone(),
+			broad_phase: self.broad_phase.clone(),
+			narrow_phase: self.narrow_phase.clone(),
+			bodies: self.bodies.clone(),
+			colliders: self.colliders.clone(),
+			impulse_joints: self.impulse_joints.clone(),
+			multibody_joints: self.multibody_joints.clone(),
+			ccd_solver: self.ccd_solver.clone(),
+			model_bodies: self.model_bodies.clone(),
+			accumulator: self.accumulator,
+		}
+	}
+}
+
+impl fmt::Debug for PhysicsWorld
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		f.debug_struct("PhysicsWorld")
+			.field("bodies", &self.bodies.len())
+			.field("colliders", &self.colliders.len())
+			.finish()
+	}
+}
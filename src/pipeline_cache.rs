@@ -0,0 +1,120 @@
+// Pipeline cache
+//
+// Every pipeline is created through one `vk::PipelineCache`, which is saved
+// to the user's cache directory on shutdown and loaded again on the next
+// startup, so the driver can skip compiling shaders it has already seen.
+//
+// The data is only valid for the device and driver that wrote it. Its header
+// is checked against the selected device before it's used, and a cache from
+// anywhere else is dropped and started over rather than handed to the driver.
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::AppData;
+
+const CACHE_FILE: &str = "pipeline_cache.bin";
+// header length, version, vendor ID and device ID, then the cache UUID
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/// Creates the cache, starting from what was saved last time if it was
+/// written by this device and driver.
+pub unsafe fn create_pipeline_cache(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()>
+{
+	let properties = instance.get_physical_device_properties(data.physical_device);
+	let path = cache_path();
+	let initial_data = match fs::read(&path)
+	{
+		Ok(bytes) if is_compatible(&bytes, &properties) =>
+		{
+			info!("Loaded pipeline cache from {} ({} bytes)", path.display(), bytes.len());
+			bytes
+		},
+		Ok(_) =>
+		{
+			info!("Pipeline cache at {} is from another device or driver, starting over", path.display());
+			vec![]
+		},
+		Err(_) => vec![],
+	};
+
+	let info = vk::PipelineCacheCreateInfo::builder()
+		.initial_data(&initial_data);
+
+	data.pipeline_cache = device.create_pipeline_cache(&info, None)?;
+
+	Ok(())
+}
+
+/// Writes the cache out and destroys it. Failing to save only costs the
+/// next startup some time, so it's logged rather than returned.
+pub unsafe fn destroy_pipeline_cache(device: &Device, data: &AppData)
+{
+	if let Err(e) = save(device, data.pipeline_cache)
+	{
+		warn!("Failed to save pipeline cache: {}", e);
+	}
+
+	device.destroy_pipeline_cache(data.pipeline_cache, None);
+}
+
+unsafe fn save(device: &Device, cache: vk::PipelineCache) -> Result<()>
+{
+	let bytes = device.get_pipeline_cache_data(cache)?;
+	let path = cache_path();
+	if let Some(parent) = path.parent()
+	{
+		fs::create_dir_all(parent)?;
+	}
+
+	// written aside and moved into place, so a crash mid-write can't leave
+	// a truncated cache behind
+	let temporary = path.with_extension("tmp");
+	fs::write(&temporary, &bytes)?;
+	fs::rename(&temporary, &path)?;
+
+	info!("Saved pipeline cache to {} ({} bytes)", path.display(), bytes.len());
+	Ok(())
+}
+
+fn is_compatible(bytes: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool
+{
+	if bytes.len() < HEADER_SIZE
+	{
+		return false;
+	}
+
+	let word = |i: usize| u32::from_ne_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+	word(0) as usize >= HEADER_SIZE
+		&& word(1) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+		&& word(2) == properties.vendor_id
+		&& word(3) == properties.device_id
+		&& bytes[16..HEADER_SIZE] == properties.pipeline_cache_uuid[..]
+}
+
+/// The platform's per-user cache directory, or the temporary directory if
+/// there isn't one.
+fn cache_path() -> PathBuf
+{
+	let directory = if cfg!(target_os = "windows")
+	{
+		env::var_os("LOCALAPPDATA").map(PathBuf::from)
+	}
+	else if cfg!(target_os = "macos")
+	{
+		env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Caches"))
+	}
+	else
+	{
+		env::var_os("XDG_CACHE_HOME")
+			.map(PathBuf::from)
+			.or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+	};
+
+	directory.unwrap_or_else(env::temp_dir).join("vulkan-tutorial").join(CACHE_FILE)
+}
@@ -0,0 +1,280 @@
+// Planar reflections
+//
+// A cheaper alternative to screen space reflections for flat mirrors and
+// water: the scene can have one mirror plane, and materials with `mirror`
+// above 0 show what the plane reflects, e.g.
+//
+// mirror: Some((point: (0.0, 0.0, -1.0), normal: (0.0, 0.0, 1.0))),
+//
+// Before the scene pass the scene is rendered once more, with the same
+// secondary command buffers, from the camera mirrored through the plane, and
+// the result copied aside like the stereo left eye. The mirrored projection
+// has its near plane moved onto the mirror (Lengyel's oblique near-plane
+// clipping) so nothing behind the mirror shows up in it, and its x flipped so
+// the mirrored winding comes out as usual. A surface on the plane is at the
+// same spot on screen in both views, so mirror materials sample the copy at
+// their own screen position with u flipped back. Only surfaces lying on the
+// plane reflect correctly, and in stereo the reflection is rendered from the
+// camera between the eyes.
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator::{self, Allocation};
+use crate::camera::Camera;
+use crate::composite::SCENE_FORMAT;
+use crate::stats::FrameStats;
+use crate::{create_image, create_image_view, transition_image_layout, AppData};
+
+/// The scene's mirror, a point on it and the direction it faces. It
+/// reflects from both sides.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MirrorPlane
+{
+	pub point: (f32, f32, f32),
+	pub normal: (f32, f32, f32),
+}
+
+impl MirrorPlane
+{
+	/// The plane as (normal, distance), facing `eye`.
+	fn facing(&self, eye: &glm::Vec3) -> glm::Vec4
+	{
+		let normal = glm::normalize(&glm::vec3(self.normal.0, self.normal.1, self.normal.2));
+		let point = glm::vec3(self.point.0, self.point.1, self.point.2);
+		let plane = glm::vec4(normal.x, normal.y, normal.z, -glm::dot(&normal, &point));
+
+		if glm::dot(&plane, &glm::vec4(eye.x, eye.y, eye.z, 1.0)) < 0.0 { -plane } else { plane }
+	}
+}
+
+/// Reflects points through `plane`.
+fn reflection_matrix(plane: &glm::Vec4) -> glm::Mat4
+{
+	let (x, y, z, d) = (plane.x, plane.y, plane.z, plane.w);
+	glm::mat4(
+		1.0 - 2.0 * x * x, -2.0 * x * y, -2.0 * x * z, -2.0 * x * d,
+		-2.0 * y * x, 1.0 - 2.0 * y * y, -2.0 * y * z, -2.0 * y * d,
+		-2.0 * z * x, -2.0 * z * y, 1.0 - 2.0 * z * z, -2.0 * z * d,
+		0.0, 0.0, 0.0, 1.0,
+	)
+}
+
+/// Replaces the near plane of `proj` with `plane`, given in view space,
+/// keeping the far plane where it is as well as it can. For depth from 0 to
+/// 1, where the near plane is the third row and the far one the fourth minus
+/// the third.
+pub fn oblique_projection(mut proj: glm::Mat4, plane: &glm::Vec4) -> glm::Mat4
+{
+	// the frustum corner opposite the plane, from the plane in clip space
+	let clip_plane = glm::transpose(&glm::inverse(&proj)) * plane;
+	let corner = glm::vec4(clip_plane.x.signum(), clip_plane.y.signum(), 1.0, 1.0);
+	let q = glm::inverse(&proj) * corner;
+
+	let row = plane * (1.0 / glm::dot(plane, &q));
+	proj.set_row(2, &row.transpose());
+	proj
+}
+
+/// The view, projection and position of `camera` mirrored through `plane`,
+/// for rendering the reflection.
+pub fn mirrored_matrices(camera: &Camera, aspect: f32, plane: &MirrorPlane) -> (glm::Mat4, glm::Mat4, glm::Vec3)
+{
+	let plane = plane.facing(&camera.eye);
+	let view = camera.view() * reflection_matrix(&plane);
+
+	// whatever is on the camera's side of the mirror, seen through it
+	let view_plane = glm::transpose(&glm::inverse(&view)) * plane;
+	let mut proj = oblique_projection(camera.proj(aspect), &view_plane);
+
+	// mirroring flips the winding, flipping x flips it back
+	let row = -proj.row(0).clone_owned();
+	proj.set_row(0, &row);
+
+	let position = reflection_matrix(&plane) * glm::vec4(camera.eye.x, camera.eye.y, camera.eye.z, 1.0);
+	(view, proj, position.xyz())
+}
+
+/// Copies the finished scene color into `target`, e.g. the mirror image,
+/// before the next pass renders over it. `target` is sampleable before and
+/// after.
+pub unsafe fn record_copy_scene(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	target: vk::Image,
+	stats: &mut FrameStats,
+	)
+{
+	data.layouts.expect(target, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, "scene copy");
+
+	// left in SHADER_READ_ONLY by the render pass
+	image_barrier(
+		device,
+		command_buffer,
+		data.scene_image,
+		(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+		(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+	);
+
+	// an earlier pass may still be sampling it
+	image_barrier(
+		device,
+		command_buffer,
+		target,
+		(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+		(vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::empty()),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+	);
+
+	let layers = vk::ImageSubresourceLayers::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.mip_level(0)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let region = vk::ImageCopy::builder()
+		.src_subresource(layers)
+		.src_offset(vk::Offset3D::default())
+		.dst_subresource(layers)
+		.dst_offset(vk::Offset3D::default())
+		.extent(vk::Extent3D { width: data.render_extent.width, height: data.render_extent.height, depth: 1 });
+
+	device.cmd_copy_image(
+		command_buffer,
+		data.scene_image,
+		vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+		target,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		&[region],
+	);
+
+	image_barrier(
+		device,
+		command_buffer,
+		target,
+		(vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+		(vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+	);
+
+	// back where the scene pass expects it
+	image_barrier(
+		device,
+		command_buffer,
+		data.scene_image,
+		(vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::empty()),
+		(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+	);
+	stats.barriers += 4;
+}
+
+/// Creates the mirror image. It's bound in every descriptor set like the
+/// refraction image, so it exists whether or not the scene has a mirror.
+pub unsafe fn create_planar_reflection_objects(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let (image, memory, view) = create_scene_copy(instance, device, data)?;
+	data.mirror_image = image;
+	data.mirror_image_memory = memory;
+	data.mirror_image_view = view;
+
+	Ok(())
+}
+
+/// Creates an image for `record_copy_scene` to copy into, sized like the
+/// scene targets and sampleable from the start.
+pub unsafe fn create_scene_copy(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	) -> Result<(vk::Image, Allocation, vk::ImageView)>
+{
+	let (image, memory) = create_image(
+		instance,
+		device,
+		data,
+		data.render_extent.width,
+		data.render_extent.height,
+		1,
+		vk::SampleCountFlags::_1,
+		SCENE_FORMAT,
+		vk::ImageTiling::OPTIMAL,
+		vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL,
+	)?;
+
+	let view = create_image_view(device, image, SCENE_FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+
+	transition_image_layout(
+		device,
+		data,
+		image,
+		SCENE_FORMAT,
+		vk::ImageLayout::UNDEFINED,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		1,
+	)?;
+	transition_image_layout(
+		device,
+		data,
+		image,
+		SCENE_FORMAT,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+		1,
+	)?;
+
+	Ok((image, memory, view))
+}
+
+pub unsafe fn destroy_planar_reflection_objects(device: &Device, data: &AppData)
+{
+	device.destroy_image_view(data.mirror_image_view, None);
+	device.destroy_image(data.mirror_image, None);
+	allocator::free(device, data.mirror_image_memory);
+}
+
+unsafe fn image_barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	(old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+	(src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	(dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	)
+{
+	let subresource_range = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(old_layout)
+		.new_layout(new_layout)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(subresource_range)
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
@@ -0,0 +1,418 @@
+// Portals
+//
+// A pair of linked rectangles, each showing what's in front of the other,
+// e.g. in the scene file
+//
+// portals: Some((
+//     a: (center: (0.0, -3.0, 0.0), normal: (0.0, 1.0, 0.0), up: (0.0, 0.0, 1.0), size: (1.5, 2.0)),
+//     b: (center: (-3.0, 0.0, 0.0), normal: (1.0, 0.0, 0.0), up: (0.0, 0.0, 1.0), size: (1.5, 2.0)),
+//     recursion: 2,
+// )),
+//
+// Like the mirror, the view through each portal is rendered before the scene
+// pass with the scene pass's own secondaries, from the camera carried over
+// to the other portal, with its near plane moved onto that portal so nothing
+// behind it shows up. The result is copied into the portal's image. In the
+// scene pass each portal's quad marks the stencil wherever it's visible,
+// without drawing any color, and a fullscreen triangle copies the portal's
+// image over just the marked pixels.
+//
+// Portals seen through portals show the view from the level below, so each
+// portal's views are rendered from the deepest level up, `recursion` of them.
+// The deepest shows whatever the previous frame left in the image. Portals
+// are one-sided, from behind they're invisible and their views are skipped.
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator;
+use crate::camera::Camera;
+use crate::planar_reflection::{create_scene_copy, oblique_projection};
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+use crate::samplers::{common_sampler, CommonSampler};
+use crate::stats::FrameStats;
+use crate::stencil::{StencilMode, PORTAL_REFERENCES};
+use crate::{create_shader_module, uniform_offset, AppData};
+
+/// How many portals deep the views go at most, each level is another pass
+/// per portal.
+pub const MAX_RECURSION: u32 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Portal
+{
+	pub center: (f32, f32, f32),
+	/// Out of the side that shows the other portal's view.
+	pub normal: (f32, f32, f32),
+	pub up: (f32, f32, f32),
+	/// Width and height.
+	pub size: (f32, f32),
+}
+
+impl Portal
+{
+	/// From the portal's space, x right, y up and z out of its front, to
+	/// the world.
+	fn transform(&self) -> glm::Mat4
+	{
+		let normal = glm::normalize(&glm::vec3(self.normal.0, self.normal.1, self.normal.2));
+		let right = glm::normalize(&glm::cross(&glm::vec3(self.up.0, self.up.1, self.up.2), &normal));
+		let up = glm::cross(&normal, &right);
+		let (x, y, z) = self.center;
+
+		glm::mat4(
+			right.x, up.x, normal.x, x,
+			right.y, up.y, normal.y, y,
+			right.z, up.z, normal.z, z,
+			0.0, 0.0, 0.0, 1.0,
+		)
+	}
+
+	/// Places the unit quad of `portal.vert`.
+	fn model(&self) -> glm::Mat4
+	{
+		glm::scale(&self.transform(), &glm::vec3(self.size.0, self.size.1, 1.0))
+	}
+
+	/// The portal's plane as (normal, distance), positive in front.
+	fn plane(&self) -> glm::Vec4
+	{
+		let normal = glm::normalize(&glm::vec3(self.normal.0, self.normal.1, self.normal.2));
+		let center = glm::vec3(self.center.0, self.center.1, self.center.2);
+		glm::vec4(normal.x, normal.y, normal.z, -glm::dot(&normal, &center))
+	}
+
+	pub fn faces(&self, eye: &glm::Vec3) -> bool
+	{
+		glm::dot(&self.plane(), &glm::vec4(eye.x, eye.y, eye.z, 1.0)) > 0.0
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PortalPair
+{
+	pub a: Portal,
+	pub b: Portal,
+	/// How many portals deep the views go, up to `MAX_RECURSION`.
+	#[serde(default = "default_recursion")]
+	pub recursion: u32,
+}
+
+fn default_recursion() -> u32
+{
+	2
+}
+
+impl PortalPair
+{
+	/// The portal at `index` and the one it looks out of.
+	pub fn ends(&self, index: usize) -> (&Portal, &Portal)
+	{
+		if index == 0 { (&self.a, &self.b) } else { (&self.b, &self.a) }
+	}
+
+	pub fn levels(&self) -> u32
+	{
+		self.recursion.clamp(1, MAX_RECURSION)
+	}
+}
+
+/// The view, projection and position the portal at `index` is seen
+/// through with, `level` portals deep.
+pub fn portal_matrices(
+	camera: &Camera,
+	aspect: f32,
+	pair: &PortalPair,
+	index: usize,
+	level: u32,
+	) -> (glm::Mat4, glm::Mat4, glm::Vec3)
+{
+	let (entry, exit) = pair.ends(index);
+
+	// half a turn about up, so looking into the front of one is looking out
+	// of the front of the other
+	let turn = glm::scaling(&glm::vec3(-1.0, 1.0, -1.0));
+	let step = entry.transform() * turn * glm::inverse(&exit.transform());
+	let carried = (0..level).fold(glm::Mat4::identity(), |m, _| m * step);
+
+	let view = camera.view() * carried;
+
+	// only what's in front of the portal it comes out of
+	let view_plane = glm::transpose(&glm::inverse(&view)) * exit.plane();
+	let proj = oblique_projection(camera.proj(aspect), &view_plane);
+
+	let position = glm::inverse(&carried) * glm::vec4(camera.eye.x, camera.eye.y, camera.eye.z, 1.0);
+	(view, proj, position.xyz())
+}
+
+/// The portal images' descriptor set layout and the pipeline layout, which
+/// don't depend on the swapchain.
+pub unsafe fn create_portal_layouts(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let samplers = &[common_sampler(data, CommonSampler::LinearClamp)];
+	let binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+		.immutable_samplers(samplers);
+
+	let bindings = &[binding];
+	let info = vk::DescriptorSetLayoutCreateInfo::builder()
+		.bindings(bindings);
+
+	data.portal_descriptor_set_layout = data.descriptors.layout(device, &info)?;
+
+	// the frame's descriptor set provides the camera, the second one the portal's view
+	let push_constant_range = push_constant_range::<glm::Mat4>(vk::ShaderStageFlags::VERTEX);
+
+	let set_layouts = &[data.descriptor_set_layout, data.portal_descriptor_set_layout];
+	let push_constant_ranges = &[push_constant_range];
+	let info = vk::PipelineLayoutCreateInfo::builder()
+		.set_layouts(set_layouts)
+		.push_constant_ranges(push_constant_ranges);
+
+	data.portal_pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+	Ok(())
+}
+
+/// Both portals' images and descriptor sets. They're made whether or not
+/// the scene has portals, since scenes can be swapped at any time.
+pub unsafe fn create_portal_objects(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let layouts = [data.portal_descriptor_set_layout; 2];
+	let sets = data.descriptors.swapchain.allocate(device, &layouts)?;
+
+	for index in 0..2
+	{
+		let (image, memory, view) = create_scene_copy(instance, device, data)?;
+		data.portal_images[index] = image;
+		data.portal_images_memory[index] = memory;
+		data.portal_image_views[index] = view;
+		data.portal_descriptor_sets[index] = sets[index];
+
+		let info = vk::DescriptorImageInfo::builder()
+			.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+			.image_view(view);
+
+		let image_info = &[info];
+		let write = vk::WriteDescriptorSet::builder()
+			.dst_set(sets[index])
+			.dst_binding(0)
+			.dst_array_element(0)
+			.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+			.image_info(image_info);
+
+		device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+	}
+
+	Ok(())
+}
+
+/// A quad marking its portal's stencil and a fullscreen copy of its view
+/// inside the marks, per portal.
+pub unsafe fn create_portal_pipelines(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	for (index, &reference) in PORTAL_REFERENCES.iter().enumerate()
+	{
+		data.portal_mark_pipelines[index] = create_portal_pipeline(device, data, StencilMode::Mark(reference))?;
+		data.portal_view_pipelines[index] = create_portal_pipeline(device, data, StencilMode::Inside(reference))?;
+	}
+
+	Ok(())
+}
+
+/// The quad when `stencil` marks, the fullscreen copy when it tests.
+unsafe fn create_portal_pipeline(
+	device: &Device,
+	data: &AppData,
+	stencil: StencilMode,
+	) -> Result<vk::Pipeline>
+{
+	let marking = matches!(stencil, StencilMode::Mark(_));
+
+	let vert: &[u8] = if marking
+	{
+		include_bytes!(concat!(env!("OUT_DIR"), "/portal_vert.spv"))
+	}
+	else
+	{
+		include_bytes!(concat!(env!("OUT_DIR"), "/composite_vert.spv"))
+	};
+	let frag = include_bytes!(concat!(env!("OUT_DIR"), "/portal_frag.spv"));
+
+	let vert_sm = create_shader_module(device, vert)?;
+	let frag_sm = create_shader_module(device, frag)?;
+
+	let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::VERTEX)
+		.module(vert_sm)
+		.name(b"main\0");
+
+	let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::FRAGMENT)
+		.module(frag_sm)
+		.name(b"main\0");
+
+	let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+	let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+		.topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+		.primitive_restart_enable(false);
+
+	let viewport = vk::Viewport::builder()
+		.x(0.0)
+		.y(0.0)
+		.width(data.render_extent.width as f32)
+		.height(data.render_extent.height as f32)
+		.min_depth(0.0)
+		.max_depth(1.0);
+
+	let scissor = vk::Rect2D::builder()
+		.offset(vk::Offset2D { x: 0, y: 0 })
+		.extent(data.render_extent);
+
+	let viewports = &[viewport];
+	let scissors = &[scissor];
+	let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+		.viewports(viewports)
+		.scissors(scissors);
+
+	// one-sided, the back of a portal doesn't mark anything
+	let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+		.depth_clamp_enable(false)
+		.rasterizer_discard_enable(false)
+		.polygon_mode(vk::PolygonMode::FILL)
+		.line_width(1.0)
+		.cull_mode(if marking { vk::CullModeFlags::BACK } else { vk::CullModeFlags::NONE })
+		.front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+		.depth_bias_enable(false);
+
+	let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+		.sample_shading_enable(false)
+		.rasterization_samples(data.msaa_samples);
+
+	// the quad only marks, its color comes with the copy
+	let color_write_mask = if marking { vk::ColorComponentFlags::empty() } else { vk::ColorComponentFlags::all() };
+	let attachment = vk::PipelineColorBlendAttachmentState::builder()
+		.color_write_mask(color_write_mask)
+		.blend_enable(false);
+	let attachments = &[attachment];
+	let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+		.logic_op_enable(false)
+		.attachments(attachments);
+
+	// the quad's depth keeps what's behind the portal from drawing over its
+	// view, the copy only goes by the stencil
+	let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+		.depth_test_enable(marking)
+		.depth_write_enable(marking)
+		.depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+		.depth_bounds_test_enable(false)
+		.stencil_test_enable(true)
+		.front(stencil.op_state())
+		.back(stencil.op_state());
+
+	// without a fragment shader the quad only touches depth and stencil
+	let stages = if marking { &[vert_stage][..] } else { &[vert_stage, frag_stage][..] };
+
+	let info = vk::GraphicsPipelineCreateInfo::builder()
+		.stages(stages)
+		.vertex_input_state(&vertex_input_state)
+		.input_assembly_state(&input_assembly_state)
+		.viewport_state(&viewport_state)
+		.rasterization_state(&rasterization_state)
+		.multisample_state(&multisample_state)
+		.depth_stencil_state(&depth_stencil_state)
+		.color_blend_state(&color_blend_state)
+		.layout(data.portal_pipeline_layout)
+		.render_pass(data.render_pass)
+		.subpass(0);
+
+	let pipeline = device.create_graphics_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(vert_sm, None);
+	device.destroy_shader_module(frag_sm, None);
+
+	Ok(pipeline)
+}
+
+/// Records both portals into a secondary command buffer inside the scene
+/// pass, after the opaque models so they can hide the portals.
+pub unsafe fn record_portals(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	image_index: usize,
+	pair: &PortalPair,
+	stats: &mut FrameStats,
+	)
+{
+	device.cmd_bind_descriptor_sets(
+		command_buffer,
+		vk::PipelineBindPoint::GRAPHICS,
+		data.portal_pipeline_layout,
+		0,
+		&[data.descriptor_set],
+		&[uniform_offset(data, image_index)]);
+	stats.descriptor_binds += 1;
+
+	for index in 0..2
+	{
+		let (portal, _) = pair.ends(index);
+
+		device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.portal_mark_pipelines[index]);
+		cmd_push_constants(device, command_buffer, data.portal_pipeline_layout, vk::ShaderStageFlags::VERTEX, &portal.model());
+		device.cmd_draw(command_buffer, 6, 1, 0, 0);
+
+		device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.portal_view_pipelines[index]);
+		device.cmd_bind_descriptor_sets(
+			command_buffer,
+			vk::PipelineBindPoint::GRAPHICS,
+			data.portal_pipeline_layout,
+			1,
+			&[data.portal_descriptor_sets[index]],
+			&[]);
+		device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+		stats.pipeline_binds += 2;
+		stats.descriptor_binds += 1;
+		stats.draw_calls += 2;
+	}
+}
+
+/// The images and pipelines, their descriptor sets go with the swapchain's
+/// pool.
+pub unsafe fn destroy_portal_objects(device: &Device, data: &AppData)
+{
+	for index in 0..2
+	{
+		device.destroy_pipeline(data.portal_mark_pipelines[index], None);
+		device.destroy_pipeline(data.portal_view_pipelines[index], None);
+		device.destroy_image_view(data.portal_image_views[index], None);
+		device.destroy_image(data.portal_images[index], None);
+		allocator::free(device, data.portal_images_memory[index]);
+	}
+}
+
+pub unsafe fn destroy_portal_layouts(device: &Device, data: &AppData)
+{
+	device.destroy_pipeline_layout(data.portal_pipeline_layout, None);
+}
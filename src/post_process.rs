@@ -0,0 +1,184 @@
+// Post-processing chain
+//
+// The effects applied to the final image are listed in the scene file and
+// run in that order by the composite pass, e.g.
+//
+// post_process: [
+//     Bloom(intensity: 0.6),
+//     Exposure(stops: 0.5),
+//     Tonemap(white: 4.0),
+//     Saturation(amount: 1.1),
+//     Vignette(strength: 0.3),
+// ],
+//
+// Every effect is a few lines of the composite shader, so the chain is just
+// which of them run and in what order, sent with the push constants. The
+// scene file is polled so effects can be added, removed and reordered while
+// the app runs.
+
+use anyhow::{anyhow, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::bloom;
+
+/// Must match `MAX_EFFECTS` in `composite.frag`.
+pub const MAX_EFFECTS: usize = 8;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Effect
+{
+	/// Adds the blurred highlights.
+	Bloom { intensity: f32 },
+	/// Scales the color by `2^stops`.
+	Exposure { stops: f32 },
+	/// Extended Reinhard, `white` is the brightness mapped to 1.
+	Tonemap { white: f32 },
+	/// 0 is greyscale, 1 leaves the color alone.
+	Saturation { amount: f32 },
+	/// Darkens towards the corners.
+	Vignette { strength: f32 },
+}
+
+impl Effect
+{
+	/// The effect's index in the composite shader and its parameter.
+	fn encode(self) -> (i32, f32)
+	{
+		match self
+		{
+			Effect::Bloom { intensity } => (0, intensity),
+			Effect::Exposure { stops } => (1, stops),
+			Effect::Tonemap { white } => (2, white),
+			Effect::Saturation { amount } => (3, amount),
+			Effect::Vignette { strength } => (4, strength),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PostProcessChain
+{
+	effects: Vec<Effect>,
+}
+
+impl Default for PostProcessChain
+{
+	/// What the composite pass did before the chain was configurable.
+	fn default() -> Self
+	{
+		Self { effects: vec![Effect::Bloom { intensity: bloom::INTENSITY }] }
+	}
+}
+
+impl PostProcessChain
+{
+	pub fn effects(&self) -> &[Effect]
+	{
+		&self.effects
+	}
+
+	/// Moves an effect to another place in the chain, shifting the ones in
+	/// between.
+	pub fn move_effect(&mut self, from: usize, to: usize)
+	{
+		let effect = self.effects.remove(from);
+		self.effects.insert(to.min(self.effects.len()), effect);
+	}
+
+	pub fn insert(&mut self, index: usize, effect: Effect)
+	{
+		self.effects.insert(index.min(self.effects.len()), effect);
+	}
+
+	pub fn remove(&mut self, index: usize) -> Effect
+	{
+		self.effects.remove(index)
+	}
+
+	/// The effect count and each effect's index and parameter, as the
+	/// composite shader takes them. Effects past `MAX_EFFECTS` are dropped.
+	pub fn encode(&self) -> (i32, [i32; MAX_EFFECTS], [f32; MAX_EFFECTS])
+	{
+		if self.effects.len() > MAX_EFFECTS
+		{
+			warn!("Only the first {} of {} post-processing effects are applied", MAX_EFFECTS, self.effects.len());
+		}
+
+		let mut effects = [0; MAX_EFFECTS];
+		let mut parameters = [0.0; MAX_EFFECTS];
+		for (i, effect) in self.effects.iter().take(MAX_EFFECTS).enumerate()
+		{
+			(effects[i], parameters[i]) = effect.encode();
+		}
+
+		(self.effects.len().min(MAX_EFFECTS) as i32, effects, parameters)
+	}
+}
+
+/// Only the chain is read back from the scene, the rest is baked data that
+/// needs the app to act on it.
+#[derive(Deserialize)]
+struct SceneChain
+{
+	#[serde(default)]
+	post_process: PostProcessChain,
+}
+
+/// Tracks the scene file so the chain can be reloaded when it changes.
+#[derive(Clone, Debug)]
+pub struct ChainWatcher
+{
+	path: PathBuf,
+	modified: Option<SystemTime>,
+	last_poll: Instant,
+}
+
+impl ChainWatcher
+{
+	pub fn new(path: impl Into<PathBuf>) -> Self
+	{
+		let path = path.into();
+		let modified = modified_time(&path);
+		Self { path, modified, last_poll: Instant::now() }
+	}
+
+	/// Returns the scene's chain if the file changed since the last poll.
+	/// Polling is throttled so this is cheap to call every frame.
+	pub fn poll(&mut self) -> Option<Result<PostProcessChain>>
+	{
+		if self.last_poll.elapsed() < POLL_INTERVAL
+		{
+			return None;
+		}
+
+		self.last_poll = Instant::now();
+
+		let modified = modified_time(&self.path);
+		if modified == self.modified
+		{
+			return None;
+		}
+
+		self.modified = modified;
+		Some(load_chain(&self.path))
+	}
+}
+
+fn load_chain(path: &Path) -> Result<PostProcessChain>
+{
+	let contents = fs::read_to_string(path)?;
+	let scene: SceneChain = ron::from_str(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+	Ok(scene.post_process)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime>
+{
+	fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,40 @@
+// Push constants
+//
+// Small per-draw data, e.g. one object's transform, is pushed straight into
+// the command buffer instead of written to a buffer and bound through a
+// descriptor set. Every pipeline layout here declares a single range
+// starting at 0, sized for a `#[repr(C)]` struct or byte array that must
+// match the shader's `push_constant` block.
+
+use vulkanalia::prelude::v1_0::*;
+
+use std::mem::size_of;
+
+/// Devices only have to support this many bytes.
+pub const MAX_PUSH_CONSTANTS_SIZE: usize = 128;
+
+/// The range for pushing a `T` to the stages, to declare on the layout.
+pub fn push_constant_range<T: Copy>(stages: vk::ShaderStageFlags) -> vk::PushConstantRange
+{
+	debug_assert!(size_of::<T>() <= MAX_PUSH_CONSTANTS_SIZE);
+
+	vk::PushConstantRange::builder()
+		.stage_flags(stages)
+		.offset(0)
+		.size(size_of::<T>() as u32)
+		.build()
+}
+
+/// Pushes the value, which must be the type the layout's range was declared
+/// with.
+pub unsafe fn cmd_push_constants<T: Copy>(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	layout: vk::PipelineLayout,
+	stages: vk::ShaderStageFlags,
+	value: &T,
+	)
+{
+	let bytes = std::slice::from_ref(value).align_to::<u8>().1;
+	device.cmd_push_constants(command_buffer, layout, stages, 0, bytes);
+}
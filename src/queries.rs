@@ -0,0 +1,339 @@
+// Query pools
+//
+// Each pool is split into one slot of queries per swapchain image, recorded
+// into that image's command buffer. A slot is reset at the start of its
+// command buffer and read back the next time the image comes around, after
+// its fence has been waited on, so reading never stalls and never sees a
+// slot the GPU hasn't finished with.
+//
+// Results come back typed by the pool's kind, e.g. timestamps are converted
+// to milliseconds, rather than as raw bytes for each caller to decode.
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::capabilities::DeviceCapabilities;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum QueryKind
+{
+	#[default]
+	Timestamp,
+	/// Samples passing the depth and stencil tests between begin and end.
+	Occlusion,
+	/// The pool's chosen pipeline counters between begin and end.
+	PipelineStatistics(vk::QueryPipelineStatisticFlags),
+}
+
+impl QueryKind
+{
+	fn query_type(self) -> vk::QueryType
+	{
+		match self
+		{
+			QueryKind::Timestamp => vk::QueryType::TIMESTAMP,
+			QueryKind::Occlusion => vk::QueryType::OCCLUSION,
+			QueryKind::PipelineStatistics(_) => vk::QueryType::PIPELINE_STATISTICS,
+		}
+	}
+
+	/// How many values each query writes.
+	fn values(self) -> usize
+	{
+		match self
+		{
+			QueryKind::PipelineStatistics(statistics) => statistics.bits().count_ones() as usize,
+			_ => 1,
+		}
+	}
+
+	fn supported(self, capabilities: &DeviceCapabilities) -> bool
+	{
+		match self
+		{
+			QueryKind::Timestamp => capabilities.timestamps,
+			QueryKind::Occlusion => true,
+			QueryKind::PipelineStatistics(_) => capabilities.pipeline_statistics,
+		}
+	}
+}
+
+/// One of a slot's queries, as handed out while recording.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Query(u32);
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Slot
+{
+	// queries handed out since the slot was last reset
+	used: u32,
+	// whether the slot has been recorded since the pool was created
+	written: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct QueryPool
+{
+	pool: vk::QueryPool,
+	kind: QueryKind,
+	queries_per_slot: u32,
+	slots: Vec<Slot>,
+	// nanoseconds per timestamp tick
+	timestamp_period: f32,
+}
+
+impl QueryPool
+{
+	/// Creates `queries_per_slot` queries for each of `slots`. Leaves the
+	/// pool null if the device can't run the kind of query, which makes
+	/// every call a no-op and every result `None`.
+	pub unsafe fn create(
+		device: &Device,
+		capabilities: &DeviceCapabilities,
+		kind: QueryKind,
+		slots: usize,
+		queries_per_slot: u32,
+		) -> Result<Self>
+	{
+		let mut pool = Self {
+			kind,
+			queries_per_slot,
+			slots: vec![Slot::default(); slots],
+			timestamp_period: capabilities.limits.timestamp_period,
+			..Default::default()
+		};
+
+		if !kind.supported(capabilities)
+		{
+			warn!("Device doesn't support {:?} queries", kind);
+			return Ok(pool);
+		}
+
+		let statistics = match kind
+		{
+			QueryKind::PipelineStatistics(statistics) => statistics,
+			_ => vk::QueryPipelineStatisticFlags::empty(),
+		};
+
+		let info = vk::QueryPoolCreateInfo::builder()
+			.query_type(kind.query_type())
+			.query_count(slots as u32 * queries_per_slot)
+			.pipeline_statistics(statistics);
+
+		pool.pool = device.create_query_pool(&info, None)?;
+
+		Ok(pool)
+	}
+
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		if !self.pool.is_null()
+		{
+			device.destroy_query_pool(self.pool, None);
+		}
+	}
+
+	pub fn is_supported(&self) -> bool
+	{
+		!self.pool.is_null()
+	}
+
+	/// Resets the slot for this frame's queries. Must be recorded outside of
+	/// a render pass, before any of the slot's queries.
+	pub unsafe fn begin_slot(&mut self, device: &Device, command_buffer: vk::CommandBuffer, slot: usize)
+	{
+		if self.pool.is_null()
+		{
+			return;
+		}
+
+		device.cmd_reset_query_pool(command_buffer, self.pool, self.first_query(slot), self.queries_per_slot);
+		self.slots[slot] = Slot { used: 0, written: true };
+	}
+
+	/// The slot's next unused query, or `None` once they've all been
+	/// handed out this frame.
+	fn next_query(&mut self, slot: usize) -> Option<Query>
+	{
+		if self.pool.is_null()
+		{
+			return None;
+		}
+
+		let state = &mut self.slots[slot];
+		if state.used == self.queries_per_slot
+		{
+			warn!("Out of {:?} queries for this frame", self.kind);
+			return None;
+		}
+
+		state.used += 1;
+		Some(Query(state.used - 1))
+	}
+
+	fn first_query(&self, slot: usize) -> u32
+	{
+		slot as u32 * self.queries_per_slot
+	}
+
+	pub unsafe fn write_timestamp(
+		&mut self,
+		device: &Device,
+		command_buffer: vk::CommandBuffer,
+		slot: usize,
+		stage: vk::PipelineStageFlags,
+		) -> Option<Query>
+	{
+		debug_assert_eq!(self.kind, QueryKind::Timestamp);
+
+		let query = self.next_query(slot)?;
+		device.cmd_write_timestamp(command_buffer, stage, self.pool, self.first_query(slot) + query.0);
+		Some(query)
+	}
+
+	/// Starts an occlusion or statistics query, which has to be ended in
+	/// the same subpass.
+	pub unsafe fn begin_query(&mut self, device: &Device, command_buffer: vk::CommandBuffer, slot: usize) -> Option<Query>
+	{
+		debug_assert_ne!(self.kind, QueryKind::Timestamp);
+
+		let query = self.next_query(slot)?;
+		device.cmd_begin_query(command_buffer, self.pool, self.first_query(slot) + query.0, vk::QueryControlFlags::empty());
+		Some(query)
+	}
+
+	pub unsafe fn end_query(&self, device: &Device, command_buffer: vk::CommandBuffer, slot: usize, query: Query)
+	{
+		device.cmd_end_query(command_buffer, self.pool, self.first_query(slot) + query.0);
+	}
+
+	/// Each query's values from the slot's last frame, if they're all
+	/// available yet.
+	unsafe fn read(&self, device: &Device, slot: usize) -> Result<Option<Vec<Vec<u64>>>>
+	{
+		let state = self.slots[slot];
+		if self.pool.is_null() || !state.written || state.used == 0
+		{
+			return Ok(None);
+		}
+
+		let values = self.kind.values();
+		let stride = 8 * values;
+		let mut bytes = vec![0u8; stride * state.used as usize];
+		let result = device.get_query_pool_results(
+			self.pool,
+			self.first_query(slot),
+			state.used,
+			&mut bytes,
+			stride as vk::DeviceSize,
+			vk::QueryResultFlags::_64,
+		)?;
+
+		if result == vk::SuccessCode::NOT_READY
+		{
+			return Ok(None);
+		}
+
+		let results = bytes
+			.chunks_exact(stride)
+			.map(|query| query.chunks_exact(8).map(|v| u64::from_ne_bytes(v.try_into().unwrap())).collect())
+			.collect();
+
+		Ok(Some(results))
+	}
+
+	/// Milliseconds from each of the slot's timestamps to the next.
+	pub unsafe fn intervals_ms(&self, device: &Device, slot: usize) -> Result<Option<Vec<f32>>>
+	{
+		Ok(self.read(device, slot)?.map(|results|
+			{
+				results.windows(2).map(|pair| self.ticks_to_ms(pair[1][0].wrapping_sub(pair[0][0]))).collect()
+			}))
+	}
+
+	/// Milliseconds from the slot's first timestamp to its last.
+	pub unsafe fn elapsed_ms(&self, device: &Device, slot: usize) -> Result<Option<f32>>
+	{
+		Ok(self.intervals_ms(device, slot)?.map(|intervals| intervals.iter().sum()))
+	}
+
+	fn ticks_to_ms(&self, ticks: u64) -> f32
+	{
+		let nanoseconds = ticks as f64 * self.timestamp_period as f64;
+		(nanoseconds / 1_000_000.0) as f32
+	}
+
+	/// Samples that passed during each of the slot's occlusion queries.
+	pub unsafe fn samples_passed(&self, device: &Device, slot: usize) -> Result<Option<Vec<u64>>>
+	{
+		Ok(self.read(device, slot)?.map(|results| results.iter().map(|r| r[0]).collect()))
+	}
+
+	/// The counters of each of the slot's statistics queries.
+	pub unsafe fn statistics(&self, device: &Device, slot: usize) -> Result<Option<Vec<PipelineStatistics>>>
+	{
+		let statistics = match self.kind
+		{
+			QueryKind::PipelineStatistics(statistics) => statistics,
+			_ => return Ok(None),
+		};
+
+		Ok(self.read(device, slot)?.map(|results|
+			{
+				results.iter().map(|values| PipelineStatistics::from_values(statistics, values)).collect()
+			}))
+	}
+}
+
+/// The counters a statistics query can collect, `None` for the ones the pool
+/// wasn't created with.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStatistics
+{
+	pub input_assembly_vertices: Option<u64>,
+	pub input_assembly_primitives: Option<u64>,
+	pub vertex_shader_invocations: Option<u64>,
+	pub clipping_invocations: Option<u64>,
+	pub clipping_primitives: Option<u64>,
+	pub fragment_shader_invocations: Option<u64>,
+	pub compute_shader_invocations: Option<u64>,
+}
+
+impl PipelineStatistics
+{
+	/// The values are written in the order of the flags' bits, skipping the
+	/// ones that weren't requested.
+	fn from_values(statistics: vk::QueryPipelineStatisticFlags, values: &[u64]) -> Self
+	{
+		use vk::QueryPipelineStatisticFlags as F;
+
+		let mut values = values.iter().copied();
+		let mut next = |flag: vk::QueryPipelineStatisticFlags|
+		{
+			if statistics.contains(flag) { values.next() } else { None }
+		};
+
+		let input_assembly_vertices = next(F::INPUT_ASSEMBLY_VERTICES);
+		let input_assembly_primitives = next(F::INPUT_ASSEMBLY_PRIMITIVES);
+		let vertex_shader_invocations = next(F::VERTEX_SHADER_INVOCATIONS);
+		next(F::GEOMETRY_SHADER_INVOCATIONS);
+		next(F::GEOMETRY_SHADER_PRIMITIVES);
+		let clipping_invocations = next(F::CLIPPING_INVOCATIONS);
+		let clipping_primitives = next(F::CLIPPING_PRIMITIVES);
+		let fragment_shader_invocations = next(F::FRAGMENT_SHADER_INVOCATIONS);
+		next(F::TESSELLATION_CONTROL_SHADER_PATCHES);
+		next(F::TESSELLATION_EVALUATION_SHADER_INVOCATIONS);
+		let compute_shader_invocations = next(F::COMPUTE_SHADER_INVOCATIONS);
+
+		Self {
+			input_assembly_vertices,
+			input_assembly_primitives,
+			vertex_shader_invocations,
+			clipping_invocations,
+			clipping_primitives,
+			fragment_shader_invocations,
+			compute_shader_invocations,
+		}
+	}
+}
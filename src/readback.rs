@@ -0,0 +1,232 @@
+// GPU readback
+//
+// Everything that brings GPU results back to the CPU goes through here: a
+// copy into a host visible buffer, a barrier making it visible to the host,
+// and a read once the copy's submission has finished.
+//
+// `read_now` is for one-offs outside the frame, like captures and the
+// headless output. It submits on its own and waits on that submission's
+// fence, not on the whole device. Cubemap captures keep a `ReadbackBuffer`
+// of their own, since their faces are copied on into probes too. Inside a
+// frame `Readbacks` queues copies recorded into the frame's command buffer
+// and reads them once the frame's fence has been waited on for its next
+// turn, so nothing stalls. A result is either handed to a callback or kept
+// until it's polled for, e.g.
+//
+// let id = readbacks.record(.., size, |command_buffer, buffer| .., None)?;
+// // some frames later
+// if let Some(bytes) = readbacks.poll(id) { .. }
+//
+// Screenshots poll for theirs, recording hands frames to its writer from a
+// callback.
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::collections::HashMap;
+
+use crate::allocator::{self, Allocation};
+use crate::{begin_single_time_commands, create_buffer, end_single_time_commands, AppData};
+
+/// Identifies a queued readback for `Readbacks::poll`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ReadbackId(u64);
+
+/// A host visible buffer copies are read back through. It can be copied
+/// from too, e.g. a cubemap capture's faces go on into a probe's cube.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReadbackBuffer
+{
+	buffer: vk::Buffer,
+	memory: Allocation,
+	size: vk::DeviceSize,
+}
+
+impl ReadbackBuffer
+{
+	pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData, size: vk::DeviceSize) -> Result<Self>
+	{
+		let (buffer, memory) = create_buffer(
+			instance,
+			device,
+			data,
+			size,
+			vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC,
+			vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+		)?;
+
+		Ok(Self { buffer, memory, size })
+	}
+
+	pub fn buffer(&self) -> vk::Buffer
+	{
+		self.buffer
+	}
+
+	/// The buffer's bytes. The submission that copied into it must have
+	/// finished.
+	pub unsafe fn read(&self, device: &Device) -> Result<Vec<u8>>
+	{
+		read_memory(device, self.memory, self.size)
+	}
+
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		device.destroy_buffer(self.buffer, None);
+		allocator::free(device, self.memory);
+	}
+}
+
+/// Makes the transfers recorded so far visible to the host, after copying
+/// into a readback buffer.
+pub unsafe fn record_host_barrier(device: &Device, command_buffer: vk::CommandBuffer)
+{
+	let barrier = vk::MemoryBarrier::builder()
+		.src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+		.dst_access_mask(vk::AccessFlags::HOST_READ);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		vk::PipelineStageFlags::TRANSFER,
+		vk::PipelineStageFlags::HOST,
+		vk::DependencyFlags::empty(),
+		&[barrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[] as &[vk::ImageMemoryBarrier],
+	);
+}
+
+/// Copies the first `size` bytes of host visible memory out.
+pub unsafe fn read_memory(device: &Device, memory: Allocation, size: vk::DeviceSize) -> Result<Vec<u8>>
+{
+	let mapped = device.map_memory(memory.memory, memory.offset, size, vk::MemoryMapFlags::empty())?;
+	let bytes = std::slice::from_raw_parts(mapped.cast::<u8>(), size as usize).to_vec();
+	device.unmap_memory(memory.memory);
+
+	Ok(bytes)
+}
+
+/// Reads `size` bytes back right away: `record` copies them into the
+/// buffer it's given, which is submitted and waited on.
+pub unsafe fn read_now(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	size: vk::DeviceSize,
+	record: impl FnOnce(vk::CommandBuffer, vk::Buffer),
+	) -> Result<Vec<u8>>
+{
+	let readback = ReadbackBuffer::create(instance, device, data, size)?;
+
+	let result = begin_single_time_commands(device, data, data.graphics_command_pool).and_then(|command_buffer|
+		{
+			record(command_buffer, readback.buffer);
+			record_host_barrier(device, command_buffer);
+			end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)
+		});
+
+	let bytes = result.and_then(|()| readback.read(device));
+	readback.destroy(device);
+	bytes
+}
+
+/// What happens to a result once it's read.
+enum Completion
+{
+	/// Kept for `Readbacks::poll`.
+	Poll,
+	Callback(Box<dyn FnOnce(Vec<u8>)>),
+}
+
+struct PendingReadback
+{
+	id: ReadbackId,
+	frame: usize,
+	buffer: ReadbackBuffer,
+	completion: Completion,
+}
+
+/// Readbacks recorded into frames, read when their frame's fence has been
+/// waited on.
+#[derive(Default)]
+pub struct Readbacks
+{
+	next_id: u64,
+	pending: Vec<PendingReadback>,
+	ready: HashMap<ReadbackId, Vec<u8>>,
+}
+
+impl Readbacks
+{
+	/// Queues a readback of `size` bytes that `record` copies into the
+	/// buffer it's given, in the frame's command buffer. With a callback
+	/// it's called with the bytes, otherwise they wait to be polled for.
+	pub unsafe fn record(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &AppData,
+		command_buffer: vk::CommandBuffer,
+		frame: usize,
+		size: vk::DeviceSize,
+		record: impl FnOnce(vk::CommandBuffer, vk::Buffer),
+		callback: Option<Box<dyn FnOnce(Vec<u8>)>>,
+		) -> Result<ReadbackId>
+	{
+		let buffer = ReadbackBuffer::create(instance, device, data, size)?;
+		record(command_buffer, buffer.buffer);
+		record_host_barrier(device, command_buffer);
+
+		let id = ReadbackId(self.next_id);
+		self.next_id += 1;
+
+		let completion = callback.map_or(Completion::Poll, Completion::Callback);
+		self.pending.push(PendingReadback { id, frame, buffer, completion });
+
+		Ok(id)
+	}
+
+	/// The bytes of a finished readback that had no callback, once.
+	pub fn poll(&mut self, id: ReadbackId) -> Option<Vec<u8>>
+	{
+		self.ready.remove(&id)
+	}
+
+	/// Reads the frame's readbacks. The frame's fence must have been waited
+	/// on.
+	pub unsafe fn collect(&mut self, device: &Device, frame: usize)
+	{
+		let (done, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+			.into_iter()
+			.partition(|p| p.frame == frame);
+		self.pending = pending;
+
+		for readback in done
+		{
+			self.complete(device, readback);
+		}
+	}
+
+	/// Reads everything still queued. Every frame must have finished.
+	pub unsafe fn flush(&mut self, device: &Device)
+	{
+		for readback in std::mem::take(&mut self.pending)
+		{
+			self.complete(device, readback);
+		}
+	}
+
+	unsafe fn complete(&mut self, device: &Device, readback: PendingReadback)
+	{
+		let bytes = readback.buffer.read(device);
+		readback.buffer.destroy(device);
+
+		match (bytes, readback.completion)
+		{
+			(Ok(bytes), Completion::Poll) => { self.ready.insert(readback.id, bytes); },
+			(Ok(bytes), Completion::Callback(callback)) => callback(bytes),
+			(Err(e), _) => warn!("Failed to read back {:?}: {}", readback.id, e),
+		}
+	}
+}
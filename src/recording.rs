@@ -0,0 +1,200 @@
+// Frame sequence recording
+//
+// Saves every Nth presented frame, for making videos of the scenes, either
+// as numbered PNGs in a directory or piped raw into ffmpeg, e.g.
+//
+// vulkan-tutorial --record frames --record-every 2
+// vulkan-tutorial --deterministic --demo media/demo.ron --record-ffmpeg demo.mp4
+//
+// Frames are queued for readback like screenshots, see readback. A frame's
+// copy is read once its fence has been waited on and handed to a writer
+// thread over a channel with a slot per frame in flight, so if encoding
+// falls behind the frame loop waits for it instead of piling frames up in
+// memory.
+// ffmpeg gets the size of the first frame, frames of any other size after a
+// resize are skipped.
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::JoinHandle;
+
+use crate::readback::Readbacks;
+use crate::screenshot::{self, SwapchainCopy};
+use crate::{AppData, MAX_FRAMES_IN_FLIGHT};
+
+/// Where recorded frames go.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordTarget
+{
+	/// `frame-000000.png` onwards in the directory.
+	Images(PathBuf),
+	/// Raw RGBA piped to `ffmpeg`, which has to be on the path.
+	Ffmpeg { output: PathBuf, fps: u32 },
+}
+
+struct Frame
+{
+	extent: vk::Extent2D,
+	pixels: Vec<u8>,
+}
+
+pub struct Recorder
+{
+	every: u32,
+	presented: u64,
+	sender: Option<SyncSender<Frame>>,
+	writer: Option<JoinHandle<Result<u64>>>,
+}
+
+impl Recorder
+{
+	pub fn start(target: RecordTarget, every: u32) -> Result<Self>
+	{
+		let (sender, receiver) = sync_channel(MAX_FRAMES_IN_FLIGHT);
+
+		let writer = std::thread::Builder::new()
+			.name("recording".into())
+			.spawn(move || write_frames(target, receiver))?;
+
+		Ok(Self {
+			every: every.max(1),
+			presented: 0,
+			sender: Some(sender),
+			writer: Some(writer),
+		})
+	}
+
+	/// Queues the readback of the swapchain image if this frame is one of
+	/// the ones kept, after the composite pass. It's handed to the writer
+	/// when it's read, waiting if the writer is behind.
+	pub unsafe fn record(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &mut AppData,
+		readbacks: &mut Readbacks,
+		command_buffer: vk::CommandBuffer,
+		frame: usize,
+		image_index: usize,
+		) -> Result<()>
+	{
+		let keep = self.presented % self.every as u64 == 0;
+		self.presented += 1;
+		if !keep
+		{
+			return Ok(());
+		}
+
+		let Some(sender) = self.sender.clone() else
+		{
+			return Ok(());
+		};
+
+		let copy = SwapchainCopy::new(instance, data)?;
+		let callback = Box::new(move |pixels: Vec<u8>|
+			{
+				let frame = Frame { extent: copy.extent(), pixels: copy.to_rgba(pixels) };
+				if sender.send(frame).is_err()
+				{
+					warn!("The recording writer stopped");
+				}
+			});
+
+		copy.record(instance, device, data, readbacks, command_buffer, frame, image_index, Some(callback))?;
+		Ok(())
+	}
+
+	/// Waits for the writer to finish. The readbacks must have been flushed
+	/// so every frame recorded has been handed to it.
+	pub fn finish(&mut self) -> Result<()>
+	{
+		// closing the channel ends the writer, once the callbacks holding
+		// it are gone too
+		self.sender = None;
+		if let Some(writer) = self.writer.take()
+		{
+			let written = writer.join().map_err(|_| anyhow!("the recording writer panicked"))??;
+			info!("Recorded {} frames", written);
+		}
+
+		Ok(())
+	}
+}
+
+fn write_frames(target: RecordTarget, receiver: Receiver<Frame>) -> Result<u64>
+{
+	let mut written = 0;
+	let mut ffmpeg: Option<(Child, vk::Extent2D)> = None;
+
+	for frame in receiver
+	{
+		match &target
+		{
+			RecordTarget::Images(directory) =>
+			{
+				let path = directory.join(format!("frame-{:06}.png", written));
+				screenshot::save(frame.extent, frame.pixels, &path)?;
+			},
+			RecordTarget::Ffmpeg { output, fps } =>
+			{
+				if ffmpeg.is_none()
+				{
+					ffmpeg = Some((spawn_ffmpeg(output, *fps, frame.extent)?, frame.extent));
+				}
+				let Some((child, extent)) = &mut ffmpeg else
+				{
+					continue;
+				};
+
+				if *extent != frame.extent
+				{
+					debug!("Skipped a {}x{} frame", frame.extent.width, frame.extent.height);
+					continue;
+				}
+
+				child.stdin
+					.as_mut()
+					.ok_or_else(|| anyhow!("ffmpeg's input is closed"))?
+					.write_all(&frame.pixels)?;
+			},
+		}
+
+		written += 1;
+	}
+
+	if let Some((mut child, _)) = ffmpeg
+	{
+		// ffmpeg finishes the file once its input ends
+		drop(child.stdin.take());
+		let status = child.wait()?;
+		if !status.success()
+		{
+			return Err(anyhow!("ffmpeg exited with {}", status));
+		}
+	}
+
+	Ok(written)
+}
+
+fn spawn_ffmpeg(output: &Path, fps: u32, extent: vk::Extent2D) -> Result<Child>
+{
+	let child = Command::new("ffmpeg")
+		.args(["-loglevel", "error", "-y", "-f", "rawvideo", "-pixel_format", "rgba"])
+		.args(["-video_size", &format!("{}x{}", extent.width, extent.height)])
+		.args(["-framerate", &fps.to_string(), "-i", "-"])
+		// yuv420p needs even dimensions
+		.args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2", "-pix_fmt", "yuv420p"])
+		.arg(output)
+		.stdin(Stdio::piped())
+		.spawn()
+		.map_err(|e| anyhow!("failed to start ffmpeg: {}", e))?;
+
+	info!("Recording {}x{} at {} fps to {}", extent.width, extent.height, fps, output.display());
+	Ok(child)
+}
@@ -0,0 +1,282 @@
+// Reflection probes
+//
+// Probes placed in the scene capture a cubemap of their surroundings when
+// the app starts and whenever lighting is rebaked. Surfaces inside a probe's
+// box sample it with box projection: the reflected ray is intersected with
+// the box and the cubemap is looked up towards the hit point from the probe,
+// which lines reflections up with the walls of a room far better than
+// treating the cubemap as infinitely far away.
+
+use anyhow::Result;
+use log::*;
+use serde::{Deserialize, Serialize};
+use vulkanalia::prelude::v1_0::*;
+
+use nalgebra_glm as glm;
+
+use crate::allocator;
+use crate::assets::Texture;
+use crate::cubemap::{CubemapCapture, CAPTURE_FORMAT};
+use crate::{
+	begin_single_time_commands,
+	end_single_time_commands,
+	AppData,
+};
+
+/// Must match the size of `reflectionProbes` in `shader.frag`.
+pub const MAX_REFLECTION_PROBES: usize = 4;
+
+const CUBEMAP_SIZE: u32 = 128;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReflectionProbe
+{
+	pub position: (f32, f32, f32),
+	/// Corners of the box the probe's surroundings are projected onto,
+	/// and the region where surfaces use this probe.
+	pub box_min: (f32, f32, f32),
+	pub box_max: (f32, f32, f32),
+}
+
+impl ReflectionProbe
+{
+	pub fn position(&self) -> glm::Vec3
+	{
+		glm::vec3(self.position.0, self.position.1, self.position.2)
+	}
+
+	/// Position and box corners as laid out in the uniform buffer.
+	pub fn uniform(&self) -> [[f32; 4]; 3]
+	{
+		let v = |v: (f32, f32, f32)| [v.0, v.1, v.2, 0.0];
+		[v(self.position), v(self.box_min), v(self.box_max)]
+	}
+}
+
+/// Creates `MAX_REFLECTION_PROBES` black cubemaps so every slot in the
+/// descriptor set is valid, whether or not it has a probe.
+pub unsafe fn create_reflection_cubemaps(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let info = vk::SamplerCreateInfo::builder()
+		.mag_filter(vk::Filter::LINEAR)
+		.min_filter(vk::Filter::LINEAR)
+		.address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+		.address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+		.address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+		.mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+		.max_lod(0.0);
+
+	data.reflection_sampler = device.create_sampler(&info, None)?;
+
+	let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+
+	for _ in 0..MAX_REFLECTION_PROBES
+	{
+		let cubemap = create_cubemap_image(instance, device, data)?;
+
+		cubemap_barrier(
+			device,
+			data,
+			command_buffer,
+			cubemap.image,
+			(vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+		);
+
+		let black = vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] };
+		device.cmd_clear_color_image(
+			command_buffer,
+			cubemap.image,
+			vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+			&black,
+			&[cubemap_range()],
+		);
+
+		cubemap_barrier(
+			device,
+			data,
+			command_buffer,
+			cubemap.image,
+			(vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+		);
+
+		data.reflection_cubemaps.push(cubemap);
+	}
+
+	end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)?;
+
+	Ok(())
+}
+
+/// Captures the scene from every probe into its cubemap. Only call this
+/// with the device idle.
+pub unsafe fn bake_reflection_probes(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	probes: &[ReflectionProbe],
+	near: f32,
+	far: f32,
+	models: &[glm::Mat4],
+	) -> Result<()>
+{
+	if probes.is_empty()
+	{
+		return Ok(());
+	}
+
+	if probes.len() > MAX_REFLECTION_PROBES
+	{
+		warn!("Only the first {} of {} reflection probes are used", MAX_REFLECTION_PROBES, probes.len());
+	}
+
+	let capture = CubemapCapture::create(instance, device, data, CUBEMAP_SIZE)?;
+
+	let result = probes
+		.iter()
+		.zip(&data.reflection_cubemaps)
+		.try_for_each(|(probe, cubemap)|
+			{
+				capture.render(device, data, &probe.position(), near, far, models)?;
+
+				let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+
+				cubemap_barrier(
+					device,
+					data,
+					command_buffer,
+					cubemap.image,
+					(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+				);
+
+				capture.copy_to_cubemap(device, command_buffer, cubemap.image);
+
+				cubemap_barrier(
+					device,
+					data,
+					command_buffer,
+					cubemap.image,
+					(vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+				);
+
+				end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)
+			});
+
+	capture.destroy(device);
+
+	result?;
+	info!("Baked {} reflection probes", probes.len().min(MAX_REFLECTION_PROBES));
+
+	Ok(())
+}
+
+fn cubemap_range() -> vk::ImageSubresourceRange
+{
+	vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(6)
+		.build()
+}
+
+unsafe fn create_cubemap_image(
+	instance: &Instance,
+	device: &Device,
+	data: &AppData,
+	) -> Result<Texture>
+{
+	let info = vk::ImageCreateInfo::builder()
+		.flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+		.image_type(vk::ImageType::_2D)
+		.extent(vk::Extent3D { width: CUBEMAP_SIZE, height: CUBEMAP_SIZE, depth: 1 })
+		.mip_levels(1)
+		.array_layers(6)
+		.samples(vk::SampleCountFlags::_1)
+		.format(CAPTURE_FORMAT)
+		.tiling(vk::ImageTiling::OPTIMAL)
+		.initial_layout(vk::ImageLayout::UNDEFINED)
+		.usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+		.sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+	let image = device.create_image(&info, None)?;
+
+	let memory = allocator::allocate_image(instance, device, data, image, vk::MemoryPropertyFlags::DEVICE_LOCAL, "reflection probe cubemap")?;
+
+	let info = vk::ImageViewCreateInfo::builder()
+		.image(image)
+		.view_type(vk::ImageViewType::CUBE)
+		.format(CAPTURE_FORMAT)
+		.subresource_range(cubemap_range());
+
+	let view = device.create_image_view(&info, None)?;
+
+	Ok(Texture {
+		image,
+		memory,
+		view,
+		format: CAPTURE_FORMAT,
+		mip_levels: 1,
+		size: requirements.size,
+	})
+}
+
+unsafe fn cubemap_barrier(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	(old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+	)
+{
+	data.layouts.transition(image, old_layout, new_layout);
+
+	let (src_stage, src_access) = match old_layout
+	{
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+		_ => (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()),
+	};
+
+	let (dst_stage, dst_access) = match new_layout
+	{
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+		_ => (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+	};
+
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(old_layout)
+		.new_layout(new_layout)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(cubemap_range())
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
+
+pub unsafe fn destroy_reflection_cubemaps(device: &Device, data: &mut AppData)
+{
+	device.destroy_sampler(data.reflection_sampler, None);
+
+	for cubemap in data.reflection_cubemaps.drain(..)
+	{
+		device.destroy_image_view(cubemap.view, None);
+		device.destroy_image(cubemap.image, None);
+		allocator::free(device, cubemap.memory);
+	}
+}
@@ -0,0 +1,179 @@
+// Render queues
+//
+// Everything drawn into the scene is tagged with a queue. The queue decides
+// which pass it's drawn in and its place in that pass: queues are drawn in
+// the order they're declared, and what's in each one is sorted by its
+// policy, e.g. blended models back to front so they composite correctly.
+//
+// Which pass a queue is drawn in and how it's sorted can be overridden in
+// the scene file, e.g.
+//
+// render_queues: {
+//     Overlay: (pass: Scene, sort: Submission),
+// },
+
+use serde::{Deserialize, Serialize};
+use vulkanalia::prelude::v1_0::*;
+
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum RenderQueue
+{
+	/// Depth of the opaque models, so their shading only runs for what's
+	/// visible.
+	DepthPrepass,
+	Opaque,
+	/// Cut out rather than blended, so still drawn front to back.
+	AlphaTest,
+	/// Fills whatever's left, so it has to come after everything that writes
+	/// depth and before anything blended over it.
+	Sky,
+	Transparent,
+	/// Refracts the finished scene behind it.
+	Transmissive,
+	/// Debug drawing and other things that shouldn't be refracted or hidden
+	/// behind glass.
+	Overlay,
+	/// Held in front of the camera, drawn after everything else.
+	FirstPerson,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pass
+{
+	Scene,
+	/// After the scene color has been copied for refraction.
+	Transmission,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortPolicy
+{
+	/// In the order they were recorded.
+	Submission,
+	/// Nearest first, so hidden fragments fail the depth test early.
+	FrontToBack,
+	/// Furthest first, so blending composites correctly.
+	BackToFront,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueSettings
+{
+	pub pass: Pass,
+	pub sort: SortPolicy,
+}
+
+impl RenderQueue
+{
+	/// Whether the draws' order only matters for performance, so they can
+	/// be batched by state.
+	pub fn is_opaque(self) -> bool
+	{
+		matches!(self, RenderQueue::Opaque | RenderQueue::AlphaTest)
+	}
+
+	pub fn default_settings(self) -> QueueSettings
+	{
+		let (pass, sort) = match self
+		{
+			RenderQueue::DepthPrepass | RenderQueue::Opaque | RenderQueue::AlphaTest => (Pass::Scene, SortPolicy::FrontToBack),
+			RenderQueue::Sky => (Pass::Scene, SortPolicy::Submission),
+			RenderQueue::Transparent => (Pass::Scene, SortPolicy::BackToFront),
+			RenderQueue::Transmissive => (Pass::Transmission, SortPolicy::BackToFront),
+			RenderQueue::Overlay => (Pass::Transmission, SortPolicy::Submission),
+			RenderQueue::FirstPerson => (Pass::Transmission, SortPolicy::FrontToBack),
+		};
+
+		QueueSettings { pass, sort }
+	}
+}
+
+/// Per-queue overrides of the default pass and sorting.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RenderQueues
+{
+	overrides: BTreeMap<RenderQueue, QueueSettings>,
+}
+
+impl RenderQueues
+{
+	pub fn settings(&self, queue: RenderQueue) -> QueueSettings
+	{
+		self.overrides.get(&queue).copied().unwrap_or_else(|| queue.default_settings())
+	}
+
+	/// The command buffers of the draws belonging to the pass, in the order
+	/// they should be executed.
+	pub fn draw_order(&self, draws: &[Draw], pass: Pass) -> Vec<vk::CommandBuffer>
+	{
+		let mut draws = draws
+			.iter()
+			.filter(|d| self.settings(d.queue).pass == pass)
+			.collect::<Vec<_>>();
+
+		// stable, so draws at the same distance keep their submission order
+		draws.sort_by(|a, b|
+		{
+			a.queue.cmp(&b.queue).then_with(|| match self.settings(a.queue).sort
+			{
+				SortPolicy::Submission => std::cmp::Ordering::Equal,
+				SortPolicy::FrontToBack => a.distance.total_cmp(&b.distance),
+				SortPolicy::BackToFront => b.distance.total_cmp(&a.distance),
+			})
+		});
+
+		draws.iter().map(|d| d.command_buffer).collect()
+	}
+}
+
+/// A recorded secondary command buffer and where it goes.
+#[derive(Copy, Clone, Debug)]
+pub struct Draw
+{
+	pub queue: RenderQueue,
+	/// From the camera, for sorting.
+	pub distance: f32,
+	pub command_buffer: vk::CommandBuffer,
+}
+
+impl Draw
+{
+	pub fn new(queue: RenderQueue, command_buffer: vk::CommandBuffer) -> Self
+	{
+		Self { queue, distance: 0.0, command_buffer }
+	}
+}
+
+/// What a draw binds, ordered from the most to the least expensive to change.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DrawState
+{
+	pub pipeline: u64,
+	pub material: usize,
+	pub mesh: usize,
+}
+
+/// Sorts opaque draws so the ones sharing state are next to each other,
+/// nearest first within each run, and splits them into those runs. Each run
+/// can be recorded with its state bound once.
+pub fn batch_by_state<T: Copy>(draws: &mut [(DrawState, f32, T)]) -> Vec<Vec<T>>
+{
+	draws.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.total_cmp(&b.1)));
+
+	let mut batches: Vec<Vec<T>> = Vec::new();
+	let mut state = None;
+	for &(draw_state, _, draw) in draws.iter()
+	{
+		match batches.last_mut()
+		{
+			Some(batch) if state == Some(draw_state) => batch.push(draw),
+			_ => batches.push(vec![draw]),
+		}
+		state = Some(draw_state);
+	}
+
+	batches
+}
@@ -0,0 +1,88 @@
+// Common samplers
+//
+// Most passes sample with one of a few fixed samplers, so those are created
+// once with the device and baked into the descriptor set layouts as
+// immutable samplers. Sets using them never write a sampler, and the
+// layouts say up front exactly how they're sampled, which drivers and the
+// best practices layer prefer.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::AppData;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CommonSampler
+{
+	/// Filtered, for render targets read back at about their own size.
+	LinearClamp,
+	/// Unfiltered, for data that mustn't be blended between texels such as
+	/// depth.
+	PointClamp,
+	/// Depth comparison with hardware PCF, for shadow maps.
+	ShadowCompare,
+}
+
+const COMMON_SAMPLERS: [CommonSampler; 3] = [
+	CommonSampler::LinearClamp,
+	CommonSampler::PointClamp,
+	CommonSampler::ShadowCompare,
+];
+
+impl CommonSampler
+{
+	fn info(self) -> vk::SamplerCreateInfoBuilder<'static>
+	{
+		let filter = match self
+		{
+			CommonSampler::PointClamp => vk::Filter::NEAREST,
+			CommonSampler::LinearClamp | CommonSampler::ShadowCompare => vk::Filter::LINEAR,
+		};
+
+		let info = vk::SamplerCreateInfo::builder()
+			.mag_filter(filter)
+			.min_filter(filter)
+			.address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+			.address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+			.address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+			.mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+			.max_lod(0.0);
+
+		match self
+		{
+			// outside the map is never in shadow
+			CommonSampler::ShadowCompare => info
+				.address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+				.address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+				.address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+				.border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+				.compare_enable(true)
+				.compare_op(vk::CompareOp::LESS_OR_EQUAL),
+			_ => info,
+		}
+	}
+}
+
+pub unsafe fn create_common_samplers(device: &Device, data: &mut AppData) -> Result<()>
+{
+	data.common_samplers = COMMON_SAMPLERS
+		.iter()
+		.map(|sampler| device.create_sampler(&sampler.info(), None))
+		.collect::<Result<Vec<_>, _>>()?;
+
+	Ok(())
+}
+
+/// The sampler, to pass to `immutable_samplers` of a layout binding. It
+/// lives as long as the device, so it outlives every layout using it.
+pub fn common_sampler(data: &AppData, sampler: CommonSampler) -> vk::Sampler
+{
+	data.common_samplers[sampler as usize]
+}
+
+pub unsafe fn destroy_common_samplers(device: &Device, data: &mut AppData)
+{
+	data.common_samplers
+		.drain(..)
+		.for_each(|s| device.destroy_sampler(s, None));
+}
@@ -0,0 +1,74 @@
+// Scene description
+//
+// Baked data that belongs to the scene as a whole rather than to any one
+// asset, saved next to the assets as `.ron`, e.g.
+//
+// (
+//     light_probes: (
+//         origin: (-1.5, -3.0, -2.0),
+//         spacing: 1.5,
+//         counts: (3, 5, 4),
+//         probes: [],
+//     ),
+//     reflection_probes: [
+//         (position: (0.0, 0.0, 0.5), box_min: (-1.5, -3.0, -1.0), box_max: (1.5, 3.0, 2.0)),
+//     ],
+//     render_queues: {},
+//     lights: [],
+//     post_process: [Bloom(intensity: 0.6)],
+//     mirror: Some((point: (0.0, 0.0, -1.0), normal: (0.0, 0.0, 1.0))),
+// )
+//
+// Anything left out falls back to its default, so a missing or empty
+// probe list just means the probes haven't been baked yet.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+
+use crate::light_probes::LightProbeGrid;
+use crate::lights::Light;
+use crate::planar_reflection::MirrorPlane;
+use crate::portals::PortalPair;
+use crate::post_process::PostProcessChain;
+use crate::reflection_probes::ReflectionProbe;
+use crate::render_queue::RenderQueues;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scene
+{
+	#[serde(default)]
+	pub light_probes: LightProbeGrid,
+	#[serde(default)]
+	pub reflection_probes: Vec<ReflectionProbe>,
+	#[serde(default)]
+	pub render_queues: RenderQueues,
+	#[serde(default)]
+	pub lights: Vec<Light>,
+	#[serde(default)]
+	pub post_process: PostProcessChain,
+	/// Reflected by mirror materials, see `planar_reflection`.
+	#[serde(default)]
+	pub mirror: Option<MirrorPlane>,
+	/// A linked pair showing each other's surroundings, see `portals`.
+	#[serde(default)]
+	pub portals: Option<PortalPair>,
+}
+
+impl Scene
+{
+	pub fn load(path: &Path) -> Result<Self>
+	{
+		let contents = fs::read_to_string(path)?;
+		ron::from_str(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))
+	}
+
+	pub fn save(&self, path: &Path) -> Result<()>
+	{
+		let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+		fs::write(path, contents)?;
+		Ok(())
+	}
+}
@@ -0,0 +1,389 @@
+// Screenshots
+//
+// F12 saves what the window is showing as a timestamped PNG under
+// `captures/`. The swapchain image is queued for readback at the end of the
+// frame that drew it and the frame goes on without waiting: it's read once
+// the frame's fence has been waited on for its next turn, see readback, and
+// the PNG is encoded on a job so the frame loop doesn't stall on that
+// either.
+//
+// The swapchain's format is whatever the surface offered, usually BGRA.
+// When the device can blit from it the image is blitted into an RGBA one,
+// which does the conversion, otherwise it's copied as it is and the
+// channels are swapped on the CPU. Recording reads frames back the same
+// way, see recording.
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::capture::CAPTURE_DIRECTORY;
+use crate::deletion_queue::Retired;
+use crate::headless;
+use crate::jobs::{Jobs, System};
+use crate::readback::{ReadbackId, Readbacks};
+use crate::{create_image, AppData};
+
+/// How the swapchain image gets copied, and what the bytes read back need
+/// to become RGBA8.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SwapchainCopy
+{
+	extent: vk::Extent2D,
+	// the format it's blitted to, none when it's copied directly
+	blit_format: Option<vk::Format>,
+	bgra: bool,
+}
+
+impl SwapchainCopy
+{
+	/// Works one out for the current swapchain images.
+	pub unsafe fn new(instance: &Instance, data: &AppData) -> Result<Self>
+	{
+		if !data.swapchain_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC)
+		{
+			return Err(anyhow!("the swapchain's images can't be copied from"));
+		}
+
+		let target_format = if is_srgb(data.swapchain_format) { vk::Format::R8G8B8A8_SRGB } else { vk::Format::R8G8B8A8_UNORM };
+		let blit = supports_blit(instance, data, data.swapchain_format, target_format);
+		let bgra = !blit && matches!(data.swapchain_format, vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM);
+
+		if !blit && !bgra && !matches!(data.swapchain_format, vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM)
+		{
+			return Err(anyhow!("can't convert from {:?} without blitting", data.swapchain_format));
+		}
+
+		Ok(Self { extent: data.swapchain_extent, blit_format: blit.then_some(target_format), bgra })
+	}
+
+	pub fn extent(&self) -> vk::Extent2D
+	{
+		self.extent
+	}
+
+	/// Queues the readback of the swapchain image, after the composite pass
+	/// has left it ready to present. The image it's blitted through is
+	/// retired once it's recorded.
+	pub unsafe fn record(
+		&self,
+		instance: &Instance,
+		device: &Device,
+		data: &mut AppData,
+		readbacks: &mut Readbacks,
+		command_buffer: vk::CommandBuffer,
+		frame: usize,
+		image_index: usize,
+		callback: Option<Box<dyn FnOnce(Vec<u8>)>>,
+		) -> Result<ReadbackId>
+	{
+		let extent = self.extent;
+		let (image, image_memory) = match self.blit_format
+		{
+			Some(format) => create_image(
+				instance,
+				device,
+				data,
+				extent.width,
+				extent.height,
+				1,
+				vk::SampleCountFlags::_1,
+				format,
+				vk::ImageTiling::OPTIMAL,
+				vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC,
+				vk::MemoryPropertyFlags::DEVICE_LOCAL,
+			)?,
+			None => (vk::Image::null(), Default::default()),
+		};
+
+		let source = data.swapchain_images[image_index];
+		let size = (extent.width * extent.height * 4) as u64;
+		let id = readbacks.record(instance, device, data, command_buffer, frame, size, |command_buffer, buffer|
+			record_copy(device, data, command_buffer, source, image, extent, buffer), callback);
+
+		if !image.is_null()
+		{
+			data.deletion_queue.retire(Retired::Image(image, image_memory));
+		}
+
+		id
+	}
+
+	/// The bytes read back as opaque RGBA8.
+	pub fn to_rgba(&self, mut pixels: Vec<u8>) -> Vec<u8>
+	{
+		for texel in pixels.chunks_exact_mut(4)
+		{
+			if self.bgra
+			{
+				texel.swap(0, 2);
+			}
+			// presented images are opaque whatever their alpha says
+			texel[3] = 255;
+		}
+
+		pixels
+	}
+}
+
+/// Records the copy of `source` into `buffer`, blitting it through `image`
+/// unless that's null.
+unsafe fn record_copy(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	source: vk::Image,
+	image: vk::Image,
+	extent: vk::Extent2D,
+	buffer: vk::Buffer,
+	)
+{
+	let layout = headless::final_layout(data);
+
+	// after the composite pass's writes
+	image_barrier(
+		device,
+		command_buffer,
+		source,
+		(layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+		(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+	);
+
+	let copy_source = if image.is_null()
+	{
+		source
+	}
+	else
+	{
+		image_barrier(
+			device,
+			command_buffer,
+			image,
+			(vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+			(vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()),
+			(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+		);
+
+		let corner = vk::Offset3D { x: extent.width as i32, y: extent.height as i32, z: 1 };
+		let region = vk::ImageBlit::builder()
+			.src_subresource(color_layers())
+			.src_offsets([vk::Offset3D::default(), corner])
+			.dst_subresource(color_layers())
+			.dst_offsets([vk::Offset3D::default(), corner]);
+
+		device.cmd_blit_image(
+			command_buffer,
+			source,
+			vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+			image,
+			vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+			&[region],
+			vk::Filter::NEAREST,
+		);
+
+		image_barrier(
+			device,
+			command_buffer,
+			image,
+			(vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+			(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+			(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+		);
+
+		image
+	};
+
+	let region = vk::BufferImageCopy::builder()
+		.buffer_offset(0)
+		.buffer_row_length(0)
+		.buffer_image_height(0)
+		.image_subresource(color_layers())
+		.image_offset(vk::Offset3D::default())
+		.image_extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 });
+
+	device.cmd_copy_image_to_buffer(command_buffer, copy_source, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, buffer, &[region]);
+
+	// back for presentation, which waits on the frame's semaphore anyway
+	image_barrier(
+		device,
+		command_buffer,
+		source,
+		(vk::ImageLayout::TRANSFER_SRC_OPTIMAL, layout),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::empty()),
+		(vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty()),
+	);
+}
+
+#[derive(Debug, Default)]
+pub struct Screenshots
+{
+	requested: bool,
+	// waiting to be read back, polled for once a frame
+	pending: Vec<(ReadbackId, SwapchainCopy, PathBuf)>,
+}
+
+impl Screenshots
+{
+	/// Takes one at the end of the next frame.
+	pub fn request(&mut self)
+	{
+		self.requested = true;
+	}
+
+	/// Queues the readback of the swapchain image if one was requested.
+	pub unsafe fn record(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &mut AppData,
+		readbacks: &mut Readbacks,
+		command_buffer: vk::CommandBuffer,
+		frame: usize,
+		image_index: usize,
+		) -> Result<()>
+	{
+		if !std::mem::take(&mut self.requested)
+		{
+			return Ok(());
+		}
+
+		let copy = SwapchainCopy::new(instance, data)?;
+		let id = copy.record(instance, device, data, readbacks, command_buffer, frame, image_index, None)?;
+
+		let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+		let path = PathBuf::from(CAPTURE_DIRECTORY).join(format!("screenshot-{}.png", stamp));
+		self.pending.push((id, copy, path));
+
+		Ok(())
+	}
+
+	/// Saves the screenshots that have been read back on jobs.
+	pub fn collect(&mut self, readbacks: &mut Readbacks, jobs: &Jobs)
+	{
+		for (pixels, copy, path) in self.take_ready(readbacks)
+		{
+			jobs.spawn(System::Encoding, move ||
+				{
+					match save(copy.extent(), copy.to_rgba(pixels), &path)
+					{
+						Ok(()) => info!("Saved {}", path.display()),
+						Err(e) => warn!("Failed to save screenshot: {}", e),
+					}
+				});
+		}
+	}
+
+	/// Saves whatever is still waiting, on this thread so it's done before
+	/// the app exits. The readbacks must have been flushed.
+	pub fn flush(&mut self, readbacks: &mut Readbacks)
+	{
+		for (pixels, copy, path) in self.take_ready(readbacks)
+		{
+			match save(copy.extent(), copy.to_rgba(pixels), &path)
+			{
+				Ok(()) => info!("Saved {}", path.display()),
+				Err(e) => warn!("Failed to save screenshot: {}", e),
+			}
+		}
+	}
+
+	fn take_ready(&mut self, readbacks: &mut Readbacks) -> Vec<(Vec<u8>, SwapchainCopy, PathBuf)>
+	{
+		let mut ready = Vec::new();
+		self.pending.retain(|(id, copy, path)|
+			{
+				match readbacks.poll(*id)
+				{
+					Some(pixels) =>
+					{
+						ready.push((pixels, *copy, path.clone()));
+						false
+					},
+					None => true,
+				}
+			});
+
+		ready
+	}
+}
+
+fn is_srgb(format: vk::Format) -> bool
+{
+	matches!(format, vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32)
+}
+
+unsafe fn supports_blit(instance: &Instance, data: &AppData, source: vk::Format, target: vk::Format) -> bool
+{
+	let features = |format| instance
+		.get_physical_device_format_properties(data.physical_device, format)
+		.optimal_tiling_features;
+
+	features(source).contains(vk::FormatFeatureFlags::BLIT_SRC)
+		&& features(target).contains(vk::FormatFeatureFlags::BLIT_DST)
+}
+
+/// Writes RGBA8 pixels from `SwapchainCopy::to_rgba` as a PNG.
+pub fn save(extent: vk::Extent2D, pixels: Vec<u8>, path: &Path) -> Result<()>
+{
+	if let Some(parent) = path.parent()
+	{
+		std::fs::create_dir_all(parent)?;
+	}
+
+	image::RgbaImage::from_raw(extent.width, extent.height, pixels)
+		.ok_or_else(|| anyhow!("screenshot is the wrong size"))?
+		.save(path)?;
+
+	Ok(())
+}
+
+fn color_layers() -> vk::ImageSubresourceLayers
+{
+	vk::ImageSubresourceLayers {
+		aspect_mask: vk::ImageAspectFlags::COLOR,
+		mip_level: 0,
+		base_array_layer: 0,
+		layer_count: 1,
+	}
+}
+
+unsafe fn image_barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	(old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+	(src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	(dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	)
+{
+	let subresource_range = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(old_layout)
+		.new_layout(new_layout)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(subresource_range)
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
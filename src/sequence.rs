@@ -0,0 +1,114 @@
+// Demo sequences
+//
+// A timeline of actions fired at set times after playback starts, for demo
+// reels and showcases that play the same way every time, e.g.
+//
+// (
+//     events: [
+//         (at: 0.0, action: CameraPath("media/camera_path.ron")),
+//         (at: 0.0, action: Caption(text: "Parallax occlusion mapping", seconds: 4.0)),
+//         (at: 4.0, action: Sun(hours: 17.0, speed: 0.5)),
+//         (at: 8.0, action: Scene("media/night.ron")),
+//         (at: 8.0, action: Models(3)),
+//         (at: 12.0, action: Stop),
+//     ],
+// )
+//
+// Events fire in time order however they're listed. The sequence only says
+// what happens when, the app carries each action out.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Action
+{
+	/// Loads a camera path and plays it from the start.
+	CameraPath(String),
+	/// Shown in the window title for a while.
+	Caption { text: String, seconds: f32 },
+	/// Sets the time of day, and how fast it moves on from there.
+	Sun { hours: f32, speed: f32 },
+	/// Replaces the scene, rebaking what depends on it.
+	Scene(String),
+	/// How many models are drawn.
+	Models(usize),
+	/// Ends playback, e.g. to hold the last shot for a while first.
+	Stop,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event
+{
+	/// Seconds after playback starts.
+	pub at: f32,
+	pub action: Action,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Sequence
+{
+	events: Vec<Event>,
+	#[serde(skip)]
+	// seconds since playback started, while it's playing
+	playback_time: Option<f32>,
+	// index of the first event that hasn't fired yet
+	#[serde(skip)]
+	next: usize,
+}
+
+impl Sequence
+{
+	pub fn load(path: &Path) -> Result<Self>
+	{
+		let contents = fs::read_to_string(path)?;
+		let mut sequence: Self = ron::from_str(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+		sequence.events.sort_by(|a, b| a.at.total_cmp(&b.at));
+		Ok(sequence)
+	}
+
+	pub fn is_playing(&self) -> bool
+	{
+		self.playback_time.is_some()
+	}
+
+	pub fn play(&mut self)
+	{
+		self.playback_time = Some(0.0);
+		self.next = 0;
+	}
+
+	pub fn stop(&mut self)
+	{
+		self.playback_time = None;
+	}
+
+	/// Advances playback by `dt` seconds and returns the actions that have
+	/// come due, in order. Playback stops on its own after the last one.
+	pub fn update(&mut self, dt: f32) -> Vec<Action>
+	{
+		let time = match &mut self.playback_time
+		{
+			Some(time) =>
+			{
+				*time += dt;
+				*time
+			},
+			None => return Vec::new(),
+		};
+
+		let due = self.events[self.next..].iter().take_while(|e| e.at <= time).count();
+		let actions = self.events[self.next..self.next + due].iter().map(|e| e.action.clone()).collect();
+		self.next += due;
+
+		if self.next == self.events.len()
+		{
+			self.playback_time = None;
+		}
+
+		actions
+	}
+}
@@ -0,0 +1,466 @@
+// Shadow map atlas
+//
+// Every spot light's shadow map, and each face of every point light's, is a
+// square tile of one large depth image rather than a render target of its
+// own. All of them render in a single pass, switching viewports instead of
+// framebuffers, and the scene samples them through one descriptor with the
+// comparison sampler.
+//
+// Tiles are handed out by a buddy allocator: the atlas splits into
+// quarters, and those into quarters, down to the smallest tile size. Lights
+// ask for a size each frame, largest first, and take a smaller tile when
+// the atlas is too full for the one they wanted, so the most important
+// lights keep their resolution.
+//
+// The pass draws the frame's objects once per shadow view, with a
+// position-only pipeline and the light's view-projection pushed in place of
+// the model constants. Each light's depth and slope bias are set per view,
+// the shader does the rest of its settings when sampling. The atlas outlives the swapchain, but its pipeline
+// is made with the scene's pipeline layout so it's rebuilt with the others.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator;
+use crate::lights::ShadowView;
+use crate::push_constants::cmd_push_constants;
+use crate::stats::FrameStats;
+use crate::{
+	begin_single_time_commands,
+	create_image,
+	create_image_view,
+	create_shader_module,
+	end_single_time_commands,
+	first_object_index,
+	get_depth_format,
+	uniform_offset,
+	AppData,
+};
+
+pub const SHADOW_ATLAS_SIZE: u32 = 4096;
+pub const MIN_SHADOW_TILE_SIZE: u32 = 128;
+
+/// A square region of the atlas, in texels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ShadowTile
+{
+	pub x: u32,
+	pub y: u32,
+	pub size: u32,
+}
+
+impl ShadowTile
+{
+	/// The viewport to render the light's view into.
+	pub fn viewport(self) -> vk::Viewport
+	{
+		vk::Viewport::builder()
+			.x(self.x as f32)
+			.y(self.y as f32)
+			.width(self.size as f32)
+			.height(self.size as f32)
+			.min_depth(0.0)
+			.max_depth(1.0)
+			.build()
+	}
+
+	pub fn scissor(self) -> vk::Rect2D
+	{
+		vk::Rect2D::builder()
+			.offset(vk::Offset2D { x: self.x as i32, y: self.y as i32 })
+			.extent(vk::Extent2D { width: self.size, height: self.size })
+			.build()
+	}
+
+	/// Offset and scale taking the light's [0, 1] shadow coordinates into
+	/// the atlas, for the shader.
+	pub fn uv_transform(self) -> [f32; 4]
+	{
+		let atlas = SHADOW_ATLAS_SIZE as f32;
+		[self.x as f32 / atlas, self.y as f32 / atlas, self.size as f32 / atlas, self.size as f32 / atlas]
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct ShadowAtlas
+{
+	/// The free tiles' corners, by level. Level 0 is the whole atlas, each
+	/// level after halves the tile size.
+	free: Vec<Vec<(u32, u32)>>,
+}
+
+impl Default for ShadowAtlas
+{
+	fn default() -> Self
+	{
+		let levels = (SHADOW_ATLAS_SIZE / MIN_SHADOW_TILE_SIZE).trailing_zeros() as usize + 1;
+		let mut free = vec![vec![]; levels];
+		free[0].push((0, 0));
+		Self { free }
+	}
+}
+
+impl ShadowAtlas
+{
+	fn level_size(level: usize) -> u32
+	{
+		SHADOW_ATLAS_SIZE >> level
+	}
+
+	/// The level whose tiles are at least `size`, clamped to the tile sizes
+	/// the atlas has.
+	fn level_for(&self, size: u32) -> usize
+	{
+		let size = size.clamp(MIN_SHADOW_TILE_SIZE, SHADOW_ATLAS_SIZE).next_power_of_two();
+		(SHADOW_ATLAS_SIZE / size).trailing_zeros() as usize
+	}
+
+	/// A tile of the size rounded up to a power of two, if there's room.
+	pub fn allocate(&mut self, size: u32) -> Option<ShadowTile>
+	{
+		let level = self.level_for(size);
+
+		// the smallest free tile that's big enough, split down to size
+		let parent = (0..=level).rev().find(|&l| !self.free[l].is_empty())?;
+		let (x, y) = self.free[parent].pop()?;
+		for l in parent + 1..=level
+		{
+			let half = Self::level_size(l);
+			self.free[l].extend([(x + half, y), (x, y + half), (x + half, y + half)]);
+		}
+
+		Some(ShadowTile { x, y, size: Self::level_size(level) })
+	}
+
+	/// Returns the tile, merging it back into larger ones where its
+	/// siblings are free too.
+	pub fn free(&mut self, tile: ShadowTile)
+	{
+		let (mut x, mut y) = (tile.x, tile.y);
+		let mut level = self.level_for(tile.size);
+
+		while level > 0
+		{
+			let parent_size = Self::level_size(level - 1);
+			let (px, py) = (x - x % parent_size, y - y % parent_size);
+			let half = Self::level_size(level);
+			let siblings = [(px, py), (px + half, py), (px, py + half), (px + half, py + half)];
+
+			let free = &mut self.free[level];
+			let all_free = siblings.iter().all(|s| *s == (x, y) || free.contains(s));
+			if !all_free
+			{
+				break;
+			}
+
+			free.retain(|s| !siblings.contains(s));
+			(x, y) = (px, py);
+			level -= 1;
+		}
+
+		self.free[level].push((x, y));
+	}
+
+	/// Frees everything and hands out a tile for each requested size,
+	/// largest first. Requests that don't fit fall back to smaller tiles,
+	/// and get none once even the smallest is gone.
+	pub fn allocate_all(&mut self, sizes: &[u32]) -> Vec<Option<ShadowTile>>
+	{
+		*self = Self::default();
+
+		let mut order = (0..sizes.len()).collect::<Vec<_>>();
+		order.sort_by_key(|&i| std::cmp::Reverse(sizes[i]));
+
+		let mut tiles = vec![None; sizes.len()];
+		for i in order
+		{
+			let mut size = sizes[i].clamp(MIN_SHADOW_TILE_SIZE, SHADOW_ATLAS_SIZE).next_power_of_two();
+			while tiles[i].is_none() && size >= MIN_SHADOW_TILE_SIZE
+			{
+				tiles[i] = self.allocate(size);
+				size /= 2;
+			}
+		}
+
+		tiles
+	}
+}
+
+/// The atlas's depth image, rendered to by the shadow pass and sampled
+/// with `CommonSampler::ShadowCompare`. It's cleared once up front so the
+/// scene can sample it before any light has rendered into it.
+pub unsafe fn create_shadow_atlas(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let format = get_depth_format(instance, data)?;
+
+	let (image, memory) = create_image(
+		instance,
+		device,
+		data,
+		SHADOW_ATLAS_SIZE,
+		SHADOW_ATLAS_SIZE,
+		1,
+		vk::SampleCountFlags::_1,
+		format,
+		vk::ImageTiling::OPTIMAL,
+		vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL,
+	)?;
+
+	data.shadow_atlas_image = image;
+	data.shadow_atlas_image_memory = memory;
+	data.shadow_atlas_image_view = create_image_view(device, image, format, vk::ImageAspectFlags::DEPTH, 1)?;
+	data.shadow_atlas = ShadowAtlas::default();
+
+	create_shadow_render_pass(device, data, format)?;
+
+	let attachments = &[data.shadow_atlas_image_view];
+	let info = vk::FramebufferCreateInfo::builder()
+		.render_pass(data.shadow_render_pass)
+		.attachments(attachments)
+		.width(SHADOW_ATLAS_SIZE)
+		.height(SHADOW_ATLAS_SIZE)
+		.layers(1);
+
+	data.shadow_framebuffer = device.create_framebuffer(&info, None)?;
+
+	let command_buffer = begin_single_time_commands(device, data, data.graphics_command_pool)?;
+	begin_shadow_pass(device, data, command_buffer);
+	device.cmd_end_render_pass(command_buffer);
+	end_single_time_commands(device, data, command_buffer, data.graphics_queue, data.graphics_command_pool)?;
+
+	Ok(())
+}
+
+unsafe fn create_shadow_render_pass(
+	device: &Device,
+	data: &mut AppData,
+	format: vk::Format,
+	) -> Result<()>
+{
+	// tiles are reallocated every frame, so nothing is kept from the last
+	let depth_attachment = vk::AttachmentDescription::builder()
+		.format(format)
+		.samples(vk::SampleCountFlags::_1)
+		.load_op(vk::AttachmentLoadOp::CLEAR)
+		.store_op(vk::AttachmentStoreOp::STORE)
+		.stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+		.stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+		.initial_layout(vk::ImageLayout::UNDEFINED)
+		.final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+	let depth_attachment_ref = vk::AttachmentReference::builder()
+		.attachment(0)
+		.layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+	let subpass = vk::SubpassDescription::builder()
+		.pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+		.depth_stencil_attachment(&depth_attachment_ref);
+
+	// the last frame's scene has to be done sampling it before it's cleared
+	let dependency = vk::SubpassDependency::builder()
+		.src_subpass(vk::SUBPASS_EXTERNAL)
+		.dst_subpass(0)
+		.src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+		.src_access_mask(vk::AccessFlags::empty())
+		.dst_stage_mask(vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+		.dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+
+	// and this frame's can only sample it once every view is written
+	let sample_dependency = vk::SubpassDependency::builder()
+		.src_subpass(0)
+		.dst_subpass(vk::SUBPASS_EXTERNAL)
+		.src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+		.src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+		.dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+		.dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+	let attachments = &[depth_attachment];
+	let subpasses = &[subpass];
+	let dependencies = &[dependency, sample_dependency];
+	let info = vk::RenderPassCreateInfo::builder()
+		.attachments(attachments)
+		.subpasses(subpasses)
+		.dependencies(dependencies);
+
+	data.shadow_render_pass = device.create_render_pass(&info, None)?;
+
+	Ok(())
+}
+
+/// The depth-only pipeline drawing the objects from a light, with each
+/// view's tile set as the viewport and scissor.
+pub unsafe fn create_shadow_pipeline(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let vert = include_bytes!(concat!(env!("OUT_DIR"), "/shadow_vert.spv"));
+	let vert_sm = create_shader_module(device, vert)?;
+
+	let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::VERTEX)
+		.module(vert_sm)
+		.name(b"main\0");
+
+	let binding_descriptions = &[data.vertex_layout.position_binding_description(data.vertex_streams)];
+	let attribute_descriptions = &[data.vertex_layout.position_attribute_description()];
+	let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+		.vertex_binding_descriptions(binding_descriptions)
+		.vertex_attribute_descriptions(attribute_descriptions);
+
+	let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+		.topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+		.primitive_restart_enable(false);
+
+	// set per view, there's just the count here
+	let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+		.viewport_count(1)
+		.scissor_count(1);
+
+	let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR, vk::DynamicState::DEPTH_BIAS];
+	let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+		.dynamic_states(dynamic_states);
+
+	// both sides cast shadows, so single sided geometry doesn't let light
+	// through from behind
+	let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+		.depth_clamp_enable(false)
+		.rasterizer_discard_enable(false)
+		.polygon_mode(vk::PolygonMode::FILL)
+		.line_width(1.0)
+		.cull_mode(vk::CullModeFlags::NONE)
+		.front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+		.depth_bias_enable(true);
+
+	let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+		.sample_shading_enable(false)
+		.rasterization_samples(vk::SampleCountFlags::_1);
+
+	let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+		.logic_op_enable(false);
+
+	let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+		.depth_test_enable(true)
+		.depth_write_enable(true)
+		.depth_compare_op(vk::CompareOp::LESS)
+		.depth_bounds_test_enable(false)
+		.stencil_test_enable(false);
+
+	let stages = &[vert_stage];
+	let info = vk::GraphicsPipelineCreateInfo::builder()
+		.stages(stages)
+		.vertex_input_state(&vertex_input_state)
+		.input_assembly_state(&input_assembly_state)
+		.viewport_state(&viewport_state)
+		.rasterization_state(&rasterization_state)
+		.multisample_state(&multisample_state)
+		.depth_stencil_state(&depth_stencil_state)
+		.color_blend_state(&color_blend_state)
+		.dynamic_state(&dynamic_state)
+		.layout(data.pipeline_layout)
+		.render_pass(data.shadow_render_pass)
+		.subpass(0);
+
+	data.shadow_pipeline = device.create_graphics_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(vert_sm, None);
+
+	Ok(())
+}
+
+/// Begins the pass over the whole atlas, clearing it.
+unsafe fn begin_shadow_pass(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer)
+{
+	let render_area = vk::Rect2D::builder()
+		.offset(vk::Offset2D::default())
+		.extent(vk::Extent2D { width: SHADOW_ATLAS_SIZE, height: SHADOW_ATLAS_SIZE });
+
+	let clear_values = &[vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } }];
+	let info = vk::RenderPassBeginInfo::builder()
+		.render_pass(data.shadow_render_pass)
+		.framebuffer(data.shadow_framebuffer)
+		.render_area(render_area)
+		.clear_values(clear_values);
+
+	device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+	data.layouts.transition(data.shadow_atlas_image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+}
+
+/// Renders the first `object_count` of the frame's objects into the tile
+/// of every shadow view that got one. Skipped when none did, leaving the
+/// last frame's shadows, which nothing looks up.
+pub unsafe fn record_shadow_pass(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	image_index: usize,
+	views: &[ShadowView],
+	object_count: u32,
+	stats: &mut FrameStats,
+	)
+{
+	if views.iter().all(|view| view.tile.is_none())
+	{
+		return;
+	}
+
+	begin_shadow_pass(device, data, command_buffer);
+
+	device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.shadow_pipeline);
+	stats.pipeline_binds += 1;
+	// the positions are first in the buffer whether they're split out or not
+	device.cmd_bind_vertex_buffers(command_buffer, 0, &[data.vertex_buffer], &data.vertex_offsets[..1]);
+	device.cmd_bind_index_buffer(command_buffer, data.index_buffer, 0, data.index_type);
+	device.cmd_bind_descriptor_sets(
+		command_buffer,
+		vk::PipelineBindPoint::GRAPHICS,
+		data.pipeline_layout,
+		0,
+		&[data.descriptor_set],
+		&[uniform_offset(data, image_index)]);
+	stats.descriptor_binds += 1;
+
+	for view in views
+	{
+		let Some(tile) = view.tile else
+		{
+			continue;
+		};
+
+		device.cmd_set_viewport(command_buffer, 0, &[tile.viewport()]);
+		device.cmd_set_scissor(command_buffer, 0, &[tile.scissor()]);
+		device.cmd_set_depth_bias(command_buffer, view.settings.depth_bias, 0.0, view.settings.slope_bias);
+		// only a mat4 of the layout's range, shadow.vert has no other constants
+		cmd_push_constants(device, command_buffer, data.pipeline_layout, vk::ShaderStageFlags::VERTEX, &view.view_proj);
+
+		device.cmd_draw_indexed(
+			command_buffer,
+			data.indices.len() as u32,
+			object_count,
+			0,
+			0,
+			first_object_index(image_index),
+		);
+		stats.record_draw_indexed(data.indices.len() as u32, object_count);
+	}
+
+	device.cmd_end_render_pass(command_buffer);
+}
+
+pub unsafe fn destroy_shadow_atlas(device: &Device, data: &AppData)
+{
+	device.destroy_framebuffer(data.shadow_framebuffer, None);
+	device.destroy_render_pass(data.shadow_render_pass, None);
+	device.destroy_image_view(data.shadow_atlas_image_view, None);
+	device.destroy_image(data.shadow_atlas_image, None);
+	allocator::free(device, data.shadow_atlas_image_memory);
+}
@@ -0,0 +1,240 @@
+// Sky and volumetric clouds
+//
+// A fullscreen triangle at the far plane fills every pixel the scene left
+// empty with a sky gradient, then ray-marches a layer of clouds through the
+// generated 3D noise, lit by the sun wherever the time of day has put it.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+use crate::time_of_day::TimeOfDay;
+use crate::{create_shader_module, uniform_offset, AppData};
+
+/// Fraction of the sky covered by clouds.
+pub const CLOUD_COVERAGE: f32 = 0.5;
+
+/// Must match the push constants of `sky.frag`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct SkyPushConstants
+{
+	sun_direction: [f32; 3],
+	coverage: f32,
+	sun_color: [f32; 3],
+	sky_brightness: f32,
+}
+
+/// Sampler and descriptor set for the cloud noise. These don't depend on
+/// the swapchain.
+pub unsafe fn create_sky_objects(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let info = vk::SamplerCreateInfo::builder()
+		.mag_filter(vk::Filter::LINEAR)
+		.min_filter(vk::Filter::LINEAR)
+		.address_mode_u(vk::SamplerAddressMode::REPEAT)
+		.address_mode_v(vk::SamplerAddressMode::REPEAT)
+		.address_mode_w(vk::SamplerAddressMode::REPEAT)
+		.mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+		.max_lod(0.0);
+
+	data.sky_sampler = device.create_sampler(&info, None)?;
+
+	let binding = vk::DescriptorSetLayoutBinding::builder()
+		.binding(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.descriptor_count(1)
+		.stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+	let bindings = &[binding];
+	let info = vk::DescriptorSetLayoutCreateInfo::builder()
+		.bindings(bindings);
+
+	data.sky_descriptor_set_layout = data.descriptors.layout(device, &info)?;
+
+	data.sky_descriptor_set = data.descriptors.persistent.allocate(device, &[data.sky_descriptor_set_layout])?[0];
+
+	data.layouts.expect(
+		data.noise_textures["clouds"].image,
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+		"cloud noise",
+	);
+
+	let image_info = vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(data.noise_textures["clouds"].view)
+		.sampler(data.sky_sampler);
+
+	let image_infos = &[image_info];
+	let write = vk::WriteDescriptorSet::builder()
+		.dst_set(data.sky_descriptor_set)
+		.dst_binding(0)
+		.dst_array_element(0)
+		.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+		.image_info(image_infos);
+
+	device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+	// the frame's descriptor set provides the camera, the second one the noise
+	let push_constant_range = push_constant_range::<SkyPushConstants>(vk::ShaderStageFlags::FRAGMENT);
+
+	let set_layouts = &[data.descriptor_set_layout, data.sky_descriptor_set_layout];
+	let push_constant_ranges = &[push_constant_range];
+	let info = vk::PipelineLayoutCreateInfo::builder()
+		.set_layouts(set_layouts)
+		.push_constant_ranges(push_constant_ranges);
+
+	data.sky_pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+	Ok(())
+}
+
+pub unsafe fn create_sky_pipeline(
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	let vert = include_bytes!(concat!(env!("OUT_DIR"), "/composite_vert.spv"));
+	let frag = include_bytes!(concat!(env!("OUT_DIR"), "/sky_frag.spv"));
+
+	let vert_sm = create_shader_module(device, vert)?;
+	let frag_sm = create_shader_module(device, frag)?;
+
+	let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::VERTEX)
+		.module(vert_sm)
+		.name(b"main\0");
+
+	let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::FRAGMENT)
+		.module(frag_sm)
+		.name(b"main\0");
+
+	let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+	let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+		.topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+		.primitive_restart_enable(false);
+
+	// pinning the depth range to 1 puts the triangle on the far plane
+	let viewport = vk::Viewport::builder()
+		.x(0.0)
+		.y(0.0)
+		.width(data.render_extent.width as f32)
+		.height(data.render_extent.height as f32)
+		.min_depth(1.0)
+		.max_depth(1.0);
+
+	let scissor = vk::Rect2D::builder()
+		.offset(vk::Offset2D { x: 0, y: 0 })
+		.extent(data.render_extent);
+
+	let viewports = &[viewport];
+	let scissors = &[scissor];
+	let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+		.viewports(viewports)
+		.scissors(scissors);
+
+	let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+		.depth_clamp_enable(false)
+		.rasterizer_discard_enable(false)
+		.polygon_mode(vk::PolygonMode::FILL)
+		.line_width(1.0)
+		.cull_mode(vk::CullModeFlags::NONE)
+		.front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+		.depth_bias_enable(false);
+
+	let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+		.sample_shading_enable(false)
+		.rasterization_samples(data.msaa_samples);
+
+	let attachment = vk::PipelineColorBlendAttachmentState::builder()
+		.color_write_mask(vk::ColorComponentFlags::all())
+		.blend_enable(false);
+	let attachments = &[attachment];
+	let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+		.logic_op_enable(false)
+		.attachments(attachments);
+
+	// only passes where nothing has been drawn yet
+	let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+		.depth_test_enable(true)
+		.depth_write_enable(false)
+		.depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+		.depth_bounds_test_enable(false)
+		.stencil_test_enable(false);
+
+	let stages = &[vert_stage, frag_stage];
+
+	let info = vk::GraphicsPipelineCreateInfo::builder()
+		.stages(stages)
+		.vertex_input_state(&vertex_input_state)
+		.input_assembly_state(&input_assembly_state)
+		.viewport_state(&viewport_state)
+		.rasterization_state(&rasterization_state)
+		.multisample_state(&multisample_state)
+		.depth_stencil_state(&depth_stencil_state)
+		.color_blend_state(&color_blend_state)
+		.layout(data.sky_pipeline_layout)
+		.render_pass(data.render_pass)
+		.subpass(0);
+
+	data.sky_pipeline = device.create_graphics_pipelines(
+		data.pipeline_cache,
+		&[info],
+		None
+		)?.0[0];
+
+	device.destroy_shader_module(vert_sm, None);
+	device.destroy_shader_module(frag_sm, None);
+
+	Ok(())
+}
+
+/// Records the sky into a secondary command buffer inside the scene pass,
+/// after everything that writes depth.
+pub unsafe fn record_sky(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	image_index: usize,
+	time_of_day: &TimeOfDay,
+	)
+{
+	device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.sky_pipeline);
+	device.cmd_bind_descriptor_sets(
+		command_buffer,
+		vk::PipelineBindPoint::GRAPHICS,
+		data.sky_pipeline_layout,
+		0,
+		&[data.descriptor_set, data.sky_descriptor_set],
+		&[uniform_offset(data, image_index)]);
+
+	let sun = time_of_day.sun_direction();
+	let sun_color = time_of_day.sun_color();
+	let push_constants = SkyPushConstants {
+		sun_direction: [sun.x, sun.y, sun.z],
+		coverage: CLOUD_COVERAGE,
+		sun_color: [sun_color.x, sun_color.y, sun_color.z],
+		sky_brightness: time_of_day.sky_brightness(),
+	};
+
+	cmd_push_constants(
+		device,
+		command_buffer,
+		data.sky_pipeline_layout,
+		vk::ShaderStageFlags::FRAGMENT,
+		&push_constants,
+	);
+
+	device.cmd_draw(command_buffer, 3, 1, 0, 0);
+}
+
+pub unsafe fn destroy_sky_objects(device: &Device, data: &AppData)
+{
+	device.destroy_pipeline_layout(data.sky_pipeline_layout, None);
+	device.destroy_sampler(data.sky_sampler, None);
+}
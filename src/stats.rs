@@ -0,0 +1,51 @@
+// Per-frame scene statistics
+//
+// Counters are accumulated while command buffers are recorded, so they
+// describe exactly what was submitted for the frame.
+
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats
+{
+	pub draw_calls: u32,
+	pub triangles: u64,
+	pub instances: u32,
+	pub pipeline_binds: u32,
+	pub descriptor_binds: u32,
+	pub barriers: u32,
+	/// Objects skipped by frustum culling.
+	pub culled: u32,
+	/// Pipeline and descriptor binds avoided by batching opaque draws that
+	/// share state.
+	pub binds_saved: u32,
+}
+
+impl FrameStats
+{
+	pub fn record_draw_indexed(&mut self, index_count: u32, instance_count: u32)
+	{
+		self.draw_calls += 1;
+		self.instances += instance_count;
+		self.triangles += (index_count / 3) as u64 * instance_count as u64;
+	}
+}
+
+impl fmt::Display for FrameStats
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(
+			f,
+			"{} draws, {} tris, {} instances, {} pipeline binds, {} descriptor binds ({} saved by sorting), {} barriers, {} culled",
+			self.draw_calls,
+			self.triangles,
+			self.instances,
+			self.pipeline_binds,
+			self.descriptor_binds,
+			self.binds_saved,
+			self.barriers,
+			self.culled,
+		)
+	}
+}
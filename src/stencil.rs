@@ -0,0 +1,62 @@
+// Stencil state
+//
+// The scene's depth buffer carries 8 bits of stencil, cleared to zero at
+// the start of every frame. Pipelines mark the pixels they cover with a
+// reference value or only draw where it is, or isn't, marked, which is all
+// masked rendering like outlines and portals needs.
+
+use vulkanalia::prelude::v1_0::*;
+
+/// Set by the shaded models, so their outline only shows around them.
+pub const OUTLINE_REFERENCE: u32 = 1;
+
+/// Set by each portal of a pair, so the view through it only shows inside
+/// its frame.
+pub const PORTAL_REFERENCES: [u32; 2] = [2, 3];
+
+/// How much bigger an outline's shell is than the model it surrounds.
+pub const OUTLINE_SCALE: f32 = 1.05;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StencilMode
+{
+	#[default]
+	Disabled,
+	/// Writes the reference wherever the pipeline draws.
+	Mark(u32),
+	/// Only draws where the reference was written, e.g. the view through a
+	/// portal's frame.
+	Inside(u32),
+	/// Only draws where it wasn't, e.g. an outline around a marked model.
+	Outside(u32),
+}
+
+impl StencilMode
+{
+	pub fn enabled(self) -> bool
+	{
+		self != StencilMode::Disabled
+	}
+
+	/// The same for front and back faces.
+	pub fn op_state(self) -> vk::StencilOpState
+	{
+		let (compare_op, pass_op, reference) = match self
+		{
+			StencilMode::Disabled => (vk::CompareOp::ALWAYS, vk::StencilOp::KEEP, 0),
+			StencilMode::Mark(reference) => (vk::CompareOp::ALWAYS, vk::StencilOp::REPLACE, reference),
+			StencilMode::Inside(reference) => (vk::CompareOp::EQUAL, vk::StencilOp::KEEP, reference),
+			StencilMode::Outside(reference) => (vk::CompareOp::NOT_EQUAL, vk::StencilOp::KEEP, reference),
+		};
+
+		vk::StencilOpState::builder()
+			.fail_op(vk::StencilOp::KEEP)
+			.pass_op(pass_op)
+			.depth_fail_op(vk::StencilOp::KEEP)
+			.compare_op(compare_op)
+			.compare_mask(0xff)
+			.write_mask(0xff)
+			.reference(reference)
+			.build()
+	}
+}
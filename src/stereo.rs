@@ -0,0 +1,359 @@
+// Stereo output
+//
+// For trying 3D out on an ordinary display, the scene can be rendered once
+// per eye and the composite pass shows the two side by side, for 3D TVs and
+// cross-eyed or cardboard viewing, or as a red/cyan anaglyph for glasses.
+// H cycles through the modes, or `--stereo side-by-side|anaglyph` starts in
+// one.
+//
+// The eyes are rendered one after the other with the same secondary command
+// buffers. Before each eye's passes the view, projection and camera position
+// in the frame's uniforms are replaced with the eye's, and the left eye's
+// scene color is copied aside before the right eye overwrites it. Everything
+// else, bloom, the depth target and the simulations, is only done once, from
+// the right eye's view. Both eyes look parallel with their projections
+// shifted so the camera's target is at screen depth, anything nearer comes
+// out of the screen.
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use std::mem::{offset_of, size_of};
+
+use crate::allocator;
+use crate::camera::{Camera, Projection};
+use crate::composite::SCENE_FORMAT;
+use crate::stats::FrameStats;
+use crate::{create_image, create_image_view, uniform_offset, AppData, UniformBufferObject};
+
+/// Distance between the eyes, in world units the scene treats as meters.
+const EYE_SEPARATION: f32 = 0.065;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StereoMode
+{
+	#[default]
+	Off,
+	SideBySide,
+	Anaglyph,
+}
+
+/// Which view a pass of the scene is rendered from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Eye
+{
+	/// The camera's own, when not in stereo.
+	Center,
+	Left,
+	Right,
+}
+
+impl StereoMode
+{
+	pub fn from_name(name: &str) -> Option<Self>
+	{
+		match name
+		{
+			"side-by-side" => Some(StereoMode::SideBySide),
+			"anaglyph" => Some(StereoMode::Anaglyph),
+			_ => None,
+		}
+	}
+
+	pub fn next(self) -> Self
+	{
+		match self
+		{
+			StereoMode::Off => StereoMode::SideBySide,
+			StereoMode::SideBySide => StereoMode::Anaglyph,
+			StereoMode::Anaglyph => StereoMode::Off,
+		}
+	}
+
+	/// Matches the constants in `composite.frag`.
+	pub fn shader_index(self) -> i32
+	{
+		self as i32
+	}
+
+	/// The eyes the scene is rendered for, in order. The last is the one
+	/// left in the scene targets.
+	pub fn eyes(self) -> &'static [Eye]
+	{
+		match self
+		{
+			StereoMode::Off => &[Eye::Center],
+			_ => &[Eye::Left, Eye::Right],
+		}
+	}
+}
+
+/// The view, projection and position the eye sees `camera` from.
+pub fn eye_matrices(camera: &Camera, aspect: f32, eye: Eye) -> (glm::Mat4, glm::Mat4, glm::Vec3)
+{
+	let mut view = camera.view();
+	let mut proj = camera.proj(aspect);
+
+	let offset = match eye
+	{
+		Eye::Center => return (view, proj, camera.eye),
+		Eye::Left => -EYE_SEPARATION / 2.0,
+		Eye::Right => EYE_SEPARATION / 2.0,
+	};
+
+	// moving the eye right moves everything it sees left
+	view = glm::translation(&glm::vec3(-offset, 0.0, 0.0)) * view;
+
+	// an orthographic camera has no parallax to converge
+	if camera.projection == Projection::Perspective
+	{
+		let convergence = glm::distance(&camera.eye, &camera.target).max(camera.near);
+		proj[(0, 2)] -= proj[(0, 0)] * offset / convergence;
+	}
+
+	let position = glm::inverse(&view) * glm::vec4(0.0, 0.0, 0.0, 1.0);
+	(view, proj, position.xyz())
+}
+
+/// Replaces the eye dependent uniforms in the swapchain image's slice of
+/// the uniform buffer. Has to be recorded outside of a render pass.
+pub unsafe fn record_eye_uniforms(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	image_index: usize,
+	(view, proj, position): (glm::Mat4, glm::Mat4, glm::Vec3),
+	stats: &mut FrameStats,
+	)
+{
+	let offset = uniform_offset(data, image_index) as u64;
+
+	// the previous eye's passes may still be reading them
+	buffer_barrier(
+		device,
+		command_buffer,
+		data.uniform_buffer,
+		(vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::UNIFORM_READ),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+	);
+
+	// view and proj are the first two members
+	let matrices = [view, proj];
+	let position = [position.x, position.y, position.z, 1.0];
+	device.cmd_update_buffer(command_buffer, data.uniform_buffer, offset, as_bytes(&matrices));
+	device.cmd_update_buffer(
+		command_buffer,
+		data.uniform_buffer,
+		offset + offset_of!(UniformBufferObject, camera_position) as u64,
+		as_bytes(&position),
+	);
+
+	buffer_barrier(
+		device,
+		command_buffer,
+		data.uniform_buffer,
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+		(vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::UNIFORM_READ),
+	);
+	stats.barriers += 2;
+}
+
+/// Copies the left eye's finished scene color aside for the composite pass,
+/// before the right eye is rendered over it.
+pub unsafe fn record_copy_left_eye(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	stats: &mut FrameStats,
+	)
+{
+	// left in SHADER_READ_ONLY by whichever pass wrote it last
+	image_barrier(
+		device,
+		command_buffer,
+		data.scene_image,
+		(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+		(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+	);
+
+	// last frame's composite pass may still be sampling it
+	image_barrier(
+		device,
+		command_buffer,
+		data.stereo_image,
+		(vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+		(vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::empty()),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+	);
+
+	let layers = vk::ImageSubresourceLayers::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.mip_level(0)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let region = vk::ImageCopy::builder()
+		.src_subresource(layers)
+		.src_offset(vk::Offset3D::default())
+		.dst_subresource(layers)
+		.dst_offset(vk::Offset3D::default())
+		.extent(vk::Extent3D { width: data.render_extent.width, height: data.render_extent.height, depth: 1 });
+
+	device.cmd_copy_image(
+		command_buffer,
+		data.scene_image,
+		vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+		data.stereo_image,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		&[region],
+	);
+
+	image_barrier(
+		device,
+		command_buffer,
+		data.stereo_image,
+		(vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+		(vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+	);
+
+	// back where the right eye's scene pass expects it
+	image_barrier(
+		device,
+		command_buffer,
+		data.scene_image,
+		(vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::empty()),
+		(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+	);
+	stats.barriers += 4;
+}
+
+/// The view the composite pass samples the left eye from. Without stereo
+/// it's bound to the scene color so the set is always complete.
+pub fn left_eye_view(data: &AppData) -> vk::ImageView
+{
+	if data.stereo_image_view.is_null() { data.scene_image_view } else { data.stereo_image_view }
+}
+
+/// Creates the left eye's copy of the scene color, sized like the scene
+/// targets, only when rendering in stereo.
+pub unsafe fn create_stereo_objects(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	if data.stereo == StereoMode::Off
+	{
+		return Ok(());
+	}
+
+	let (image, memory) = create_image(
+		instance,
+		device,
+		data,
+		data.render_extent.width,
+		data.render_extent.height,
+		1,
+		vk::SampleCountFlags::_1,
+		SCENE_FORMAT,
+		vk::ImageTiling::OPTIMAL,
+		vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL,
+	)?;
+
+	data.stereo_image = image;
+	data.stereo_image_memory = memory;
+	data.stereo_image_view = create_image_view(device, image, SCENE_FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+
+	Ok(())
+}
+
+/// The mode may have changed since they were created, so this goes by what
+/// exists instead.
+pub unsafe fn destroy_stereo_objects(device: &Device, data: &mut AppData)
+{
+	if data.stereo_image.is_null()
+	{
+		return;
+	}
+
+	device.destroy_image_view(data.stereo_image_view, None);
+	device.destroy_image(data.stereo_image, None);
+	allocator::free(device, data.stereo_image_memory);
+	data.stereo_image = vk::Image::null();
+	data.stereo_image_view = vk::ImageView::null();
+}
+
+fn as_bytes<T>(values: &[T]) -> &[u8]
+{
+	unsafe { std::slice::from_raw_parts(values.as_ptr().cast(), size_of::<T>() * values.len()) }
+}
+
+unsafe fn buffer_barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	buffer: vk::Buffer,
+	(src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	(dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	)
+{
+	let barrier = vk::BufferMemoryBarrier::builder()
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.buffer(buffer)
+		.offset(0)
+		.size(vk::WHOLE_SIZE);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[barrier],
+		&[] as &[vk::ImageMemoryBarrier],
+	);
+}
+
+unsafe fn image_barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	(old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+	(src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	(dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	)
+{
+	let subresource_range = vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(old_layout)
+		.new_layout(new_layout)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(subresource_range)
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
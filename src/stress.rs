@@ -0,0 +1,130 @@
+// Stress testing
+//
+// `--stress resize` drives the window through hundreds of size changes,
+// minimizes and restores, and trips in and out of fullscreen, rendering a
+// few frames after each. `--stress recreate` leaves the window alone and
+// recreates the swapchain every few frames. Either way the recreation path
+// and everything torn down with it gets far more exercise than anyone
+// would give it by hand, with the validation layer watching. The app quits
+// once every step is done, after tearing everything down, and exits with a
+// failure if the layer reported a single error, or a warning about misusing
+// the API, along the way.
+
+use log::*;
+use winit::dpi::PhysicalSize;
+
+use crate::window_mode::WindowMode;
+
+const STEPS: usize = 400;
+// frames between steps, so the window system has caught up with the last one
+const SETTLE_FRAMES: u32 = 3;
+// odd sizes, slivers and a single pixel included
+const SIZES: &[(u32, u32)] = &[
+	(800, 600),
+	(1, 1),
+	(1920, 1080),
+	(333, 777),
+	(64, 2000),
+	(1024, 768),
+	(2560, 31),
+	(517, 513),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StressMode
+{
+	Resize,
+	Recreate,
+}
+
+impl StressMode
+{
+	pub fn from_name(name: &str) -> Option<Self>
+	{
+		match name
+		{
+			"resize" => Some(StressMode::Resize),
+			"recreate" => Some(StressMode::Recreate),
+			_ => None,
+		}
+	}
+}
+
+/// Something done to the window or swapchain between frames.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StressStep
+{
+	Resize(PhysicalSize<u32>),
+	Minimize,
+	Restore,
+	WindowMode(WindowMode),
+	Recreate,
+}
+
+#[derive(Clone, Debug)]
+pub struct StressTest
+{
+	mode: StressMode,
+	step: usize,
+	// frames left before the next step
+	settle: u32,
+}
+
+impl StressTest
+{
+	pub fn new(mode: StressMode) -> Self
+	{
+		info!("Stress test: {:?}, {} steps", mode, STEPS);
+		Self { mode, step: 0, settle: 0 }
+	}
+
+	pub fn is_done(&self) -> bool
+	{
+		self.step == STEPS && self.settle == 0
+	}
+
+	/// Called once a frame, returns the step to take if the last one has
+	/// settled.
+	pub fn next(&mut self) -> Option<StressStep>
+	{
+		if self.settle > 0 || self.step == STEPS
+		{
+			self.settle = self.settle.saturating_sub(1);
+			return None;
+		}
+
+		let step = match self.mode
+		{
+			StressMode::Resize => resize_step(self.step),
+			StressMode::Recreate => StressStep::Recreate,
+		};
+
+		self.step += 1;
+		self.settle = SETTLE_FRAMES;
+		if self.step % 50 == 0
+		{
+			info!("Stress test: {}/{} steps", self.step, STEPS);
+		}
+
+		Some(step)
+	}
+}
+
+// a resize most of the time, with a minimize and restore and a round trip
+// through both fullscreen modes in every ten steps
+fn resize_step(step: usize) -> StressStep
+{
+	match step % 10
+	{
+		3 => StressStep::Minimize,
+		4 => StressStep::Restore,
+		6 => StressStep::WindowMode(WindowMode::Borderless),
+		7 => StressStep::WindowMode(WindowMode::Exclusive),
+		8 => StressStep::WindowMode(WindowMode::Windowed),
+		_ =>
+		{
+			let (width, height) = SIZES[step % SIZES.len()];
+			StressStep::Resize(PhysicalSize::new(width, height))
+		},
+	}
+}
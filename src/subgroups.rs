@@ -0,0 +1,186 @@
+// Subgroup operations
+//
+// Where the device supports them, reductions and scans across a workgroup
+// use subgroup arithmetic instead of rounds of shared memory and barriers.
+// Shaders are compiled ahead of time, so each one that can use them is
+// built twice by build.rs, with and without the SUBGROUPS define, and the
+// permutation is picked here from what the device reports.
+//
+// `Reductions` sums or prefix sums a buffer of floats. Both run as a single
+// workgroup walking the whole buffer, which is plenty for the few thousand
+// values a histogram or a light list holds, not for millions.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::InstanceV1_1;
+use vulkanalia::Version;
+
+use crate::create_shader_module;
+use crate::push_constants::{cmd_push_constants, push_constant_range};
+
+/// The subgroups' size and what they can do, all empty before Vulkan 1.1.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SubgroupSupport
+{
+	pub size: u32,
+	pub stages: vk::ShaderStageFlags,
+	pub operations: vk::SubgroupFeatureFlags,
+}
+
+impl SubgroupSupport
+{
+	pub unsafe fn query(instance: &Instance, physical_device: vk::PhysicalDevice, api_version: Version) -> Self
+	{
+		if api_version < Version::new(1, 1, 0)
+		{
+			return Self::default();
+		}
+
+		let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+		let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut subgroup);
+		instance.get_physical_device_properties2(physical_device, &mut properties);
+
+		Self {
+			size: subgroup.subgroup_size,
+			stages: subgroup.supported_stages,
+			operations: subgroup.supported_operations,
+		}
+	}
+
+	/// Whether compute shaders can use the SUBGROUPS permutations, which
+	/// need at least 16 invocations per subgroup.
+	pub fn compute_arithmetic(&self) -> bool
+	{
+		self.stages.contains(vk::ShaderStageFlags::COMPUTE)
+			&& self.operations.contains(vk::SubgroupFeatureFlags::BASIC | vk::SubgroupFeatureFlags::ARITHMETIC)
+			&& self.size >= 16
+	}
+
+	/// The defines the permutations for this device were built with.
+	pub fn defines(&self) -> &'static [&'static str]
+	{
+		if self.compute_arithmetic() { &["SUBGROUPS"] } else { &[] }
+	}
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Reductions
+{
+	descriptor_set_layout: vk::DescriptorSetLayout,
+	pipeline_layout: vk::PipelineLayout,
+	reduce_pipeline: vk::Pipeline,
+	prefix_sum_pipeline: vk::Pipeline,
+}
+
+impl Reductions
+{
+	pub unsafe fn create(device: &Device, subgroups: &SubgroupSupport, pipeline_cache: vk::PipelineCache) -> Result<Self>
+	{
+		let bindings = [0, 1].map(|binding|
+			{
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(binding)
+					.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build()
+			});
+
+		let info = vk::DescriptorSetLayoutCreateInfo::builder()
+			.bindings(&bindings);
+
+		let descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+		let set_layouts = &[descriptor_set_layout];
+		let push_constant_ranges = &[push_constant_range::<u32>(vk::ShaderStageFlags::COMPUTE)];
+		let info = vk::PipelineLayoutCreateInfo::builder()
+			.set_layouts(set_layouts)
+			.push_constant_ranges(push_constant_ranges);
+
+		let pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+		let (reduce, prefix_sum): (&[u8], &[u8]) = if subgroups.compute_arithmetic()
+		{
+			(include_bytes!(concat!(env!("OUT_DIR"), "/reduce_subgroups_comp.spv")), include_bytes!(concat!(env!("OUT_DIR"), "/prefix_sum_subgroups_comp.spv")))
+		}
+		else
+		{
+			(include_bytes!(concat!(env!("OUT_DIR"), "/reduce_comp.spv")), include_bytes!(concat!(env!("OUT_DIR"), "/prefix_sum_comp.spv")))
+		};
+
+		Ok(Self {
+			descriptor_set_layout,
+			pipeline_layout,
+			reduce_pipeline: create_pipeline(device, pipeline_cache, pipeline_layout, reduce)?,
+			prefix_sum_pipeline: create_pipeline(device, pipeline_cache, pipeline_layout, prefix_sum)?,
+		})
+	}
+
+	pub unsafe fn destroy(&self, device: &Device)
+	{
+		device.destroy_pipeline(self.reduce_pipeline, None);
+		device.destroy_pipeline(self.prefix_sum_pipeline, None);
+		device.destroy_pipeline_layout(self.pipeline_layout, None);
+		device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+	}
+
+	/// The layout of the sets passed to the records, storage buffers with
+	/// the input floats in binding 0 and the results in binding 1.
+	pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout
+	{
+		self.descriptor_set_layout
+	}
+
+	/// Records summing the first `count` inputs into the first result.
+	pub unsafe fn record_reduce(&self, device: &Device, command_buffer: vk::CommandBuffer, set: vk::DescriptorSet, count: u32)
+	{
+		self.record(device, command_buffer, self.reduce_pipeline, set, count);
+	}
+
+	/// Records an exclusive prefix sum of the first `count` inputs, result
+	/// `i` being the sum of the inputs before `i`.
+	pub unsafe fn record_prefix_sum(&self, device: &Device, command_buffer: vk::CommandBuffer, set: vk::DescriptorSet, count: u32)
+	{
+		self.record(device, command_buffer, self.prefix_sum_pipeline, set, count);
+	}
+
+	unsafe fn record(
+		&self,
+		device: &Device,
+		command_buffer: vk::CommandBuffer,
+		pipeline: vk::Pipeline,
+		set: vk::DescriptorSet,
+		count: u32,
+		)
+	{
+		device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+		device.cmd_bind_descriptor_sets(
+			command_buffer,
+			vk::PipelineBindPoint::COMPUTE,
+			self.pipeline_layout,
+			0,
+			&[set],
+			&[]);
+		cmd_push_constants(device, command_buffer, self.pipeline_layout, vk::ShaderStageFlags::COMPUTE, &count);
+		device.cmd_dispatch(command_buffer, 1, 1, 1);
+	}
+}
+
+unsafe fn create_pipeline(device: &Device, pipeline_cache: vk::PipelineCache, layout: vk::PipelineLayout, comp: &[u8]) -> Result<vk::Pipeline>
+{
+	let comp_sm = create_shader_module(device, comp)?;
+
+	let stage = vk::PipelineShaderStageCreateInfo::builder()
+		.stage(vk::ShaderStageFlags::COMPUTE)
+		.module(comp_sm)
+		.name(b"main\0");
+
+	let info = vk::ComputePipelineCreateInfo::builder()
+		.stage(stage)
+		.layout(layout);
+
+	let pipeline = device.create_compute_pipelines(pipeline_cache, &[info], None)?.0[0];
+	device.destroy_shader_module(comp_sm, None);
+
+	Ok(pipeline)
+}
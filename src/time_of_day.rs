@@ -0,0 +1,105 @@
+// Time of day
+//
+// A clock that moves the sun along its arc over a day, rising in the east
+// at 6:00 and setting in the west at 18:00. Everything that depends on the
+// sun is derived from the clock each frame: its direction for the shading
+// and the sky, its color reddening towards the horizon, how bright the sky
+// is, and an exposure boost so nights stay readable.
+
+use nalgebra_glm as glm;
+
+const HOURS_PER_DAY: f32 = 24.0;
+/// How far the sun's arc is tilted up from the horizon, it's highest at
+/// noon.
+const MAX_ELEVATION: f32 = 1.05;
+const NOON_COLOR: [f32; 3] = [1.0, 0.95, 0.85];
+const HORIZON_COLOR: [f32; 3] = [1.0, 0.45, 0.2];
+const SUN_INTENSITY: f32 = 3.0;
+/// Exposure added in stops once the sun has fully set.
+const NIGHT_EXPOSURE: f32 = 2.0;
+/// Moonlight and stars, as a fraction of daylight.
+const NIGHT_SKY: f32 = 0.04;
+
+#[derive(Copy, Clone, Debug)]
+pub struct TimeOfDay
+{
+	/// Hours since midnight, from 0 up to 24.
+	pub hours: f32,
+	/// In-game hours per real second, 0 stops the clock.
+	pub speed: f32,
+}
+
+impl Default for TimeOfDay
+{
+	/// Mid-morning with the clock stopped, where the sun used to be fixed.
+	fn default() -> Self
+	{
+		Self { hours: 10.0, speed: 0.0 }
+	}
+}
+
+impl TimeOfDay
+{
+	pub fn update(&mut self, dt: f32)
+	{
+		self.hours = (self.hours + self.speed * dt).rem_euclid(HOURS_PER_DAY);
+	}
+
+	/// Multiplies the speed, starting the clock at an hour per second if
+	/// it's stopped.
+	pub fn scale_speed(&mut self, factor: f32)
+	{
+		self.speed = if self.speed == 0.0 { 1.0 } else { self.speed * factor };
+	}
+
+	/// Stops the clock, or starts it at an hour per second.
+	pub fn toggle_paused(&mut self)
+	{
+		self.speed = if self.speed == 0.0 { 1.0 } else { 0.0 };
+	}
+
+	/// Towards the sun, below the horizon at night.
+	pub fn sun_direction(&self) -> glm::Vec3
+	{
+		// 0 at sunrise, half a turn at sunset
+		let angle = (self.hours - 6.0) / HOURS_PER_DAY * std::f32::consts::TAU;
+		glm::normalize(&glm::vec3(
+			-angle.cos(),
+			angle.sin() * MAX_ELEVATION.cos(),
+			angle.sin() * MAX_ELEVATION.sin(),
+		))
+	}
+
+	/// How much of the day's light there is, 0 once the sun is well below
+	/// the horizon and 1 once it's well above.
+	pub fn daylight(&self) -> f32
+	{
+		smoothstep(-0.1, 0.15, self.sun_direction().z)
+	}
+
+	/// The sun's color times its brightness, reddening towards the horizon.
+	pub fn sun_color(&self) -> glm::Vec3
+	{
+		let elevation = self.sun_direction().z;
+		let color = glm::lerp(&glm::Vec3::from(HORIZON_COLOR), &glm::Vec3::from(NOON_COLOR), smoothstep(0.0, 0.5, elevation));
+		color * SUN_INTENSITY * self.daylight()
+	}
+
+	/// Brightness of the sky and its ambient light.
+	pub fn sky_brightness(&self) -> f32
+	{
+		self.daylight().max(NIGHT_SKY)
+	}
+
+	/// Stops of exposure to add to the final image.
+	pub fn exposure(&self) -> f32
+	{
+		(1.0 - self.daylight()) * NIGHT_EXPOSURE
+	}
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32
+{
+	let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+	t * t * (3.0 - 2.0 * t)
+}
@@ -0,0 +1,294 @@
+// Transmission
+//
+// Transmissive materials show the scene behind them bent by refraction, so
+// they can't be drawn along with everything else. When the material is
+// transmissive the models are left out of the scene pass, the resolved
+// scene color is copied into the refraction image, and the models are drawn
+// in a second pass over the same targets that samples the copy. The second
+// pass is compatible with the scene render pass, so it shares its
+// framebuffer, pipelines and secondary command buffers.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::allocator;
+use crate::composite::SCENE_FORMAT;
+use crate::stats::FrameStats;
+use crate::{
+	create_image,
+	create_image_view,
+	transition_image_layout,
+	AppData,
+};
+
+pub unsafe fn create_transmission_objects(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	) -> Result<()>
+{
+	// the scene pass already stored both, so they're drawn on top of
+	let color_attachment = vk::AttachmentDescription::builder()
+		.format(SCENE_FORMAT)
+		.samples(data.msaa_samples)
+		.load_op(vk::AttachmentLoadOp::LOAD)
+		.store_op(vk::AttachmentStoreOp::DONT_CARE)
+		.stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+		.stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+		.initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+		.final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+	let depth_stencil_attachment = vk::AttachmentDescription::builder()
+		.format(data.depth_format)
+		.samples(data.msaa_samples)
+		.load_op(vk::AttachmentLoadOp::LOAD)
+		.store_op(vk::AttachmentStoreOp::STORE)
+		.stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+		.stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+		.initial_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+		.final_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL);
+
+	// the resolve overwrites the whole scene color, which was copied out by then
+	let color_resolve_attachment = vk::AttachmentDescription::builder()
+		.format(SCENE_FORMAT)
+		.samples(vk::SampleCountFlags::_1)
+		.load_op(vk::AttachmentLoadOp::DONT_CARE)
+		.store_op(vk::AttachmentStoreOp::STORE)
+		.stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+		.stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+		.initial_layout(vk::ImageLayout::UNDEFINED)
+		.final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+	let color_attachment_ref = vk::AttachmentReference::builder()
+		.attachment(0)
+		.layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+	let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
+		.attachment(1)
+		.layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+	let color_resolve_attachment_ref = vk::AttachmentReference::builder()
+		.attachment(2)
+		.layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+	let color_attachments = &[color_attachment_ref];
+	let resolve_attachments = &[color_resolve_attachment_ref];
+	let subpass = vk::SubpassDescription::builder()
+		.pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+		.color_attachments(color_attachments)
+		.depth_stencil_attachment(&depth_stencil_attachment_ref)
+		.resolve_attachments(resolve_attachments);
+
+	// wait for the scene pass's writes and for the copy to finish reading
+	let dependency = vk::SubpassDependency::builder()
+		.src_subpass(vk::SUBPASS_EXTERNAL)
+		.dst_subpass(0)
+		.src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+			| vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+			| vk::PipelineStageFlags::TRANSFER)
+		.src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+			| vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+		.dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+			| vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+		.dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ
+			| vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+			| vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+			| vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+
+	// same as the scene pass, the bloom and composite passes read the results
+	let composite_dependency = vk::SubpassDependency::builder()
+		.src_subpass(0)
+		.dst_subpass(vk::SUBPASS_EXTERNAL)
+		.src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+			| vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+		.src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+			| vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+		.dst_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER
+			| vk::PipelineStageFlags::FRAGMENT_SHADER)
+		.dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+	let attachments = &[color_attachment, depth_stencil_attachment, color_resolve_attachment];
+	let subpasses = &[subpass];
+	let dependencies = &[dependency, composite_dependency];
+
+	let info = vk::RenderPassCreateInfo::builder()
+		.attachments(attachments)
+		.subpasses(subpasses)
+		.dependencies(dependencies);
+
+	data.transmission_render_pass = device.create_render_pass(&info, None)?;
+
+	let (refraction_image, refraction_image_memory) = create_image(
+		instance,
+		device,
+		data,
+		data.render_extent.width,
+		data.render_extent.height,
+		1,
+		vk::SampleCountFlags::_1,
+		SCENE_FORMAT,
+		vk::ImageTiling::OPTIMAL,
+		vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+		vk::MemoryPropertyFlags::DEVICE_LOCAL,
+	)?;
+
+	data.refraction_image = refraction_image;
+	data.refraction_image_memory = refraction_image_memory;
+	data.refraction_image_view = create_image_view(
+		device,
+		refraction_image,
+		SCENE_FORMAT,
+		vk::ImageAspectFlags::COLOR,
+		1,
+	)?;
+
+	// it's bound in every descriptor set, so it has to be sampleable from the start
+	transition_image_layout(
+		device,
+		data,
+		refraction_image,
+		SCENE_FORMAT,
+		vk::ImageLayout::UNDEFINED,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		1,
+	)?;
+	transition_image_layout(
+		device,
+		data,
+		refraction_image,
+		SCENE_FORMAT,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+		1,
+	)?;
+
+	Ok(())
+}
+
+fn color_range() -> vk::ImageSubresourceRange
+{
+	vk::ImageSubresourceRange::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.base_mip_level(0)
+		.level_count(1)
+		.base_array_layer(0)
+		.layer_count(1)
+		.build()
+}
+
+unsafe fn image_barrier(
+	device: &Device,
+	command_buffer: vk::CommandBuffer,
+	image: vk::Image,
+	(old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+	(src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	(dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+	)
+{
+	let barrier = vk::ImageMemoryBarrier::builder()
+		.old_layout(old_layout)
+		.new_layout(new_layout)
+		.src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+		.image(image)
+		.subresource_range(color_range())
+		.src_access_mask(src_access)
+		.dst_access_mask(dst_access);
+
+	device.cmd_pipeline_barrier(
+		command_buffer,
+		src_stage,
+		dst_stage,
+		vk::DependencyFlags::empty(),
+		&[] as &[vk::MemoryBarrier],
+		&[] as &[vk::BufferMemoryBarrier],
+		&[barrier],
+	);
+}
+
+/// Copies the opaque scene into the refraction image and draws the
+/// transmission pass's queues over it. Must be right after the scene pass.
+pub unsafe fn record_transmission_pass(
+	device: &Device,
+	data: &AppData,
+	command_buffer: vk::CommandBuffer,
+	secondary_command_buffers: &[vk::CommandBuffer],
+	stats: &mut FrameStats,
+	)
+{
+	data.layouts.expect(data.refraction_image, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, "refraction copy");
+
+	// the scene color isn't tracked, the render passes move it in and out of SHADER_READ_ONLY
+	image_barrier(
+		device,
+		command_buffer,
+		data.scene_image,
+		(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+		(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+	);
+
+	// last frame's transmission pass may still be sampling it
+	image_barrier(
+		device,
+		command_buffer,
+		data.refraction_image,
+		(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+		(vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::empty()),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+	);
+	stats.barriers += 2;
+
+	let layers = vk::ImageSubresourceLayers::builder()
+		.aspect_mask(vk::ImageAspectFlags::COLOR)
+		.mip_level(0)
+		.base_array_layer(0)
+		.layer_count(1);
+
+	let region = vk::ImageCopy::builder()
+		.src_subresource(layers)
+		.src_offset(vk::Offset3D::default())
+		.dst_subresource(layers)
+		.dst_offset(vk::Offset3D::default())
+		.extent(vk::Extent3D { width: data.render_extent.width, height: data.render_extent.height, depth: 1 });
+
+	device.cmd_copy_image(
+		command_buffer,
+		data.scene_image,
+		vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+		data.refraction_image,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		&[region],
+	);
+
+	image_barrier(
+		device,
+		command_buffer,
+		data.refraction_image,
+		(vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+		(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+		(vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+	);
+	stats.barriers += 1;
+
+	let render_area = vk::Rect2D::builder()
+		.offset(vk::Offset2D::default())
+		.extent(data.render_extent);
+
+	let info = vk::RenderPassBeginInfo::builder()
+		.render_pass(data.transmission_render_pass)
+		.framebuffer(data.scene_framebuffer)
+		.render_area(render_area);
+
+	device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::SECONDARY_COMMAND_BUFFERS);
+	device.cmd_execute_commands(command_buffer, secondary_command_buffers);
+	device.cmd_end_render_pass(command_buffer);
+}
+
+pub unsafe fn destroy_transmission_objects(device: &Device, data: &AppData)
+{
+	device.destroy_image_view(data.refraction_image_view, None);
+	device.destroy_image(data.refraction_image, None);
+	allocator::free(device, data.refraction_image_memory);
+	device.destroy_render_pass(data.transmission_render_pass, None);
+}
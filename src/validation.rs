@@ -0,0 +1,95 @@
+// Validation messages
+//
+// Errors and warnings from the validation layer are collected here as well
+// as logged, so whatever runs the renderer can check them afterwards
+// instead of scraping the log. The stress test and `--fail-on-validation`
+// exit with a failure if any of them count as one, and a test driving the
+// renderer can look at `messages` or `failures` once it's done.
+//
+// Only the first few hundred messages are kept, a broken frame repeats the
+// same ones every frame, but every failure is counted.
+
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const MAX_MESSAGES: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct Message
+{
+	pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+	pub type_: vk::DebugUtilsMessageTypeFlagsEXT,
+	pub text: String,
+}
+
+impl Message
+{
+	/// Every error fails a run, and so does a warning about using the API
+	/// wrong, but not one that's only about performance.
+	pub fn is_failure(&self) -> bool
+	{
+		self.severity >= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+			|| (self.severity >= vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+				&& self.type_.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION))
+	}
+}
+
+static MESSAGES: Mutex<Vec<Message>> = Mutex::new(Vec::new());
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// Collects a message from the debug callback, anything below a warning is
+/// left to the log.
+pub fn record(severity: vk::DebugUtilsMessageSeverityFlagsEXT, type_: vk::DebugUtilsMessageTypeFlagsEXT, text: &str)
+{
+	if severity < vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+	{
+		return;
+	}
+
+	let message = Message { severity, type_, text: text.to_string() };
+	if message.is_failure()
+	{
+		FAILURES.fetch_add(1, Ordering::Relaxed);
+	}
+
+	let mut messages = MESSAGES.lock().unwrap();
+	if messages.len() < MAX_MESSAGES
+	{
+		messages.push(message);
+	}
+}
+
+/// The errors and warnings collected so far, up to the first few hundred.
+pub fn messages() -> Vec<Message>
+{
+	MESSAGES.lock().unwrap().clone()
+}
+
+/// How many messages have counted as failures.
+pub fn failures() -> usize
+{
+	FAILURES.load(Ordering::Relaxed)
+}
+
+/// Logs the failures again, together at the end of a run where they can't
+/// be missed, and returns how many there were.
+pub fn report_failures() -> usize
+{
+	let failures = failures();
+	if failures == 0
+	{
+		info!("No validation failures");
+		return 0;
+	}
+
+	error!("{} validation failures", failures);
+	for message in messages().iter().filter(|m| m.is_failure())
+	{
+		error!("  {}", message.text);
+	}
+
+	failures
+}
@@ -0,0 +1,316 @@
+// Compact vertex and index formats
+//
+// Meshes are converted to the smallest formats that hold them when they're
+// uploaded: 16 bit indices when there are few enough vertices, and half
+// float positions and UVs with a 10-10-10-2 color when the mesh is small
+// enough for half floats to stay precise. The CPU keeps the full `Vertex`
+// for physics and anything else that reads the mesh back.
+//
+// There are no vertex normals yet, the shaders derive them from screen
+// space derivatives, so the 10-10-10-2 attribute holds the vertex color.
+//
+// Either layout can be uploaded interleaved or with the positions split
+// into a stream of their own. Positions always come first, so passes that
+// only need them bind the buffer's start either way, but split out they
+// don't drag the other attributes through the cache.
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use nalgebra_glm as glm;
+
+use std::mem::{offset_of, size_of};
+
+use crate::allocator::Allocation;
+use crate::{create_device_local_buffer, AppData, Vertex};
+
+/// Half floats step by 1/256 just below this, a few millimeters for models
+/// in meters.
+const PACKED_POSITION_LIMIT: f32 = 8.0;
+/// Half floats step by 1/2048 just below this, finer than a texel of a
+/// 2048 texture.
+const PACKED_UV_LIMIT: f32 = 1.0;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VertexLayout
+{
+	/// `Vertex` as it is, 40 bytes.
+	#[default]
+	Full,
+	/// `PackedVertex`, 20 bytes.
+	Packed,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum IndexWidth
+{
+	/// 16 bit when every index fits, 32 bit otherwise.
+	#[default]
+	Smallest,
+	/// Always 32 bit, e.g. for meshes that will grow past 65536 vertices.
+	Wide,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VertexStreams
+{
+	/// Each vertex's attributes next to each other in one stream.
+	#[default]
+	Interleaved,
+	/// Positions in binding 0, everything else in binding 1.
+	Deinterleaved,
+}
+
+/// Must match the inputs of `shader.vert`, location by location.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PackedVertex
+{
+	// half floats, w pads to a format every device supports for vertices
+	pos: [u16; 4],
+	color: u32,
+	tex_coord: [u16; 2],
+	lightmap_coord: [u16; 2],
+}
+
+impl From<&Vertex> for PackedVertex
+{
+	fn from(vertex: &Vertex) -> Self
+	{
+		Self {
+			pos: [half(vertex.pos.x), half(vertex.pos.y), half(vertex.pos.z), half(1.0)],
+			color: pack_unorm_2_10_10_10(&vertex.color),
+			tex_coord: [half(vertex.tex_coord.x), half(vertex.tex_coord.y)],
+			lightmap_coord: [half(vertex.lightmap_coord.x), half(vertex.lightmap_coord.y)],
+		}
+	}
+}
+
+impl VertexLayout
+{
+	/// The smallest layout the vertices fit in without visible error.
+	pub fn select(vertices: &[Vertex]) -> Self
+	{
+		let fits = vertices.iter().all(|v|
+		{
+			v.pos.iter().all(|c| c.abs() <= PACKED_POSITION_LIMIT)
+				&& v.tex_coord.iter().chain(v.lightmap_coord.iter()).all(|c| c.abs() <= PACKED_UV_LIMIT)
+		});
+
+		if fits { VertexLayout::Packed } else { VertexLayout::Full }
+	}
+
+	fn stride(self) -> usize
+	{
+		match self
+		{
+			VertexLayout::Full => size_of::<Vertex>(),
+			VertexLayout::Packed => size_of::<PackedVertex>(),
+		}
+	}
+
+	/// Size of the position, which starts every vertex.
+	fn position_size(self) -> usize
+	{
+		match self
+		{
+			VertexLayout::Full => size_of::<glm::Vec3>(),
+			VertexLayout::Packed => size_of::<[u16; 4]>(),
+		}
+	}
+
+	/// Stride of each binding.
+	fn binding_strides(self, streams: VertexStreams) -> Vec<usize>
+	{
+		match streams
+		{
+			VertexStreams::Interleaved => vec![self.stride()],
+			VertexStreams::Deinterleaved => vec![self.position_size(), self.stride() - self.position_size()],
+		}
+	}
+
+	/// Format and offset of the position, color, UV and lightmap UV, in
+	/// location order.
+	fn attributes(self) -> [(vk::Format, usize); 4]
+	{
+		match self
+		{
+			VertexLayout::Full => [
+				(vk::Format::R32G32B32_SFLOAT, offset_of!(Vertex, pos)),
+				(vk::Format::R32G32B32_SFLOAT, offset_of!(Vertex, color)),
+				(vk::Format::R32G32_SFLOAT, offset_of!(Vertex, tex_coord)),
+				(vk::Format::R32G32_SFLOAT, offset_of!(Vertex, lightmap_coord)),
+			],
+			VertexLayout::Packed => [
+				(vk::Format::R16G16B16A16_SFLOAT, offset_of!(PackedVertex, pos)),
+				(vk::Format::A2B10G10R10_UNORM_PACK32, offset_of!(PackedVertex, color)),
+				(vk::Format::R16G16_SFLOAT, offset_of!(PackedVertex, tex_coord)),
+				(vk::Format::R16G16_SFLOAT, offset_of!(PackedVertex, lightmap_coord)),
+			],
+		}
+	}
+
+	pub fn binding_descriptions(self, streams: VertexStreams) -> Vec<vk::VertexInputBindingDescription>
+	{
+		self.binding_strides(streams)
+			.iter()
+			.enumerate()
+			.map(|(binding, &stride)|
+			{
+				vk::VertexInputBindingDescription::builder()
+					.binding(binding as u32)
+					.stride(stride as u32)
+					.input_rate(vk::VertexInputRate::VERTEX)
+					.build()
+			})
+			.collect()
+	}
+
+	pub fn attribute_descriptions(self, streams: VertexStreams) -> Vec<vk::VertexInputAttributeDescription>
+	{
+		self.attributes()
+			.iter()
+			.enumerate()
+			.map(|(location, &(format, offset))|
+			{
+				// split out, everything after the position moves to the second stream
+				let (binding, offset) = match streams
+				{
+					VertexStreams::Deinterleaved if location > 0 => (1, offset - self.position_size()),
+					_ => (0, offset),
+				};
+
+				vk::VertexInputAttributeDescription::builder()
+					.binding(binding)
+					.location(location as u32)
+					.format(format)
+					.offset(offset as u32)
+					.build()
+			})
+			.collect()
+	}
+
+	/// Binding 0 on its own, which starts with the position in either
+	/// layout, for passes that need nothing else.
+	pub fn position_binding_description(self, streams: VertexStreams) -> vk::VertexInputBindingDescription
+	{
+		self.binding_descriptions(streams)[0]
+	}
+
+	pub fn position_attribute_description(self) -> vk::VertexInputAttributeDescription
+	{
+		self.attribute_descriptions(VertexStreams::Interleaved)[0]
+	}
+
+	/// The vertices in the layout, each stream's one after the other.
+	unsafe fn bytes(self, vertices: &[Vertex], streams: VertexStreams) -> Vec<u8>
+	{
+		let interleaved = match self
+		{
+			VertexLayout::Full => as_bytes(vertices).to_vec(),
+			VertexLayout::Packed => as_bytes(&vertices.iter().map(PackedVertex::from).collect::<Vec<_>>()).to_vec(),
+		};
+
+		match streams
+		{
+			VertexStreams::Interleaved => interleaved,
+			VertexStreams::Deinterleaved =>
+			{
+				let (positions, rest): (Vec<_>, Vec<_>) = interleaved
+					.chunks_exact(self.stride())
+					.map(|vertex| vertex.split_at(self.position_size()))
+					.unzip();
+				positions.concat().into_iter().chain(rest.concat()).collect()
+			},
+		}
+	}
+}
+
+/// Uploads the vertices converted to the layout, returning the offset to
+/// bind each stream of the buffer at.
+pub unsafe fn create_vertex_buffer(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	vertices: &[Vertex],
+	layout: VertexLayout,
+	streams: VertexStreams,
+	) -> Result<(vk::Buffer, Allocation, Vec<vk::DeviceSize>)>
+{
+	let bytes = layout.bytes(vertices, streams);
+	let (buffer, memory) = create_device_local_buffer(instance, device, data, &bytes, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+
+	let offsets = layout
+		.binding_strides(streams)
+		.iter()
+		.scan(0, |offset, stride|
+		{
+			let start = *offset;
+			*offset += (stride * vertices.len()) as vk::DeviceSize;
+			Some(start)
+		})
+		.collect();
+
+	Ok((buffer, memory, offsets))
+}
+
+/// Uploads the indices at the width, returning the type to bind the buffer
+/// with.
+pub unsafe fn create_index_buffer(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	indices: &[u32],
+	width: IndexWidth,
+	) -> Result<(vk::Buffer, Allocation, vk::IndexType)>
+{
+	let usage = vk::BufferUsageFlags::INDEX_BUFFER;
+	if width == IndexWidth::Smallest && indices.iter().all(|&i| i <= u16::MAX as u32)
+	{
+		let short = indices.iter().map(|&i| i as u16).collect::<Vec<_>>();
+		let (buffer, memory) = create_device_local_buffer(instance, device, data, &short, usage)?;
+		Ok((buffer, memory, vk::IndexType::UINT16))
+	}
+	else
+	{
+		let (buffer, memory) = create_device_local_buffer(instance, device, data, indices, usage)?;
+		Ok((buffer, memory, vk::IndexType::UINT32))
+	}
+}
+
+unsafe fn as_bytes<T: Copy>(items: &[T]) -> &[u8]
+{
+	items.align_to::<u8>().1
+}
+
+/// Converts to a half float, rounding to nearest. Values too large become
+/// infinity, which can't happen within the packing limits, and ones too
+/// small for a normal half flush to zero.
+fn half(value: f32) -> u16
+{
+	let bits = value.to_bits();
+	let sign = ((bits >> 16) & 0x8000) as u16;
+	let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+	let mantissa = bits & 0x7f_ffff;
+
+	if exponent <= 0
+	{
+		return sign;
+	}
+
+	if exponent >= 31
+	{
+		return sign | 0x7c00;
+	}
+
+	// rounding up may carry into the exponent, which is still the nearest half
+	let rounded = (((exponent as u32) << 10) | (mantissa >> 13)) + ((mantissa >> 12) & 1);
+	sign | rounded as u16
+}
+
+/// Packs a color into `A2B10G10R10_UNORM_PACK32`, red in the lowest bits.
+fn pack_unorm_2_10_10_10(color: &glm::Vec3) -> u32
+{
+	let channel = |c: f32| (c.clamp(0.0, 1.0) * 1023.0).round() as u32;
+	channel(color.x) | (channel(color.y) << 10) | (channel(color.z) << 20) | (3 << 30)
+}
@@ -0,0 +1,383 @@
+// Voxel terrain
+//
+// The world is a procedural heightmap split into chunks of CHUNK_SIZE³
+// voxels. Each chunk is greedy meshed (coplanar faces of the same voxel type
+// are merged into as few quads as possible) and uploaded as its own mesh.
+// Chunks stream in around the camera a few per frame, meshed in parallel and
+// uploaded one after the other, and are culled against the view frustum
+// individually.
+
+use anyhow::Result;
+use rayon::prelude::*;
+use vulkanalia::prelude::v1_0::*;
+
+use nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use crate::allocator::{self, Allocation};
+use crate::camera::Frustum;
+use crate::jobs::{Jobs, System};
+use crate::vertex_format::IndexWidth;
+use crate::{create_device_local_buffer, vertex_format, AppData, Vertex, MAX_FRAMES_IN_FLIGHT};
+
+pub const CHUNK_SIZE: usize = 16;
+const VOXEL_SIZE: f32 = 0.25;
+/// World height of the bottom of the chunks.
+const GROUND_LEVEL: f32 = -5.0;
+/// Chunks are loaded within this many chunks of the camera...
+const LOAD_RADIUS: i32 = 4;
+/// ...and unloaded once they're further than this, so chunks at the edge
+/// don't churn as the camera moves back and forth.
+const UNLOAD_RADIUS: i32 = 5;
+/// Limits the stall from meshing and uploading when many chunks are needed.
+const MAX_UPLOADS_PER_FRAME: usize = 2;
+
+type Voxel = u8;
+const AIR: Voxel = 0;
+const GRASS: Voxel = 1;
+const DIRT: Voxel = 2;
+const STONE: Voxel = 3;
+
+/// Voxel at world voxel coordinates. Terrain is a pure function of
+/// position, so chunks can look into their neighbours while meshing.
+fn voxel_at(x: i32, y: i32, z: i32) -> Voxel
+{
+	// everything below the chunks is solid so their bottoms aren't meshed
+	if z < 0
+	{
+		return STONE;
+	}
+
+	let (fx, fy) = (x as f32, y as f32);
+	let height = (6.0 + 3.0 * (fx * 0.15).sin() + 3.0 * (fy * 0.11).cos() + 1.5 * ((fx + fy) * 0.3).sin()) as i32;
+
+	if z >= height
+	{
+		AIR
+	}
+	else if z == height - 1
+	{
+		GRASS
+	}
+	else if z >= height - 3
+	{
+		DIRT
+	}
+	else
+	{
+		STONE
+	}
+}
+
+fn voxel_color(voxel: Voxel) -> glm::Vec3
+{
+	match voxel
+	{
+		GRASS => glm::vec3(0.3, 0.7, 0.2),
+		DIRT => glm::vec3(0.5, 0.35, 0.2),
+		_ => glm::vec3(0.5, 0.5, 0.5),
+	}
+}
+
+/// Greedy meshes the chunk at chunk coordinates `(cx, cy)`.
+fn mesh_chunk(cx: i32, cy: i32) -> (Vec<Vertex>, Vec<u32>)
+{
+	let n = CHUNK_SIZE as i32;
+	let origin = [cx * n, cy * n, 0];
+	let get = |p: [i32; 3]| voxel_at(origin[0] + p[0], origin[1] + p[1], origin[2] + p[2]);
+
+	let mut vertices = Vec::new();
+	let mut indices = Vec::new();
+	let mut mask = vec![0i32; CHUNK_SIZE * CHUNK_SIZE];
+
+	for d in 0..3
+	{
+		let u = (d + 1) % 3;
+		let v = (d + 2) % 3;
+		let mut q = [0; 3];
+		q[d] = 1;
+
+		// each plane sits between a voxel and its +d neighbour, the plane
+		// on the chunk's -d side belongs to the neighbouring chunk
+		let mut x = [0; 3];
+		for slice in 0..n
+		{
+			x[d] = slice;
+
+			// positive for faces pointing along +d, negative for -d
+			for j in 0..n
+			{
+				for i in 0..n
+				{
+					x[u] = i;
+					x[v] = j;
+					let a = get(x);
+					let b = get([x[0] + q[0], x[1] + q[1], x[2] + q[2]]);
+
+					mask[(j * n + i) as usize] = match (a != AIR, b != AIR)
+					{
+						(true, false) => a as i32,
+						(false, true) => -(b as i32),
+						_ => 0,
+					};
+				}
+			}
+
+			for j in 0..n
+			{
+				let mut i = 0;
+				while i < n
+				{
+					let c = mask[(j * n + i) as usize];
+					if c == 0
+					{
+						i += 1;
+						continue;
+					}
+
+					let mut width = 1;
+					while i + width < n && mask[(j * n + i + width) as usize] == c
+					{
+						width += 1;
+					}
+
+					let mut height = 1;
+					'grow: while j + height < n
+					{
+						for k in 0..width
+						{
+							if mask[((j + height) * n + i + k) as usize] != c
+							{
+								break 'grow;
+							}
+						}
+						height += 1;
+					}
+
+					let mut corner = [0; 3];
+					corner[d] = slice + 1;
+					corner[u] = i;
+					corner[v] = j;
+					let mut du = [0; 3];
+					du[u] = width;
+					let mut dv = [0; 3];
+					dv[v] = height;
+
+					let position = |offset: [i32; 3]| glm::vec3(
+						(origin[0] + corner[0] + offset[0]) as f32 * VOXEL_SIZE,
+						(origin[1] + corner[1] + offset[1]) as f32 * VOXEL_SIZE,
+						(origin[2] + corner[2] + offset[2]) as f32 * VOXEL_SIZE + GROUND_LEVEL,
+					);
+
+					// cheap directional shading so the faces are distinguishable
+					let shade = match (d, c > 0)
+					{
+						(2, true) => 1.0,
+						(2, false) => 0.5,
+						(0, _) => 0.8,
+						_ => 0.65,
+					};
+					let color = voxel_color(c.unsigned_abs() as Voxel) * shade;
+
+					let base = vertices.len() as u32;
+					for offset in [[0; 3], du, [du[0] + dv[0], du[1] + dv[1], du[2] + dv[2]], dv]
+					{
+						vertices.push(Vertex { pos: position(offset), color, tex_coord: glm::vec2(0.0, 0.0), lightmap_coord: glm::vec2(0.0, 0.0) });
+					}
+
+					// counter-clockwise seen from the side the face points to
+					if c > 0
+					{
+						indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+					}
+					else
+					{
+						indices.extend_from_slice(&[base, base + 2, base + 1, base, base + 3, base + 2]);
+					}
+
+					for h in 0..height
+					{
+						for k in 0..width
+						{
+							mask[((j + h) * n + i + k) as usize] = 0;
+						}
+					}
+
+					i += width;
+				}
+			}
+		}
+	}
+
+	(vertices, indices)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ChunkMesh
+{
+	pub vertex_buffer: vk::Buffer,
+	vertex_buffer_memory: Allocation,
+	pub index_buffer: vk::Buffer,
+	index_buffer_memory: Allocation,
+	pub index_count: u32,
+	pub index_type: vk::IndexType,
+	min: glm::Vec3,
+	max: glm::Vec3,
+}
+
+impl ChunkMesh
+{
+	unsafe fn destroy(&self, device: &Device)
+	{
+		// fully empty chunks never get buffers
+		if self.index_count > 0
+		{
+			device.destroy_buffer(self.vertex_buffer, None);
+			allocator::free(device, self.vertex_buffer_memory);
+			device.destroy_buffer(self.index_buffer, None);
+			allocator::free(device, self.index_buffer_memory);
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VoxelWorld
+{
+	pub enabled: bool,
+	chunks: HashMap<(i32, i32), ChunkMesh>,
+	/// Unloaded chunks with the frame they were unloaded on, kept until
+	/// no frame in flight can still be using them.
+	retired: Vec<(u64, ChunkMesh)>,
+	frame: u64,
+}
+
+impl VoxelWorld
+{
+	/// Streams chunks in and out around `eye`.
+	pub unsafe fn update(
+		&mut self,
+		instance: &Instance,
+		device: &Device,
+		data: &mut AppData,
+		eye: glm::Vec3,
+		) -> Result<()>
+	{
+		self.frame += 1;
+
+		let chunk_world_size = CHUNK_SIZE as f32 * VOXEL_SIZE;
+		let center = (
+			(eye.x / chunk_world_size).floor() as i32,
+			(eye.y / chunk_world_size).floor() as i32,
+		);
+		let distance = |(x, y): (i32, i32)| (x - center.0).abs().max((y - center.1).abs());
+
+		let radius = if self.enabled { UNLOAD_RADIUS } else { -1 };
+		let frame = self.frame;
+		let retired = &mut self.retired;
+		self.chunks.retain(|key, chunk|
+			{
+				let keep = distance(*key) <= radius;
+				if !keep
+				{
+					retired.push((frame, *chunk));
+				}
+				keep
+			});
+
+		let frames_in_flight = MAX_FRAMES_IN_FLIGHT as u64;
+		self.retired.retain(|(retired_frame, chunk)|
+			{
+				let done = frame > retired_frame + frames_in_flight;
+				if done
+				{
+					chunk.destroy(device);
+				}
+				!done
+			});
+
+		if !self.enabled
+		{
+			return Ok(());
+		}
+
+		let mut missing = (-LOAD_RADIUS..=LOAD_RADIUS)
+			.flat_map(|x| (-LOAD_RADIUS..=LOAD_RADIUS).map(move |y| (center.0 + x, center.1 + y)))
+			.filter(|key| !self.chunks.contains_key(key))
+			.collect::<Vec<_>>();
+
+		missing.sort_by_key(|key| distance(*key));
+
+		missing.truncate(MAX_UPLOADS_PER_FRAME);
+		let meshes = data.jobs.map(System::Meshing, &missing, |&(cx, cy)| mesh_chunk(cx, cy));
+		for (key, (vertices, indices)) in missing.into_iter().zip(meshes)
+		{
+			let chunk = load_chunk(instance, device, data, key, &vertices, &indices)?;
+			self.chunks.insert(key, chunk);
+		}
+
+		Ok(())
+	}
+
+	/// Loaded chunks that intersect the frustum, and how many were culled.
+	pub fn visible_chunks(&self, jobs: &Jobs, frustum: &Frustum) -> (Vec<ChunkMesh>, u32)
+	{
+		let meshed = self.chunks.values().filter(|chunk| chunk.index_count > 0).count();
+
+		let visible = jobs.install(System::Culling, ||
+			{
+				self.chunks
+					.par_iter()
+					.map(|(_, chunk)| chunk)
+					.filter(|chunk| chunk.index_count > 0 && frustum.intersects_aabb(&chunk.min, &chunk.max))
+					.copied()
+					.collect::<Vec<_>>()
+			});
+
+		let culled = (meshed - visible.len()) as u32;
+		(visible, culled)
+	}
+
+	pub unsafe fn destroy(&mut self, device: &Device)
+	{
+		self.chunks.drain().for_each(|(_, c)| c.destroy(device));
+		self.retired.drain(..).for_each(|(_, c)| c.destroy(device));
+	}
+}
+
+unsafe fn load_chunk(
+	instance: &Instance,
+	device: &Device,
+	data: &mut AppData,
+	(cx, cy): (i32, i32),
+	vertices: &[Vertex],
+	indices: &[u32],
+	) -> Result<ChunkMesh>
+{
+	let chunk_world_size = CHUNK_SIZE as f32 * VOXEL_SIZE;
+	let min = glm::vec3(cx as f32 * chunk_world_size, cy as f32 * chunk_world_size, GROUND_LEVEL);
+	let max = min + glm::vec3(chunk_world_size, chunk_world_size, chunk_world_size);
+
+	let mut chunk = ChunkMesh {
+		vertex_buffer: vk::Buffer::null(),
+		vertex_buffer_memory: Allocation::default(),
+		index_buffer: vk::Buffer::null(),
+		index_buffer_memory: Allocation::default(),
+		index_count: indices.len() as u32,
+		index_type: vk::IndexType::UINT32,
+		min,
+		max,
+	};
+
+	if indices.is_empty()
+	{
+		return Ok(chunk);
+	}
+
+	(chunk.vertex_buffer, chunk.vertex_buffer_memory) =
+		create_device_local_buffer(instance, device, data, vertices, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+	(chunk.index_buffer, chunk.index_buffer_memory, chunk.index_type) =
+		vertex_format::create_index_buffer(instance, device, data, indices, IndexWidth::Smallest)?;
+
+	Ok(chunk)
+}
@@ -0,0 +1,100 @@
+// Window modes
+//
+// The window cycles between windowed, borderless fullscreen on its current
+// monitor, and exclusive fullscreen at the monitor's largest video mode.
+// The windowed size is remembered on the way into fullscreen and restored
+// on the way out. Every change resizes the window, which recreates the
+// swapchain with the new extent like any other resize.
+
+use log::*;
+use winit::dpi::PhysicalSize;
+use winit::monitor::VideoMode;
+use winit::window::{Fullscreen, Window};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum WindowMode
+{
+	#[default]
+	Windowed,
+	Borderless,
+	/// Falls back to borderless if the monitor has no video modes to pick.
+	Exclusive,
+}
+
+impl WindowMode
+{
+	pub fn next(self) -> Self
+	{
+		match self
+		{
+			WindowMode::Windowed => WindowMode::Borderless,
+			WindowMode::Borderless => WindowMode::Exclusive,
+			WindowMode::Exclusive => WindowMode::Windowed,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct WindowModeState
+{
+	mode: WindowMode,
+	// the size to go back to when leaving fullscreen
+	windowed_size: Option<PhysicalSize<u32>>,
+}
+
+impl WindowModeState
+{
+	pub fn mode(&self) -> WindowMode
+	{
+		self.mode
+	}
+
+	/// Switches to the next mode.
+	pub fn cycle(&mut self, window: &Window)
+	{
+		self.set(window, self.mode.next());
+	}
+
+	pub fn set(&mut self, window: &Window, mode: WindowMode)
+	{
+		if self.mode == WindowMode::Windowed
+		{
+			self.windowed_size = Some(window.inner_size());
+		}
+
+		match mode
+		{
+			WindowMode::Windowed =>
+			{
+				window.set_fullscreen(None);
+				if let Some(size) = self.windowed_size
+				{
+					window.set_inner_size(size);
+				}
+			},
+			WindowMode::Borderless => window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor()))),
+			WindowMode::Exclusive => match largest_video_mode(window)
+			{
+				Some(video_mode) => window.set_fullscreen(Some(Fullscreen::Exclusive(video_mode))),
+				None =>
+				{
+					warn!("No video modes for exclusive fullscreen, staying borderless");
+					window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor())));
+				},
+			},
+		}
+
+		self.mode = mode;
+		info!("Window mode: {:?}", mode);
+	}
+}
+
+/// The current monitor's highest resolution, at its highest refresh rate.
+fn largest_video_mode(window: &Window) -> Option<VideoMode>
+{
+	window.current_monitor()?.video_modes().max_by_key(|m|
+		{
+			let size = m.size();
+			(size.width * size.height, m.refresh_rate_millihertz())
+		})
+}